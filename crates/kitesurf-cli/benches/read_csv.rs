@@ -0,0 +1,31 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use kitesurf_cli::{generate, parse_csv_bytes, read_csv, read_csv_fast, GeneratorConfig};
+
+fn input() -> Vec<u8> {
+    let config = GeneratorConfig {
+        rows: 100_000,
+        ..GeneratorConfig::default()
+    };
+    let mut input = Vec::new();
+    generate(&config, &mut input).unwrap();
+    input
+}
+
+fn read(c: &mut Criterion) {
+    let input = input();
+    let mut group = c.benchmark_group("read 100k rows");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+    group.bench_function("read_csv", |b| {
+        b.iter(|| read_csv(input.as_slice()).unwrap())
+    });
+    group.bench_function("read_csv_fast", |b| {
+        b.iter(|| read_csv_fast(input.as_slice()).unwrap())
+    });
+    group.bench_function("parse_csv_bytes", |b| {
+        b.iter(|| parse_csv_bytes(&input).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, read);
+criterion_main!(benches);
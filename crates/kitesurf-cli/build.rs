@@ -0,0 +1,11 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var(
+            "PROTOC",
+            protoc_bin_vendored::protoc_bin_path().expect("vendored protoc"),
+        );
+        tonic_build::compile_protos("proto/transaction_resolver.proto")
+            .expect("compile proto/transaction_resolver.proto");
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{Error, ErrorCode};
+
+/// How `parse_amount` treats amounts that aren't plain decimals.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum AmountParsing {
+    /// Plain decimals only, e.g. `-12.50`: scientific notation, a leading
+    /// `+` and several dots are ambiguous and rejected, as is anything that
+    /// isn't a finite number (`inf`, `NaN`).
+    #[default]
+    Strict,
+    /// Also amounts in quotes (`'12.50'`), with a leading `+`, in scientific
+    /// notation (`1.25e3`) or with dots grouping thousands (`1.234.567`),
+    /// normalized to their value. What's still ambiguous, e.g. `1.2.3`, is
+    /// rejected.
+    Lenient,
+}
+
+/// Parse the amount cell `field`, already unquoted and trimmed by the CSV
+/// reader.
+pub fn parse_amount(field: &str, parsing: AmountParsing) -> Result<f64, Error> {
+    match parsing {
+        AmountParsing::Strict => parse_strict(field),
+        AmountParsing::Lenient => parse_lenient(field),
+    }
+}
+
+fn parse_strict(field: &str) -> Result<f64, Error> {
+    let invalid = |reason: &str| {
+        Error::coded(
+            ErrorCode::Csv,
+            &format!("CSV Error: invalid amount {}: {}", field, reason),
+        )
+    };
+    let unsigned = field.strip_prefix('-').unwrap_or(field);
+    if unsigned.starts_with('+') {
+        return Err(invalid("leading +"));
+    }
+    if unsigned.contains(['e', 'E']) && unsigned.parse::<f64>().is_ok() {
+        return Err(invalid("scientific notation"));
+    }
+    if unsigned.matches('.').count() > 1 {
+        return Err(invalid("several decimal points"));
+    }
+    if !unsigned.chars().any(|c| c.is_ascii_digit())
+        || !unsigned.chars().all(|c| c.is_ascii_digit() || c == '.')
+    {
+        return Err(invalid("not a decimal number"));
+    }
+    match field.parse::<f64>() {
+        Ok(amount) if amount.is_finite() => Ok(amount),
+        _ => Err(invalid("out of range")),
+    }
+}
+
+fn parse_lenient(field: &str) -> Result<f64, Error> {
+    let invalid = || {
+        Error::coded(
+            ErrorCode::Csv,
+            &format!("CSV Error: invalid amount {}", field),
+        )
+    };
+    let mut number = field.trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = number
+            .strip_prefix(quote)
+            .and_then(|number| number.strip_suffix(quote))
+        {
+            number = inner.trim();
+        }
+    }
+    let (negative, unsigned) = match number.as_bytes().first() {
+        Some(b'-') => (true, &number[1..]),
+        Some(b'+') => (false, &number[1..]),
+        _ => (false, number),
+    };
+    let unsigned = match unsigned.split('.').collect::<Vec<_>>().as_slice() {
+        [first, groups @ ..] if groups.len() > 1 => {
+            let digits = |group: &str| group.chars().all(|c| c.is_ascii_digit());
+            if first.is_empty()
+                || first.len() > 3
+                || !digits(first)
+                || !groups.iter().all(|group| group.len() == 3 && digits(group))
+            {
+                return Err(invalid());
+            }
+            unsigned.replace('.', "")
+        }
+        _ => unsigned.to_string(),
+    };
+    // f64 also takes `inf`, `NaN` and a second sign
+    if !unsigned.starts_with(|c: char| c.is_ascii_digit() || c == '.')
+        || !unsigned
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'))
+    {
+        return Err(invalid());
+    }
+    match unsigned.parse::<f64>() {
+        Ok(amount) if amount.is_finite() => Ok(if negative { -amount } else { amount }),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strict_parsing_takes_plain_decimals_only() {
+        for (field, amount) in [
+            ("0", 0.0),
+            ("12", 12.0),
+            ("12.5", 12.5),
+            ("-12.5", -12.5),
+            ("0.0001", 0.0001),
+            (".5", 0.5),
+            ("5.", 5.0),
+            ("007.10", 7.1),
+        ] {
+            assert_eq!(
+                parse_amount(field, AmountParsing::Strict).ok(),
+                Some(amount),
+                "{}",
+                field
+            );
+        }
+        for field in [
+            "",
+            "-",
+            ".",
+            "+1",
+            "-+1",
+            "+-1",
+            "1e3",
+            "1E3",
+            "1.5e-2",
+            "-1e3",
+            "1.2.3",
+            "1..2",
+            "1.234.567",
+            "'1.5'",
+            "\"1.5\"",
+            "1,5",
+            "1 000",
+            "inf",
+            "-inf",
+            "NaN",
+            "infinity",
+            "0x10",
+            "1-",
+            "--1",
+            "abc",
+            "1.5 USD",
+        ] {
+            assert!(
+                parse_amount(field, AmountParsing::Strict).is_err(),
+                "{}",
+                field
+            );
+        }
+        let huge = format!("1{}", "0".repeat(400));
+        assert!(parse_amount(&huge, AmountParsing::Strict).is_err());
+    }
+
+    #[test]
+    fn strict_errors_name_the_ambiguity() {
+        let reason = |field: &str| parse_amount(field, AmountParsing::Strict).unwrap_err();
+        assert_eq!(reason("1e3").code, ErrorCode::Csv);
+        assert!(reason("1e3").message.contains("scientific notation"));
+        assert!(reason("+1").message.contains("leading +"));
+        assert!(reason("1.2.3").message.contains("several decimal points"));
+        assert!(reason("inf").message.contains("not a decimal number"));
+    }
+
+    #[test]
+    fn lenient_parsing_normalizes_amounts() {
+        for (field, amount) in [
+            ("12.5", 12.5),
+            ("-12.5", -12.5),
+            ("+12.5", 12.5),
+            ("1e3", 1000.0),
+            ("1.25E3", 1250.0),
+            ("+1.5e-2", 0.015),
+            ("-2e2", -200.0),
+            ("'12.5'", 12.5),
+            ("\"12.5\"", 12.5),
+            ("' -3 '", -3.0),
+            ("\"'+4'\"", 4.0),
+            ("1.234.567", 1_234_567.0),
+            ("-12.345.678", -12_345_678.0),
+        ] {
+            assert_eq!(
+                parse_amount(field, AmountParsing::Lenient).ok(),
+                Some(amount),
+                "{}",
+                field
+            );
+        }
+        for field in [
+            "",
+            "''",
+            "+",
+            "-",
+            "1.2.3",
+            "1..2",
+            "1234.567.890",
+            ".123.456",
+            "1.23.456",
+            "++1",
+            "+-1",
+            "--1",
+            "inf",
+            "+inf",
+            "NaN",
+            "infinity",
+            "1e",
+            "e3",
+            "1e3e3",
+            "1e400",
+            "'1.5",
+            "abc",
+            "1,5",
+        ] {
+            assert!(
+                parse_amount(field, AmountParsing::Lenient).is_err(),
+                "{}",
+                field
+            );
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::sync::Mutex;
+use std::time::Instant;
+use tonic::{Request, Status};
+
+use crate::Error;
+
+/// Metadata key clients put their API key in.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Allows `rate` requests per second on average, and bursts of up to
+/// `burst`. Tokens are refilled when taken rather than by a timer.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Starts full.
+    pub fn new(rate: f64, burst: f64, now: Instant) -> Self {
+        Self {
+            rate,
+            burst,
+            tokens: burst,
+            refilled_at: now,
+        }
+    }
+
+    /// Take a token if one is left as of `now`.
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        self.refilled_at = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+fn digest(key: &str) -> [u8; 32] {
+    Sha256::digest(key.as_bytes()).into()
+}
+
+/// API keys accepted by the gRPC service, each with its own token bucket
+/// when rate limited. Keys are kept as SHA-256 digests, so a lookup takes
+/// the same time however much of a wrong key matches.
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    keys: HashMap<[u8; 32], Mutex<Option<TokenBucket>>>,
+}
+
+impl ApiKeys {
+    /// Limit every key to `rate` requests per second, with bursts of as
+    /// many.
+    pub fn with_rate_limit(mut self, rate: f64) -> Self {
+        let now = Instant::now();
+        for bucket in self.keys.values_mut() {
+            *bucket = Mutex::new(Some(TokenBucket::new(rate, rate.max(1.0), now)));
+        }
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Let `request` through if it carries a known key with tokens left.
+    #[allow(clippy::result_large_err)] // tonic interceptors return `Status`
+    pub fn check(&self, request: Request<()>) -> Result<Request<()>, Status> {
+        let key = request
+            .metadata()
+            .get(API_KEY_HEADER)
+            .and_then(|key| key.to_str().ok())
+            .ok_or_else(|| Status::unauthenticated("Missing API key"))?;
+        let bucket = self
+            .keys
+            .get(&digest(key))
+            .ok_or_else(|| Status::unauthenticated("Unknown API key"))?;
+        let mut bucket = bucket
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let limited = bucket
+            .as_mut()
+            .is_some_and(|bucket| !bucket.try_take(Instant::now()));
+        if limited {
+            return Err(Status::resource_exhausted("Rate limit exceeded"));
+        }
+        Ok(request)
+    }
+}
+
+/// Read API keys, one per line. Blank lines and lines starting with `#`
+/// are skipped.
+pub fn read_api_keys<R: BufRead>(buf: R) -> Result<ApiKeys, Error> {
+    let mut keys = ApiKeys::default();
+    for line in buf.lines() {
+        let line = line?;
+        let key = line.trim();
+        if key.is_empty() || key.starts_with('#') {
+            continue;
+        }
+        keys.keys.insert(digest(key), Mutex::new(None));
+    }
+    if keys.is_empty() {
+        return Err(Error::new("No API keys found"));
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn request(key: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(key) = key {
+            request
+                .metadata_mut()
+                .insert(API_KEY_HEADER, key.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn bucket_refills_at_its_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 2.0, start);
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(start + Duration::from_millis(600)));
+    }
+
+    #[test]
+    fn requests_need_a_known_key_within_its_limit() -> Result<(), Error> {
+        let keys = read_api_keys("# ops\nkey-a\n\nkey-b\n".as_bytes())?.with_rate_limit(1.0);
+        assert_eq!(keys.len(), 2);
+        let code = |result: Result<Request<()>, Status>| result.map(|_| ()).map_err(|s| s.code());
+        assert_eq!(
+            code(keys.check(request(None))),
+            Err(tonic::Code::Unauthenticated)
+        );
+        assert_eq!(
+            code(keys.check(request(Some("key-c")))),
+            Err(tonic::Code::Unauthenticated)
+        );
+        assert_eq!(code(keys.check(request(Some("key-a")))), Ok(()));
+        assert_eq!(
+            code(keys.check(request(Some("key-a")))),
+            Err(tonic::Code::ResourceExhausted)
+        );
+        // buckets are per key
+        assert_eq!(code(keys.check(request(Some("key-b")))), Ok(()));
+        Ok(())
+    }
+}
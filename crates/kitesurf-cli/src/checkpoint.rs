@@ -0,0 +1,197 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+#[cfg(feature = "encryption")]
+use crate::StateKey;
+use crate::{EngineState, Error, ErrorCode, Snapshot};
+
+/// The engine state taken partway through an input file, to resume an
+/// interrupted run after the first `records` transactions of `input`.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Checkpoint {
+    pub input: String,
+    pub records: usize,
+    /// Older checkpoints only kept a `Snapshot` of it.
+    #[serde(alias = "snapshot")]
+    pub state: EngineState,
+    /// Head of the `TxHashChain` of the transactions applied so far.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_chain: Option<String>,
+}
+
+/// Start of the state files written with a `StateKey`, which tells them
+/// apart from JSON ones.
+pub const ENCRYPTED_HEADER: &[u8] = b"kitesurf-state-aes256gcm-v1\n";
+
+/// How snapshots and checkpoints are stored: as JSON, or with the
+/// `encryption` feature and a `StateKey`, encrypted at rest. Encrypted files
+/// are recognized by their header, so files written before a key was set up
+/// are still read.
+#[derive(Debug, Default, Clone)]
+pub struct StateFiles {
+    #[cfg(feature = "encryption")]
+    key: Option<StateKey>,
+}
+
+impl StateFiles {
+    /// Files written encrypted with `key`.
+    #[cfg(feature = "encryption")]
+    pub fn encrypted(key: StateKey) -> Self {
+        StateFiles { key: Some(key) }
+    }
+
+    pub fn read_snapshot(&self, path: &str) -> Result<Snapshot, Error> {
+        self.read(path, "snapshot")
+    }
+
+    pub fn write_snapshot(&self, path: &str, snapshot: &Snapshot) -> Result<(), Error> {
+        fs::write(path, self.encode(snapshot)?)?;
+        Ok(())
+    }
+
+    pub fn read_checkpoint(&self, path: &str) -> Result<Checkpoint, Error> {
+        self.read(path, "checkpoint")
+    }
+
+    /// Written to a temporary file renamed over `path`, so an interruption
+    /// never leaves a truncated checkpoint behind.
+    pub fn write_checkpoint(&self, path: &str, checkpoint: &Checkpoint) -> Result<(), Error> {
+        let tmp_path = format!("{}.tmp", path);
+        fs::write(&tmp_path, self.encode(checkpoint)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    fn read<T: DeserializeOwned>(&self, path: &str, what: &str) -> Result<T, Error> {
+        let data = fs::read(path)
+            .map_err(|err| Error::new(&format!("Unable to open {} {}: {}", what, path, err)))?;
+        if !data.starts_with(ENCRYPTED_HEADER) {
+            return Ok(serde_json::from_slice(&data)?);
+        }
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.key {
+            let data = key.decrypt(&data).map_err(|err| {
+                Error::coded(err.code, &format!("{} {}: {}", what, path, err.message))
+            })?;
+            return Ok(serde_json::from_slice(&data)?);
+        }
+        Err(Error::coded(
+            ErrorCode::Encryption,
+            &format!(
+                "{} {} is encrypted, {}",
+                what,
+                path,
+                if cfg!(feature = "encryption") {
+                    "pass its key with --state-key-file or KITESURF_STATE_KEY"
+                } else {
+                    "build with the encryption feature to read it"
+                }
+            ),
+        ))
+    }
+
+    fn encode(&self, value: &impl Serialize) -> Result<Vec<u8>, Error> {
+        let data = serde_json::to_vec(value)?;
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.key {
+            return key.encrypt(&data);
+        }
+        Ok(data)
+    }
+}
+
+pub fn read_snapshot(path: &str) -> Result<Snapshot, Error> {
+    StateFiles::default().read_snapshot(path)
+}
+
+pub fn write_snapshot(path: &str, snapshot: &Snapshot) -> Result<(), Error> {
+    StateFiles::default().write_snapshot(path, snapshot)
+}
+
+pub fn read_checkpoint(path: &str) -> Result<Checkpoint, Error> {
+    StateFiles::default().read_checkpoint(path)
+}
+
+/// See `StateFiles::write_checkpoint`.
+pub fn write_checkpoint(path: &str, checkpoint: &Checkpoint) -> Result<(), Error> {
+    StateFiles::default().write_checkpoint(path, checkpoint)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Amount, Engine, Tx, TxType};
+
+    fn deposit() -> Tx {
+        Tx::new(TxType::Deposit, 1, 1).with_amount(Amount::new(5.0).unwrap())
+    }
+
+    #[test]
+    fn snapshot_roundtrip_through_json() -> Result<(), Error> {
+        let mut engine = Engine::new();
+        engine.process(deposit())?;
+        let snapshot = engine.snapshot();
+        let json = serde_json::to_string(&snapshot)?;
+        assert_eq!(serde_json::from_str::<Snapshot>(&json)?, snapshot);
+        Ok(())
+    }
+
+    #[test]
+    fn checkpoint_roundtrip_through_file() -> Result<(), Error> {
+        let path = std::env::temp_dir().join(format!("checkpoint-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut engine = Engine::new();
+        engine.process(deposit())?;
+        let checkpoint = Checkpoint {
+            input: "input.csv".to_string(),
+            records: 1,
+            state: engine.state(),
+            tx_chain: Some("ab".repeat(32)),
+        };
+        write_checkpoint(path, &checkpoint)?;
+        let read = read_checkpoint(path);
+        fs::remove_file(path)?;
+        assert_eq!(read?, checkpoint);
+        Ok(())
+    }
+
+    #[test]
+    fn older_checkpoints_hold_a_snapshot() -> Result<(), Error> {
+        let mut engine = Engine::new();
+        engine.process(deposit())?;
+        let json = serde_json::json!({
+            "input": "input.csv",
+            "records": 1,
+            "snapshot": engine.snapshot(),
+        });
+        let checkpoint: Checkpoint = serde_json::from_value(json)?;
+        assert_eq!(checkpoint.state.accounts, engine.snapshot().accounts);
+        assert_eq!(checkpoint.state.processed, 0);
+        Ok(())
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_snapshot_needs_its_key() -> Result<(), Error> {
+        let path = std::env::temp_dir().join(format!("encrypted-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut engine = Engine::new();
+        engine.process(deposit())?;
+        let files = StateFiles::encrypted(StateKey::new([7; 32]));
+        files.write_snapshot(path, &engine.snapshot())?;
+        let plain = read_snapshot(path);
+        let other_key = StateFiles::encrypted(StateKey::new([8; 32])).read_snapshot(path);
+        let read = files.read_snapshot(path);
+        // and JSON ones are still read with a key
+        write_snapshot(path, &engine.snapshot())?;
+        let migrated = files.read_snapshot(path);
+        fs::remove_file(path)?;
+
+        assert_eq!(plain.unwrap_err().code, ErrorCode::Encryption);
+        assert_eq!(other_key.unwrap_err().code, ErrorCode::Encryption);
+        assert_eq!(read?, engine.snapshot());
+        assert_eq!(migrated?, engine.snapshot());
+        Ok(())
+    }
+}
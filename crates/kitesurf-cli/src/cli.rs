@@ -0,0 +1,1489 @@
+use encoding_rs::Encoding;
+use std::str::FromStr;
+
+use kitesurf_cli::{
+    encoding, AccountCreation, AccountFilter, AlertRule, Amount, ClientId, Compat,
+    DisputeHoldPolicy, Error, GeneratorConfig, LockedPolicy, RoundingMode, StressConfig,
+    TimestampFormat, ZeroAmountPolicy,
+};
+
+#[derive(Debug, Default, PartialEq)]
+pub enum Command {
+    /// Process a transaction file from scratch.
+    #[default]
+    Process,
+    /// Load the given snapshots and apply a transaction file across them.
+    Merge { snapshots: Vec<String> },
+    /// Process a transaction file at a fixed rate, in transactions per second.
+    Replay { rate: f64 },
+    /// Write synthetic transactions to stdout, no input file.
+    Generate(GeneratorConfig),
+    /// Check the engine on random transactions, no input file, see `stress`.
+    Stress(StressConfig),
+    /// Write the double-entry journal of the snapshot given as input.
+    Ledger,
+    /// Write the data-quality profile of the transaction file given as
+    /// input, see `profile_csv`.
+    Profile,
+    /// Print what processing the transaction row given as input would do,
+    /// on the accounts of `snapshot`, see `Engine::explain`.
+    Explain { snapshot: Option<String> },
+    /// Browse the accounts of the snapshot or output file given as input.
+    #[cfg(feature = "tui")]
+    View,
+    /// Process the files dropped into `input`, writing reports to
+    /// `output`, see `WatchDir`.
+    #[cfg(feature = "watch")]
+    Watch { input: String, output: String },
+    /// Serve the gRPC API, no input file.
+    #[cfg(feature = "grpc")]
+    ServeGrpc {
+        addr: std::net::SocketAddr,
+        /// File of the API keys accepted, see `read_api_keys`.
+        api_keys: Option<String>,
+        /// Requests per second allowed per API key.
+        rate_limit: Option<f64>,
+        /// Size of the ingestion queue submitted transactions go through,
+        /// see `GrpcService::with_queue`.
+        queue_capacity: Option<usize>,
+        /// Snapshot to start from, e.g. the `--snapshot-out` of the last run.
+        snapshot: Option<String>,
+    },
+}
+
+/// Format of the input transactions, see `--format`.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    /// Statement transactions of an OFX download, see `read_ofx`.
+    Ofx,
+    /// QIF export of a single client's account, see `read_qif`.
+    Qif,
+    /// camt.053 or pain.001 XML, see `read_iso20022`.
+    #[cfg(feature = "iso20022")]
+    Iso20022,
+    /// A worksheet of an Excel workbook, in the CSV schema, see `read_xlsx`.
+    #[cfg(feature = "xlsx")]
+    Xlsx,
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "csv" => Ok(InputFormat::Csv),
+            "ofx" => Ok(InputFormat::Ofx),
+            "qif" => Ok(InputFormat::Qif),
+            #[cfg(feature = "iso20022")]
+            "iso20022" => Ok(InputFormat::Iso20022),
+            #[cfg(feature = "xlsx")]
+            "xlsx" => Ok(InputFormat::Xlsx),
+            _ => Err(Error::new(&format!("Unknown input format: {}", format))),
+        }
+    }
+}
+
+impl InputFormat {
+    /// Rows in the CSV schema, read by the CSV readers.
+    fn is_tabular(self) -> bool {
+        match self {
+            InputFormat::Csv => true,
+            #[cfg(feature = "xlsx")]
+            InputFormat::Xlsx => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub command: Command,
+    pub input: String,
+    pub format: InputFormat,
+    /// Client the transactions of a QIF input belong to.
+    pub client: Option<ClientId>,
+    /// Worksheet of an xlsx input, the first one when not given.
+    pub sheet: Option<String>,
+    pub check_seq: bool,
+    pub tx_refs: bool,
+    /// Key clients by the `tenant` column too, see `ReadOptions::tenants`.
+    pub tenants: bool,
+    pub lenient: bool,
+    /// Add a `last_activity` column to the output.
+    pub last_activity: bool,
+    /// Add the columns of each client's `ClientStats` to the output.
+    pub client_stats: bool,
+    /// Only write the accounts whose balances or status differ from the
+    /// ones the run started with, see `diff_accounts`.
+    pub delta_only: bool,
+    /// `column,field` table of the output's columns, see `OutputSchema`.
+    pub output_schema: Option<String>,
+    /// Amounts like `1.234,56` with `--lenient`.
+    pub decimal_comma: bool,
+    /// How the `timestamp` column is written, auto-detected when not given.
+    pub timestamp_format: Option<TimestampFormat>,
+    /// Memory-map the input and parse it with `read_csv_mmap`.
+    #[cfg(feature = "mmap")]
+    pub mmap: bool,
+    /// Columns a CSV input is ordered by before it's read, see
+    /// `ExternalSort`, and the rows it sorts in memory at once.
+    pub sort_by: Vec<String>,
+    pub sort_buffer: Option<usize>,
+    /// Detached minisign signature of the input, and the public key it's
+    /// checked with, see `verify_signature`.
+    #[cfg(feature = "signatures")]
+    pub verify: Option<String>,
+    #[cfg(feature = "signatures")]
+    pub pubkey: Option<String>,
+    /// File of the key encrypting snapshots and checkpoints, see
+    /// `StateKey::load`.
+    #[cfg(feature = "encryption")]
+    pub state_key_file: Option<String>,
+    /// Encoding of the input file, UTF-8 when not given.
+    pub encoding: Option<&'static Encoding>,
+    pub pending_out: Option<String>,
+    pub risk_out: Option<String>,
+    pub category_out: Option<String>,
+    pub payouts_out: Option<String>,
+    pub suspense_out: Option<String>,
+    /// Where to write the disputes, resolves and chargebacks referring to
+    /// other clients' transactions, see `Engine::probes`.
+    pub security_out: Option<String>,
+    pub recoveries_out: Option<String>,
+    pub locks_out: Option<String>,
+    /// Withdrawal fee schedule, and the tier of each client.
+    pub fees: Option<String>,
+    pub fee_tiers: Option<String>,
+    pub revenue_out: Option<String>,
+    /// `currency,rate` table converting amounts into the base currency.
+    pub rates: Option<String>,
+    pub snapshot_out: Option<String>,
+    pub audit_out: Option<String>,
+    pub initial_accounts: Option<String>,
+    pub account_creation: AccountCreation,
+    /// `client` list of the accounts opened before the first transaction.
+    pub registered_accounts: Option<String>,
+    /// `client` lists of the only clients whose transactions are processed,
+    /// and of those whose transactions are dropped.
+    pub clients_allow: Option<String>,
+    pub clients_deny: Option<String>,
+    pub dispute_timeout: Option<u64>,
+    /// Transactions an early resolve or chargeback waits for its dispute.
+    pub reorder_window: Option<u64>,
+    /// What locked accounts accept, the one of the `--compat` mode without
+    /// one.
+    pub locked_policy: Option<LockedPolicy>,
+    /// Semantics to follow, `Compat::V2` without one.
+    pub compat: Option<Compat>,
+    pub dispute_holds: DisputeHoldPolicy,
+    /// Disputes flagged under `DisputeHoldPolicy::FlagForReview`.
+    pub hold_reviews_out: Option<String>,
+    /// Self-contained HTML summary of the run, see `write_html_report`.
+    pub report_html: Option<String>,
+    /// Replace client ids by pseudonyms in the output and reports, see
+    /// `Pseudonymizer`.
+    pub pseudonymize: bool,
+    pub pseudonym_key_file: Option<String>,
+    /// `pseudonym,client` table of the pseudonyms given out.
+    pub pseudonym_map: Option<String>,
+    pub zero_amounts: ZeroAmountPolicy,
+    pub adjust_negative_deposits: bool,
+    pub rounding: Option<RoundingMode>,
+    pub self_check: bool,
+    pub skip_empty_accounts: bool,
+    /// Which accounts are written to the output.
+    pub filter: AccountFilter,
+    /// Write the accounts to this directory, split in `partitions` files,
+    /// instead of stdout.
+    pub output_dir: Option<String>,
+    /// Where to write the accounts instead of stdout, see `sink`.
+    pub output: Option<String>,
+    pub partitions: Option<usize>,
+    /// Write a checkpoint here every `checkpoint_every` transactions and at
+    /// the end.
+    pub checkpoint_out: Option<String>,
+    pub checkpoint_every: Option<usize>,
+    pub resume: Option<String>,
+    pub max_amount: Option<Amount>,
+    pub max_balance: Option<Amount>,
+    /// Abort once the run would hold more accounts or transaction states.
+    pub max_accounts: Option<usize>,
+    pub max_tx_states: Option<usize>,
+    /// Where to write the provenance `Manifest` of the run.
+    pub manifest_out: Option<String>,
+    /// Report errors as JSON lines, to `errors_out` or stderr.
+    pub errors_json: bool,
+    pub errors_out: Option<String>,
+    /// Process with `process_parallel` on this many threads.
+    pub threads: Option<usize>,
+    /// Write each partition's accounts as soon as its thread is done, see
+    /// `process_parallel_streaming`.
+    pub stream_output: bool,
+    /// Accounts the output is expected to match, see `diff_accounts`.
+    pub expect: Option<String>,
+    /// Rules logged to stderr as soon as they hold, see `AlertMonitor`.
+    pub alerts: Vec<AlertRule>,
+    /// Stop the run once more than this percentage of the last
+    /// `rejection_window` transactions were rejected, or more than
+    /// `max_chargebacks_per_minute` chargebacks came in, see
+    /// `CircuitBreaker`.
+    pub max_rejection_rate: Option<f64>,
+    pub rejection_window: Option<usize>,
+    pub max_chargebacks_per_minute: Option<usize>,
+}
+
+/// Parse command line arguments, excluding the program name.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Args, Error> {
+    let mut args = args.into_iter().peekable();
+    let mut parsed = Args::default();
+    match args.peek().map(String::as_str) {
+        Some("merge") => {
+            args.next();
+            parsed.command = Command::Merge { snapshots: vec![] };
+        }
+        Some("replay") => {
+            args.next();
+            parsed.command = Command::Replay { rate: 0.0 };
+        }
+        Some("generate") => {
+            args.next();
+            parsed.command = Command::Generate(parse_generate(args)?);
+            return Ok(parsed);
+        }
+        Some("stress") => {
+            args.next();
+            parsed.command = Command::Stress(parse_stress(args)?);
+            return Ok(parsed);
+        }
+        Some("explain") => {
+            args.next();
+            parsed.command = Command::Explain { snapshot: None };
+        }
+        Some("ledger") => {
+            args.next();
+            parsed.command = Command::Ledger;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--output" => parsed.output = Some(value(&arg, args.next())?),
+                    flag if flag.starts_with("--") => {
+                        return Err(Error::new(&format!("Unknown option for ledger: {}", flag)))
+                    }
+                    _ if !parsed.input.is_empty() => {
+                        return Err(Error::new("Only one snapshot expected"))
+                    }
+                    _ => parsed.input = arg,
+                }
+            }
+            if parsed.input.is_empty() {
+                return Err(Error::new("ledger expects a snapshot"));
+            }
+            return Ok(parsed);
+        }
+        Some("profile") => {
+            args.next();
+            parsed.command = Command::Profile;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--output" => parsed.output = Some(value(&arg, args.next())?),
+                    flag if flag.starts_with("--") => {
+                        return Err(Error::new(&format!("Unknown option for profile: {}", flag)))
+                    }
+                    _ if !parsed.input.is_empty() => {
+                        return Err(Error::new("Only one input file expected"))
+                    }
+                    _ => parsed.input = arg,
+                }
+            }
+            if parsed.input.is_empty() {
+                return Err(Error::new("profile expects a transaction file"));
+            }
+            return Ok(parsed);
+        }
+        #[cfg(feature = "tui")]
+        Some("view") => {
+            args.next();
+            parsed.command = Command::View;
+            parsed.input = match (args.next(), args.next()) {
+                (Some(input), None) if !input.starts_with("--") => input,
+                _ => return Err(Error::new("view expects a snapshot or output file")),
+            };
+            return Ok(parsed);
+        }
+        #[cfg(feature = "watch")]
+        Some("watch") => {
+            args.next();
+            let mut dirs = vec![];
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--compat" => parsed.compat = Some(value(&arg, args.next())?.parse()?),
+                    #[cfg(feature = "encryption")]
+                    "--state-key-file" => parsed.state_key_file = Some(value(&arg, args.next())?),
+                    flag if flag.starts_with("--") => {
+                        return Err(Error::new(&format!("Unknown option for watch: {}", flag)))
+                    }
+                    _ => dirs.push(arg),
+                }
+            }
+            let [input, output] = <[String; 2]>::try_from(dirs)
+                .map_err(|_| Error::new("watch expects an input and an output directory"))?;
+            parsed.command = Command::Watch { input, output };
+            return Ok(parsed);
+        }
+        #[cfg(feature = "grpc")]
+        Some("serve-grpc") => {
+            args.next();
+            let mut addr = "127.0.0.1:50051".to_string();
+            let (mut api_keys, mut rate_limit, mut queue_capacity) = (None, None, None);
+            let mut snapshot = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--addr" => addr = value(&arg, args.next())?,
+                    "--api-keys" => api_keys = Some(value(&arg, args.next())?),
+                    "--rate-limit" => rate_limit = Some(number(&arg, args.next())?),
+                    "--queue-capacity" => queue_capacity = Some(number(&arg, args.next())?),
+                    "--compat" => parsed.compat = Some(value(&arg, args.next())?.parse()?),
+                    "--snapshot" => snapshot = Some(value(&arg, args.next())?),
+                    "--snapshot-out" => parsed.snapshot_out = Some(value(&arg, args.next())?),
+                    "--locked-policy" => {
+                        parsed.locked_policy = Some(value(&arg, args.next())?.parse()?)
+                    }
+                    _ => {
+                        return Err(Error::new(&format!(
+                            "Unknown option for serve-grpc: {}",
+                            arg
+                        )))
+                    }
+                }
+            }
+            let addr = addr
+                .parse()
+                .map_err(|_| Error::new(&format!("Invalid address: {}", addr)))?;
+            match rate_limit {
+                Some(rate) if rate <= 0.0 => {
+                    return Err(Error::new("--rate-limit expects a positive rate"))
+                }
+                Some(_) if api_keys.is_none() => {
+                    return Err(Error::new("--rate-limit expects --api-keys"))
+                }
+                _ => {}
+            }
+            if queue_capacity == Some(0) {
+                return Err(Error::new("--queue-capacity expects a positive size"));
+            }
+            if parsed.compat == Some(Compat::V1) {
+                return Err(Error::new(
+                    "serve-grpc can't run --compat v1, whose disputes reach across clients",
+                ));
+            }
+            parsed.command = Command::ServeGrpc {
+                addr,
+                api_keys,
+                rate_limit,
+                queue_capacity,
+                snapshot,
+            };
+            return Ok(parsed);
+        }
+        _ => {}
+    }
+
+    let mut input = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check-seq" => parsed.check_seq = true,
+            "--rate" => match &mut parsed.command {
+                Command::Replay { rate } => *rate = number(&arg, args.next())?,
+                _ => return Err(Error::new("--rate is only valid for replay")),
+            },
+            "--tx-refs" => parsed.tx_refs = true,
+            "--tenants" => parsed.tenants = true,
+            "--format" => parsed.format = value(&arg, args.next())?.parse()?,
+            "--client" => parsed.client = Some(number(&arg, args.next())?),
+            "--sheet" => parsed.sheet = Some(value(&arg, args.next())?),
+            "--lenient" => parsed.lenient = true,
+            "--decimal-comma" => parsed.decimal_comma = true,
+            "--timestamp-format" => {
+                parsed.timestamp_format = Some(value(&arg, args.next())?.parse()?)
+            }
+            "--last-activity" => parsed.last_activity = true,
+            "--client-stats" => parsed.client_stats = true,
+            "--delta-only" => parsed.delta_only = true,
+            "--output-schema" => parsed.output_schema = Some(value(&arg, args.next())?),
+            "--self-check" => parsed.self_check = true,
+            "--skip-empty-accounts" => parsed.skip_empty_accounts = true,
+            "--adjust-negative-deposits" => parsed.adjust_negative_deposits = true,
+            "--only-locked" => parsed.filter.only_locked = true,
+            "--nonzero-only" => parsed.filter.nonzero_only = true,
+            "--min-balance" => parsed.filter.min_balance = Some(number(&arg, args.next())?),
+            "--clients" => parsed.filter.clients = Some(value(&arg, args.next())?.parse()?),
+            "--output-dir" => parsed.output_dir = Some(value(&arg, args.next())?),
+            "--output" => parsed.output = Some(value(&arg, args.next())?),
+            "--partitions" => parsed.partitions = Some(number(&arg, args.next())?),
+            "--checkpoint" => parsed.checkpoint_out = Some(value(&arg, args.next())?),
+            "--checkpoint-every" => parsed.checkpoint_every = Some(number(&arg, args.next())?),
+            "--resume" => parsed.resume = Some(value(&arg, args.next())?),
+            "--max-amount" => parsed.max_amount = Some(number(&arg, args.next())?),
+            "--max-balance" => parsed.max_balance = Some(number(&arg, args.next())?),
+            "--max-accounts" => parsed.max_accounts = Some(number(&arg, args.next())?),
+            "--max-tx-states" => parsed.max_tx_states = Some(number(&arg, args.next())?),
+            "--manifest" => parsed.manifest_out = Some(value(&arg, args.next())?),
+            "--errors-json" => parsed.errors_json = true,
+            "--threads" => parsed.threads = Some(number(&arg, args.next())?),
+            "--stream-output" => parsed.stream_output = true,
+            "--errors-out" => parsed.errors_out = Some(value(&arg, args.next())?),
+            "--expect" => parsed.expect = Some(value(&arg, args.next())?),
+            "--alert" => parsed.alerts.push(value(&arg, args.next())?.parse()?),
+            "--max-rejection-rate" => parsed.max_rejection_rate = Some(number(&arg, args.next())?),
+            "--rejection-window" => parsed.rejection_window = Some(number(&arg, args.next())?),
+            "--max-chargebacks-per-minute" => {
+                parsed.max_chargebacks_per_minute = Some(number(&arg, args.next())?)
+            }
+            #[cfg(feature = "mmap")]
+            "--mmap" => parsed.mmap = true,
+            "--sort-by" => {
+                parsed.sort_by = value(&arg, args.next())?
+                    .split(',')
+                    .map(|key| key.trim().to_string())
+                    .filter(|key| !key.is_empty())
+                    .collect();
+                if parsed.sort_by.is_empty() {
+                    return Err(Error::new("--sort-by expects column names"));
+                }
+            }
+            "--sort-buffer" => parsed.sort_buffer = Some(number(&arg, args.next())?),
+            #[cfg(feature = "signatures")]
+            "--verify" => parsed.verify = Some(value(&arg, args.next())?),
+            #[cfg(feature = "signatures")]
+            "--pubkey" => parsed.pubkey = Some(value(&arg, args.next())?),
+            #[cfg(feature = "encryption")]
+            "--state-key-file" => parsed.state_key_file = Some(value(&arg, args.next())?),
+            "--encoding" => parsed.encoding = Some(encoding(&value(&arg, args.next())?)?),
+            "--snapshot-out" => parsed.snapshot_out = Some(value(&arg, args.next())?),
+            "--initial-accounts" => parsed.initial_accounts = Some(value(&arg, args.next())?),
+            "--account-creation" => parsed.account_creation = value(&arg, args.next())?.parse()?,
+            "--registered-accounts" => parsed.registered_accounts = Some(value(&arg, args.next())?),
+            "--clients-allow" => parsed.clients_allow = Some(value(&arg, args.next())?),
+            "--clients-deny" => parsed.clients_deny = Some(value(&arg, args.next())?),
+            "--pending" => parsed.pending_out = Some(value(&arg, args.next())?),
+            "--risk-report" => parsed.risk_out = Some(value(&arg, args.next())?),
+            "--category-report" => parsed.category_out = Some(value(&arg, args.next())?),
+            "--payouts" => parsed.payouts_out = Some(value(&arg, args.next())?),
+            "--suspense" => parsed.suspense_out = Some(value(&arg, args.next())?),
+            "--security-report" => parsed.security_out = Some(value(&arg, args.next())?),
+            "--recoveries" => parsed.recoveries_out = Some(value(&arg, args.next())?),
+            "--locks" => parsed.locks_out = Some(value(&arg, args.next())?),
+            "--fees" => parsed.fees = Some(value(&arg, args.next())?),
+            "--fee-tiers" => parsed.fee_tiers = Some(value(&arg, args.next())?),
+            "--revenue-report" => parsed.revenue_out = Some(value(&arg, args.next())?),
+            "--rates" => parsed.rates = Some(value(&arg, args.next())?),
+            "--locked-policy" => parsed.locked_policy = Some(value(&arg, args.next())?.parse()?),
+            "--compat" => parsed.compat = Some(value(&arg, args.next())?.parse()?),
+            "--dispute-holds" => parsed.dispute_holds = value(&arg, args.next())?.parse()?,
+            "--zero-amounts" => parsed.zero_amounts = value(&arg, args.next())?.parse()?,
+            "--hold-reviews" => parsed.hold_reviews_out = Some(value(&arg, args.next())?),
+            "--report-html" => parsed.report_html = Some(value(&arg, args.next())?),
+            "--pseudonymize" => parsed.pseudonymize = true,
+            "--pseudonym-key-file" => parsed.pseudonym_key_file = Some(value(&arg, args.next())?),
+            "--pseudonym-map" => parsed.pseudonym_map = Some(value(&arg, args.next())?),
+            "--rounding" => parsed.rounding = Some(value(&arg, args.next())?.parse()?),
+            "--audit" => parsed.audit_out = Some(value(&arg, args.next())?),
+            "--dispute-timeout" => parsed.dispute_timeout = Some(number(&arg, args.next())?),
+            "--reorder-window" => parsed.reorder_window = Some(number(&arg, args.next())?),
+            "--snapshot" => match &mut parsed.command {
+                Command::Merge { snapshots } => snapshots.push(value(&arg, args.next())?),
+                Command::Explain { snapshot: None } => {
+                    parsed.command = Command::Explain {
+                        snapshot: Some(value(&arg, args.next())?),
+                    }
+                }
+                Command::Explain { .. } => {
+                    return Err(Error::new("explain expects a single --snapshot"))
+                }
+                _ => return Err(Error::new("--snapshot is only valid for merge and explain")),
+            },
+            flag if flag.starts_with("--") => {
+                return Err(Error::new(&format!("Unknown option: {}", flag)));
+            }
+            _ => {
+                if input.replace(arg).is_some() {
+                    return Err(Error::new("Only one filepath expected"));
+                }
+            }
+        }
+    }
+    parsed.input = input.ok_or_else(|| Error::new("Filepath expected"))?;
+    match (&parsed.output_dir, parsed.partitions) {
+        (Some(_), Some(0)) => return Err(Error::new("--partitions expects at least 1")),
+        (Some(_), Some(_)) | (None, None) => {}
+        _ => {
+            return Err(Error::new(
+                "--output-dir and --partitions must be given together",
+            ))
+        }
+    }
+    if parsed.fees.is_none() && (parsed.fee_tiers.is_some() || parsed.revenue_out.is_some()) {
+        return Err(Error::new("--fee-tiers and --revenue-report expect --fees"));
+    }
+    if (parsed.dispute_holds == DisputeHoldPolicy::FlagForReview)
+        != parsed.hold_reviews_out.is_some()
+    {
+        return Err(Error::new(
+            "--dispute-holds flag_for_review and --hold-reviews must be given together",
+        ));
+    }
+    if parsed.output.is_some() && parsed.output_dir.is_some() {
+        return Err(Error::new("--output can't be combined with --output-dir"));
+    }
+    if parsed.errors_out.is_some() && !parsed.errors_json {
+        return Err(Error::new("--errors-out expects --errors-json"));
+    }
+    if parsed.checkpoint_every == Some(0) {
+        return Err(Error::new("--checkpoint-every expects at least 1"));
+    }
+    if parsed.checkpoint_every.is_some() && parsed.checkpoint_out.is_none() {
+        return Err(Error::new("--checkpoint-every expects --checkpoint"));
+    }
+    if parsed.resume.is_some()
+        && (parsed.initial_accounts.is_some() || matches!(parsed.command, Command::Merge { .. }))
+    {
+        return Err(Error::new(
+            "--resume can't be combined with --initial-accounts or merge",
+        ));
+    }
+    if parsed.threads == Some(0) {
+        return Err(Error::new("--threads expects at least 1"));
+    }
+    if parsed.threads.is_some() && parsed.compat == Some(Compat::V1) {
+        return Err(Error::new(
+            "--threads can't be combined with --compat v1, whose disputes reach across clients",
+        ));
+    }
+    if parsed.threads.is_some()
+        && (parsed.checkpoint_out.is_some()
+            || parsed.resume.is_some()
+            || parsed.dispute_timeout.is_some()
+            || parsed.reorder_window.is_some()
+            || parsed.pending_out.is_some()
+            || !parsed.alerts.is_empty()
+            || parsed.max_rejection_rate.is_some()
+            || parsed.max_chargebacks_per_minute.is_some()
+            || parsed.max_accounts.is_some()
+            || parsed.max_tx_states.is_some()
+            || parsed.security_out.is_some()
+            || matches!(parsed.command, Command::Replay { .. }))
+    {
+        return Err(Error::new(
+            "--threads can't be combined with --checkpoint, --resume, --dispute-timeout, --reorder-window, --pending, --alert, the circuit breaker, resource limits, --security-report or replay",
+        ));
+    }
+    if parsed
+        .max_rejection_rate
+        .is_some_and(|percent| !(0.0..100.0).contains(&percent))
+    {
+        return Err(Error::new(
+            "--max-rejection-rate expects a percentage from 0 up to 100",
+        ));
+    }
+    if parsed.rejection_window.is_some() && parsed.max_rejection_rate.is_none() {
+        return Err(Error::new(
+            "--rejection-window expects --max-rejection-rate",
+        ));
+    }
+    if parsed.rejection_window == Some(0) {
+        return Err(Error::new("--rejection-window expects at least 1"));
+    }
+    if parsed.stream_output && parsed.threads.is_none() {
+        return Err(Error::new("--stream-output expects --threads"));
+    }
+    if parsed.stream_output
+        && (parsed.expect.is_some()
+            || parsed.self_check
+            || parsed.last_activity
+            || parsed.partitions.is_some()
+            || parsed
+                .output
+                .as_deref()
+                .is_some_and(|output| output.contains("://")))
+    {
+        return Err(Error::new(
+            "--stream-output can't be combined with --expect, --self-check, --last-activity, --partitions or a remote --output",
+        ));
+    }
+    if parsed.last_activity && parsed.partitions.is_some() {
+        return Err(Error::new(
+            "--last-activity can't be combined with --partitions",
+        ));
+    }
+    if parsed.delta_only && (parsed.resume.is_some() || parsed.stream_output) {
+        return Err(Error::new(
+            "--delta-only can't be combined with --resume or --stream-output",
+        ));
+    }
+    if parsed.client_stats
+        && (parsed.last_activity
+            || parsed.partitions.is_some()
+            || parsed.stream_output
+            || parsed.tenants)
+    {
+        return Err(Error::new(
+            "--client-stats can't be combined with --last-activity, --partitions, --stream-output or --tenants",
+        ));
+    }
+    if parsed.output_schema.is_some()
+        && (parsed.last_activity
+            || parsed.client_stats
+            || parsed.tenants
+            || parsed.partitions.is_some()
+            || parsed.stream_output)
+    {
+        return Err(Error::new(
+            "--output-schema can't be combined with --last-activity, --client-stats, --tenants, --partitions or --stream-output",
+        ));
+    }
+    if parsed.report_html.is_some() && parsed.stream_output {
+        return Err(Error::new(
+            "--report-html can't be combined with --stream-output",
+        ));
+    }
+    if (parsed.pseudonym_key_file.is_some() || parsed.pseudonym_map.is_some())
+        && !parsed.pseudonymize
+    {
+        return Err(Error::new(
+            "--pseudonym-key-file and --pseudonym-map expect --pseudonymize",
+        ));
+    }
+    if parsed.pseudonymize && (parsed.partitions.is_some() || parsed.stream_output) {
+        return Err(Error::new(
+            "--pseudonymize can't be combined with --partitions or --stream-output",
+        ));
+    }
+    if parsed.decimal_comma && !parsed.lenient {
+        return Err(Error::new("--decimal-comma expects --lenient"));
+    }
+    if !parsed.format.is_tabular() && (parsed.lenient || parsed.tx_refs) {
+        return Err(Error::new(
+            "--lenient and --tx-refs are only valid for csv and xlsx input",
+        ));
+    }
+    #[cfg(feature = "xlsx")]
+    if parsed.format == InputFormat::Xlsx && parsed.encoding.is_some() {
+        return Err(Error::new(
+            "--encoding can't be combined with --format xlsx",
+        ));
+    }
+    #[cfg(feature = "xlsx")]
+    let xlsx = parsed.format == InputFormat::Xlsx;
+    #[cfg(not(feature = "xlsx"))]
+    let xlsx = false;
+    if parsed.sheet.is_some() && !xlsx {
+        return Err(Error::new("--sheet expects --format xlsx"));
+    }
+    if (parsed.format == InputFormat::Qif) != parsed.client.is_some() {
+        return Err(Error::new(
+            "--format qif and --client must be given together",
+        ));
+    }
+    if parsed.tenants
+        && (!parsed.format.is_tabular()
+            || parsed.last_activity
+            || parsed.partitions.is_some()
+            || parsed.stream_output
+            || parsed.initial_accounts.is_some()
+            || parsed.filter.clients.is_some()
+            || parsed.clients_allow.is_some()
+            || parsed.clients_deny.is_some()
+            || parsed.expect.is_some()
+            || matches!(parsed.command, Command::Explain { .. }))
+    {
+        return Err(Error::new(
+            "--tenants is only valid for csv and xlsx input, and can't be combined with --last-activity, --partitions, --stream-output, --initial-accounts, --clients, --clients-allow, --clients-deny, --expect or explain",
+        ));
+    }
+    if parsed.timestamp_format.is_some() && !parsed.format.is_tabular() {
+        return Err(Error::new(
+            "--timestamp-format is only valid for csv and xlsx input",
+        ));
+    }
+    #[cfg(feature = "mmap")]
+    if parsed.mmap
+        && (parsed.lenient
+            || parsed.tx_refs
+            || parsed.encoding.is_some()
+            || parsed.format != InputFormat::Csv)
+    {
+        return Err(Error::new(
+            "--mmap can't be combined with --lenient, --tx-refs, --encoding or --format",
+        ));
+    }
+    #[cfg(feature = "mmap")]
+    if parsed.mmap && (!parsed.sort_by.is_empty() || parsed.tenants) {
+        return Err(Error::new(
+            "--mmap can't be combined with --sort-by or --tenants",
+        ));
+    }
+    if parsed.sort_buffer.is_some() && parsed.sort_by.is_empty() {
+        return Err(Error::new("--sort-buffer expects --sort-by"));
+    }
+    if parsed.sort_buffer == Some(0) {
+        return Err(Error::new(
+            "--sort-buffer expects a positive number of rows",
+        ));
+    }
+    if !parsed.sort_by.is_empty()
+        && (parsed.format != InputFormat::Csv || matches!(parsed.command, Command::Explain { .. }))
+    {
+        return Err(Error::new("--sort-by is only valid for csv input"));
+    }
+
+    #[cfg(feature = "signatures")]
+    if parsed.verify.is_some() != parsed.pubkey.is_some() {
+        return Err(Error::new("--verify and --pubkey must be given together"));
+    }
+    #[cfg(feature = "signatures")]
+    if parsed.verify.is_some()
+        && (parsed.input == "-" || matches!(parsed.command, Command::Explain { .. }))
+    {
+        return Err(Error::new(
+            "--verify expects an input file, read once to check it and once to process it",
+        ));
+    }
+
+    match &parsed.command {
+        Command::Merge { snapshots } => {
+            if snapshots.is_empty() {
+                return Err(Error::new("merge expects at least one --snapshot"));
+            }
+            if parsed.snapshot_out.is_none() {
+                return Err(Error::new("merge expects --snapshot-out"));
+            }
+        }
+        Command::Replay { rate } if *rate <= 0.0 => {
+            return Err(Error::new("replay expects a positive --rate"));
+        }
+        Command::Explain { .. }
+            if parsed.threads.is_some()
+                || parsed.resume.is_some()
+                || parsed.checkpoint_out.is_some() =>
+        {
+            return Err(Error::new(
+                "explain can't be combined with --threads, --resume or --checkpoint",
+            ));
+        }
+        _ => {}
+    }
+    Ok(parsed)
+}
+
+fn parse_generate<I: Iterator<Item = String>>(mut args: I) -> Result<GeneratorConfig, Error> {
+    let mut config = GeneratorConfig::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => config.seed = number(&arg, args.next())?,
+            "--clients" => config.clients = number(&arg, args.next())?,
+            "--rows" => config.rows = number(&arg, args.next())?,
+            "--dispute-rate" => config.dispute_rate = number(&arg, args.next())?,
+            "--fraud-rate" => config.fraud_rate = number(&arg, args.next())?,
+            _ => return Err(Error::new(&format!("Unknown option for generate: {}", arg))),
+        }
+    }
+    Ok(config)
+}
+
+fn parse_stress<I: Iterator<Item = String>>(mut args: I) -> Result<StressConfig, Error> {
+    let mut config = StressConfig::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => config.seed = number(&arg, args.next())?,
+            "--runs" => config.runs = number(&arg, args.next())?,
+            "--rows" => config.rows = number(&arg, args.next())?,
+            "--clients" => config.clients = number(&arg, args.next())?,
+            "--threads" => config.threads = number(&arg, args.next())?,
+            _ => return Err(Error::new(&format!("Unknown option for stress: {}", arg))),
+        }
+    }
+    if config.clients == 0 || config.threads == 0 {
+        return Err(Error::new("stress expects at least 1 client and 1 thread"));
+    }
+    Ok(config)
+}
+
+fn value(flag: &str, value: Option<String>) -> Result<String, Error> {
+    value.ok_or_else(|| Error::new(&format!("{} expects a value", flag)))
+}
+
+fn number<T: FromStr>(flag: &str, raw: Option<String>) -> Result<T, Error> {
+    let raw = value(flag, raw)?;
+    raw.parse()
+        .map_err(|_| Error::new(&format!("{} expects a number, got {}", flag, raw)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_filepath_and_flags() -> Result<(), Error> {
+        assert_eq!(
+            parse_args(args(&["data/input.csv", "--check-seq", "--tx-refs"]))?,
+            Args {
+                input: "data/input.csv".to_string(),
+                check_seq: true,
+                tx_refs: true,
+                ..Args::default()
+            }
+        );
+        assert!(parse_args(args(&["data/input.csv", "--lenient"]))?.lenient);
+        assert!(parse_args(args(&["data/input.csv", "--self-check"]))?.self_check);
+        assert!(
+            parse_args(args(&["data/input.csv", "--skip-empty-accounts"]))?.skip_empty_accounts
+        );
+        assert!(
+            parse_args(args(&["data/input.csv", "--adjust-negative-deposits"]))?
+                .adjust_negative_deposits
+        );
+        let compat = parse_args(args(&["data/input.csv", "--compat", "v1"]))?;
+        assert_eq!(
+            (compat.compat, compat.locked_policy),
+            (Some(Compat::V1), None)
+        );
+        assert!(parse_args(args(&["data/input.csv", "--compat", "v3"])).is_err());
+        assert!(parse_args(args(&[
+            "data/input.csv",
+            "--compat",
+            "v1",
+            "--threads",
+            "2"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&["in.xml", "--format", "xml"])).is_err());
+        let qif = parse_args(args(&["in.qif", "--format", "qif", "--client", "7"]))?;
+        assert_eq!((qif.format, qif.client), (InputFormat::Qif, Some(7)));
+        assert!(parse_args(args(&["in.qif", "--format", "qif"])).is_err());
+        assert!(parse_args(args(&["in.ofx", "--format", "ofx", "--client", "7"])).is_err());
+        #[cfg(feature = "iso20022")]
+        {
+            let xml = parse_args(args(&["in.xml", "--format", "iso20022"]))?;
+            assert_eq!(xml.format, InputFormat::Iso20022);
+            assert!(parse_args(args(&["in.xml", "--format", "iso20022", "--lenient"])).is_err());
+        }
+        #[cfg(feature = "xlsx")]
+        {
+            let xlsx = parse_args(args(&[
+                "in.xlsx",
+                "--format",
+                "xlsx",
+                "--sheet",
+                "Q1",
+                "--lenient",
+            ]))?;
+            assert_eq!(xlsx.sheet, Some("Q1".to_string()));
+            assert!(parse_args(args(&[
+                "in.xlsx",
+                "--format",
+                "xlsx",
+                "--encoding",
+                "latin1"
+            ]))
+            .is_err());
+        }
+        assert!(parse_args(args(&["in.csv", "--sheet", "Q1"])).is_err());
+        assert!(parse_args(args(&["in.csv", "--lenient", "--decimal-comma"]))?.decimal_comma);
+        assert!(parse_args(args(&["in.csv", "--decimal-comma"])).is_err());
+        assert_eq!(
+            parse_args(args(&["in.csv", "--timestamp-format", "millis"]))?.timestamp_format,
+            Some(TimestampFormat::EpochMillis)
+        );
+        assert!(parse_args(args(&["in.csv", "--timestamp-format", "julian"])).is_err());
+        assert!(parse_args(args(&[
+            "in.ofx",
+            "--format",
+            "ofx",
+            "--timestamp-format",
+            "seconds"
+        ]))
+        .is_err());
+        let combined = parse_args(args(&[
+            "in.csv",
+            "--timestamp-format",
+            "seconds",
+            "--lenient",
+            "--tx-refs",
+            "--tenants",
+        ]))?;
+        assert!(combined.lenient && combined.tx_refs && combined.tenants);
+        assert!(parse_args(args(&["in.csv", "--last-activity"]))?.last_activity);
+        assert!(parse_args(args(&["in.csv", "--client-stats"]))?.client_stats);
+        assert!(parse_args(args(&["in.csv", "--client-stats", "--last-activity"])).is_err());
+        assert_eq!(
+            parse_args(args(&["in.csv", "--report-html", "out.html"]))?.report_html,
+            Some("out.html".to_string())
+        );
+        assert!(parse_args(args(&["in.csv", "--pseudonymize"]))?.pseudonymize);
+        assert!(parse_args(args(&["in.csv", "--pseudonym-map", "map.csv"])).is_err());
+        assert!(parse_args(args(&["in.csv", "--pseudonymize", "--stream-output"])).is_err());
+        assert!(parse_args(args(&["in.csv", "--delta-only"]))?.delta_only);
+        assert!(parse_args(args(&["in.csv", "--delta-only", "--resume", "c.json"])).is_err());
+        assert_eq!(
+            parse_args(args(&["in.csv", "--output-schema", "schema.csv"]))?.output_schema,
+            Some("schema.csv".to_string())
+        );
+        assert!(parse_args(args(&[
+            "in.csv",
+            "--output-schema",
+            "schema.csv",
+            "--client-stats"
+        ]))
+        .is_err());
+        assert_eq!(
+            parse_args(args(&["in.csv", "--threads", "4"]))?.threads,
+            Some(4)
+        );
+        assert!(parse_args(args(&["in.csv", "--threads", "0"])).is_err());
+        assert!(parse_args(args(&["in.csv", "--threads", "4", "--stream-output"]))?.stream_output);
+        assert!(parse_args(args(&["in.csv", "--stream-output"])).is_err());
+        assert!(parse_args(args(&[
+            "in.csv",
+            "--threads",
+            "4",
+            "--stream-output",
+            "--output",
+            "s3://bucket/accounts.csv"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&[
+            "in.csv",
+            "--threads",
+            "4",
+            "--dispute-timeout",
+            "9"
+        ]))
+        .is_err());
+        let filtered = parse_args(args(&[
+            "data/input.csv",
+            "--only-locked",
+            "--nonzero-only",
+            "--min-balance",
+            "100",
+            "--clients",
+            "1,5,9-20",
+        ]))?;
+        assert_eq!(
+            filtered.filter,
+            AccountFilter {
+                only_locked: true,
+                min_balance: Some(100.0),
+                clients: Some("1,5,9-20".parse()?),
+                nonzero_only: true,
+            }
+        );
+        assert!(parse_args(args(&["data/input.csv", "--clients", "9-"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_alerts() -> Result<(), Error> {
+        let parsed = parse_args(args(&[
+            "in.csv",
+            "--alert",
+            "held>1000",
+            "--alert",
+            "chargebacks>5",
+        ]))?;
+        assert_eq!(
+            parsed.alerts,
+            vec![AlertRule::HeldAbove(1000.0), AlertRule::ChargebacksAbove(5)]
+        );
+        assert!(parse_args(args(&["in.csv", "--alert", "held"])).is_err());
+        assert!(parse_args(args(&["in.csv", "--alert", "held>1", "--threads", "2"])).is_err());
+        assert!(parse_args(args(&["in.csv", "--max-accounts", "9", "--threads", "2"])).is_err());
+        assert!(parse_args(args(&[
+            "in.csv",
+            "--security-report",
+            "s.csv",
+            "--threads",
+            "2"
+        ]))
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_circuit_breaker() -> Result<(), Error> {
+        let parsed = parse_args(args(&[
+            "in.csv",
+            "--max-rejection-rate",
+            "20",
+            "--rejection-window",
+            "500",
+            "--max-chargebacks-per-minute",
+            "30",
+        ]))?;
+        assert_eq!(parsed.max_rejection_rate, Some(20.0));
+        assert_eq!(parsed.rejection_window, Some(500));
+        assert_eq!(parsed.max_chargebacks_per_minute, Some(30));
+        assert!(parse_args(args(&["in.csv", "--max-rejection-rate", "100"])).is_err());
+        assert!(parse_args(args(&["in.csv", "--rejection-window", "10"])).is_err());
+        assert!(parse_args(args(&[
+            "in.csv",
+            "--max-chargebacks-per-minute",
+            "3",
+            "--threads",
+            "2"
+        ]))
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_tenants() -> Result<(), Error> {
+        assert!(parse_args(args(&["in.csv", "--tenants"]))?.tenants);
+        assert!(parse_args(args(&["in.csv", "--tenants", "--clients", "1"])).is_err());
+        assert!(parse_args(args(&["in.ofx", "--format", "ofx", "--tenants"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_sort_by() -> Result<(), Error> {
+        let sorted = parse_args(args(&[
+            "in.csv",
+            "--sort-by",
+            "timestamp, tx",
+            "--sort-buffer",
+            "1000",
+        ]))?;
+        assert_eq!(sorted.sort_by, vec!["timestamp", "tx"]);
+        assert_eq!(sorted.sort_buffer, Some(1000));
+        assert!(parse_args(args(&["in.csv", "--sort-by", ","])).is_err());
+        assert!(parse_args(args(&["in.csv", "--sort-buffer", "10"])).is_err());
+        assert!(parse_args(args(&["in.ofx", "--format", "ofx", "--sort-by", "date"])).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn parse_mmap() -> Result<(), Error> {
+        assert!(parse_args(args(&["in.csv", "--mmap"]))?.mmap);
+        assert!(parse_args(args(&["in.csv", "--mmap", "--lenient"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_valued_options() -> Result<(), Error> {
+        let parsed = parse_args(args(&[
+            "in.csv",
+            "--audit",
+            "audit.csv",
+            "--dispute-timeout",
+            "100",
+            "--reorder-window",
+            "3",
+            "--initial-accounts",
+            "accounts.csv",
+            "--account-creation",
+            "first_deposit",
+            "--registered-accounts",
+            "kyc.csv",
+            "--clients-allow",
+            "allow.csv",
+            "--clients-deny",
+            "deny.csv",
+            "--pending",
+            "pending.csv",
+            "--risk-report",
+            "risk.csv",
+            "--category-report",
+            "categories.csv",
+            "--payouts",
+            "payouts.csv",
+            "--suspense",
+            "suspense.csv",
+            "--security-report",
+            "security.csv",
+            "--recoveries",
+            "recoveries.csv",
+            "--locks",
+            "locks.csv",
+            "--fees",
+            "fees.csv",
+            "--fee-tiers",
+            "tiers.csv",
+            "--revenue-report",
+            "revenue.csv",
+            "--rates",
+            "rates.csv",
+            "--encoding",
+            "latin1",
+            "--rounding",
+            "half_even",
+            "--locked-policy",
+            "allow_disputes",
+            "--dispute-holds",
+            "flag_for_review",
+            "--hold-reviews",
+            "reviews.csv",
+            "--zero-amounts",
+            "reject",
+            "--checkpoint",
+            "checkpoint.json",
+            "--checkpoint-every",
+            "1000",
+            "--max-amount",
+            "10000",
+            "--max-balance",
+            "1e6",
+            "--max-accounts",
+            "1000",
+            "--max-tx-states",
+            "50000",
+            "--manifest",
+            "manifest.json",
+            "--errors-json",
+            "--errors-out",
+            "errors.jsonl",
+            "--expect",
+            "expected.csv",
+            "--output-dir",
+            "out",
+            "--partitions",
+            "128",
+        ]))?;
+        assert_eq!(parsed.locked_policy, Some(LockedPolicy::AllowDisputes));
+        assert_eq!(parsed.dispute_holds, DisputeHoldPolicy::FlagForReview);
+        assert_eq!(parsed.hold_reviews_out, Some("reviews.csv".to_string()));
+        assert!(parse_args(args(&["in.csv", "--hold-reviews", "reviews.csv"])).is_err());
+        assert_eq!(parsed.zero_amounts, ZeroAmountPolicy::Reject);
+        assert_eq!(parsed.risk_out, Some("risk.csv".to_string()));
+        assert_eq!(parsed.category_out, Some("categories.csv".to_string()));
+        assert_eq!(parsed.payouts_out, Some("payouts.csv".to_string()));
+        assert_eq!(parsed.suspense_out, Some("suspense.csv".to_string()));
+        assert_eq!(parsed.security_out, Some("security.csv".to_string()));
+        assert_eq!(parsed.recoveries_out, Some("recoveries.csv".to_string()));
+        assert_eq!(parsed.locks_out, Some("locks.csv".to_string()));
+        assert_eq!(parsed.fees, Some("fees.csv".to_string()));
+        assert_eq!(parsed.fee_tiers, Some("tiers.csv".to_string()));
+        assert_eq!(parsed.revenue_out, Some("revenue.csv".to_string()));
+        assert!(parse_args(args(&["in.csv", "--fee-tiers", "tiers.csv"])).is_err());
+        assert_eq!(parsed.rates, Some("rates.csv".to_string()));
+        assert_eq!(parsed.encoding, Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(parsed.rounding, Some(RoundingMode::HalfEven));
+        assert_eq!(parsed.pending_out, Some("pending.csv".to_string()));
+        assert_eq!(parsed.initial_accounts, Some("accounts.csv".to_string()));
+        assert_eq!(parsed.account_creation, AccountCreation::FirstDeposit);
+        assert_eq!(parsed.registered_accounts, Some("kyc.csv".to_string()));
+        assert_eq!(parsed.clients_allow, Some("allow.csv".to_string()));
+        assert_eq!(parsed.clients_deny, Some("deny.csv".to_string()));
+        assert_eq!(parsed.audit_out, Some("audit.csv".to_string()));
+        assert_eq!(parsed.dispute_timeout, Some(100));
+        assert_eq!(parsed.reorder_window, Some(3));
+        assert_eq!(parsed.output_dir, Some("out".to_string()));
+        assert_eq!(parsed.partitions, Some(128));
+        assert_eq!(parsed.max_amount, Some(Amount::new(10000.0)?));
+        assert_eq!(parsed.max_balance, Some(Amount::new(1e6)?));
+        assert_eq!(parsed.max_accounts, Some(1000));
+        assert_eq!(parsed.max_tx_states, Some(50000));
+        assert_eq!(parsed.manifest_out, Some("manifest.json".to_string()));
+        assert!(parsed.errors_json);
+        assert_eq!(parsed.errors_out, Some("errors.jsonl".to_string()));
+        assert_eq!(parsed.expect, Some("expected.csv".to_string()));
+        assert!(parse_args(args(&["in.csv", "--errors-out", "errors.jsonl"])).is_err());
+        assert_eq!(parsed.checkpoint_out, Some("checkpoint.json".to_string()));
+        assert_eq!(parsed.checkpoint_every, Some(1000));
+        let resumed = parse_args(args(&["in.csv", "--resume", "checkpoint.json"]))?;
+        assert_eq!(resumed.resume, Some("checkpoint.json".to_string()));
+        assert!(parse_args(args(&[
+            "in.csv",
+            "--resume",
+            "checkpoint.json",
+            "--initial-accounts",
+            "accounts.csv"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&["in.csv", "--checkpoint-every", "10"])).is_err());
+        assert!(parse_args(args(&["in.csv", "--dispute-timeout", "soon"])).is_err());
+        assert!(parse_args(args(&["in.csv", "--partitions", "4"])).is_err());
+        assert_eq!(
+            parse_args(args(&["in.csv", "--output", "s3://bucket/accounts.csv"]))?.output,
+            Some("s3://bucket/accounts.csv".to_string())
+        );
+        assert!(parse_args(args(&[
+            "in.csv",
+            "--output",
+            "accounts.csv",
+            "--output-dir",
+            "out",
+            "--partitions",
+            "4"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&[
+            "in.csv",
+            "--output-dir",
+            "out",
+            "--partitions",
+            "0"
+        ]))
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_merge() -> Result<(), Error> {
+        let parsed = parse_args(args(&[
+            "merge",
+            "--snapshot",
+            "a.json",
+            "--snapshot",
+            "b.json",
+            "--snapshot-out",
+            "out.json",
+            "delta.csv",
+        ]))?;
+        assert_eq!(
+            parsed,
+            Args {
+                command: Command::Merge {
+                    snapshots: vec!["a.json".to_string(), "b.json".to_string()]
+                },
+                input: "delta.csv".to_string(),
+                snapshot_out: Some("out.json".to_string()),
+                ..Args::default()
+            }
+        );
+        assert!(parse_args(args(&["merge", "--snapshot-out", "o.json", "d.csv"])).is_err());
+        assert!(parse_args(args(&["merge", "--snapshot", "a.json", "d.csv"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_replay() -> Result<(), Error> {
+        let parsed = parse_args(args(&["replay", "--rate", "250", "in.csv"]))?;
+        assert_eq!(parsed.command, Command::Replay { rate: 250.0 });
+        assert!(parse_args(args(&["replay", "in.csv"])).is_err());
+        assert!(parse_args(args(&["in.csv", "--rate", "250"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_generate() -> Result<(), Error> {
+        let parsed = parse_args(args(&["generate", "--seed", "42", "--rows", "10"]))?;
+        assert_eq!(
+            parsed.command,
+            Command::Generate(GeneratorConfig {
+                seed: 42,
+                rows: 10,
+                ..GeneratorConfig::default()
+            })
+        );
+        assert!(parse_args(args(&["generate", "in.csv"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_stress() -> Result<(), Error> {
+        let parsed = parse_args(args(&["stress", "--seed", "9", "--runs", "5"]))?;
+        assert_eq!(
+            parsed.command,
+            Command::Stress(StressConfig {
+                seed: 9,
+                runs: 5,
+                ..StressConfig::default()
+            })
+        );
+        assert!(parse_args(args(&["stress", "--threads", "0"])).is_err());
+        assert!(parse_args(args(&["stress", "in.csv"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_ledger() -> Result<(), Error> {
+        let parsed = parse_args(args(&["ledger", "--output", "ledger.csv", "state.json"]))?;
+        assert_eq!(
+            parsed,
+            Args {
+                command: Command::Ledger,
+                input: "state.json".to_string(),
+                output: Some("ledger.csv".to_string()),
+                ..Args::default()
+            }
+        );
+        assert!(parse_args(args(&["ledger"])).is_err());
+        assert!(parse_args(args(&["ledger", "a.json", "b.json"])).is_err());
+        assert!(parse_args(args(&["ledger", "--audit", "a.csv", "a.json"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_profile() -> Result<(), Error> {
+        let parsed = parse_args(args(&["profile", "feed.csv", "--output", "profile.json"]))?;
+        assert_eq!(parsed.command, Command::Profile);
+        assert_eq!(parsed.input, "feed.csv");
+        assert_eq!(parsed.output, Some("profile.json".to_string()));
+        assert!(parse_args(args(&["profile"])).is_err());
+        assert!(parse_args(args(&["profile", "--lenient", "feed.csv"])).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "signatures")]
+    #[test]
+    fn parse_verify() -> Result<(), Error> {
+        let parsed = parse_args(args(&[
+            "--verify",
+            "in.csv.minisig",
+            "--pubkey",
+            "partner.pub",
+            "in.csv",
+        ]))?;
+        assert_eq!(parsed.verify, Some("in.csv.minisig".to_string()));
+        assert_eq!(parsed.pubkey, Some("partner.pub".to_string()));
+        assert!(parse_args(args(&["--verify", "in.csv.minisig", "in.csv"])).is_err());
+        assert!(parse_args(args(&[
+            "--verify",
+            "in.minisig",
+            "--pubkey",
+            "partner.pub",
+            "-"
+        ]))
+        .is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn parse_state_key_file() -> Result<(), Error> {
+        let parsed = parse_args(args(&["--state-key-file", "state.key", "in.csv"]))?;
+        assert_eq!(parsed.state_key_file, Some("state.key".to_string()));
+        #[cfg(feature = "watch")]
+        {
+            let parsed = parse_args(args(&["watch", "in", "out", "--state-key-file", "k"]))?;
+            assert_eq!(parsed.state_key_file, Some("k".to_string()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn parse_explain() -> Result<(), Error> {
+        let parsed = parse_args(args(&[
+            "explain",
+            "--snapshot",
+            "state.json",
+            "--locked-policy",
+            "allow_disputes",
+            "dispute,1,3,",
+        ]))?;
+        assert_eq!(
+            parsed.command,
+            Command::Explain {
+                snapshot: Some("state.json".to_string())
+            }
+        );
+        assert_eq!(parsed.input, "dispute,1,3,");
+        assert_eq!(parsed.locked_policy, Some(LockedPolicy::AllowDisputes));
+        assert!(parse_args(args(&["explain"])).is_err());
+        assert!(parse_args(args(&[
+            "explain",
+            "--snapshot",
+            "a.json",
+            "--snapshot",
+            "b.json",
+            "deposit,1,1,1.0"
+        ]))
+        .is_err());
+        assert!(parse_args(args(&["explain", "--threads", "2", "deposit,1,1,1.0"])).is_err());
+        assert!(parse_args(args(&["--snapshot", "a.json", "in.csv"])).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "tui")]
+    #[test]
+    fn parse_view() -> Result<(), Error> {
+        let parsed = parse_args(args(&["view", "state.json"]))?;
+        assert_eq!(
+            (parsed.command, parsed.input),
+            (Command::View, "state.json".to_string())
+        );
+        assert!(parse_args(args(&["view"])).is_err());
+        assert!(parse_args(args(&["view", "a.csv", "b.csv"])).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn parse_watch() -> Result<(), Error> {
+        let parsed = parse_args(args(&["watch", "in", "out", "--compat", "v1"]))?;
+        assert_eq!(
+            parsed.command,
+            Command::Watch {
+                input: "in".to_string(),
+                output: "out".to_string()
+            }
+        );
+        assert_eq!(parsed.compat, Some(Compat::V1));
+        assert!(parse_args(args(&["watch", "in"])).is_err());
+        assert!(parse_args(args(&["watch", "in", "out", "--threads", "2"])).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "grpc")]
+    #[test]
+    fn parse_serve_grpc() -> Result<(), Error> {
+        let parsed = parse_args(args(&["serve-grpc", "--addr", "0.0.0.0:9000"]))?;
+        assert_eq!(
+            parsed.command,
+            Command::ServeGrpc {
+                addr: "0.0.0.0:9000".parse().unwrap(),
+                api_keys: None,
+                rate_limit: None,
+                queue_capacity: None,
+                snapshot: None,
+            }
+        );
+        let limited = parse_args(args(&[
+            "serve-grpc",
+            "--api-keys",
+            "keys.txt",
+            "--rate-limit",
+            "20",
+        ]))?;
+        assert!(matches!(
+            limited.command,
+            Command::ServeGrpc { api_keys: Some(_), rate_limit: Some(rate), .. } if rate == 20.0
+        ));
+        let queued = parse_args(args(&["serve-grpc", "--queue-capacity", "500"]))?;
+        assert!(matches!(
+            queued.command,
+            Command::ServeGrpc {
+                queue_capacity: Some(500),
+                ..
+            }
+        ));
+        assert!(parse_args(args(&["serve-grpc", "--queue-capacity", "0"])).is_err());
+        assert!(parse_args(args(&["serve-grpc", "--rate-limit", "20"])).is_err());
+        assert!(parse_args(args(&["serve-grpc", "--addr", "nowhere"])).is_err());
+        let blocking = parse_args(args(&[
+            "serve-grpc",
+            "--locked-policy",
+            "block_all",
+            "--snapshot-out",
+            "state.json",
+        ]))?;
+        assert_eq!(blocking.locked_policy, Some(LockedPolicy::BlockAll));
+        assert_eq!(blocking.snapshot_out.as_deref(), Some("state.json"));
+        assert!(parse_args(args(&["serve-grpc", "--compat", "v1"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn missing_filepath_or_unknown_flag_is_error() {
+        assert!(parse_args(args(&[])).is_err());
+        assert!(parse_args(args(&["data/input.csv", "--nope"])).is_err());
+        assert!(parse_args(args(&["a.csv", "b.csv"])).is_err());
+        assert!(parse_args(args(&["a.csv", "--snapshot-out"])).is_err());
+        assert!(parse_args(args(&["a.csv", "--snapshot", "a.json"])).is_err());
+    }
+}
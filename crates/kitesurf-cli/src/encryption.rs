@@ -0,0 +1,136 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use std::fmt;
+use std::fs;
+
+use crate::{Error, ErrorCode, ENCRYPTED_HEADER};
+
+/// Environment variable with the hex state key, when there's no key file.
+pub const STATE_KEY_VAR: &str = "KITESURF_STATE_KEY";
+
+/// Bytes of the random nonce following the header of an encrypted file.
+const NONCE_LEN: usize = 12;
+
+/// 256-bit key encrypting state files at rest with AES-GCM, see
+/// `StateFiles::encrypted`. Each file gets a random nonce, stored after its
+/// header, and the header is authenticated along with the content.
+#[derive(Clone)]
+pub struct StateKey(Aes256Gcm);
+
+impl StateKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        StateKey(Aes256Gcm::new(&key.into()))
+    }
+
+    /// A key given as 64 hex digits.
+    pub fn from_hex(hex: &str) -> Result<Self, Error> {
+        let hex = hex.trim();
+        let invalid = || {
+            Error::coded(
+                ErrorCode::Encryption,
+                "State key must be 64 hex digits (256 bits)",
+            )
+        };
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let mut key = [0; 32];
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+        }
+        Ok(Self::new(key))
+    }
+
+    /// The key of `file`, e.g. one a KMS agent or secret store mounts for
+    /// the process, or else of `KITESURF_STATE_KEY`. None without either.
+    pub fn load(file: Option<&str>) -> Result<Option<Self>, Error> {
+        if let Some(path) = file {
+            let hex = fs::read_to_string(path).map_err(|err| {
+                Error::new(&format!("Unable to open state key {}: {}", path, err))
+            })?;
+            return Self::from_hex(&hex).map(Some);
+        }
+        match std::env::var(STATE_KEY_VAR) {
+            Ok(hex) => Self::from_hex(&hex).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: plaintext,
+            aad: ENCRYPTED_HEADER,
+        };
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, payload)
+            .map_err(|_| Error::coded(ErrorCode::Encryption, "Unable to encrypt state"))?;
+        let mut data = ENCRYPTED_HEADER.to_vec();
+        data.extend_from_slice(&nonce);
+        data.extend(ciphertext);
+        Ok(data)
+    }
+
+    /// The plaintext of `data`, as written by `encrypt`.
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let invalid = || {
+            Error::coded(
+                ErrorCode::Encryption,
+                "Unable to decrypt, wrong key or corrupted file",
+            )
+        };
+        let data = data.strip_prefix(ENCRYPTED_HEADER).ok_or_else(invalid)?;
+        if data.len() < NONCE_LEN {
+            return Err(invalid());
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: ENCRYPTED_HEADER,
+        };
+        self.0
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| invalid())
+    }
+}
+
+// never prints the key
+impl fmt::Debug for StateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("StateKey(..)")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_the_same_key_decrypts() -> Result<(), Error> {
+        let key = StateKey::from_hex(&"0f".repeat(32))?;
+        let data = key.encrypt(b"{\"accounts\":{}}")?;
+        assert!(data.starts_with(ENCRYPTED_HEADER));
+        assert!(!data.windows(8).any(|window| window == b"accounts"));
+        assert_eq!(key.decrypt(&data)?, b"{\"accounts\":{}}");
+        // a fresh nonce each time
+        assert_ne!(key.encrypt(b"{}")?, key.encrypt(b"{}")?);
+
+        let other = StateKey::new([1; 32]);
+        assert_eq!(
+            other.decrypt(&data).unwrap_err().code,
+            ErrorCode::Encryption
+        );
+        let mut tampered = data.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(key.decrypt(&tampered).is_err());
+        assert!(key.decrypt(&data[..ENCRYPTED_HEADER.len() + 4]).is_err());
+
+        for invalid in ["", "0f", &"zz".repeat(32), &"é".repeat(32)] {
+            assert!(StateKey::from_hex(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(format!("{:?}", key), "StateKey(..)");
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use serde::Serialize;
+use std::io::Write;
+
+use crate::{ClientId, Error, ErrorCode, TxId};
+
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorSeverity {
+    /// A transaction was rejected, processing went on.
+    Rejection,
+    /// The run failed.
+    Fatal,
+}
+
+/// One error as a JSON object, for `--errors-json`. `index`, `client` and
+/// `tx` identify the rejected transaction, and are null for fatal errors.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct ErrorRecord {
+    pub severity: ErrorSeverity,
+    pub code: ErrorCode,
+    pub message: String,
+    pub index: Option<u64>,
+    pub client: Option<ClientId>,
+    pub tx: Option<TxId>,
+}
+
+impl ErrorRecord {
+    /// `index` is the position of the transaction in the input, starting
+    /// at 1.
+    pub fn rejection(index: u64, client: ClientId, tx: TxId, error: &Error) -> Self {
+        Self {
+            severity: ErrorSeverity::Rejection,
+            code: error.code,
+            message: error.message.clone(),
+            index: Some(index),
+            client: Some(client),
+            tx: Some(tx),
+        }
+    }
+
+    pub fn fatal(error: &Error) -> Self {
+        Self {
+            severity: ErrorSeverity::Fatal,
+            code: error.code,
+            message: error.message.clone(),
+            index: None,
+            client: None,
+            tx: None,
+        }
+    }
+}
+
+/// Write `record` as a single line of JSON.
+pub fn write_error_json(record: &ErrorRecord, output: &mut impl Write) -> Result<(), Error> {
+    serde_json::to_writer(&mut *output, record)?;
+    writeln!(output)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Engine, Tx, TxType};
+
+    #[test]
+    fn rejection_as_json() -> Result<(), Error> {
+        let tx = Tx::new(TxType::Withdrawal, 2, 7);
+        let error = Engine::new().process(tx).unwrap_err();
+        let mut out = vec![];
+        write_error_json(&ErrorRecord::rejection(3, 2, 7, &error), &mut out)?;
+        write_error_json(&ErrorRecord::fatal(&Error::new("Boom")), &mut out)?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"severity\":\"rejection\",\"code\":\"missing_amount\",\
+             \"message\":\"Withdrawal transaction expected to have an amount\",\
+             \"index\":3,\"client\":2,\"tx\":7}\n\
+             {\"severity\":\"fatal\",\"code\":\"other\",\"message\":\"Boom\",\
+             \"index\":null,\"client\":null,\"tx\":null}\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use csv::ByteRecord;
+use csv_core::{ReadRecordResult, Reader};
+use std::str::FromStr;
+
+use crate::io::csv_reader;
+use crate::{parse_amount, AmountParsing, Error, ErrorCode, SignedAmount, Tx, TxType};
+
+pub(crate) const COLUMNS: [&[u8]; 4] = [b"type", b"client", b"tx", b"amount"];
+
+/// Longest row the fast path accepts, in bytes.
+const MAX_ROW_LEN: usize = 1024;
+
+/// Parse CSV in the plain `type,client,tx,amount` schema straight from
+/// bytes: fields are split by `csv-core` into a fixed buffer and parsed by
+/// hand, without serde or a per-row allocation. Inputs with other columns
+/// need `read_csv`.
+pub fn parse_csv_bytes(data: &[u8]) -> Result<Vec<Tx>, Error> {
+    let mut reader = Reader::new();
+    let mut output = [0u8; MAX_ROW_LEN];
+    let mut ends = [0usize; 8];
+    let (mut out_len, mut ends_len) = (0, 0);
+    // like `read_csv`, a UTF-8 BOM isn't part of the first header
+    let mut input = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    let mut header = true;
+    let mut txs = Vec::with_capacity(data.len() / 24);
+    let mut line = 1;
+    loop {
+        if out_len == 0 && ends_len == 0 {
+            line = reader.line();
+        }
+        let (result, nin, nout, nends) =
+            reader.read_record(input, &mut output[out_len..], &mut ends[ends_len..]);
+        input = &input[nin..];
+        // ends are relative to the start of the record, across calls
+        out_len += nout;
+        ends_len += nends;
+        match result {
+            ReadRecordResult::InputEmpty => continue,
+            ReadRecordResult::OutputFull => {
+                return Err(row_error(&format!(
+                    "Row {} is longer than {} bytes",
+                    line, MAX_ROW_LEN
+                ))
+                .with_line(line))
+            }
+            ReadRecordResult::OutputEndsFull => {
+                return Err(row_error(&format!("Row {} has too many columns", line)).with_line(line))
+            }
+            ReadRecordResult::Record => {
+                let mut fields = [&[][..]; 4];
+                let mut start = 0;
+                for (i, &end) in ends[..ends_len].iter().enumerate() {
+                    if i < fields.len() {
+                        fields[i] = output[start..end].trim_ascii();
+                    }
+                    start = end;
+                }
+                let columns = ends_len;
+                (out_len, ends_len) = (0, 0);
+                if columns == 1 && fields[0].is_empty() {
+                    continue;
+                }
+                if header {
+                    header = false;
+                    if fields != COLUMNS || columns != 4 {
+                        return Err(Error::new(
+                            "Fast path expects exactly the type,client,tx,amount columns",
+                        ));
+                    }
+                    continue;
+                }
+                if columns != 4 {
+                    return Err(row_error(&format!(
+                        "Row {} has {} columns, expected 4",
+                        line, columns
+                    ))
+                    .with_line(line));
+                }
+                txs.push(parse_row(&fields, line as usize)?);
+            }
+            ReadRecordResult::End => break,
+        }
+    }
+    Ok(txs)
+}
+
+/// Like `read_csv` for the plain `type,client,tx,amount` schema, but reuses
+/// a single `ByteRecord` and parses it with `tx_from_record` instead of
+/// deserializing every row through serde.
+pub fn read_csv_fast<R: std::io::Read>(buf: R) -> Result<Vec<Tx>, Error> {
+    let mut csv_reader = csv_reader(buf);
+    if csv_reader.byte_headers()?.iter().ne(COLUMNS) {
+        return Err(Error::new(
+            "Fast path expects exactly the type,client,tx,amount columns",
+        ));
+    }
+
+    let mut data: Vec<Tx> = vec![];
+    let mut record = ByteRecord::new();
+    while csv_reader.read_byte_record(&mut record)? {
+        data.push(tx_from_record(&record)?);
+    }
+
+    Ok(data)
+}
+
+/// Parse a trimmed `type,client,tx,amount` record.
+pub fn tx_from_record(record: &ByteRecord) -> Result<Tx, Error> {
+    let row = record
+        .position()
+        .map_or(0, |position| position.line() as usize);
+    match (record.get(0), record.get(1), record.get(2), record.get(3)) {
+        (Some(type_), Some(client), Some(tx), Some(amount)) if record.len() == 4 => {
+            parse_row(&[type_, client, tx, amount], row)
+        }
+        _ => Err(row_error(&format!(
+            "Row {} has {} columns, expected 4",
+            row,
+            record.len()
+        ))
+        .with_line(row as u64)),
+    }
+}
+
+/// Parse one row from its trimmed `type`, `client`, `tx` and `amount`
+/// fields, the last one possibly empty. `row` is only used in errors, as
+/// their line.
+pub(crate) fn parse_row(fields: &[&[u8]; 4], row: usize) -> Result<Tx, Error> {
+    let [type_, client, tx, amount] = *fields;
+    let type_ = match type_ {
+        b"deposit" => TxType::Deposit,
+        b"withdrawal" => TxType::Withdrawal,
+        b"dispute" => TxType::Dispute,
+        b"resolve" => TxType::Resolve,
+        b"chargeback" => TxType::Chargeback,
+        b"close_account" => TxType::CloseAccount,
+        b"adjustment" => TxType::Adjustment,
+        other => {
+            return Err(row_error(&format!(
+                "Row {}: unknown transaction type {}",
+                row,
+                String::from_utf8_lossy(other)
+            ))
+            .with_line(row as u64))
+        }
+    };
+    let amount = match std::str::from_utf8(amount) {
+        Ok("") => None,
+        Ok(amount) => Some(
+            parse_amount(amount, AmountParsing::Strict)
+                .and_then(SignedAmount::new)
+                .map_err(|err| err.with_line(row as u64))?,
+        ),
+        Err(_) => return Err(parse_number::<f64>(amount, "amount", row).unwrap_err()),
+    };
+    Ok(Tx::new(
+        type_,
+        parse_number(client, "client", row)?,
+        parse_number(tx, "tx", row)?,
+    )
+    .with_signed_amount(amount))
+}
+
+fn parse_number<T: FromStr>(field: &[u8], column: &str, row: usize) -> Result<T, Error> {
+    std::str::from_utf8(field)
+        .ok()
+        .and_then(|field| field.parse().ok())
+        .ok_or_else(|| {
+            row_error(&format!(
+                "Row {}: invalid {} {}",
+                row,
+                column,
+                String::from_utf8_lossy(field)
+            ))
+            .with_line(row as u64)
+        })
+}
+
+/// A malformed row, coded like the errors of the `csv` reader.
+fn row_error(message: &str) -> Error {
+    Error::coded(ErrorCode::Csv, &format!("CSV Error: {}", message))
+}
+
+/// `parse_csv_bytes` over a memory-mapped file, so the input is never
+/// copied into the process' heap.
+#[cfg(feature = "mmap")]
+pub fn read_csv_mmap(path: &str) -> Result<Vec<Tx>, Error> {
+    let file = std::fs::File::open(path).map_err(|err| {
+        Error::coded(
+            crate::ErrorCode::Io,
+            &format!("Unable to open file {}: {}", path, err),
+        )
+    })?;
+    // SAFETY: the mapping is only read while parsing, and input files aren't
+    // expected to be modified during a run.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    parse_csv_bytes(&map)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::read_csv;
+
+    #[test]
+    fn same_result_as_read_csv() -> Result<(), Error> {
+        let data = "type, client, tx, amount\r\n\
+                    deposit, 1, 1, 1.5\r\n\
+                    withdrawal,2,5,3.0\n\
+                    \n\
+                    dispute, 1, 1,\n\
+                    resolve,1,1,\n\
+                    chargeback,1,1,";
+        assert_eq!(
+            parse_csv_bytes(data.as_bytes())?,
+            read_csv(data.as_bytes())?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bom_is_skipped() -> Result<(), Error> {
+        let data = "\u{feff}type,client,tx,amount\ndeposit,1,1,1.0\n";
+        assert_eq!(
+            parse_csv_bytes(data.as_bytes())?,
+            read_csv(data.as_bytes())?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_csv_fast_matches_read_csv() -> Result<(), Error> {
+        let data = "type, client, tx, amount\n\
+                    deposit, 1, 1, 1.5\n\
+                    withdrawal,2,5,3.0\n\
+                    dispute, 1, 1,\n";
+        assert_eq!(read_csv_fast(data.as_bytes())?, read_csv(data.as_bytes())?);
+        assert!(read_csv_fast("type,client,tx\ndeposit,1,1\n".as_bytes()).is_err());
+        assert!(read_csv_fast("type,client,tx,amount\ndeposit,1,x,1\n".as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_rows_are_errors() {
+        for data in [
+            "type,client,tx,amount,seq\n",
+            "type,client,tx,amount\ntransfer,1,1,1.0\n",
+            "type,client,tx,amount\ndeposit,1\n",
+            "type,client,tx,amount\ndeposit,1,1,one\n",
+            "type,client,tx,amount\ndeposit,-1,1,1.0\n",
+        ] {
+            assert!(parse_csv_bytes(data.as_bytes()).is_err(), "{}", data);
+        }
+    }
+}
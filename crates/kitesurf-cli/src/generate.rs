@@ -0,0 +1,184 @@
+use std::io::Write;
+
+use crate::{ClientId, Error, TxId};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    pub clients: ClientId,
+    pub rows: usize,
+    /// Probability that a row disputes an earlier deposit, and separately that
+    /// a row resolves or charges back an open dispute.
+    pub dispute_rate: f64,
+    /// Probability that a row starts a fraud pattern: deposit, withdraw it
+    /// all, then dispute and charge back the deposit.
+    pub fraud_rate: f64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            clients: 100,
+            rows: 1000,
+            dispute_rate: 0.02,
+            fraud_rate: 0.001,
+        }
+    }
+}
+
+/// SplitMix64, small and good enough for synthetic data. Keeping it in-crate
+/// means the output for a given seed never changes with a dependency upgrade.
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n.max(1)
+    }
+
+    pub(crate) fn chance(&mut self, probability: f64) -> bool {
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+
+    /// Amount between 0.0001 and 1000.0 with 4 decimal places.
+    pub(crate) fn amount(&mut self) -> f64 {
+        (self.below(10_000_000) + 1) as f64 / 10_000.0
+    }
+}
+
+struct Rows<W: Write> {
+    writer: csv::Writer<W>,
+    written: usize,
+    next_tx_id: TxId,
+}
+
+impl<W: Write> Rows<W> {
+    fn write(
+        &mut self,
+        type_: &str,
+        client: ClientId,
+        tx_id: TxId,
+        amount: Option<f64>,
+    ) -> Result<(), Error> {
+        let amount = amount
+            .map(|amount| format!("{:.4}", amount))
+            .unwrap_or_default();
+        self.writer
+            .write_record([type_, &client.to_string(), &tx_id.to_string(), &amount])?;
+        self.written += 1;
+        Ok(())
+    }
+
+    fn new_tx_id(&mut self) -> Result<TxId, Error> {
+        let tx_id = self.next_tx_id;
+        self.next_tx_id = tx_id
+            .checked_add(1)
+            .ok_or_else(|| Error::new("Ran out of transaction ids"))?;
+        Ok(tx_id)
+    }
+}
+
+/// Write `config.rows` synthetic transactions as CSV. The same config always
+/// produces the same output.
+pub fn generate(config: &GeneratorConfig, output: &mut impl Write) -> Result<(), Error> {
+    let mut rng = Rng(config.seed);
+    let mut rows = Rows {
+        writer: csv::WriterBuilder::new()
+            .delimiter(b',')
+            .from_writer(output),
+        written: 0,
+        next_tx_id: 1,
+    };
+    rows.writer
+        .write_record(["type", "client", "tx", "amount"])?;
+
+    let mut deposits: Vec<(ClientId, TxId)> = vec![];
+    let mut open_disputes: Vec<(ClientId, TxId)> = vec![];
+    while rows.written < config.rows {
+        #[allow(clippy::unnecessary_cast)] // ClientId is u64 with wide-client-ids
+        let client = rng.below(config.clients as u64) as ClientId + 1;
+        if config.rows - rows.written >= 4 && rng.chance(config.fraud_rate) {
+            let tx_id = rows.new_tx_id()?;
+            let withdrawal_id = rows.new_tx_id()?;
+            let amount = rng.amount();
+            rows.write("deposit", client, tx_id, Some(amount))?;
+            rows.write("withdrawal", client, withdrawal_id, Some(amount))?;
+            rows.write("dispute", client, tx_id, None)?;
+            rows.write("chargeback", client, tx_id, None)?;
+        } else if !deposits.is_empty() && rng.chance(config.dispute_rate) {
+            let (client, tx_id) = deposits.swap_remove(rng.below(deposits.len() as u64) as usize);
+            rows.write("dispute", client, tx_id, None)?;
+            open_disputes.push((client, tx_id));
+        } else if !open_disputes.is_empty() && rng.chance(config.dispute_rate) {
+            let index = rng.below(open_disputes.len() as u64) as usize;
+            let (client, tx_id) = open_disputes.swap_remove(index);
+            let type_ = if rng.chance(0.8) {
+                "resolve"
+            } else {
+                "chargeback"
+            };
+            rows.write(type_, client, tx_id, None)?;
+        } else if rng.chance(0.6) {
+            let tx_id = rows.new_tx_id()?;
+            rows.write("deposit", client, tx_id, Some(rng.amount()))?;
+            deposits.push((client, tx_id));
+        } else {
+            let tx_id = rows.new_tx_id()?;
+            rows.write("withdrawal", client, tx_id, Some(rng.amount()))?;
+        }
+    }
+    rows.writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{read_csv, Engine};
+
+    fn generated(config: &GeneratorConfig) -> Vec<u8> {
+        let mut output: Vec<u8> = Vec::new();
+        generate(config, &mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn same_seed_same_output() {
+        let config = GeneratorConfig::default();
+        assert_eq!(generated(&config), generated(&config));
+        let other = GeneratorConfig {
+            seed: 1,
+            ..config.clone()
+        };
+        assert_ne!(generated(&config), generated(&other));
+    }
+
+    #[test]
+    fn generated_csv_is_processable() -> Result<(), Error> {
+        let config = GeneratorConfig {
+            clients: 10,
+            rows: 500,
+            dispute_rate: 0.1,
+            fraud_rate: 0.05,
+            ..GeneratorConfig::default()
+        };
+        let txs = read_csv(generated(&config).as_slice())?;
+        assert_eq!(txs.len(), 500);
+
+        let mut engine = Engine::new();
+        for tx in txs {
+            engine.process(tx)?;
+        }
+        assert!(engine.account_count() <= 10);
+        assert!(engine.accounts().any(|account| account.locked));
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+// every tonic handler returns `Status` as its error, large or not
+#![allow(clippy::result_large_err)]
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+
+use crate::{
+    ingest_queue, AccountRecord, ApiKeys, ClientAccount, ClientId, Drained, Error, IngestSender,
+    SignedAmount, StateStore, Submit, Tx, TxId, TxType,
+};
+
+pub mod proto {
+    tonic::include_proto!("transaction_resolver");
+}
+
+use proto::transaction_processor_server::{TransactionProcessor, TransactionProcessorServer};
+
+/// Queue of one client's transactions: `turn` is handed out in arrival
+/// order, as tokio's mutex is fair.
+#[derive(Default)]
+struct Lane {
+    turn: tokio::sync::Mutex<()>,
+    depth: AtomicUsize,
+}
+
+/// Per-client queues: transactions of a client are applied one at a time
+/// in the order they arrived, those of different clients concurrently. The
+/// map is only locked to find a client's queue, not while applying.
+#[derive(Default)]
+pub struct ClientLanes {
+    lanes: Mutex<HashMap<ClientId, Arc<Lane>>>,
+}
+
+/// Place in a client's queue, left when dropped, even by a cancelled
+/// request. Idle queues are removed.
+struct LaneGuard<'a> {
+    lanes: &'a ClientLanes,
+    client_id: ClientId,
+    lane: Arc<Lane>,
+}
+
+impl Drop for LaneGuard<'_> {
+    fn drop(&mut self) {
+        let mut lanes = self.lanes.lock();
+        // joining a lane also takes the map lock, so none is on its way
+        if self.lane.depth.fetch_sub(1, Ordering::SeqCst) == 1 {
+            lanes.remove(&self.client_id);
+        }
+    }
+}
+
+impl ClientLanes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<ClientId, Arc<Lane>>> {
+        // the map stays consistent even if a holder panicked
+        self.lanes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Run `apply` on the blocking pool once the transactions of
+    /// `client_id` that arrived before are done.
+    pub async fn run<T: Send + 'static>(
+        &self,
+        client_id: ClientId,
+        apply: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, Status> {
+        let lane = {
+            let mut lanes = self.lock();
+            let lane = Arc::clone(lanes.entry(client_id).or_default());
+            lane.depth.fetch_add(1, Ordering::SeqCst);
+            lane
+        };
+        let guard = LaneGuard {
+            lanes: self,
+            client_id,
+            lane,
+        };
+        let _turn = guard.lane.turn.lock().await;
+        tokio::task::spawn_blocking(apply)
+            .await
+            .map_err(|err| Status::internal(err.to_string()))
+    }
+
+    /// Transactions waiting or being applied per client, ordered by client.
+    pub fn depths(&self) -> Vec<(ClientId, usize)> {
+        let mut depths: Vec<(ClientId, usize)> = self
+            .lock()
+            .iter()
+            .map(|(&client_id, lane)| (client_id, lane.depth.load(Ordering::SeqCst)))
+            .collect();
+        depths.sort_unstable();
+        depths
+    }
+}
+
+/// gRPC front for any `StateStore`, see `proto/transaction_resolver.proto`.
+pub struct GrpcService {
+    store: Arc<dyn StateStore>,
+    lanes: ClientLanes,
+    queue: Option<IngestSender>,
+}
+
+impl GrpcService {
+    pub fn new(store: Arc<dyn StateStore>) -> Self {
+        Self {
+            store,
+            lanes: ClientLanes::new(),
+            queue: None,
+        }
+    }
+
+    /// Submit transactions to `queue` instead of applying them, answering
+    /// once queued, and `RESOURCE_EXHAUSTED` while the queue is full.
+    pub fn with_queue(mut self, queue: IngestSender) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+}
+
+impl From<ClientAccount> for proto::Account {
+    #[allow(clippy::unnecessary_cast)] // ClientId is u64 with wide-client-ids
+    fn from(account: ClientAccount) -> Self {
+        let record = AccountRecord::from(&account);
+        Self {
+            client: record.client as u64,
+            available: record.available,
+            held: record.held,
+            total: record.total,
+            locked: record.locked,
+            closed: record.closed,
+        }
+    }
+}
+
+impl TryFrom<proto::Transaction> for Tx {
+    type Error = Status;
+
+    fn try_from(tx: proto::Transaction) -> Result<Self, Self::Error> {
+        let type_ = match proto::TransactionType::try_from(tx.r#type) {
+            Ok(proto::TransactionType::Deposit) => TxType::Deposit,
+            Ok(proto::TransactionType::Withdrawal) => TxType::Withdrawal,
+            Ok(proto::TransactionType::Dispute) => TxType::Dispute,
+            Ok(proto::TransactionType::Resolve) => TxType::Resolve,
+            Ok(proto::TransactionType::Chargeback) => TxType::Chargeback,
+            Ok(proto::TransactionType::CloseAccount) => TxType::CloseAccount,
+            Ok(proto::TransactionType::Adjustment) => TxType::Adjustment,
+            Err(_) => return Err(Status::invalid_argument("Unknown transaction type")),
+        };
+        let tx_id = TxId::try_from(tx.tx)
+            .map_err(|_| Status::invalid_argument("Transaction id out of range"))?;
+        let amount = tx
+            .amount
+            .map(SignedAmount::new)
+            .transpose()
+            .map_err(|err| Status::invalid_argument(err.to_string()))?;
+        let mut converted = Tx::new(type_, client_id(tx.client)?, tx_id).with_signed_amount(amount);
+        converted.seq = tx.seq;
+        Ok(converted)
+    }
+}
+
+fn client_id(client: u64) -> Result<ClientId, Status> {
+    ClientId::try_from(client).map_err(|_| Status::invalid_argument("Client id out of range"))
+}
+
+type AccountStream = Pin<Box<dyn Stream<Item = Result<proto::Account, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl TransactionProcessor for GrpcService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::SubmitTransactionResponse>, Status> {
+        let tx = Tx::try_from(request.into_inner())?;
+        if let Some(queue) = &self.queue {
+            return match queue.try_submit(tx) {
+                Ok(Submit::Accepted) => Ok(Response::new(proto::SubmitTransactionResponse {})),
+                Ok(Submit::Backpressure(_)) => Err(Status::resource_exhausted(
+                    "Ingestion queue is full, retry later",
+                )),
+                Err(err) => Err(Status::unavailable(err.message)),
+            };
+        }
+        let store = Arc::clone(&self.store);
+        // stores may block on IO, e.g. `RedisStore`
+        self.lanes
+            .run(tx.client_id, move || store.apply(tx))
+            .await?
+            .map_err(|err| Status::invalid_argument(err.message))?;
+        Ok(Response::new(proto::SubmitTransactionResponse {}))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let client_id = client_id(request.into_inner().client)?;
+        match self.store.account(client_id) {
+            Ok(Some(account)) => Ok(Response::new(account.into())),
+            Ok(None) => Err(Status::not_found(format!(
+                "No account for client {}",
+                client_id
+            ))),
+            Err(err) => Err(Status::internal(err.message)),
+        }
+    }
+
+    type StreamAccountsStream = AccountStream;
+
+    async fn stream_accounts(
+        &self,
+        _request: Request<proto::StreamAccountsRequest>,
+    ) -> Result<Response<Self::StreamAccountsStream>, Status> {
+        let mut accounts: Vec<ClientAccount> = self
+            .store
+            .accounts()
+            .map_err(|err| Status::internal(err.message))?
+            .into_values()
+            .collect();
+        accounts.sort_by_key(|account| account.client);
+        let stream = tokio_stream::iter(
+            accounts
+                .into_iter()
+                .map(|account| Ok(proto::Account::from(account))),
+        );
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    #[allow(clippy::unnecessary_cast)] // ClientId is u64 with wide-client-ids
+    async fn get_queue_depths(
+        &self,
+        _request: Request<proto::QueueDepthsRequest>,
+    ) -> Result<Response<proto::QueueDepths>, Status> {
+        let clients: Vec<proto::ClientQueueDepth> = self
+            .lanes
+            .depths()
+            .into_iter()
+            .map(|(client, depth)| proto::ClientQueueDepth {
+                client: client as u64,
+                depth: depth as u64,
+            })
+            .collect();
+        Ok(Response::new(proto::QueueDepths {
+            queued: clients.iter().map(|client| client.depth).sum(),
+            clients,
+        }))
+    }
+}
+
+/// Serve the gRPC API on `addr` until the process is stopped.
+pub async fn serve_grpc(addr: SocketAddr, store: Arc<dyn StateStore>) -> Result<(), Error> {
+    serve_grpc_until(addr, store, None, None, std::future::pending()).await?;
+    Ok(())
+}
+
+/// Like `serve_grpc`, but stops accepting requests once `shutdown`
+/// completes, and returns after the ones in flight have been answered.
+/// With `api_keys`, requests without one of them, or beyond its rate
+/// limit, are refused. With `queue_capacity`, submitted transactions go
+/// through an ingestion queue of that size, see `GrpcService::with_queue`,
+/// applied by a thread of their own until the server stops; the returned
+/// `Drained` counts them.
+pub async fn serve_grpc_until(
+    addr: SocketAddr,
+    store: Arc<dyn StateStore>,
+    api_keys: Option<ApiKeys>,
+    queue_capacity: Option<usize>,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> Result<Drained, Error> {
+    let (service, consumer) = match queue_capacity {
+        Some(capacity) => {
+            let (sender, receiver) = ingest_queue(capacity);
+            let consumer = std::thread::spawn({
+                let store = Arc::clone(&store);
+                move || receiver.run(&*store)
+            });
+            (GrpcService::new(store).with_queue(sender), Some(consumer))
+        }
+        None => (GrpcService::new(store), None),
+    };
+    // the interceptor is cloned per connection
+    let api_keys = api_keys.map(Arc::new);
+    let service =
+        TransactionProcessorServer::with_interceptor(service, move |request| match &api_keys {
+            Some(api_keys) => api_keys.check(request),
+            None => Ok(request),
+        });
+    tonic::transport::Server::builder()
+        .add_service(service)
+        .serve_with_shutdown(addr, shutdown)
+        .await
+        .map_err(|err| Error::new(&format!("gRPC server error: {}", err)))?;
+    // the queue closes with the service, once the transactions in it are applied
+    match consumer {
+        Some(consumer) => consumer
+            .join()
+            .map_err(|_| Error::new("Ingestion queue consumer panicked")),
+        None => Ok(Drained::default()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::SharedEngine;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn submit_and_read_accounts() -> Result<(), Status> {
+        let service = GrpcService::new(Arc::new(SharedEngine::new(2)));
+        for (client, tx) in [(2, 1), (1, 2)] {
+            service
+                .submit_transaction(Request::new(proto::Transaction {
+                    r#type: proto::TransactionType::Deposit as i32,
+                    client,
+                    tx,
+                    amount: Some(1.5),
+                    seq: None,
+                }))
+                .await?;
+        }
+
+        let account = service
+            .get_account(Request::new(proto::GetAccountRequest { client: 1 }))
+            .await?
+            .into_inner();
+        assert_eq!(account.available, 1.5);
+
+        let missing = service
+            .get_account(Request::new(proto::GetAccountRequest { client: 3 }))
+            .await;
+        assert_eq!(missing.unwrap_err().code(), tonic::Code::NotFound);
+
+        let clients: Vec<u64> = service
+            .stream_accounts(Request::new(proto::StreamAccountsRequest {}))
+            .await?
+            .into_inner()
+            .map(|account| account.unwrap().client)
+            .collect()
+            .await;
+        assert_eq!(clients, vec![1, 2]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn same_client_waits_for_its_turn() -> Result<(), Status> {
+        let lanes = Arc::new(ClientLanes::new());
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+        let (started, running) = tokio::sync::oneshot::channel();
+        let first = tokio::spawn({
+            let lanes = Arc::clone(&lanes);
+            async move {
+                lanes
+                    .run(1, move || {
+                        let _ = started.send(());
+                        blocked.recv().ok()
+                    })
+                    .await
+            }
+        });
+        running
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?;
+        let second = tokio::spawn({
+            let lanes = Arc::clone(&lanes);
+            async move { lanes.run(1, || 2).await }
+        });
+        assert_eq!(lanes.run(2, || 3).await?, 3);
+        while lanes.depths() != vec![(1, 2)] {
+            tokio::task::yield_now().await;
+        }
+        release
+            .send(())
+            .map_err(|err| Status::internal(err.to_string()))?;
+        let join = |err: tokio::task::JoinError| Status::internal(err.to_string());
+        assert_eq!(first.await.map_err(join)??, Some(()));
+        assert_eq!(second.await.map_err(join)??, 2);
+        assert!(lanes.depths().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn full_queue_is_resource_exhausted() -> Result<(), Status> {
+        let engine = Arc::new(SharedEngine::new(1));
+        let (sender, receiver) = ingest_queue(1);
+        let service = GrpcService::new(engine.clone()).with_queue(sender);
+        let deposit = |tx| {
+            Request::new(proto::Transaction {
+                r#type: proto::TransactionType::Deposit as i32,
+                client: 1,
+                tx,
+                amount: Some(1.0),
+                seq: None,
+            })
+        };
+        service.submit_transaction(deposit(1)).await?;
+        let full = service.submit_transaction(deposit(2)).await;
+        assert_eq!(full.unwrap_err().code(), tonic::Code::ResourceExhausted);
+
+        drop(service);
+        assert_eq!(
+            receiver.run(&*engine),
+            Drained {
+                applied: 1,
+                rejected: 0
+            }
+        );
+        let account = engine
+            .account(1)
+            .map_err(|err| Status::internal(err.message))?;
+        assert_eq!(account.map(|account| account.total().value()), Some(1.0));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_transaction_is_rejected() {
+        let service = GrpcService::new(Arc::new(SharedEngine::new(1)));
+        let result = service
+            .submit_transaction(Request::new(proto::Transaction {
+                r#type: proto::TransactionType::Withdrawal as i32,
+                client: 1,
+                tx: 1,
+                amount: None,
+                seq: None,
+            }))
+            .await;
+        assert_eq!(result.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+
+use crate::{
+    AccountRecord, ClientAccount, ClientId, ClientStats, Engine, Error, RoundingMode, RunSummary,
+};
+
+/// Page the report is rendered into, with a `{{section}}` placeholder for
+/// each section.
+const TEMPLATE: &str = include_str!("../templates/report.html");
+
+/// Accounts listed and charted under "Top accounts".
+const TOP_ACCOUNTS: usize = 10;
+
+/// Width in pixels of the longest bar of a chart.
+const BAR_WIDTH: f64 = 400.0;
+
+/// Write a self-contained HTML summary of a run: totals, the accounts with
+/// the largest balances, how deposits went through disputes, and the
+/// locked accounts. Charts are inline SVG, so the page needs nothing else
+/// to display. The transactions counted are those of `summary`, and the
+/// dispute funnel needs `Engine::with_client_stats`.
+pub fn write_html_report(
+    engine: &Engine,
+    summary: &RunSummary,
+    rounding: RoundingMode,
+    output: &mut impl Write,
+) -> Result<(), Error> {
+    let clients = |client: ClientId| client.to_string();
+    write_html_report_with_clients(engine, summary, rounding, &clients, output)
+}
+
+/// `write_html_report`, naming clients with `clients`, e.g. their
+/// `Pseudonymizer::pseudonym`.
+pub fn write_html_report_with_clients(
+    engine: &Engine,
+    summary: &RunSummary,
+    rounding: RoundingMode,
+    clients: &dyn Fn(ClientId) -> String,
+    output: &mut impl Write,
+) -> Result<(), Error> {
+    let accounts: Vec<&ClientAccount> = engine.accounts_sorted().collect();
+    let mut html = TEMPLATE.to_string();
+    for (placeholder, section) in [
+        ("{{totals}}", totals(&accounts, summary, rounding)),
+        (
+            "{{top_accounts}}",
+            top_accounts(&accounts, rounding, clients),
+        ),
+        ("{{dispute_funnel}}", dispute_funnel(engine.client_stats())),
+        (
+            "{{locked_accounts}}",
+            locked_accounts(&accounts, rounding, clients),
+        ),
+    ] {
+        html = html.replace(placeholder, &section);
+    }
+    output.write_all(html.as_bytes())?;
+    Ok(())
+}
+
+fn totals(accounts: &[&ClientAccount], summary: &RunSummary, rounding: RoundingMode) -> String {
+    let records: Vec<AccountRecord> = accounts
+        .iter()
+        .map(|account| AccountRecord::from(*account))
+        .collect();
+    let sum =
+        |balance: fn(&AccountRecord) -> f64| rounding.round(records.iter().map(balance).sum());
+    let locked = accounts.iter().filter(|account| account.locked).count();
+    let tiles = [
+        ("Transactions", summary.processed.to_string()),
+        ("Rejected", summary.rejected().to_string()),
+        ("Accounts", accounts.len().to_string()),
+        ("Locked", locked.to_string()),
+        ("Available", sum(|record| record.available).to_string()),
+        ("Held", sum(|record| record.held).to_string()),
+        ("Total", sum(|record| record.total).to_string()),
+    ];
+    let mut html = String::from("<div class=\"tiles\">\n");
+    for (label, value) in tiles {
+        let _ = writeln!(
+            html,
+            "<div class=\"tile\">{}<b>{}</b></div>",
+            label,
+            escape(&value)
+        );
+    }
+    html.push_str("</div>");
+    html
+}
+
+fn top_accounts(
+    accounts: &[&ClientAccount],
+    rounding: RoundingMode,
+    clients: &dyn Fn(ClientId) -> String,
+) -> String {
+    let mut top = accounts.to_vec();
+    top.sort_by(|a, b| {
+        (b.total().value())
+            .total_cmp(&a.total().value())
+            .then(a.client.cmp(&b.client))
+    });
+    top.truncate(TOP_ACCOUNTS);
+    if top.is_empty() {
+        return empty("No accounts.");
+    }
+    let bars: Vec<(String, f64)> = top
+        .iter()
+        .map(|account| {
+            (
+                format!("client {}", clients(account.client)),
+                rounding.round(account.total().value()),
+            )
+        })
+        .collect();
+    let mut html = bar_chart(&bars);
+    html.push_str(&account_table(&top, rounding, clients, false));
+    html
+}
+
+fn dispute_funnel(stats: Option<&HashMap<ClientId, ClientStats>>) -> String {
+    let Some(stats) = stats else {
+        return empty("Not recorded for this run.");
+    };
+    let mut sum = ClientStats::default();
+    for stats in stats.values() {
+        sum.deposits += stats.deposits;
+        sum.open_disputes += stats.open_disputes;
+        sum.resolved_disputes += stats.resolved_disputes;
+        sum.chargebacks += stats.chargebacks;
+    }
+    let disputed = sum.open_disputes + sum.resolved_disputes + sum.chargebacks;
+    bar_chart(&[
+        ("deposits".to_string(), sum.deposits as f64),
+        ("disputed".to_string(), disputed as f64),
+        ("resolved".to_string(), sum.resolved_disputes as f64),
+        ("charged back".to_string(), sum.chargebacks as f64),
+        ("still open".to_string(), sum.open_disputes as f64),
+    ])
+}
+
+fn locked_accounts(
+    accounts: &[&ClientAccount],
+    rounding: RoundingMode,
+    clients: &dyn Fn(ClientId) -> String,
+) -> String {
+    let locked: Vec<&ClientAccount> = accounts
+        .iter()
+        .copied()
+        .filter(|account| account.locked)
+        .collect();
+    if locked.is_empty() {
+        return empty("No locked accounts.");
+    }
+    account_table(&locked, rounding, clients, true)
+}
+
+fn account_table(
+    accounts: &[&ClientAccount],
+    rounding: RoundingMode,
+    clients: &dyn Fn(ClientId) -> String,
+    locks: bool,
+) -> String {
+    let mut html =
+        String::from("<table>\n<tr><th>Client</th><th>Available</th><th>Held</th><th>Total</th>");
+    if locks {
+        html.push_str("<th>Locked by</th>");
+    }
+    html.push_str("</tr>\n");
+    for account in accounts {
+        let record = AccountRecord::from(*account);
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td>",
+            escape(&clients(account.client)),
+            rounding.round(record.available),
+            rounding.round(record.held),
+            rounding.round(record.total)
+        );
+        if locks {
+            let by = match account.lock {
+                Some(lock) => format!("{:?} of tx {}", lock.reason, lock.tx).to_lowercase(),
+                None => "unknown".to_string(),
+            };
+            let _ = write!(html, "<td>{}</td>", escape(&by));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>");
+    html
+}
+
+/// Horizontal bars of `bars`' values, scaled to the largest one. Negative
+/// values are drawn in red, from the same origin.
+fn bar_chart(bars: &[(String, f64)]) -> String {
+    const ROW: usize = 24;
+    const LABEL: f64 = 110.0;
+    let largest = bars
+        .iter()
+        .map(|(_, value)| value.abs())
+        .fold(0.0, f64::max);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" role=\"img\">\n",
+        LABEL + BAR_WIDTH + 100.0,
+        bars.len() * ROW
+    );
+    for (row, (label, value)) in bars.iter().enumerate() {
+        let width = if largest > 0.0 {
+            value.abs() / largest * BAR_WIDTH
+        } else {
+            0.0
+        };
+        let color = if *value < 0.0 { "#c0392b" } else { "#2e86c1" };
+        let y = row * ROW;
+        let _ = writeln!(
+            svg,
+            "<text x=\"0\" y=\"{}\">{}</text><rect x=\"{}\" y=\"{}\" width=\"{:.1}\" height=\"{}\" fill=\"{}\"/><text x=\"{:.1}\" y=\"{}\">{}</text>",
+            y + 16,
+            escape(label),
+            LABEL,
+            y + 4,
+            width,
+            ROW - 8,
+            color,
+            LABEL + width + 6.0,
+            y + 16,
+            value
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn empty(message: &str) -> String {
+    format!("<p class=\"empty\">{}</p>", message)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::TxBuilder;
+    use crate::CancellationToken;
+
+    #[test]
+    fn report_has_every_section() -> Result<(), Error> {
+        let mut engine = Engine::new().with_client_stats();
+        let summary = engine.process_stream(
+            [
+                TxBuilder::deposit(1).tx(1).amount(10.0).build(),
+                TxBuilder::deposit(2).tx(2).amount(4.0).build(),
+                // rejected without an amount
+                TxBuilder::withdrawal(1).tx(3).build(),
+                TxBuilder::dispute(2).tx(2).build(),
+                TxBuilder::chargeback(2).tx(2).build(),
+            ],
+            &CancellationToken::new(),
+        );
+        let mut out = vec![];
+        write_html_report(&engine, &summary, RoundingMode::default(), &mut out)?;
+        let html = String::from_utf8(out).unwrap();
+        assert!(!html.contains("{{"), "{}", html);
+        assert!(html.contains("Transactions<b>5</b>"));
+        assert!(html.contains("Rejected<b>1</b>"));
+        assert!(html.contains("Total<b>10</b>"));
+        assert!(html.contains("<text x=\"0\" y=\"88\">charged back</text>"));
+        assert!(html.contains("<td>chargeback of tx 2</td>"));
+
+        let mut out = vec![];
+        write_html_report(&Engine::new(), &summary, RoundingMode::default(), &mut out)?;
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains("No accounts.") && html.contains("Not recorded"));
+
+        let mut out = vec![];
+        let clients = |client| format!("c-{}", client);
+        write_html_report_with_clients(
+            &engine,
+            &summary,
+            RoundingMode::default(),
+            &clients,
+            &mut out,
+        )?;
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains("<td>c-2</td>") && html.contains(">client c-1</text>"));
+        assert!(!html.contains("<td>2</td>"));
+        Ok(())
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        let chart = bar_chart(&[("<b>&".to_string(), -1.0)]);
+        assert!(chart.contains("&lt;b&gt;&amp;"));
+        assert!(chart.contains("#c0392b"));
+    }
+}
@@ -0,0 +1,1323 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+
+use encoding_rs::Encoding;
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+
+use crate::fast::{tx_from_record, COLUMNS};
+
+use crate::{
+    parse_amount, AccountRecord, AmountParsing, ClientAccount, ClientId, ClientStats,
+    CurrencyConverter, CustomTxRegistry, Error, ErrorCode, FeeRule, FeeSchedule, ParallelRun,
+    RoundingMode, SignedAmount, Tenants, TimestampFormat, Tx, TxRefs,
+};
+
+pub fn open_file(path: &str) -> Result<BufReader<fs::File>, Error> {
+    let file = fs::File::open(path).map_err(|err| {
+        Error::coded(
+            ErrorCode::Io,
+            &format!("Unable to open file {}: {}", path, err),
+        )
+    })?;
+    let buf_reader = BufReader::new(file);
+    Ok(buf_reader)
+}
+
+/// Look up an encoding by its WHATWG label, e.g. `windows-1252` or `latin1`.
+pub fn encoding(label: &str) -> Result<&'static Encoding, Error> {
+    Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| Error::new(&format!("Unknown encoding: {}", label)))
+}
+
+/// Transcode `buf` from `encoding` to UTF-8. A BOM, if any, takes precedence
+/// over `encoding` and is stripped.
+pub fn decode<R: std::io::Read>(
+    buf: R,
+    encoding: &'static Encoding,
+) -> DecodeReaderBytes<R, Vec<u8>> {
+    DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .build(buf)
+}
+
+/// Without an explicit encoding the input is passed through as is, except
+/// for a UTF-8 BOM which would otherwise end up in the first header.
+fn strip_bom<R: std::io::Read>(buf: R) -> DecodeReaderBytes<R, Vec<u8>> {
+    DecodeReaderBytesBuilder::new().build(buf)
+}
+
+pub(crate) fn csv_reader<R: std::io::Read>(buf: R) -> csv::Reader<DecodeReaderBytes<R, Vec<u8>>> {
+    csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(b',')
+        .trim(csv::Trim::All)
+        .from_reader(strip_bom(buf))
+}
+
+pub fn read_csv<R: std::io::Read>(buf: R) -> Result<Vec<Tx>, Error> {
+    read_csv_iter(buf).collect()
+}
+
+/// `read_csv` one row at a time, so transactions can be processed as
+/// they're parsed without holding the whole input, and a malformed row only
+/// errors once the rows before it went through.
+///
+/// The plain `type,client,tx,amount` schema is parsed by `tx_from_record`
+/// from a reused `ByteRecord`, other columns go through serde.
+pub fn read_csv_iter<R: std::io::Read>(buf: R) -> impl Iterator<Item = Result<Tx, Error>> {
+    let mut csv_reader = csv_reader(buf);
+    // a header that can't be read is the first error
+    let (headers, header_error) = match csv_reader.headers() {
+        Ok(headers) => (headers.clone(), None),
+        Err(err) => (csv::StringRecord::new(), Some(Error::from(err))),
+    };
+    let plain = headers.as_byte_record().iter().eq(COLUMNS);
+    let amount_at = amount_column(&headers);
+    let mut byte_record = csv::ByteRecord::new();
+    let mut record = csv::StringRecord::new();
+    let rows = std::iter::from_fn(move || {
+        if plain {
+            return match csv_reader.read_byte_record(&mut byte_record) {
+                Ok(true) => Some(tx_from_record(&byte_record)),
+                Ok(false) => None,
+                Err(err) => Some(Err(err.into())),
+            };
+        }
+        match csv_reader.read_record(&mut record) {
+            Ok(true) => Some(
+                check_amount(&record, amount_at)
+                    .and_then(|_| record.deserialize(Some(&headers)).map_err(Error::from)),
+            ),
+            Ok(false) => None,
+            Err(err) => Some(Err(err.into())),
+        }
+    });
+    header_error.map(Err).into_iter().chain(rows)
+}
+
+fn amount_column(headers: &csv::StringRecord) -> Option<usize> {
+    headers.iter().position(|header| header == "amount")
+}
+
+/// Reject the amount of `record` unless it's a plain decimal, see
+/// `AmountParsing::Strict`: serde takes anything `f64::from_str` does.
+fn check_amount(record: &csv::StringRecord, amount_at: Option<usize>) -> Result<(), Error> {
+    match amount_at.and_then(|at| record.get(at)) {
+        Some(amount) if !amount.is_empty() => {
+            parse_amount(amount, AmountParsing::Strict).map_err(|err| match record.position() {
+                Some(position) => err.with_line(position.line()),
+                None => err,
+            })?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parse a single `type,client,tx,amount` row without header, optionally
+/// followed by a `currency`, e.g. `dispute,1,3,`.
+pub fn read_tx_row(row: &str) -> Result<Tx, Error> {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(row.as_bytes());
+    let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount", "currency"]);
+    let record = csv_reader
+        .records()
+        .next()
+        .ok_or_else(|| Error::new("Transaction row expected"))??;
+    if record.len() > headers.len() {
+        return Err(Error::new(&format!("Too many columns in row: {}", row)));
+    }
+    check_amount(&record, amount_column(&headers))?;
+    Ok(record.deserialize(Some(&headers))?)
+}
+
+/// Rows seen by `read_csv_with`, and what happened to the malformed ones.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReadSummary {
+    pub rows: usize,
+    pub repaired: usize,
+    pub skipped: usize,
+}
+
+/// How `ReadOptions::lenient` reads amounts that aren't plain decimals, as
+/// found in bank exports: `1,234.56`, `€12.00`, `12.00 USD` or `(5.00)` for
+/// a negative amount.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AmountFormat {
+    pub thousands_separator: char,
+    pub decimal_separator: char,
+    /// Symbols and codes stripped before or after the number.
+    pub currencies: Vec<String>,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        Self {
+            thousands_separator: ',',
+            decimal_separator: '.',
+            currencies: ["$", "€", "£", "¥", "USD", "EUR", "GBP", "JPY", "CHF"]
+                .iter()
+                .map(|currency| currency.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl AmountFormat {
+    /// `1.234,56`, as written in most of continental Europe.
+    pub fn decimal_comma() -> Self {
+        Self {
+            thousands_separator: '.',
+            decimal_separator: ',',
+            ..Self::default()
+        }
+    }
+
+    /// Parse `field`, or `None` if it isn't an amount in this format.
+    /// Thousands separators must separate groups of three digits.
+    pub fn parse(&self, field: &str) -> Option<f64> {
+        let mut field = field.trim();
+        let mut negative = false;
+        if let Some(inner) = field.strip_prefix('(').and_then(|f| f.strip_suffix(')')) {
+            field = inner.trim();
+            negative = true;
+        }
+        // the sign may come before or after the currency, -$5 or $-5
+        for _ in 0..2 {
+            if let Some(rest) = field.strip_prefix('-') {
+                field = rest.trim();
+                negative = !negative;
+            }
+            field = self.strip_currency(field);
+        }
+
+        let (int, decimals) = field
+            .split_once(self.decimal_separator)
+            .unwrap_or((field, ""));
+        let mut groups = int.split(self.thousands_separator);
+        let first = groups.next()?;
+        let digits = |group: &str| group.chars().all(|c| c.is_ascii_digit());
+        let grouped = int.contains(self.thousands_separator);
+        if first.is_empty() && decimals.is_empty()
+            || !digits(first)
+            || grouped && (first.is_empty() || first.len() > 3)
+            || !groups.all(|group| group.len() == 3 && digits(group))
+            || !digits(decimals)
+        {
+            return None;
+        }
+        let plain = format!("{}.{}", int.replace(self.thousands_separator, ""), decimals);
+        let amount: f64 = plain.trim_end_matches('.').parse().ok()?;
+        Some(if negative { -amount } else { amount })
+    }
+
+    fn strip_currency<'a>(&self, field: &'a str) -> &'a str {
+        for currency in &self.currencies {
+            if let Some(rest) = field.strip_prefix(currency.as_str()) {
+                return rest.trim();
+            }
+            if let Some(rest) = field.strip_suffix(currency.as_str()) {
+                return rest.trim();
+            }
+        }
+        field
+    }
+}
+
+/// Fix up a row to have exactly `columns` fields, returning whether anything
+/// changed, or `None` if it can't be repaired.
+fn repair_record(
+    record: &csv::StringRecord,
+    columns: usize,
+    type_at: Option<usize>,
+    amount_at: Option<usize>,
+    format: &AmountFormat,
+) -> Option<(csv::StringRecord, bool)> {
+    let mut fields: Vec<String> = record
+        .iter()
+        .map(|field| field.trim_matches('"').trim().to_string())
+        .collect();
+    if let Some(field) = type_at.and_then(|at| fields.get_mut(at)) {
+        *field = field.to_lowercase();
+    }
+    let mut repaired = fields
+        .iter()
+        .zip(record.iter())
+        .any(|(new, old)| new != old);
+    if let Some(at) = amount_at {
+        // an unquoted 1,000.50 is split over two fields
+        while format.thousands_separator == ','
+            && fields.len() > columns
+            && at + 1 < fields.len()
+            && is_digit_group(&fields[at + 1])
+        {
+            let group = fields.remove(at + 1);
+            fields[at].push(',');
+            fields[at].push_str(&group);
+            repaired = true;
+        }
+        if let Some(field) = fields.get_mut(at).filter(|field| {
+            !field.is_empty() && parse_amount(field, AmountParsing::Strict).is_err()
+        }) {
+            let amount = format
+                .parse(field)
+                .or_else(|| parse_amount(field, AmountParsing::Lenient).ok())?;
+            *field = amount.to_string();
+            repaired = true;
+        }
+    }
+    while fields.len() > columns && fields.last().is_some_and(String::is_empty) {
+        fields.pop();
+        repaired = true;
+    }
+    if fields.len() > columns {
+        return None;
+    }
+    if fields.len() < columns {
+        fields.resize(columns, String::new());
+        repaired = true;
+    }
+    Some((csv::StringRecord::from(fields), repaired))
+}
+
+/// Three digits, optionally followed by decimals: what follows a thousands
+/// separator.
+fn is_digit_group(field: &str) -> bool {
+    let (int, decimals) = field.split_once('.').unwrap_or((field, "0"));
+    int.len() == 3
+        && int.chars().all(|c| c.is_ascii_digit())
+        && decimals.chars().all(|c| c.is_ascii_digit())
+}
+
+/// How `read_csv_with` reads transactions. The options combine freely, e.g.
+/// repairing the rows of an input keyed by tenant with string references in
+/// its `tx` column.
+#[derive(Debug, Default)]
+pub struct ReadOptions<'a> {
+    /// Repair malformed rows where possible and skip the others instead of
+    /// failing: stray quotes, missing or extra trailing delimiters,
+    /// capitalized types, amounts in this format and those
+    /// `AmountParsing::Lenient` normalizes.
+    pub lenient: Option<AmountFormat>,
+    /// The `tx` column holds string references (e.g. UUIDs), interned here
+    /// to produce the transaction ids.
+    pub tx_refs: Option<&'a mut TxRefs>,
+    /// Key clients by the `tenant` column too: each `(tenant, client)` pair
+    /// is interned here to produce the client ids. Rows without a tenant
+    /// belong to the unnamed one.
+    pub tenants: Option<&'a mut Tenants>,
+    /// How the `timestamp` column is written, e.g. for epoch milliseconds
+    /// small enough to pass for seconds.
+    pub timestamp_format: TimestampFormat,
+    /// Types accepted besides the built-in ones.
+    pub custom_types: Option<&'a CustomTxRegistry>,
+}
+
+/// Row as written, before `ReadOptions` turn it into a `Tx`.
+#[derive(Debug, Deserialize)]
+struct TxRecord {
+    #[serde(default)]
+    tenant: Option<String>,
+    #[serde(rename = "type")]
+    type_: String,
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "tx")]
+    tx_ref: String,
+    amount: Option<f64>,
+    seq: Option<u64>,
+    description: Option<String>,
+    merchant: Option<String>,
+    category: Option<String>,
+    currency: Option<String>,
+    #[serde(default)]
+    timestamp: Option<String>,
+}
+
+impl ReadOptions<'_> {
+    fn tx(&mut self, record: &csv::StringRecord, headers: &csv::StringRecord) -> Result<Tx, Error> {
+        let row: TxRecord = record.deserialize(Some(headers))?;
+        // reported like the fields serde rejects
+        let field_error = |err: Error| {
+            let error = Error::coded(err.code, &format!("CSV Error: {}", err.message));
+            match record.position() {
+                Some(position) => error.with_line(position.line()),
+                None => error,
+            }
+        };
+        let type_ = match self.custom_types {
+            Some(registry) => registry.tx_type(&row.type_),
+            None => row.type_.parse(),
+        }
+        .map_err(field_error)?;
+        let client_id = match self.tenants.as_deref_mut() {
+            Some(tenants) => {
+                tenants.intern(row.tenant.as_deref().unwrap_or_default(), row.client_id)?
+            }
+            None => row.client_id,
+        };
+        let tx_id = match self.tx_refs.as_deref_mut() {
+            // a repaired short row pads the reference with an empty field
+            Some(_) if row.tx_ref.is_empty() => {
+                return Err(field_error(Error::new("missing transaction id")))
+            }
+            Some(refs) => refs.intern(&row.tx_ref)?,
+            None => row.tx_ref.parse().map_err(|_| {
+                field_error(Error::new(&format!(
+                    "invalid transaction id {}",
+                    row.tx_ref
+                )))
+            })?,
+        };
+        let amount = row
+            .amount
+            .map(SignedAmount::new)
+            .transpose()
+            .map_err(field_error)?;
+        let mut tx = Tx::new(type_, client_id, tx_id).with_signed_amount(amount);
+        tx.seq = row.seq;
+        tx.description = row.description;
+        tx.merchant = row.merchant;
+        tx.category = row.category;
+        tx.currency = row.currency;
+        tx.timestamp = match row.timestamp.as_deref() {
+            Some("") | None => None,
+            Some(timestamp) => Some(
+                self.timestamp_format
+                    .parse(timestamp)
+                    .map_err(field_error)?,
+            ),
+        };
+        Ok(tx)
+    }
+}
+
+/// Like `read_csv`, read as `options` say. The summary counts the rows
+/// repaired and skipped with `ReadOptions::lenient`.
+pub fn read_csv_with<R: std::io::Read>(
+    buf: R,
+    mut options: ReadOptions,
+) -> Result<(Vec<Tx>, ReadSummary), Error> {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .delimiter(b',')
+        .trim(csv::Trim::All)
+        .flexible(options.lenient.is_some())
+        .from_reader(strip_bom(buf));
+    let lenient = options.lenient.take();
+    let headers: csv::StringRecord = match lenient {
+        Some(_) => csv_reader
+            .headers()?
+            .iter()
+            .map(|header| header.trim_matches('"').to_lowercase())
+            .collect(),
+        None => csv_reader.headers()?.clone(),
+    };
+    let type_at = headers.iter().position(|header| header == "type");
+    let amount_at = amount_column(&headers);
+
+    let mut data: Vec<Tx> = vec![];
+    let mut summary = ReadSummary::default();
+    for result in csv_reader.records() {
+        summary.rows += 1;
+        let Some(format) = &lenient else {
+            let record = result?;
+            check_amount(&record, amount_at)?;
+            data.push(options.tx(&record, &headers)?);
+            continue;
+        };
+        let repaired = result.ok().and_then(|record| {
+            let (record, repaired) =
+                repair_record(&record, headers.len(), type_at, amount_at, format)?;
+            let tx = options.tx(&record, &headers).ok()?;
+            Some((tx, repaired))
+        });
+        match repaired {
+            Some((tx, repaired)) => {
+                if repaired {
+                    summary.repaired += 1;
+                }
+                data.push(tx);
+            }
+            None => summary.skipped += 1,
+        }
+    }
+
+    Ok((data, summary))
+}
+
+/// Read client accounts in the same schema `output_to_stdout` writes.
+pub fn read_accounts_csv<R: std::io::Read>(
+    buf: R,
+) -> Result<HashMap<ClientId, ClientAccount>, Error> {
+    let mut csv_reader = csv_reader(buf);
+
+    let mut accounts = HashMap::new();
+    for result in csv_reader.deserialize() {
+        let account: ClientAccount = result?;
+        let client = account.client;
+        if accounts.insert(client, account).is_some() {
+            return Err(Error::new(&format!(
+                "Duplicate account for client {}",
+                client
+            )));
+        }
+    }
+
+    Ok(accounts)
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientRecord {
+    client: ClientId,
+}
+
+/// Read a list of clients, one `client` column, e.g. the registered
+/// accounts for `AccountCreation::Never`.
+pub fn read_clients_csv<R: std::io::Read>(buf: R) -> Result<Vec<ClientId>, Error> {
+    let mut csv_reader = csv_reader(buf);
+
+    let mut clients = Vec::new();
+    for result in csv_reader.deserialize() {
+        let record: ClientRecord = result?;
+        clients.push(record.client);
+    }
+
+    Ok(clients)
+}
+
+#[derive(Debug, Deserialize)]
+struct TierRecord {
+    tier: String,
+    flat: f64,
+    percent: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientTierRecord {
+    client: ClientId,
+    tier: String,
+}
+
+/// Read a `tier,flat,percent` schedule, and optionally the `client,tier`
+/// assignments.
+pub fn read_fee_schedule<R: std::io::Read>(
+    tiers: R,
+    clients: Option<R>,
+) -> Result<FeeSchedule, Error> {
+    let mut schedule = FeeSchedule::new();
+    for result in csv_reader(tiers).deserialize() {
+        let record: TierRecord = result?;
+        let rule = FeeRule {
+            flat: record.flat,
+            percent: record.percent,
+        };
+        schedule = schedule.with_tier(&record.tier, rule);
+    }
+    if let Some(clients) = clients {
+        for result in csv_reader(clients).deserialize() {
+            let record: ClientTierRecord = result?;
+            schedule.assign(record.client, &record.tier)?;
+        }
+    }
+    Ok(schedule)
+}
+
+#[derive(Debug, Deserialize)]
+struct RateRecord {
+    currency: String,
+    rate: f64,
+}
+
+/// Read a `currency,rate` table, each rate being the value of one unit of
+/// the currency in the base currency.
+pub fn read_rates<R: std::io::Read>(buf: R) -> Result<CurrencyConverter, Error> {
+    let mut converter = CurrencyConverter::new();
+    for result in csv_reader(buf).deserialize() {
+        let record: RateRecord = result?;
+        if !(record.rate.is_finite() && record.rate > 0.0) {
+            return Err(Error::new(&format!(
+                "Invalid rate {} for currency {}",
+                record.rate, record.currency
+            )));
+        }
+        converter = converter.with_rate(&record.currency, record.rate);
+    }
+    Ok(converter)
+}
+
+/// Accounts ordered by client, so the same accounts are always written the
+/// same way.
+fn by_client(accounts: HashMap<ClientId, ClientAccount>) -> Vec<ClientAccount> {
+    let mut accounts: Vec<ClientAccount> = accounts.into_values().collect();
+    accounts.sort_by_key(|account| account.client);
+    accounts
+}
+
+pub fn output_to_stdout(
+    accounts: HashMap<ClientId, ClientAccount>,
+    output: &mut impl Write,
+) -> Result<(), Error> {
+    output_to_stdout_with_rounding(accounts, RoundingMode::default(), output)
+}
+
+pub fn output_to_stdout_with_rounding(
+    accounts: HashMap<ClientId, ClientAccount>,
+    rounding: RoundingMode,
+    output: &mut impl Write,
+) -> Result<(), Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b',')
+        .has_headers(true)
+        .from_writer(output);
+
+    for account in by_client(accounts) {
+        writer.serialize(output_row(&account, rounding))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn output_row(account: &ClientAccount, rounding: RoundingMode) -> ClientAccount {
+    let mut row = account.rounded(rounding);
+    // in the `--locks` report instead
+    row.lock = None;
+    row
+}
+
+/// Writes accounts in the schema of `output_to_stdout_with_rounding` a
+/// batch at a time, e.g. as the partitions of `process_parallel_streaming`
+/// finish. Rows are by client within a batch only.
+pub struct AccountStream<W: Write> {
+    writer: csv::Writer<W>,
+    rounding: RoundingMode,
+    written: usize,
+}
+
+impl<W: Write> AccountStream<W> {
+    pub fn new(output: W, rounding: RoundingMode) -> Self {
+        Self {
+            writer: csv::WriterBuilder::new()
+                .delimiter(b',')
+                .has_headers(true)
+                .from_writer(output),
+            rounding,
+            written: 0,
+        }
+    }
+
+    /// Write and flush `accounts`.
+    pub fn write<'a>(
+        &mut self,
+        accounts: impl IntoIterator<Item = &'a ClientAccount>,
+    ) -> Result<(), Error> {
+        for account in accounts {
+            self.writer.serialize(output_row(account, self.rounding))?;
+            self.written += 1;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Number of accounts written so far.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+}
+
+/// Output row of `output_with_last_activity`.
+#[derive(Debug, Serialize)]
+struct ActiveAccount {
+    client: ClientId,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+    last_activity: Option<u64>,
+}
+
+/// Like `output_to_stdout_with_rounding`, with a `last_activity` column
+/// from `last_activity`, empty for accounts without any.
+pub fn output_with_last_activity(
+    accounts: HashMap<ClientId, ClientAccount>,
+    last_activity: &HashMap<ClientId, u64>,
+    rounding: RoundingMode,
+    output: &mut impl Write,
+) -> Result<(), Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b',')
+        .has_headers(true)
+        .from_writer(output);
+
+    for account in by_client(accounts) {
+        let record = AccountRecord::from(&account);
+        writer.serialize(ActiveAccount {
+            client: account.client,
+            available: rounding.round(record.available),
+            held: rounding.round(record.held),
+            total: rounding.round(record.total),
+            locked: account.locked,
+            last_activity: last_activity.get(&account.client).copied(),
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Output row of `output_with_client_stats`.
+#[derive(Debug, Serialize)]
+struct AccountWithStats {
+    client: ClientId,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+    deposits: u64,
+    withdrawals: u64,
+    open_disputes: u64,
+    resolved_disputes: u64,
+    chargebacks: u64,
+}
+
+/// Like `output_to_stdout_with_rounding`, followed by the columns of the
+/// client's `ClientStats`, zero for accounts without any.
+pub fn output_with_client_stats(
+    accounts: HashMap<ClientId, ClientAccount>,
+    stats: &HashMap<ClientId, ClientStats>,
+    rounding: RoundingMode,
+    output: &mut impl Write,
+) -> Result<(), Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b',')
+        .has_headers(true)
+        .from_writer(output);
+
+    for account in by_client(accounts) {
+        let stats = stats.get(&account.client).copied().unwrap_or_default();
+        let record = AccountRecord::from(&account);
+        writer.serialize(AccountWithStats {
+            client: account.client,
+            available: rounding.round(record.available),
+            held: rounding.round(record.held),
+            total: rounding.round(record.total),
+            locked: account.locked,
+            deposits: stats.deposits,
+            withdrawals: stats.withdrawals,
+            open_disputes: stats.open_disputes,
+            resolved_disputes: stats.resolved_disputes,
+            chargebacks: stats.chargebacks,
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Output row of `output_with_tenants`.
+#[derive(Debug, Serialize)]
+struct TenantAccount<'a> {
+    tenant: &'a str,
+    client: ClientId,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+}
+
+/// Like `output_to_stdout_with_rounding` for accounts read with
+/// `ReadOptions::tenants`: a leading `tenant` column, and the clients as
+/// given in the input, ordered by tenant then client.
+pub fn output_with_tenants(
+    accounts: HashMap<ClientId, ClientAccount>,
+    tenants: &Tenants,
+    rounding: RoundingMode,
+    output: &mut impl Write,
+) -> Result<(), Error> {
+    let mut rows = accounts
+        .values()
+        .map(|account| {
+            let (tenant, client) = tenants
+                .resolve(account.client)
+                .ok_or_else(|| Error::new(&format!("Client {} of no tenant", account.client)))?;
+            let record = AccountRecord::from(account);
+            Ok(TenantAccount {
+                tenant,
+                client,
+                available: rounding.round(record.available),
+                held: rounding.round(record.held),
+                total: rounding.round(record.total),
+                locked: account.locked,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    rows.sort_by(|a, b| (a.tenant, a.client).cmp(&(b.tenant, b.client)));
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b',')
+        .has_headers(true)
+        .from_writer(output);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write accounts split across `accounts-000.csv`, `accounts-001.csv`, ...
+/// in `dir` by `ParallelRun::partition` of their client id. Every partition
+/// gets a file, empty if no client falls into it.
+pub fn write_partitioned(
+    accounts: HashMap<ClientId, ClientAccount>,
+    rounding: RoundingMode,
+    partitions: usize,
+    dir: &str,
+) -> Result<(), Error> {
+    if partitions == 0 {
+        return Err(Error::new("Expected at least one partition"));
+    }
+    let mut split: Vec<HashMap<ClientId, ClientAccount>> = vec![HashMap::new(); partitions];
+    for (client_id, account) in accounts {
+        split[ParallelRun::partition(client_id, partitions)].insert(client_id, account);
+    }
+    fs::create_dir_all(dir)?;
+    for (index, accounts) in split.into_iter().enumerate() {
+        let path = std::path::Path::new(dir).join(format!("accounts-{:03}.csv", index));
+        let mut file = std::io::BufWriter::new(fs::File::create(path)?);
+        output_to_stdout_with_rounding(accounts, rounding, &mut file)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{AccountOps, Amount, CustomTx, Engine, Timestamp, TxType};
+
+    fn lenient<'a>() -> ReadOptions<'a> {
+        ReadOptions {
+            lenient: Some(AmountFormat::default()),
+            ..ReadOptions::default()
+        }
+    }
+
+    #[test]
+    fn read_csv_from_buffer() {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 1.0
+withdrawal, 2, 5, 3.0
+dispute, 1, 1,
+resolve, 1, 1,
+chargeback, 1, 1,
+";
+        assert_eq!(
+            read_csv(data.as_bytes()).unwrap(),
+            vec![
+                Tx::new(TxType::Deposit, 1, 1).with_amount(Amount::new(1.0).unwrap()),
+                Tx::new(TxType::Withdrawal, 2, 5).with_amount(Amount::new(3.0).unwrap()),
+                Tx::new(TxType::Dispute, 1, 1),
+                Tx::new(TxType::Resolve, 1, 1),
+                Tx::new(TxType::Chargeback, 1, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn read_csv_with_bom() -> Result<(), Error> {
+        let data = "\u{feff}type,client,tx,amount\ndeposit,1,1,1.0\n";
+        assert_eq!(
+            read_csv(data.as_bytes())?[0].amount(),
+            Some(SignedAmount::new(1.0)?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_csv_iter_errors_at_the_malformed_row() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\nrefund,1,2,1.0\ndeposit,1,3,2.0\n";
+        let mut txs = read_csv_iter(data.as_bytes());
+        assert_eq!(txs.next().map(|tx| tx.map(|tx| tx.tx_id)), Some(Ok(1)));
+        let err = txs.next().unwrap().unwrap_err();
+        assert_eq!((err.code, err.line), (ErrorCode::Csv, Some(3)));
+        assert_eq!(txs.next().map(|tx| tx.map(|tx| tx.tx_id)), Some(Ok(3)));
+        assert!(txs.next().is_none());
+    }
+
+    #[test]
+    fn read_timestamps() -> Result<(), Error> {
+        let data = "\
+type,client,tx,amount,timestamp
+deposit,1,1,1.0,2024-01-02T12:00:00Z
+deposit,1,2,1.0,1704196800
+deposit,1,3,1.0,
+";
+        let timestamps: Vec<Option<Timestamp>> = read_csv(data.as_bytes())?
+            .iter()
+            .map(|tx| tx.timestamp)
+            .collect();
+        assert_eq!(
+            timestamps,
+            vec![Some(1_704_196_800_000), Some(1_704_196_800_000), None]
+        );
+
+        let millis = || ReadOptions {
+            timestamp_format: TimestampFormat::EpochMillis,
+            ..ReadOptions::default()
+        };
+        assert!(read_csv_with(data.as_bytes(), millis()).is_err());
+        let data = "type,client,tx,amount,timestamp\ndeposit,1,1,1.0,1704196800\n";
+        let (txs, _) = read_csv_with(data.as_bytes(), millis())?;
+        assert_eq!(txs[0].timestamp, Some(1_704_196_800));
+        assert!(
+            read_csv("type,client,tx,amount,timestamp\ndeposit,1,1,1.0,soon\n".as_bytes()).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_windows_1252_csv() -> Result<(), Error> {
+        // "Café" with é as 0xE9
+        let data = b"type,client,tx,amount,merchant\ndeposit,1,1,1.0,Caf\xe9\n";
+        let txs = read_csv(decode(&data[..], encoding("windows-1252")?))?;
+        assert_eq!(txs[0].merchant.as_deref(), Some("Caf\u{e9}"));
+        assert!(encoding("klingon").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn read_csv_with_metadata_columns() -> Result<(), Error> {
+        let data = "\
+type, client, tx, amount, description, merchant, category
+withdrawal, 1, 1, 2.0,\"Lunch, with team\", 7-Eleven, food
+deposit, 1, 2, 5.0, , ,
+";
+        let txs = read_csv(data.as_bytes())?;
+        assert_eq!(txs[0].description.as_deref(), Some("Lunch, with team"));
+        assert_eq!(txs[0].merchant.as_deref(), Some("7-Eleven"));
+        assert_eq!(txs[0].category.as_deref(), Some("food"));
+        assert_eq!(txs[1].category, None);
+        Ok(())
+    }
+
+    #[test]
+    fn read_csv_lenient_repairs_rows() -> Result<(), Error> {
+        let data = "\u{feff}type,client,tx,amount\r\n\
+Deposit,1,1,\"1,000.50\"\r\n\
+deposit,1,2,1,000.25\r\n\
+dispute,1,1\r\n\
+withdrawal,1,3,2.0,,\r\n\
+deposit,1,4,1.0\"\r\n\
+deposit,1\r\n\
+transfer,1,5,1.0\r\n\
+withdrawal,1,6,1.0,2.0\r\n";
+        let (txs, summary) = read_csv_with(data.as_bytes(), lenient())?;
+        let amounts: Vec<Option<f64>> = txs
+            .iter()
+            .map(|tx| tx.amount().map(SignedAmount::value))
+            .collect();
+        assert_eq!(
+            amounts,
+            vec![Some(1000.5), Some(1000.25), None, Some(2.0), Some(1.0)]
+        );
+        assert_eq!(txs[0].type_, TxType::Deposit);
+        assert_eq!(
+            summary,
+            ReadSummary {
+                rows: 8,
+                repaired: 5,
+                skipped: 3
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_amount_formats() {
+        let format = AmountFormat::default();
+        let amounts = [
+            ("1,234.56", Some(1234.56)),
+            ("€12.00", Some(12.0)),
+            ("12.00 USD", Some(12.0)),
+            ("(5.00)", Some(-5.0)),
+            ("($1,000)", Some(-1000.0)),
+            ("-$5", Some(-5.0)),
+            ("$-5", Some(-5.0)),
+            (".5", Some(0.5)),
+            ("1,23.4", None),
+            ("1234,567", None),
+            ("12 apples", None),
+            ("(5.00", None),
+            ("$", None),
+        ];
+        for (field, amount) in amounts {
+            assert_eq!(format.parse(field), amount, "{}", field);
+        }
+        let format = AmountFormat::decimal_comma();
+        assert_eq!(format.parse("€1.234,56"), Some(1234.56));
+        assert_eq!(format.parse("1,234.56"), None);
+    }
+
+    #[test]
+    fn read_csv_lenient_parses_amount_formats() -> Result<(), Error> {
+        let data = "type,client,tx,amount\n\
+deposit,1,1,\"$1,234.56\"\n\
+withdrawal,1,2,€2,000.00\n\
+deposit,1,3,1.0\n\
+deposit,1,4,five\n";
+        let (txs, summary) = read_csv_with(data.as_bytes(), lenient())?;
+        let amounts: Vec<Option<f64>> = txs
+            .iter()
+            .map(|tx| tx.amount().map(SignedAmount::value))
+            .collect();
+        assert_eq!(amounts, vec![Some(1234.56), Some(2000.0), Some(1.0)]);
+        assert_eq!((summary.repaired, summary.skipped), (2, 1));
+
+        let data = "type,client,tx,amount\ndeposit,1,1,\"1.234,5\"\n";
+        let options = ReadOptions {
+            lenient: Some(AmountFormat::decimal_comma()),
+            ..ReadOptions::default()
+        };
+        let (txs, _) = read_csv_with(data.as_bytes(), options)?;
+        assert_eq!(txs[0].amount(), Some(SignedAmount::new(1234.5)?));
+        Ok(())
+    }
+
+    #[test]
+    fn ambiguous_amounts_are_strict_unless_lenient() -> Result<(), Error> {
+        let data = "type,client,tx,amount\n\
+deposit,1,1,\"2.5\"\n\
+deposit,1,2,1e3\n\
+deposit,1,3,+4\n\
+deposit,1,4,'5.5'\n\
+deposit,1,5,1.2.3\n";
+        let strict: Vec<Result<Tx, Error>> = read_csv_iter(data.as_bytes()).collect();
+        assert_eq!(
+            strict[0].as_ref().map(|tx| tx.amount()).ok(),
+            Some(Some(SignedAmount::new(2.5)?))
+        );
+        for (rejected, line) in strict[1..].iter().zip(3..) {
+            let err = rejected.as_ref().unwrap_err();
+            assert_eq!((err.code, err.line), (ErrorCode::Csv, Some(line)));
+        }
+        let mut refs = TxRefs::new();
+        let options = ReadOptions {
+            tx_refs: Some(&mut refs),
+            ..ReadOptions::default()
+        };
+        assert!(read_csv_with(data.as_bytes(), options).is_err());
+        assert!(read_tx_row("deposit,1,1,1e3").is_err());
+
+        let (txs, summary) = read_csv_with(data.as_bytes(), lenient())?;
+        let amounts: Vec<Option<f64>> = txs
+            .iter()
+            .map(|tx| tx.amount().map(SignedAmount::value))
+            .collect();
+        assert_eq!(amounts, vec![Some(2.5), Some(1000.0), Some(4.0), Some(5.5)]);
+        assert_eq!((summary.repaired, summary.skipped), (3, 1));
+        Ok(())
+    }
+
+    #[test]
+    fn read_csv_with_string_tx_refs() -> Result<(), Error> {
+        let data = "\
+type, client, tx, amount
+deposit, 1, a1b2-c3, 1.0
+deposit, 1, 42, 2.0
+dispute, 1, a1b2-c3,
+";
+        let mut refs = TxRefs::new();
+        let options = ReadOptions {
+            tx_refs: Some(&mut refs),
+            ..ReadOptions::default()
+        };
+        let (txs, _) = read_csv_with(data.as_bytes(), options)?;
+        assert_eq!(txs.len(), 3);
+        assert_eq!(txs[0].tx_id, txs[2].tx_id);
+        assert_ne!(txs[0].tx_id, txs[1].tx_id);
+        assert_eq!(refs.resolve(txs[1].tx_id), Some("42"));
+        Ok(())
+    }
+
+    #[test]
+    fn tenants_keep_clients_apart() -> Result<(), Error> {
+        let data = "\
+tenant, type, client, tx, amount
+shop-b, deposit, 1, 1, 2.0
+shop-a, deposit, 1, 2, 1.0
+, deposit, 1, 3, 4.0
+shop-b, withdrawal, 1, 4, 0.5
+";
+        let mut tenants = Tenants::new();
+        let options = ReadOptions {
+            tenants: Some(&mut tenants),
+            ..ReadOptions::default()
+        };
+        let (txs, _) = read_csv_with(data.as_bytes(), options)?;
+        assert_eq!(tenants.len(), 3);
+        assert_eq!(txs[0].client_id, txs[3].client_id);
+        assert_ne!(txs[0].client_id, txs[1].client_id);
+
+        let mut engine = Engine::new();
+        for tx in txs {
+            engine.process(tx)?;
+        }
+        let mut out = vec![];
+        output_with_tenants(
+            engine.into_accounts(),
+            &tenants,
+            RoundingMode::default(),
+            &mut out,
+        )?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "tenant,client,available,held,total,locked\n\
+             ,1,4.0,0.0,4.0,false\n\
+             shop-a,1,1.0,0.0,1.0,false\n\
+             shop-b,1,1.5,0.0,1.5,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_options_combine() -> Result<(), Error> {
+        let data = "\
+Tenant,Type,Client,Tx,Amount,Timestamp
+shop-a,deposit,1,a1,\"1,000.50\",1704196800000
+shop-b,deposit,1,a1,2.0,
+shop-a,dispute,1,a1,,
+shop-a,deposit,1
+";
+        let mut refs = TxRefs::new();
+        let mut tenants = Tenants::new();
+        let options = ReadOptions {
+            tx_refs: Some(&mut refs),
+            tenants: Some(&mut tenants),
+            timestamp_format: TimestampFormat::EpochMillis,
+            ..lenient()
+        };
+        let (txs, summary) = read_csv_with(data.as_bytes(), options)?;
+        assert_eq!((summary.repaired, summary.skipped), (1, 1));
+        assert_eq!(txs[0].amount(), Some(SignedAmount::new(1000.5)?));
+        assert_eq!(txs[0].timestamp, Some(1_704_196_800_000));
+        assert_eq!(txs[0].tx_id, txs[1].tx_id);
+        assert_eq!(txs[0].client_id, txs[2].client_id);
+        assert_ne!(txs[0].client_id, txs[1].client_id);
+        assert_eq!(refs.resolve(txs[0].tx_id), Some("a1"));
+        assert_eq!(tenants.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn read_accounts_from_buffer() -> Result<(), Error> {
+        let data = "\
+client, available, held, total, locked
+1, 10.0, 20.0, 30.0, false
+2, 0.0, 0.0, 0.0, true
+";
+        let accounts = read_accounts_csv(data.as_bytes())?;
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(
+            AccountRecord::from(&accounts[&1]),
+            AccountRecord {
+                client: 1,
+                available: 10.0,
+                held: 20.0,
+                total: 30.0,
+                locked: false,
+                closed: false,
+                lock: None,
+            }
+        );
+        assert!(accounts[&2].locked);
+
+        let duplicate = "client,available,held,total,locked\n1,1,0,1,false\n1,2,0,2,false\n";
+        assert!(read_accounts_csv(duplicate.as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn read_clients_from_buffer() -> Result<(), Error> {
+        let data = "client\n3\n 1\n";
+        assert_eq!(read_clients_csv(data.as_bytes())?, vec![3, 1]);
+        assert!(read_clients_csv("client\nnobody\n".as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn output_csv_to_stdout() -> Result<(), Error> {
+        // Testing stdout idea from https://jeffkreeftmeijer.com/rust-stdin-stdout-testing/
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        accounts.insert(
+            1,
+            ClientAccount::try_from(AccountRecord {
+                client: 1,
+                available: 10.0,
+                held: 20.0,
+                total: 30.0,
+                locked: false,
+                closed: false,
+                lock: None,
+            })?,
+        );
+        accounts.insert(0, ClientAccount::new(0));
+        // ordered by client
+        let mut output: Vec<u8> = Vec::new();
+        output_to_stdout(accounts, &mut output)?;
+        assert_eq!(
+            &output,
+            b"client,available,held,total,locked\n\
+              0,0.0,0.0,0.0,false\n\
+              1,10.0,20.0,30.0,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn accounts_are_streamed_in_batches() -> Result<(), Error> {
+        let mut out = vec![];
+        let mut stream = AccountStream::new(&mut out, RoundingMode::default());
+        stream.write(&[ClientAccount::new(3)])?;
+        stream.write(&[])?;
+        stream.write(&[ClientAccount::new(1), ClientAccount::new(2)])?;
+        assert_eq!(stream.written(), 3);
+        drop(stream);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked\n\
+             3,0.0,0.0,0.0,false\n\
+             1,0.0,0.0,0.0,false\n\
+             2,0.0,0.0,0.0,false\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn output_csv_with_last_activity() -> Result<(), Error> {
+        let mut accounts = HashMap::new();
+        for client in [1, 2] {
+            accounts.insert(client, ClientAccount::new(client));
+        }
+        let mut out = vec![];
+        output_with_last_activity(
+            accounts,
+            &HashMap::from([(1, 7)]),
+            RoundingMode::default(),
+            &mut out,
+        )?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,last_activity\n\
+             1,0.0,0.0,0.0,false,7\n\
+             2,0.0,0.0,0.0,false,\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn output_csv_with_client_stats() -> Result<(), Error> {
+        let mut accounts = HashMap::new();
+        for client in [1, 2] {
+            accounts.insert(client, ClientAccount::new(client));
+        }
+        let stats = ClientStats {
+            deposits: 3,
+            open_disputes: 1,
+            ..ClientStats::default()
+        };
+        let mut out = vec![];
+        output_with_client_stats(
+            accounts,
+            &HashMap::from([(2, stats)]),
+            RoundingMode::default(),
+            &mut out,
+        )?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,deposits,withdrawals,open_disputes,resolved_disputes,chargebacks\n\
+             1,0.0,0.0,0.0,false,0,0,0,0,0\n\
+             2,0.0,0.0,0.0,false,3,0,1,0,0\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn fees_follow_the_client_tier() -> Result<(), Error> {
+        let tiers = "tier,flat,percent\ndefault,1.0,0.5\npremium,0.0,0.1\n";
+        let clients = "client,tier\n2,premium\n";
+        let schedule = read_fee_schedule(tiers.as_bytes(), Some(clients.as_bytes()))?;
+        assert_eq!(schedule.fee(1, 100.0), 1.5);
+        assert_eq!(schedule.fee(2, 100.0), 0.1);
+        assert!(
+            read_fee_schedule(tiers.as_bytes(), Some("client,tier\n1,gold\n".as_bytes())).is_err()
+        );
+        assert_eq!(FeeSchedule::new().fee(1, 100.0), 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn read_rates_and_currency_column() -> Result<(), Error> {
+        let converter = read_rates("currency,rate\nUSD,1.0\nEUR,1.1\n".as_bytes())?;
+        assert_eq!(converter.rate("eur"), Some(1.1));
+        assert!(read_rates("currency,rate\nEUR,-1\n".as_bytes()).is_err());
+
+        let txs = read_csv("type,client,tx,amount,currency\ndeposit,1,1,2.0,EUR\n".as_bytes())?;
+        assert_eq!(txs[0].currency.as_deref(), Some("EUR"));
+        assert_eq!(txs[0].original_amount(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn read_single_row() -> Result<(), Error> {
+        let tx = read_tx_row("dispute, 1, 3,")?;
+        assert_eq!(tx.amount(), None);
+        assert_eq!((tx.type_, tx.client_id, tx.tx_id), (TxType::Dispute, 1, 3));
+        let tx = read_tx_row("deposit,2,4,1.5,EUR")?;
+        assert_eq!(
+            (tx.amount(), tx.currency.as_deref()),
+            (Some(SignedAmount::new(1.5)?), Some("EUR"))
+        );
+        assert_eq!(read_tx_row("withdrawal,1,2")?.amount(), None);
+        assert!(read_tx_row("").is_err());
+        assert!(read_tx_row("deposit,1,2,1.0,EUR,extra").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn read_registered_custom_types() -> Result<(), Error> {
+        struct Bonus;
+        impl CustomTx for Bonus {
+            fn apply(&self, tx: &Tx, account: &mut AccountOps) -> Result<(), Error> {
+                account.credit(tx.amount().unwrap_or_default().magnitude())
+            }
+        }
+
+        let mut registry = CustomTxRegistry::new();
+        registry.register("bonus", Bonus)?;
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\nbonus,1,2,0.5\n";
+        let custom = || ReadOptions {
+            custom_types: Some(&registry),
+            ..ReadOptions::default()
+        };
+        let types: Vec<TxType> = read_csv_with(data.as_bytes(), custom())?
+            .0
+            .into_iter()
+            .map(|tx| tx.type_)
+            .collect();
+        assert_eq!(
+            types,
+            vec![TxType::Deposit, TxType::Custom("bonus".to_string())]
+        );
+        assert!(read_csv(data.as_bytes()).is_err());
+        assert!(read_csv_with(
+            "type,client,tx,amount\nreversal,1,1,\n".as_bytes(),
+            custom()
+        )
+        .is_err());
+        Ok(())
+    }
+}
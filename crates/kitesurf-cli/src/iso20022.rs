@@ -0,0 +1,219 @@
+use roxmltree::{Document, Node};
+use std::io::Read;
+
+use crate::{Amount, ClientId, Error, ErrorCode, Tx, TxId, TxRefs, TxType};
+
+fn import_error(message: &str) -> Error {
+    Error::coded(ErrorCode::Import, &format!("ISO 20022 Error: {}", message))
+}
+
+/// First child element of `node` with local name `name`, whatever the
+/// namespace of the message version.
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+/// Trimmed text of the element at `path` below `node`.
+fn text<'a>(node: Node<'a, '_>, path: &[&str]) -> Option<&'a str> {
+    path.iter()
+        .try_fold(node, |node, name| child(node, name))
+        .and_then(|node| node.text())
+        .map(str::trim)
+}
+
+/// Client owning `account`, from its proprietary (`Othr`) identification.
+/// IBANs have no client id to map to.
+fn client_of(account: Option<Node>) -> Result<ClientId, Error> {
+    let id = account
+        .and_then(|account| text(account, &["Id", "Othr", "Id"]))
+        .ok_or_else(|| import_error("account without an Othr/Id client id"))?;
+    id.parse()
+        .map_err(|_| import_error(&format!("account id {} is not a client id", id)))
+}
+
+fn amount_of(node: Node, path: &[&str]) -> Result<Amount, Error> {
+    let amount = text(node, path).ok_or_else(|| import_error("missing amount"))?;
+    amount
+        .parse()
+        .ok()
+        .and_then(|parsed| Amount::new(parsed).ok())
+        .ok_or_else(|| import_error(&format!("invalid amount {}", amount)))
+}
+
+fn transfer(
+    type_: TxType,
+    client_id: ClientId,
+    tx_id: TxId,
+    amount: Amount,
+    description: Option<&str>,
+) -> Tx {
+    let mut tx = Tx::new(type_, client_id, tx_id).with_amount(amount);
+    tx.description = description.map(str::to_string);
+    tx
+}
+
+/// Booked entries of camt.053 bank statements: credits are deposits and
+/// debits withdrawals of the client owning the statement's account.
+fn read_statements(message: Node, refs: &mut TxRefs) -> Result<Vec<Tx>, Error> {
+    let mut txs = vec![];
+    for statement in children(message, "Stmt") {
+        let client_id = client_of(child(statement, "Acct"))?;
+        for entry in children(statement, "Ntry") {
+            let status = text(entry, &["Sts", "Cd"]).or_else(|| text(entry, &["Sts"]));
+            if status.is_some_and(|status| status != "BOOK") {
+                continue;
+            }
+            let type_ = match text(entry, &["CdtDbtInd"]) {
+                Some("CRDT") => TxType::Deposit,
+                Some("DBIT") => TxType::Withdrawal,
+                other => {
+                    return Err(import_error(&format!(
+                        "invalid credit/debit indicator {:?}",
+                        other.unwrap_or_default()
+                    )))
+                }
+            };
+            let tx_ref = text(entry, &["AcctSvcrRef"])
+                .or_else(|| text(entry, &["NtryRef"]))
+                .or_else(|| text(entry, &["NtryDtls", "TxDtls", "Refs", "EndToEndId"]))
+                .ok_or_else(|| import_error("entry without a reference"))?;
+            txs.push(transfer(
+                type_,
+                client_id,
+                refs.intern(tx_ref)?,
+                amount_of(entry, &["Amt"])?,
+                text(entry, &["AddtlNtryInf"]),
+            ));
+        }
+    }
+    Ok(txs)
+}
+
+/// Credit transfers of pain.001 payment initiations: withdrawals of the
+/// client owning the debtor account.
+fn read_initiations(message: Node, refs: &mut TxRefs) -> Result<Vec<Tx>, Error> {
+    let mut txs = vec![];
+    for payment in children(message, "PmtInf") {
+        let client_id = client_of(child(payment, "DbtrAcct"))?;
+        for transfer_info in children(payment, "CdtTrfTxInf") {
+            let tx_ref = text(transfer_info, &["PmtId", "EndToEndId"])
+                .or_else(|| text(transfer_info, &["PmtId", "InstrId"]))
+                .ok_or_else(|| import_error("credit transfer without an EndToEndId"))?;
+            txs.push(transfer(
+                TxType::Withdrawal,
+                client_id,
+                refs.intern(tx_ref)?,
+                amount_of(transfer_info, &["Amt", "InstdAmt"])?,
+                text(transfer_info, &["RmtInf", "Ustrd"]),
+            ));
+        }
+    }
+    Ok(txs)
+}
+
+/// Read an ISO 20022 camt.053 statement or pain.001 payment initiation as
+/// deposits and withdrawals, in document order. Their references are
+/// interned into `refs` to produce the transaction ids.
+pub fn read_iso20022<R: Read>(mut buf: R, refs: &mut TxRefs) -> Result<Vec<Tx>, Error> {
+    let mut xml = String::new();
+    buf.read_to_string(&mut xml)?;
+    let document = Document::parse(&xml).map_err(|err| import_error(&err.to_string()))?;
+    let message = document
+        .root_element()
+        .children()
+        .find(Node::is_element)
+        .ok_or_else(|| import_error("empty document"))?;
+    match message.tag_name().name() {
+        "BkToCstmrStmt" => read_statements(message, refs),
+        "CstmrCdtTrfInitn" => read_initiations(message, refs),
+        other => Err(import_error(&format!("unsupported message {}", other))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn camt053_entries_are_deposits_and_withdrawals() -> Result<(), Error> {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">
+  <BkToCstmrStmt>
+    <GrpHdr><MsgId>STMT-1</MsgId></GrpHdr>
+    <Stmt>
+      <Acct><Id><Othr><Id>7</Id></Othr></Id></Acct>
+      <Ntry>
+        <Amt Ccy="EUR">150.25</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+        <AcctSvcrRef>REF-1</AcctSvcrRef>
+        <AddtlNtryInf>Salary</AddtlNtryInf>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">20</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts><Cd>PDNG</Cd></Sts>
+        <AcctSvcrRef>REF-2</AcctSvcrRef>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">50</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <NtryDtls><TxDtls><Refs><EndToEndId>E2E-3</EndToEndId></Refs></TxDtls></NtryDtls>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+        let mut refs = TxRefs::new();
+        let txs = read_iso20022(xml.as_bytes(), &mut refs)?;
+        assert_eq!(
+            txs,
+            vec![
+                transfer(TxType::Deposit, 7, 0, Amount::new(150.25)?, Some("Salary")),
+                transfer(TxType::Withdrawal, 7, 1, Amount::new(50.0)?, None),
+            ]
+        );
+        assert_eq!(refs.resolve(1), Some("E2E-3"));
+        Ok(())
+    }
+
+    #[test]
+    fn pain001_transfers_are_withdrawals() -> Result<(), Error> {
+        let xml = r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.09">
+  <CstmrCdtTrfInitn>
+    <PmtInf>
+      <DbtrAcct><Id><Othr><Id>3</Id></Othr></Id></DbtrAcct>
+      <CdtTrfTxInf>
+        <PmtId><EndToEndId>INV-42</EndToEndId></PmtId>
+        <Amt><InstdAmt Ccy="EUR">12.5</InstdAmt></Amt>
+        <RmtInf><Ustrd>Invoice 42</Ustrd></RmtInf>
+      </CdtTrfTxInf>
+    </PmtInf>
+  </CstmrCdtTrfInitn>
+</Document>"#;
+        let txs = read_iso20022(xml.as_bytes(), &mut TxRefs::new())?;
+        assert_eq!(
+            txs,
+            vec![transfer(
+                TxType::Withdrawal,
+                3,
+                0,
+                Amount::new(12.5)?,
+                Some("Invoice 42")
+            )]
+        );
+        let iban = xml.replace("<Othr><Id>3</Id></Othr>", "<IBAN>DE00</IBAN>");
+        let err = read_iso20022(iban.as_bytes(), &mut TxRefs::new()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::Import);
+        Ok(())
+    }
+}
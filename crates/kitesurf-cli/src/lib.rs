@@ -0,0 +1,86 @@
+//! Everything around `kitesurf-core` that touches the outside world: CSV
+//! inputs and reports, files, remote sources and sinks, and the stores and
+//! services sharing an engine. The core is re-exported, so embedding code
+//! only needs this crate.
+
+pub use kitesurf_core::*;
+
+mod amount;
+#[cfg(feature = "grpc")]
+mod auth;
+mod checkpoint;
+#[cfg(feature = "encryption")]
+mod encryption;
+mod error_log;
+mod fast;
+mod generate;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod html;
+mod io;
+#[cfg(feature = "iso20022")]
+mod iso20022;
+mod manifest;
+mod ofx;
+mod profile;
+mod pseudonym;
+mod qif;
+#[cfg(feature = "redis")]
+mod redis_store;
+mod replay;
+mod report;
+mod schema;
+#[cfg(feature = "signatures")]
+mod signature;
+mod sink;
+mod sort;
+mod source;
+mod state_lock;
+mod stress;
+#[cfg(feature = "tiered")]
+mod tiered_store;
+#[cfg(feature = "tui")]
+mod view;
+mod watch;
+#[cfg(feature = "xlsx")]
+mod xlsx;
+
+pub use crate::amount::*;
+#[cfg(feature = "grpc")]
+pub use crate::auth::*;
+pub use crate::checkpoint::*;
+#[cfg(feature = "encryption")]
+pub use crate::encryption::*;
+pub use crate::error_log::*;
+pub use crate::fast::*;
+pub use crate::generate::*;
+#[cfg(feature = "grpc")]
+pub use crate::grpc::*;
+pub use crate::html::*;
+pub use crate::io::*;
+#[cfg(feature = "iso20022")]
+pub use crate::iso20022::*;
+pub use crate::manifest::*;
+pub use crate::ofx::*;
+pub use crate::profile::*;
+pub use crate::pseudonym::*;
+pub use crate::qif::*;
+#[cfg(feature = "redis")]
+pub use crate::redis_store::*;
+pub use crate::replay::*;
+pub use crate::report::*;
+pub use crate::schema::*;
+#[cfg(feature = "signatures")]
+pub use crate::signature::*;
+pub use crate::sink::*;
+pub use crate::sort::*;
+pub use crate::source::*;
+pub use crate::state_lock::*;
+pub use crate::stress::*;
+#[cfg(feature = "tiered")]
+pub use crate::tiered_store::*;
+#[cfg(feature = "tui")]
+pub use crate::view::*;
+pub use crate::watch::*;
+#[cfg(feature = "xlsx")]
+pub use crate::xlsx::*;
@@ -0,0 +1,942 @@
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use kitesurf_cli::*;
+
+mod cli;
+
+/// Accounts scoring at least this are listed in the risk report.
+const RISK_THRESHOLD: f64 = 0.5;
+
+/// Transactions between checkpoints when `--checkpoint-every` isn't given.
+const CHECKPOINT_EVERY: usize = 100_000;
+
+/// Transactions `--max-rejection-rate` looks at when `--rejection-window`
+/// isn't given.
+const REJECTION_WINDOW: usize = 1000;
+
+/// Exit code after a SIGINT or SIGTERM, once state has been flushed.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// Render a report in memory and hand it to the sink for `target`, see
+/// `sink`.
+fn write_report(
+    target: &str,
+    write: impl FnOnce(&mut Vec<u8>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let mut report = vec![];
+    write(&mut report)?;
+    sink(target)?.put(&report)
+}
+
+/// `write_report` for a CSV report with a `client` column, whose ids are
+/// replaced by their pseudonyms with `--pseudonymize`.
+fn write_client_report(
+    target: &str,
+    pseudonyms: Option<&Pseudonymizer>,
+    write: impl FnOnce(&mut Vec<u8>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    write_report(target, |out| match pseudonyms {
+        Some(pseudonyms) => {
+            let mut report = vec![];
+            write(&mut report)?;
+            out.extend(pseudonyms.pseudonymize_csv(&report)?);
+            Ok(())
+        }
+        None => write(out),
+    })
+}
+
+/// `write_error_json`, with the pseudonym of the client with
+/// `--pseudonymize`.
+fn write_rejection(
+    record: &ErrorRecord,
+    pseudonyms: Option<&Pseudonymizer>,
+    out: &mut impl Write,
+) -> Result<(), Error> {
+    match pseudonyms {
+        Some(pseudonyms) => pseudonyms.write_error_json(record, out),
+        None => write_error_json(record, out),
+    }
+}
+
+/// Call `handler` on SIGINT or SIGTERM instead of terminating.
+fn on_shutdown_signal(handler: impl Fn() + Send + 'static) -> Result<(), Error> {
+    ctrlc::set_handler(handler)
+        .map_err(|err| Error::new(&format!("Unable to handle signals: {}", err)))
+}
+
+#[cfg(feature = "grpc")]
+const GRPC_SHARDS: usize = 64;
+
+use cli::{Command, InputFormat};
+
+/// Transactions of the input, parsed as they're processed where the format
+/// allows it, see `read_csv_iter`.
+type TxStream<'a> = Box<dyn Iterator<Item = Result<Tx, Error>> + 'a>;
+
+/// Read the input transactions, and hash them as read when a manifest is
+/// requested, which reads the whole input up front. With `--tenants`,
+/// clients are interned into `tenants`.
+fn read_input(
+    args: &cli::Args,
+    tenants: &mut Tenants,
+) -> Result<(TxStream<'static>, Option<InputDigest>), Error> {
+    if let Command::Explain { .. } = args.command {
+        return Ok((Box::new(std::iter::once(read_tx_row(&args.input))), None));
+    }
+    #[cfg(feature = "mmap")]
+    if args.mmap {
+        let digest = args
+            .manifest_out
+            .as_ref()
+            .map(|_| digest_file(&args.input))
+            .transpose()?;
+        return Ok((
+            Box::new(read_csv_mmap(&args.input)?.into_iter().map(Ok)),
+            digest,
+        ));
+    }
+    let buf = source(&args.input)?.open()?;
+    if args.manifest_out.is_none() {
+        return Ok((read_txs(args, buf, tenants)?, None));
+    }
+    let mut buf = DigestReader::new(buf);
+    let txs = read_txs(args, &mut buf, tenants)?.collect::<Result<Vec<Tx>, Error>>()?;
+    Ok((
+        Box::new(txs.into_iter().map(Ok)),
+        Some(buf.finish(&args.input)),
+    ))
+}
+
+fn read_txs<'a>(
+    args: &cli::Args,
+    buf: impl std::io::Read + 'a,
+    tenants: &mut Tenants,
+) -> Result<TxStream<'a>, Error> {
+    let buf: Box<dyn std::io::Read + 'a> = match args.encoding {
+        Some(encoding) => Box::new(decode(buf, encoding)),
+        None => Box::new(buf),
+    };
+    let buf: Box<dyn std::io::Read + 'a> = match args.sort_by.as_slice() {
+        [] => buf,
+        keys => {
+            let sort = ExternalSort::new(keys.to_vec());
+            let sort = match args.sort_buffer {
+                Some(rows) => sort.with_chunk_rows(rows),
+                None => sort,
+            };
+            Box::new(sort.sort(buf)?)
+        }
+    };
+    // xlsx goes through the CSV readers
+    #[cfg(feature = "xlsx")]
+    let buf: Box<dyn std::io::Read + 'a> = match args.format {
+        InputFormat::Xlsx => Box::new(std::io::Cursor::new(xlsx_to_csv(
+            buf,
+            args.sheet.as_deref(),
+        )?)),
+        _ => buf,
+    };
+    let txs = match args.format {
+        InputFormat::Csv => return read_csv_txs(args, buf, tenants),
+        #[cfg(feature = "xlsx")]
+        InputFormat::Xlsx => return read_csv_txs(args, buf, tenants),
+        InputFormat::Ofx => read_ofx(buf, &mut TxRefs::new())?,
+        InputFormat::Qif => {
+            let client_id = args.client.unwrap_or_default();
+            read_qif(buf, client_id, &mut TxRefs::new())?
+        }
+        #[cfg(feature = "iso20022")]
+        InputFormat::Iso20022 => read_iso20022(buf, &mut TxRefs::new())?,
+    };
+    Ok(Box::new(txs.into_iter().map(Ok)))
+}
+
+/// Transactions of a CSV input, streamed unless a reader needs all of them.
+fn read_csv_txs<'a>(
+    args: &cli::Args,
+    buf: impl std::io::Read + 'a,
+    tenants: &mut Tenants,
+) -> Result<TxStream<'a>, Error> {
+    // the plain schema is parsed as it's processed
+    let plain = !(args.tenants || args.tx_refs || args.lenient || args.timestamp_format.is_some());
+    if plain {
+        return Ok(Box::new(read_csv_iter(buf)));
+    }
+    let mut refs = TxRefs::new();
+    let options = ReadOptions {
+        lenient: args.lenient.then(|| {
+            if args.decimal_comma {
+                AmountFormat::decimal_comma()
+            } else {
+                AmountFormat::default()
+            }
+        }),
+        tx_refs: args.tx_refs.then_some(&mut refs),
+        tenants: args.tenants.then_some(tenants),
+        timestamp_format: args.timestamp_format.unwrap_or_default(),
+        custom_types: None,
+    };
+    let (txs, summary) = read_csv_with(buf, options)?;
+    if args.lenient {
+        eprintln!(
+            "Read {} rows: {} repaired, {} skipped",
+            summary.rows, summary.repaired, summary.skipped
+        );
+    }
+    Ok(Box::new(txs.into_iter().map(Ok)))
+}
+
+/// Digests of the other files the run reads: initial and registered
+/// accounts, client lists, fee schedule, currency rates, checkpoint, merged snapshots,
+/// expected accounts, output schema and the input's signature and key.
+fn digest_other_inputs(args: &cli::Args) -> Result<Vec<InputDigest>, Error> {
+    let mut paths: Vec<&String> = [
+        &args.initial_accounts,
+        &args.registered_accounts,
+        &args.clients_allow,
+        &args.clients_deny,
+        &args.fees,
+        &args.fee_tiers,
+        &args.rates,
+        &args.resume,
+        &args.expect,
+        &args.output_schema,
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if let Command::Merge { snapshots } = &args.command {
+        paths.extend(snapshots);
+    }
+    #[cfg(feature = "signatures")]
+    paths.extend(args.verify.iter().chain(&args.pubkey));
+    paths.into_iter().map(|path| digest_file(path)).collect()
+}
+
+/// `process_parallel`, writing the accounts as each thread finishes with
+/// `--stream-output`, in which case it also returns how many it wrote.
+fn run_parallel<T: Borrow<Tx> + Into<Tx> + Send>(
+    args: &cli::Args,
+    engine: Engine,
+    txs: Vec<T>,
+    threads: usize,
+) -> Result<(ParallelRun, Option<usize>), Error> {
+    if !args.stream_output {
+        return Ok((process_parallel(engine, txs, threads)?, None));
+    }
+    let output: Box<dyn Write> = match args.output.as_deref() {
+        None | Some("-") => Box::new(std::io::stdout()),
+        Some(path) => Box::new(std::fs::File::create(path)?),
+    };
+    let mut stream = AccountStream::new(output, args.rounding.unwrap_or_default());
+    let run = process_parallel_streaming(engine, txs, threads, |accounts| {
+        stream.write(accounts.into_iter().filter(|account| {
+            !(args.skip_empty_accounts && account.is_empty())
+                && (args.filter.is_empty() || args.filter.matches(account))
+        }))
+    })?;
+    Ok((run, Some(stream.written())))
+}
+
+fn main() {
+    let argv: Vec<String> = env::args().skip(1).collect();
+    let mut errors = None;
+    if let Err(err) = run(argv.clone(), &mut errors) {
+        match errors.as_mut() {
+            // not much left to do if even that fails
+            Some(out) => {
+                let _ = write_error_json(&ErrorRecord::fatal(&err), out);
+            }
+            // failed before --errors-out was opened
+            None if argv.iter().any(|arg| arg == "--errors-json") => {
+                let _ = write_error_json(&ErrorRecord::fatal(&err), &mut std::io::stderr());
+            }
+            None => {}
+        }
+        if errors.is_none() || argv.iter().any(|arg| arg == "--errors-out") {
+            eprintln!("Error: {:?}", err);
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Where `--errors-json` records go.
+type ErrorOutput = Option<Box<dyn std::io::Write>>;
+
+fn run(argv: Vec<String>, errors: &mut ErrorOutput) -> Result<(), Error> {
+    // cli
+    let started_at = unix_time();
+    let args = cli::parse_args(argv.clone())
+        .map_err(|err| Error::coded(ErrorCode::Usage, &err.message))?;
+    if args.errors_json {
+        *errors = Some(match &args.errors_out {
+            // line buffered, as the process may exit without unwinding
+            Some(path) => Box::new(std::io::LineWriter::new(std::fs::File::create(path)?)),
+            None => Box::new(std::io::stderr()),
+        });
+    }
+    // held until the run ends, so that a concurrent run fails before reading
+    // any input instead of writing over this one's state
+    let _state_locks = StateLock::acquire_for(
+        [&args.resume, &args.checkpoint_out, &args.snapshot_out]
+            .into_iter()
+            .flatten()
+            .map(String::as_str),
+    )?;
+    #[cfg(feature = "encryption")]
+    let state_files = match StateKey::load(args.state_key_file.as_deref())? {
+        Some(key) => StateFiles::encrypted(key),
+        None => StateFiles::default(),
+    };
+    #[cfg(not(feature = "encryption"))]
+    let state_files = StateFiles::default();
+    if let Command::Generate(config) = &args.command {
+        return generate(config, &mut std::io::stdout());
+    }
+    if let Command::Stress(config) = &args.command {
+        return stress(config, &mut std::io::stdout());
+    }
+    if let Command::Ledger = args.command {
+        let lines = ledger(&state_files.read_snapshot(&args.input)?);
+        return write_report(args.output.as_deref().unwrap_or("-"), |out| {
+            write_ledger(&lines, out)
+        });
+    }
+    if let Command::Profile = args.command {
+        let profile = profile_csv(open_file(&args.input)?)?;
+        return write_report(args.output.as_deref().unwrap_or("-"), |out| {
+            write_profile(&profile, out)
+        });
+    }
+    #[cfg(feature = "tui")]
+    if let Command::View = args.command {
+        let snapshot = if args.input.ends_with(".json") {
+            state_files.read_snapshot(&args.input)?
+        } else {
+            Snapshot {
+                accounts: read_accounts_csv(open_file(&args.input)?)?,
+                ..Snapshot::default()
+            }
+        };
+        return run_view(AccountView::new(snapshot));
+    }
+    #[cfg(feature = "watch")]
+    if let Command::Watch { input, output } = &args.command {
+        let compat = args.compat.unwrap_or(Compat::V2);
+        let builder = Engine::builder()
+            .compat(compat)
+            .locked_policy(compat.locked_policy());
+        let (input, output) = (std::path::Path::new(input), std::path::Path::new(output));
+        let mut dir = WatchDir::open(input, output, builder, state_files)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        on_shutdown_signal(move || flag.store(true, Ordering::SeqCst))?;
+        return watch(
+            &mut dir,
+            || stop.load(Ordering::SeqCst),
+            &mut std::io::stdout(),
+        );
+    }
+    #[cfg(feature = "grpc")]
+    if let Command::ServeGrpc {
+        addr,
+        api_keys,
+        rate_limit,
+        queue_capacity,
+        snapshot,
+    } = &args.command
+    {
+        let api_keys = api_keys
+            .as_deref()
+            .map(|path| {
+                let keys = read_api_keys(open_file(path)?)?;
+                Ok::<_, Error>(match rate_limit {
+                    Some(rate) => keys.with_rate_limit(*rate),
+                    None => keys,
+                })
+            })
+            .transpose()?;
+        let compat = args.compat.unwrap_or(Compat::V2);
+        let mut builder = Engine::builder()
+            .compat(compat)
+            .locked_policy(args.locked_policy.unwrap_or(compat.locked_policy()));
+        if let Some(path) = snapshot {
+            builder = builder.storage(state_files.read_snapshot(path)?);
+        }
+        let store = Arc::new(SharedEngine::with_builder(GRPC_SHARDS, builder)?);
+        let shutdown = Arc::new(tokio::sync::Notify::new());
+        let notify = shutdown.clone();
+        on_shutdown_signal(move || notify.notify_one())?;
+        let drained = tokio::runtime::Runtime::new()?.block_on(serve_grpc_until(
+            *addr,
+            store.clone(),
+            api_keys,
+            *queue_capacity,
+            async move { shutdown.notified().await },
+        ))?;
+        eprintln!("Interrupted, in-flight requests answered");
+        // the queue is drained by now, nothing else writes to the store
+        if let Some(path) = &args.snapshot_out {
+            state_files.write_snapshot(path, &store.snapshot()?)?;
+        }
+        if queue_capacity.is_some() {
+            eprintln!(
+                "{} queued transactions applied, {} rejected",
+                drained.applied, drained.rejected
+            );
+        }
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+
+    // Input from csv, once its signature checked
+    #[cfg(feature = "signatures")]
+    if let (Some(signature), Some(pubkey)) = (&args.verify, &args.pubkey) {
+        verify_signature(
+            source(&args.input)?.open()?,
+            &std::fs::read_to_string(signature)?,
+            &std::fs::read_to_string(pubkey)?,
+        )?;
+    }
+    let mut tenants = Tenants::new();
+    let (mut txs, input_digest) = read_input(&args, &mut tenants)?;
+    let mut manifest = match input_digest {
+        Some(input_digest) => {
+            let mut inputs = vec![input_digest];
+            inputs.extend(digest_other_inputs(&args)?);
+            let mut config = argv;
+            if let Some(at) = config.iter().rposition(|arg| *arg == args.input) {
+                config.remove(at);
+            }
+            Some(Manifest::new(inputs, config, started_at))
+        }
+        None => None,
+    };
+
+    // State
+    let resumed = args
+        .resume
+        .as_deref()
+        .map(|path| state_files.read_checkpoint(path))
+        .transpose()?;
+    // kept in checkpoints too, so a resumed run's manifest covers the whole input
+    let mut chain = match resumed.as_ref().map(|checkpoint| &checkpoint.tx_chain) {
+        _ if manifest.is_none() && args.checkpoint_out.is_none() => None,
+        Some(Some(head)) => Some(TxHashChain::resume(head)?),
+        Some(None) if manifest.is_some() => {
+            return Err(Error::new(
+                "Checkpoint has no transaction chain, resume without --manifest",
+            ))
+        }
+        // the transactions before the checkpoint are unknown
+        Some(None) => None,
+        None => Some(TxHashChain::new()),
+    };
+    let mut skip = 0;
+    let mut resumed_state = None;
+    let storage = match (&args.command, resumed) {
+        (_, Some(checkpoint)) => {
+            if checkpoint.input != args.input {
+                return Err(Error::new(&format!(
+                    "Checkpoint is for {} transactions of {}, not {}",
+                    checkpoint.records, checkpoint.input, args.input
+                )));
+            }
+            skip = checkpoint.records;
+            resumed_state = Some(checkpoint.state);
+            Snapshot::default()
+        }
+        (Command::Merge { snapshots }, None) => {
+            let snapshots = snapshots
+                .iter()
+                .map(|path| state_files.read_snapshot(path))
+                .collect::<Result<Vec<_>, Error>>()?;
+            Snapshot::merge(snapshots)?
+        }
+        (
+            Command::Explain {
+                snapshot: Some(path),
+            },
+            None,
+        ) => state_files.read_snapshot(path)?,
+        _ => Snapshot::default(),
+    };
+    let compat = args.compat.unwrap_or(Compat::V2);
+    let mut builder = Engine::builder()
+        .storage(storage)
+        .compat(compat)
+        .locked_policy(args.locked_policy.unwrap_or(compat.locked_policy()))
+        .dispute_holds(args.dispute_holds)
+        .zero_amounts(args.zero_amounts)
+        .account_creation(args.account_creation);
+    if let Some(rounding) = args.rounding {
+        builder = builder.rounding(rounding);
+    }
+    if let Some(timeout) = args.dispute_timeout {
+        builder = builder.dispute_policy(DisputePolicy::AutoResolveAfter(timeout));
+    }
+    let mut engine = builder.build()?;
+    // sequences, pending withdrawals, reorder window and dispute timers too
+    if let Some(state) = resumed_state {
+        engine = engine.with_state(state);
+    }
+    if let Some(path) = &args.initial_accounts {
+        engine.seed_accounts(read_accounts_csv(open_file(path)?)?)?;
+    }
+    if let Some(path) = &args.registered_accounts {
+        engine.register_accounts(read_clients_csv(open_file(path)?)?);
+    }
+    // what the run started from, for --delta-only
+    let seeded = args.delta_only.then(|| engine.accounts_by_client().clone());
+    // unless a resumed run goes on from the checkpoint's
+    if args.check_seq && engine.sequence_checker().is_none() {
+        engine = engine.with_sequence_check();
+    }
+    if args.adjust_negative_deposits {
+        engine = engine.with_negative_deposit_adjustments();
+    }
+    if let (Some(window), None) = (args.reorder_window, engine.reorder_buffer()) {
+        engine = engine.with_reorder_window(window);
+    }
+    if args.audit_out.is_some() {
+        engine = engine.with_audit();
+    }
+    if args.risk_out.is_some() {
+        engine = engine.with_risk_monitor();
+    }
+    if args.suspense_out.is_some() {
+        engine = engine.with_suspense();
+    }
+    if args.security_out.is_some() {
+        engine = engine.with_probe_log();
+    }
+    if args.recoveries_out.is_some() {
+        engine = engine.with_recoveries();
+    }
+    if args.category_out.is_some() {
+        engine = engine.with_category_spend();
+    }
+    if args.pending_out.is_some() && engine.pending_withdrawals().is_none() {
+        engine = engine.with_pending_withdrawals();
+    }
+    // read up front, so a bad schema fails before the input is processed
+    let schema = args
+        .output_schema
+        .as_deref()
+        .map(|path| read_output_schema(open_file(path)?))
+        .transpose()?;
+    let pseudonyms = match args.pseudonymize {
+        true => {
+            // the owners of the transactions in --security-report are clients too
+            let pseudonyms = Pseudonymizer::load(args.pseudonym_key_file.as_deref())?
+                .with_client_column("owner");
+            let columns = schema.iter().flat_map(OutputSchema::client_columns);
+            Some(columns.fold(pseudonyms, Pseudonymizer::with_client_column))
+        }
+        false => None,
+    };
+    if let Some(path) = &args.fees {
+        let tiers = args.fee_tiers.as_deref().map(open_file).transpose()?;
+        engine = engine.with_fees(read_fee_schedule(open_file(path)?, tiers)?);
+    }
+    // clones of the client lists kept to count what they dropped
+    let mut middleware = MiddlewareChain::new();
+    let clients_allow = match &args.clients_allow {
+        Some(path) => Some(ClientAllowlist::new(read_clients_csv(open_file(path)?)?)),
+        None => None,
+    };
+    let clients_deny = match &args.clients_deny {
+        Some(path) => Some(ClientDenylist::new(read_clients_csv(open_file(path)?)?)),
+        None => None,
+    };
+    if let Some(allow) = &clients_allow {
+        middleware = middleware.with(allow.clone());
+    }
+    if let Some(deny) = &clients_deny {
+        middleware = middleware.with(deny.clone());
+    }
+    if let Some(path) = &args.rates {
+        middleware = middleware.with(read_rates(open_file(path)?)?);
+    }
+    if !middleware.is_empty() {
+        engine = engine.with_middleware(middleware);
+    }
+    if let Some(max_amount) = args.max_amount {
+        engine = engine.with_max_amount(max_amount);
+    }
+    if let Some(max_balance) = args.max_balance {
+        engine = engine.with_max_balance(max_balance);
+    }
+    if let Some(max_accounts) = args.max_accounts {
+        engine = engine.with_max_accounts(max_accounts);
+    }
+    if let Some(max_tx_states) = args.max_tx_states {
+        engine = engine.with_max_tx_states(max_tx_states);
+    }
+    if args.last_activity {
+        engine = engine.with_last_activity();
+    }
+    // the HTML report's dispute funnel adds them up
+    if args.client_stats || args.report_html.is_some() {
+        engine = engine.with_client_stats();
+    }
+
+    if let Command::Explain { .. } = args.command {
+        for tx in txs {
+            print!("{}", engine.explain(tx?));
+        }
+        return Ok(());
+    }
+
+    // Process transactions, until interrupted
+    let interrupted = Arc::new(AtomicBool::new(false));
+    if args.threads.is_none() {
+        let flag = interrupted.clone();
+        on_shutdown_signal(move || flag.store(true, Ordering::SeqCst))?;
+    }
+    let mut pacer = match args.command {
+        Command::Replay { rate } => Some(Pacer::new(rate)?),
+        _ => None,
+    };
+    let checkpoint_every = args.checkpoint_every.unwrap_or(CHECKPOINT_EVERY);
+    let checkpoint =
+        |engine: &Engine, records: usize, chain: &Option<TxHashChain>| -> Result<(), Error> {
+            match &args.checkpoint_out {
+                Some(path) => state_files.write_checkpoint(
+                    path,
+                    &Checkpoint {
+                        input: args.input.clone(),
+                        records,
+                        state: engine.state(),
+                        tx_chain: chain.as_ref().map(TxHashChain::head),
+                    },
+                ),
+                None => Ok(()),
+            }
+        };
+    let mut breaker = match (args.max_rejection_rate, args.max_chargebacks_per_minute) {
+        (None, None) => None,
+        (rejection_rate, chargebacks) => {
+            let mut breaker = CircuitBreaker::new();
+            if let Some(percent) = rejection_rate {
+                let window = args.rejection_window.unwrap_or(REJECTION_WINDOW);
+                breaker = breaker.with_rejection_rate(percent / 100.0, window);
+            }
+            if let Some(max) = chargebacks {
+                breaker = breaker.with_chargeback_rate(max, Duration::from_secs(60));
+            }
+            Some(breaker)
+        }
+    };
+    let mut tripped = None;
+    let mut alerts = (!args.alerts.is_empty()).then(|| AlertMonitor::new(args.alerts.clone()));
+    let mut records = skip;
+    // of the transactions processed by this run, after those skipped
+    let mut summary = RunSummary::new();
+    let started = Instant::now();
+    // accounts written as the threads finished, with `--stream-output`
+    let mut streamed = None;
+    if let Some(threads) = args.threads {
+        let txs = txs.collect::<Result<Vec<Tx>, Error>>()?;
+        records = txs.len();
+        // lent to the threads, the chain and summary go through them after
+        let (run, written) = run_parallel(&args, engine, txs.iter().collect(), threads)?;
+        let rejections: HashMap<u64, &Error> = run
+            .rejections
+            .iter()
+            .map(|rejection| (rejection.index, &rejection.error))
+            .collect();
+        for (index, tx) in (1..).zip(&txs) {
+            let error = rejections.get(&index).copied();
+            summary.record(&tx.type_, tx.client_id, tx.tx_id, error);
+            if let (Some(chain), None) = (chain.as_mut(), error) {
+                chain.push(tx)?;
+            }
+        }
+        streamed = written;
+        engine = run.engine;
+        if let Some(out) = errors.as_mut() {
+            for rejection in &run.rejections {
+                let record = ErrorRecord::rejection(
+                    rejection.index,
+                    rejection.client,
+                    rejection.tx,
+                    &rejection.error,
+                );
+                write_rejection(&record, pseudonyms.as_ref(), out)?;
+            }
+        }
+    } else {
+        for _ in 0..skip {
+            if txs.next().transpose()?.is_none() {
+                return Err(Error::new(&format!(
+                    "Checkpoint is for {} transactions, {} has fewer",
+                    skip, args.input
+                )));
+            }
+        }
+        for tx in txs {
+            if interrupted.load(Ordering::SeqCst) {
+                break;
+            }
+            let tx = tx?;
+            if let Some(pacer) = pacer.as_mut() {
+                pacer.wait();
+            }
+            let (client_id, tx_id, tx_type) = (tx.client_id, tx.tx_id, tx.type_.clone());
+            records += 1;
+            let observed =
+                (chain.is_some() || alerts.is_some() || breaker.is_some()).then(|| tx.clone());
+            let result = TransactionProcessor::apply(&mut engine, tx);
+            if let (Some(monitor), Some(tx)) = (alerts.as_mut(), &observed) {
+                for alert in monitor.observe(records as u64, tx, &result, &engine) {
+                    eprintln!("Alert: {}", alert);
+                }
+            }
+            let trip = match (breaker.as_mut(), &observed) {
+                (Some(breaker), Some(tx)) => breaker.record(tx, &result, Instant::now()).err(),
+                _ => None,
+            };
+            match &result {
+                Ok(()) => {
+                    if let (Some(chain), Some(tx)) = (chain.as_mut(), &observed) {
+                        chain.push(tx)?;
+                    }
+                }
+                // a corrupt or oversized input, not a rejection
+                Err(err) if err.code == ErrorCode::ResourceLimit => {
+                    return Err(err.clone().with_line(records as u64));
+                }
+                Err(err) => {
+                    if let Some(out) = errors.as_mut() {
+                        let record = ErrorRecord::rejection(records as u64, client_id, tx_id, err);
+                        write_rejection(&record, pseudonyms.as_ref(), out)?;
+                    }
+                }
+            }
+            summary.record(&tx_type, client_id, tx_id, result.as_ref().err());
+            if records % checkpoint_every == 0 {
+                checkpoint(&engine, records, &chain)?;
+            }
+            if trip.is_some() {
+                tripped = trip;
+                break;
+            }
+        }
+    }
+    summary.duration = started.elapsed();
+    checkpoint(&engine, records, &chain)?;
+    let filtered = clients_allow.as_ref().map_or(0, ClientAllowlist::dropped)
+        + clients_deny.as_ref().map_or(0, ClientDenylist::dropped);
+    if clients_allow.is_some() || clients_deny.is_some() {
+        eprintln!("Filtered out {} transactions by client", filtered);
+    }
+    // stopped by the circuit breaker like by a signal, reporting the cause
+    let interrupted = interrupted.load(Ordering::SeqCst) || tripped.is_some();
+
+    if args.self_check && !interrupted {
+        engine.check_invariants()?;
+    }
+
+    if let Some(path) = &args.audit_out {
+        write_client_report(path, pseudonyms.as_ref(), |out| {
+            write_audit(engine.audit_trail(), out)
+        })?;
+    }
+    if let (Some(path), Some(pending)) = (&args.pending_out, engine.pending_withdrawals()) {
+        write_client_report(path, pseudonyms.as_ref(), |out| {
+            write_pending(&pending.totals(), out)
+        })?;
+    }
+    if let (Some(path), Some(risk)) = (&args.risk_out, engine.risk_monitor()) {
+        let flags = risk.flagged(&DefaultRiskScorer::default(), RISK_THRESHOLD);
+        write_client_report(path, pseudonyms.as_ref(), |out| {
+            write_risk_report(&flags, out)
+        })?;
+    }
+    if let (Some(path), Some(spend)) = (&args.category_out, engine.category_spend()) {
+        write_client_report(path, pseudonyms.as_ref(), |out| {
+            write_category_report(&spend.totals(), out)
+        })?;
+    }
+    if let (Some(path), Some(revenue), Some(fees)) =
+        (&args.revenue_out, engine.revenue(), engine.fee_schedule())
+    {
+        write_client_report(path, pseudonyms.as_ref(), |out| {
+            write_revenue_report(&revenue.lines(fees), out)
+        })?;
+    }
+    if let Some(path) = &args.suspense_out {
+        write_client_report(path, pseudonyms.as_ref(), |out| {
+            write_suspense(engine.suspense_ledger(), out)
+        })?;
+    }
+    if let Some(path) = &args.security_out {
+        write_client_report(path, pseudonyms.as_ref(), |out| {
+            write_security_report(engine.probes(), out)
+        })?;
+    }
+    if let Some(path) = &args.payouts_out {
+        write_client_report(path, pseudonyms.as_ref(), |out| {
+            write_payouts(engine.payouts(), out)
+        })?;
+    }
+    if let (Some(path), Some(recoveries)) = (&args.recoveries_out, engine.recoveries()) {
+        write_client_report(path, pseudonyms.as_ref(), |out| {
+            write_recoveries(&recoveries.lines(), out)
+        })?;
+    }
+    if let Some(path) = &args.locks_out {
+        write_client_report(path, pseudonyms.as_ref(), |out| {
+            write_locks(&lock_report(engine.accounts_by_client()), out)
+        })?;
+    }
+    if let Some(path) = &args.hold_reviews_out {
+        write_client_report(path, pseudonyms.as_ref(), |out| {
+            write_hold_reviews(engine.hold_reviews(), out)
+        })?;
+    }
+    if let Some(path) = &args.report_html {
+        let rounding = args.rounding.unwrap_or_default();
+        write_report(path, |out| match &pseudonyms {
+            Some(pseudonyms) => {
+                let clients = |client| pseudonyms.pseudonym(client);
+                write_html_report_with_clients(&engine, &summary, rounding, &clients, out)
+            }
+            None => write_html_report(&engine, &summary, rounding, out),
+        })?;
+    }
+    if let Some(path) = &args.snapshot_out {
+        state_files.write_snapshot(path, &TransactionProcessor::snapshot(&engine))?;
+    }
+
+    if args.skip_empty_accounts {
+        engine.retain_accounts(|account| !account.is_empty());
+    }
+    if !args.filter.is_empty() {
+        engine.retain_accounts(|account| args.filter.matches(account));
+    }
+    if let Some(seeded) = &seeded {
+        let rounding = args.rounding.unwrap_or_default();
+        let changed: HashSet<ClientId> =
+            diff_accounts(engine.accounts_by_client(), seeded, rounding)
+                .iter()
+                .filter(|diff| !matches!(diff, AccountDiff::Missing(_)))
+                .map(AccountDiff::client)
+                .collect();
+        engine.retain_accounts(|account| changed.contains(&account.client));
+    }
+    if let Some(manifest) = manifest.as_mut() {
+        manifest.interrupted = interrupted;
+        manifest.rows = RowCounts {
+            read: records,
+            skipped: skip,
+            filtered,
+            processed: records - skip - filtered,
+            rejected: summary.rejected(),
+            accounts: if interrupted {
+                0
+            } else {
+                streamed.unwrap_or(engine.account_count())
+            },
+        };
+        if let Some(chain) = &chain {
+            manifest.tx_chain = chain.head();
+        }
+    }
+    let write_manifest_to = |manifest: &mut Option<Manifest>| -> Result<(), Error> {
+        match (&args.manifest_out, manifest) {
+            (Some(path), Some(manifest)) => {
+                manifest.finished_at = unix_time();
+                write_report(path, |out| write_manifest(manifest, out))
+            }
+            _ => Ok(()),
+        }
+    };
+
+    if interrupted {
+        // reports and checkpoint are flushed, the balances are incomplete
+        write_manifest_to(&mut manifest)?;
+        if let Some(err) = tripped {
+            return Err(err);
+        }
+        eprintln!("Interrupted after {} transactions", records);
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+
+    // Compared before the accounts are written out
+    let expected = match &args.expect {
+        Some(path) => {
+            let expected = read_accounts_csv(open_file(path)?)?;
+            let diffs = diff_accounts(
+                engine.accounts_by_client(),
+                &expected,
+                args.rounding.unwrap_or_default(),
+            );
+            Some((path, diffs))
+        }
+        None => None,
+    };
+
+    // Output to Stdout
+    let stats = engine.client_stats().filter(|_| args.client_stats).cloned();
+    let last_activity = engine.last_activity().cloned();
+    let accounts = engine.into_accounts();
+    match (&args.output_dir, args.partitions) {
+        _ if streamed.is_some() => {}
+        (Some(dir), Some(partitions)) => {
+            write_partitioned(accounts, args.rounding.unwrap_or_default(), partitions, dir)?
+        }
+        _ => write_client_report(
+            args.output.as_deref().unwrap_or("-"),
+            pseudonyms.as_ref(),
+            |out| {
+                let rounding = args.rounding.unwrap_or_default();
+                if let Some(schema) = &schema {
+                    return schema.write(accounts, rounding, out);
+                }
+                if args.tenants {
+                    return output_with_tenants(accounts, &tenants, rounding, out);
+                }
+                if let Some(stats) = stats {
+                    return output_with_client_stats(accounts, &stats, rounding, out);
+                }
+                match last_activity {
+                    Some(last_activity) => {
+                        // indices of the input rows, when resumed after `skip`
+                        let last_activity = last_activity
+                            .iter()
+                            .map(|(client, index)| (*client, index + skip as u64))
+                            .collect();
+                        output_with_last_activity(accounts, &last_activity, rounding, out)
+                    }
+                    None => output_to_stdout_with_rounding(accounts, rounding, out),
+                }
+            },
+        )?,
+    }
+    if let (Some(path), Some(pseudonyms)) = (&args.pseudonym_map, &pseudonyms) {
+        write_report(path, |out| pseudonyms.write_mapping(out))?;
+    }
+    write_manifest_to(&mut manifest)?;
+
+    match expected {
+        Some((path, diffs)) if !diffs.is_empty() => {
+            eprintln!("Accounts differ from {}:", path);
+            for diff in &diffs {
+                eprintln!("  {}", diff);
+            }
+            Err(Error::coded(
+                ErrorCode::Mismatch,
+                &format!("{} accounts differ from {}", diffs.len(), path),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{open_file, Error, Tx};
+
+/// SHA-256 and size of an input, as read by the run.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct InputDigest {
+    pub path: String,
+    pub sha256: String,
+    pub bytes: u64,
+}
+
+/// Passes reads through, hashing the bytes on the way, so an input that
+/// can only be read once (stdin, HTTP) is hashed exactly as processed.
+pub struct DigestReader<R> {
+    inner: R,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<R: Read> DigestReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0,
+        }
+    }
+
+    /// Digest of everything read so far.
+    pub fn finish(self, path: &str) -> InputDigest {
+        InputDigest {
+            path: path.to_string(),
+            sha256: hex(&self.hasher.finalize()),
+            bytes: self.bytes,
+        }
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.bytes += read as u64;
+        Ok(read)
+    }
+}
+
+/// Hash the whole of a local file.
+pub fn digest_file(path: &str) -> Result<InputDigest, Error> {
+    let mut reader = DigestReader::new(open_file(path)?);
+    io::copy(&mut reader, &mut io::sink())?;
+    Ok(reader.finish(path))
+}
+
+/// Rolling SHA-256 over the transactions a run applied, i.e. didn't
+/// reject, in input order: each link hashes the previous head with the JSON
+/// of the next transaction, so the final head pins the whole sequence. Two
+/// runs with the same head applied the same transactions.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TxHashChain {
+    head: [u8; 32],
+}
+
+impl TxHashChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Continue from the `head` of an earlier run, see `Checkpoint::tx_chain`.
+    pub fn resume(head: &str) -> Result<Self, Error> {
+        let invalid = || Error::new(&format!("Invalid transaction chain head: {}", head));
+        if head.len() != 64 || !head.is_ascii() {
+            return Err(invalid());
+        }
+        let mut chain = Self::new();
+        for (byte, pair) in chain.head.iter_mut().zip(head.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+        }
+        Ok(chain)
+    }
+
+    pub fn push(&mut self, tx: &Tx) -> Result<(), Error> {
+        let mut link = Sha256::new();
+        link.update(self.head);
+        link.update(serde_json::to_vec(tx)?);
+        self.head = link.finalize().into();
+        Ok(())
+    }
+
+    pub fn head(&self) -> String {
+        hex(&self.head)
+    }
+}
+
+/// Transactions seen by the run.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct RowCounts {
+    /// Transactions read from the input, up to where an interrupted run
+    /// stopped.
+    pub read: usize,
+    /// Skipped because a resumed checkpoint already covered them.
+    pub skipped: usize,
+    /// Dropped by `--clients-allow` or `--clients-deny`.
+    #[serde(default)]
+    pub filtered: usize,
+    pub processed: usize,
+    /// Processed, but rejected with an error.
+    pub rejected: usize,
+    /// Accounts in the output.
+    pub accounts: usize,
+}
+
+/// Provenance of a run's output, written as a JSON sidecar. The
+/// `report_id` only depends on the crate version, the inputs' content and
+/// the configuration, so rerunning the same inputs with the same settings
+/// gives the same id.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct Manifest {
+    pub report_id: String,
+    pub version: String,
+    pub inputs: Vec<InputDigest>,
+    /// Command line arguments, except the input path.
+    pub config: Vec<String>,
+    pub config_digest: String,
+    /// Seconds since the Unix epoch.
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub interrupted: bool,
+    pub rows: RowCounts,
+    /// Head of the `TxHashChain` of the transactions applied.
+    pub tx_chain: String,
+}
+
+impl Manifest {
+    pub fn new(inputs: Vec<InputDigest>, config: Vec<String>, started_at: u64) -> Self {
+        let version = env!("CARGO_PKG_VERSION").to_string();
+        let config_digest = sha256_hex(config.join("\0").as_bytes());
+        let mut id = Sha256::new();
+        id.update(version.as_bytes());
+        for input in &inputs {
+            id.update(input.sha256.as_bytes());
+        }
+        id.update(config_digest.as_bytes());
+        Self {
+            report_id: hex(&id.finalize()),
+            version,
+            inputs,
+            config,
+            config_digest,
+            started_at,
+            finished_at: started_at,
+            interrupted: false,
+            rows: RowCounts::default(),
+            tx_chain: TxHashChain::new().head(),
+        }
+    }
+}
+
+/// Seconds since the Unix epoch, 0 if the clock is before it.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+pub fn write_manifest(manifest: &Manifest, output: &mut impl Write) -> Result<(), Error> {
+    serde_json::to_writer_pretty(&mut *output, manifest)?;
+    writeln!(output)?;
+    Ok(())
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Amount, TxType};
+
+    #[test]
+    fn report_id_depends_on_inputs_and_config_only() -> Result<(), Error> {
+        let mut reader = DigestReader::new("abc".as_bytes());
+        io::copy(&mut reader, &mut io::sink())?;
+        let input = reader.finish("-");
+        assert_eq!(
+            input.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(input.bytes, 3);
+
+        let config = vec!["--audit".to_string(), "audit.csv".to_string()];
+        let manifest = Manifest::new(vec![input.clone()], config.clone(), 1);
+        let rerun = Manifest::new(vec![input.clone()], config, 2);
+        assert_eq!(manifest.report_id, rerun.report_id);
+        let other = Manifest::new(vec![input], vec![], 1);
+        assert_ne!(manifest.report_id, other.report_id);
+        Ok(())
+    }
+
+    #[test]
+    fn tx_chain_depends_on_the_order() -> Result<(), Error> {
+        let tx = |tx_id| Tx::new(TxType::Deposit, 1, tx_id).with_amount(Amount::new(1.0).unwrap());
+        let mut chain = TxHashChain::new();
+        chain.push(&tx(1))?;
+        let mut resumed = TxHashChain::resume(&chain.head())?;
+        assert_eq!(resumed, chain);
+        chain.push(&tx(2))?;
+        resumed.push(&tx(2))?;
+        assert_eq!(resumed.head(), chain.head());
+
+        let mut reordered = TxHashChain::new();
+        reordered.push(&tx(2))?;
+        reordered.push(&tx(1))?;
+        assert_ne!(reordered.head(), chain.head());
+        assert!(TxHashChain::resume("xyz").is_err());
+        Ok(())
+    }
+}
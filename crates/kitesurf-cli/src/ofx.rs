@@ -0,0 +1,117 @@
+use std::io::Read;
+
+use crate::{Amount, AmountFormat, ClientId, Error, ErrorCode, Tx, TxId, TxRefs, TxType};
+
+fn import_error(message: &str) -> Error {
+    Error::coded(ErrorCode::Import, &format!("OFX Error: {}", message))
+}
+
+/// Fields of a `STMTTRN` being read.
+#[derive(Default)]
+struct StatementTx {
+    amount: Option<String>,
+    fit_id: Option<String>,
+    name: Option<String>,
+    memo: Option<String>,
+}
+
+/// Deposit for a positive amount, withdrawal for a negative one.
+pub(crate) fn signed_transfer(client_id: ClientId, tx_id: TxId, amount: f64) -> Result<Tx, Error> {
+    let type_ = if amount < 0.0 {
+        TxType::Withdrawal
+    } else {
+        TxType::Deposit
+    };
+    Ok(Tx::new(type_, client_id, tx_id).with_amount(Amount::new(amount.abs())?))
+}
+
+/// Read the statement transactions of an OFX download, SGML (1.x) or XML
+/// (2.x): credits are deposits and debits withdrawals of the client whose
+/// id is the statement's `ACCTID`. The `FITID`s are interned into `refs` to
+/// produce the transaction ids; `NAME` is kept as the merchant and `MEMO`
+/// as the description.
+pub fn read_ofx<R: Read>(mut buf: R, refs: &mut TxRefs) -> Result<Vec<Tx>, Error> {
+    let mut ofx = String::new();
+    buf.read_to_string(&mut ofx)?;
+    let mut txs = vec![];
+    let mut client_id: Option<ClientId> = None;
+    let mut current: Option<StatementTx> = None;
+    // SGML leaf elements aren't closed: `<TRNAMT>-20.00<FITID>...`
+    for token in ofx.split('<').skip(1) {
+        let (tag, value) = token.split_once('>').unwrap_or((token, ""));
+        let value = value.trim();
+        match (tag.trim(), current.as_mut()) {
+            ("ACCTID", _) => {
+                client_id = Some(value.parse().map_err(|_| {
+                    import_error(&format!("account id {} is not a client id", value))
+                })?)
+            }
+            ("STMTTRN", _) => current = Some(StatementTx::default()),
+            ("/STMTTRN", Some(_)) => {
+                let tx = current.take().unwrap_or_default();
+                let client_id =
+                    client_id.ok_or_else(|| import_error("transaction before any ACCTID"))?;
+                let raw = tx
+                    .amount
+                    .ok_or_else(|| import_error("transaction without TRNAMT"))?;
+                let amount = AmountFormat::default()
+                    .parse(&raw)
+                    .ok_or_else(|| import_error(&format!("invalid amount {}", raw)))?;
+                let fit_id = tx
+                    .fit_id
+                    .ok_or_else(|| import_error("transaction without FITID"))?;
+                let mut transfer = signed_transfer(client_id, refs.intern(&fit_id)?, amount)?;
+                transfer.description = tx.memo;
+                transfer.merchant = tx.name;
+                txs.push(transfer);
+            }
+            ("TRNAMT", Some(tx)) => tx.amount = Some(value.to_string()),
+            ("FITID", Some(tx)) => tx.fit_id = Some(value.to_string()),
+            ("NAME", Some(tx)) => tx.name = Some(value.to_string()),
+            ("MEMO", Some(tx)) => tx.memo = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    Ok(txs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sgml_statement_transactions() -> Result<(), Error> {
+        let ofx = "OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+
+<OFX>
+<BANKMSGSRSV1><STMTTRNRS><STMTRS>
+<CURDEF>USD
+<BANKACCTFROM><BANKID>121000248<ACCTID>42<ACCTTYPE>CHECKING</BANKACCTFROM>
+<BANKTRANLIST>
+<STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20240105<TRNAMT>1,200.00<FITID>2024010501<NAME>ACME PAYROLL</STMTTRN>
+<STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20240107<TRNAMT>-45.10<FITID>2024010702<NAME>GROCER<MEMO>Card 1234</STMTTRN>
+</BANKTRANLIST>
+</STMTRS></STMTTRNRS></BANKMSGSRSV1>
+</OFX>";
+        let mut refs = TxRefs::new();
+        let txs = read_ofx(ofx.as_bytes(), &mut refs)?;
+        let mut payroll = signed_transfer(42, 0, 1200.0)?;
+        payroll.merchant = Some("ACME PAYROLL".to_string());
+        let mut groceries = signed_transfer(42, 1, -45.1)?;
+        groceries.merchant = Some("GROCER".to_string());
+        groceries.description = Some("Card 1234".to_string());
+        assert_eq!(txs, vec![payroll, groceries]);
+        assert_eq!(txs[1].type_, TxType::Withdrawal);
+        assert_eq!(refs.resolve(1), Some("2024010702"));
+        Ok(())
+    }
+
+    #[test]
+    fn xml_statement_needs_a_numeric_account() {
+        let ofx = "<OFX><BANKACCTFROM><ACCTID>CHK-1</ACCTID></BANKACCTFROM></OFX>";
+        let err = read_ofx(ofx.as_bytes(), &mut TxRefs::new()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::Import);
+    }
+}
@@ -0,0 +1,182 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+
+use crate::amount::{parse_amount, AmountParsing};
+use crate::io::csv_reader;
+use crate::{ClientId, Error, TxId, TxType};
+
+/// Data-quality profile of a transaction feed, see `profile_csv`.
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct FeedProfile {
+    /// Rows read, malformed ones included.
+    pub rows: usize,
+    /// Rows with more or fewer cells than the header, left out of the
+    /// column profiles.
+    pub malformed_rows: usize,
+    pub distinct_clients: usize,
+    /// Of the well-formed amounts, `None` without any.
+    pub amounts: Option<AmountStats>,
+    /// Rows per value of the `type` column, unknown types included.
+    pub tx_types: BTreeMap<String, usize>,
+    pub columns: Vec<ColumnProfile>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct AmountStats {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// Cells of one column of the well-formed rows.
+#[derive(Debug, Default, Serialize, PartialEq)]
+pub struct ColumnProfile {
+    pub column: String,
+    /// Empty cells.
+    pub nulls: usize,
+    pub null_rate: f64,
+    /// Cells that can't be read as the column's type, e.g. an unknown
+    /// transaction type or a client id out of range. Only the `type`,
+    /// `client`, `tx`, `amount` and `seq` columns have one.
+    pub malformed: usize,
+    /// Cells per kind of value: `empty`, `integer`, `decimal` or `text`.
+    pub kinds: BTreeMap<&'static str, usize>,
+}
+
+/// Profile the CSV transactions in `buf` without processing them, so a new
+/// feed can be assessed before a full run. Unlike the readers, malformed
+/// rows and cells are counted instead of failing.
+pub fn profile_csv<R: std::io::Read>(buf: R) -> Result<FeedProfile, Error> {
+    let mut csv_reader = csv_reader(buf);
+    let headers = csv_reader.headers()?.clone();
+    let mut profile = FeedProfile {
+        columns: headers
+            .iter()
+            .map(|column| ColumnProfile {
+                column: column.to_string(),
+                ..ColumnProfile::default()
+            })
+            .collect(),
+        ..FeedProfile::default()
+    };
+    let mut clients = HashSet::new();
+    let mut amounts = vec![];
+    let mut well_formed = 0;
+    for result in csv_reader.records() {
+        profile.rows += 1;
+        let record = match result {
+            Ok(record) => record,
+            Err(err) if matches!(err.kind(), csv::ErrorKind::UnequalLengths { .. }) => {
+                profile.malformed_rows += 1;
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        well_formed += 1;
+        for (column, cell) in profile.columns.iter_mut().zip(record.iter()) {
+            *column.kinds.entry(kind(cell)).or_default() += 1;
+            if cell.is_empty() {
+                column.nulls += 1;
+                continue;
+            }
+            let valid = match column.column.as_str() {
+                "type" => {
+                    *profile.tx_types.entry(cell.to_string()).or_default() += 1;
+                    cell.parse::<TxType>().is_ok()
+                }
+                "client" => cell
+                    .parse::<ClientId>()
+                    .map(|client| clients.insert(client))
+                    .is_ok(),
+                "tx" => cell.parse::<TxId>().is_ok(),
+                "amount" => parse_amount(cell, AmountParsing::Strict)
+                    .map(|amount| amounts.push(amount))
+                    .is_ok(),
+                "seq" => cell.parse::<u64>().is_ok(),
+                _ => true,
+            };
+            column.malformed += usize::from(!valid);
+        }
+    }
+    for column in &mut profile.columns {
+        if well_formed > 0 {
+            column.null_rate = column.nulls as f64 / well_formed as f64;
+        }
+    }
+    profile.distinct_clients = clients.len();
+    if !amounts.is_empty() {
+        profile.amounts = Some(AmountStats {
+            count: amounts.len(),
+            min: amounts.iter().copied().fold(f64::INFINITY, f64::min),
+            max: amounts.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            mean: amounts.iter().sum::<f64>() / amounts.len() as f64,
+        });
+    }
+    Ok(profile)
+}
+
+fn kind(cell: &str) -> &'static str {
+    if cell.is_empty() {
+        "empty"
+    } else if cell.parse::<i64>().is_ok() {
+        "integer"
+    } else if cell.parse::<f64>().is_ok() {
+        "decimal"
+    } else {
+        "text"
+    }
+}
+
+pub fn write_profile(profile: &FeedProfile, output: &mut impl Write) -> Result<(), Error> {
+    serde_json::to_writer_pretty(&mut *output, profile)?;
+    writeln!(output)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn profile_counts_nulls_and_malformed_cells() -> Result<(), Error> {
+        let data = "\
+type, client, tx, amount
+deposit, 1, 1, 2.5
+deposit, 2, 2, 1
+dispute, 1, 1,
+refund, abc, 3, abc
+withdrawal, 1, 4
+deposit, 2, x, 4.5
+deposit, 3, 5, inf
+";
+        let profile = profile_csv(data.as_bytes())?;
+        assert_eq!((profile.rows, profile.malformed_rows), (7, 1));
+        assert_eq!(profile.distinct_clients, 3);
+        assert_eq!(
+            profile.amounts,
+            Some(AmountStats {
+                count: 3,
+                min: 1.0,
+                max: 4.5,
+                mean: 8.0 / 3.0,
+            })
+        );
+        assert_eq!(profile.tx_types["deposit"], 4);
+        assert_eq!(profile.tx_types["refund"], 1);
+
+        let column = |name: &str| profile.columns.iter().find(|c| c.column == name).unwrap();
+        assert_eq!(column("type").malformed, 1);
+        assert_eq!(column("client").malformed, 1);
+        assert_eq!(column("tx").malformed, 1);
+        let amount = column("amount");
+        assert_eq!((amount.nulls, amount.malformed), (1, 2));
+        assert_eq!(amount.null_rate, 1.0 / 6.0);
+        // `inf` reads as a float, not as an amount
+        assert_eq!(amount.kinds["decimal"], 3);
+        assert_eq!(amount.kinds["integer"], 1);
+        assert_eq!(amount.kinds["text"], 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+
+use crate::{ClientId, Error, ErrorRecord};
+
+/// Environment variable with the pseudonym key, when there's no key file.
+pub const PSEUDONYM_KEY_VAR: &str = "KITESURF_PSEUDONYM_KEY";
+
+/// Shortest key accepted, in bytes.
+const MIN_KEY_LEN: usize = 16;
+
+/// Bytes of the HMAC kept in a pseudonym, written as twice as many hex
+/// digits.
+const PSEUDONYM_LEN: usize = 8;
+
+/// Replaces client ids by pseudonyms in outputs and reports, so they can
+/// be shared without the real identifiers, see `--pseudonymize`. The
+/// pseudonym of a client is the start of the HMAC-SHA256 of its id under a
+/// secret key: the same for every run with that key, and only computable
+/// with it. The pseudonyms given out are kept for `write_mapping`.
+#[derive(Clone)]
+pub struct Pseudonymizer {
+    mac: Hmac<Sha256>,
+    client_columns: Vec<String>,
+    given: RefCell<BTreeMap<ClientId, String>>,
+}
+
+impl Pseudonymizer {
+    pub fn new(key: &[u8]) -> Result<Self, Error> {
+        if key.len() < MIN_KEY_LEN {
+            return Err(Error::new(&format!(
+                "Pseudonym key must be at least {} bytes",
+                MIN_KEY_LEN
+            )));
+        }
+        let mac = Hmac::<Sha256>::new_from_slice(key)
+            .map_err(|err| Error::new(&format!("Invalid pseudonym key: {}", err)))?;
+        Ok(Self {
+            mac,
+            client_columns: vec!["client".to_string()],
+            given: RefCell::new(BTreeMap::new()),
+        })
+    }
+
+    /// With the key of `file`, or else of `KITESURF_PSEUDONYM_KEY`.
+    pub fn load(file: Option<&str>) -> Result<Self, Error> {
+        let key = match file {
+            Some(path) => std::fs::read_to_string(path).map_err(|err| {
+                Error::new(&format!("Unable to open pseudonym key {}: {}", path, err))
+            })?,
+            None => std::env::var(PSEUDONYM_KEY_VAR).map_err(|_| {
+                Error::new(&format!(
+                    "--pseudonymize expects --pseudonym-key-file or {}",
+                    PSEUDONYM_KEY_VAR
+                ))
+            })?,
+        };
+        Self::new(key.trim().as_bytes())
+    }
+
+    /// Also replace the ids of CSV columns named `column`, besides `client`.
+    pub fn with_client_column(mut self, column: &str) -> Self {
+        self.client_columns.push(column.to_string());
+        self
+    }
+
+    pub fn pseudonym(&self, client: ClientId) -> String {
+        self.given
+            .borrow_mut()
+            .entry(client)
+            .or_insert_with(|| {
+                let mac = self.mac.clone().chain_update(client.to_string());
+                mac.finalize().into_bytes()[..PSEUDONYM_LEN]
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect()
+            })
+            .clone()
+    }
+
+    /// `csv` with the ids of its client columns replaced by pseudonyms.
+    /// Empty cells are left as they are, and so is input without a client
+    /// column.
+    pub fn pseudonymize_csv(&self, csv: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(csv);
+        let mut records = reader.records();
+        let Some(header) = records.next().transpose()? else {
+            return Ok(csv.to_vec());
+        };
+        let columns: Vec<usize> = header
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| self.client_columns.iter().any(|column| column == name))
+            .map(|(index, _)| index)
+            .collect();
+        if columns.is_empty() {
+            return Ok(csv.to_vec());
+        }
+        let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(vec![]);
+        writer.write_record(&header)?;
+        for record in records {
+            let record = record?;
+            let mut cells: Vec<String> = record.iter().map(str::to_string).collect();
+            for &column in &columns {
+                let Some(cell) = cells.get_mut(column).filter(|cell| !cell.is_empty()) else {
+                    continue;
+                };
+                let client = cell.parse::<ClientId>().map_err(|_| {
+                    Error::new(&format!("Unexpected client id {} to pseudonymize", cell))
+                })?;
+                *cell = self.pseudonym(client);
+            }
+            writer.write_record(&cells)?;
+        }
+        writer
+            .into_inner()
+            .map_err(|err| Error::new(&format!("CSV Error: {}", err)))
+    }
+
+    /// `record` as a line of JSON like `write_error_json`, with the
+    /// pseudonym of its client.
+    pub fn write_error_json(
+        &self,
+        record: &ErrorRecord,
+        output: &mut impl Write,
+    ) -> Result<(), Error> {
+        let mut json = serde_json::to_value(record)?;
+        if let Some(client) = record.client {
+            json["client"] = self.pseudonym(client).into();
+        }
+        serde_json::to_writer(&mut *output, &json)?;
+        writeln!(output)?;
+        Ok(())
+    }
+
+    /// Write the `pseudonym,client` table of the pseudonyms given out so
+    /// far, by client, for whoever needs to trace them back.
+    pub fn write_mapping(&self, output: &mut impl Write) -> Result<(), Error> {
+        #[derive(Serialize)]
+        struct MappingRow<'a> {
+            pseudonym: &'a str,
+            client: ClientId,
+        }
+        let mut writer = csv::Writer::from_writer(output);
+        for (client, pseudonym) in self.given.borrow().iter() {
+            writer.serialize(MappingRow {
+                pseudonym,
+                client: *client,
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+// never prints the key
+impl fmt::Debug for Pseudonymizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pseudonymizer")
+            .field("client_columns", &self.client_columns)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ErrorCode;
+
+    #[test]
+    fn client_columns_are_pseudonymized() -> Result<(), Error> {
+        let pseudonyms = Pseudonymizer::new(b"0123456789abcdef")?;
+        let (one, two) = (pseudonyms.pseudonym(1), pseudonyms.pseudonym(2));
+        assert_eq!(one.len(), 16);
+        assert_ne!(one, two);
+        // keyed: the same with the same key only
+        assert_eq!(Pseudonymizer::new(b"0123456789abcdef")?.pseudonym(1), one);
+        assert_ne!(Pseudonymizer::new(b"fedcba9876543210")?.pseudonym(1), one);
+        assert!(Pseudonymizer::new(b"short").is_err());
+
+        let csv = pseudonyms.pseudonymize_csv(b"index,client,tx\n1,2,7\n2,,8\n")?;
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            format!("index,client,tx\n1,{},7\n2,,8\n", two)
+        );
+        let untouched = b"client_count\n3\n";
+        assert_eq!(pseudonyms.pseudonymize_csv(untouched)?, untouched);
+        let renamed = pseudonyms.with_client_column("id");
+        let csv = renamed.pseudonymize_csv(b"id,balance\n1,5.0\n")?;
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            format!("id,balance\n{},5.0\n", one)
+        );
+
+        let mut json = vec![];
+        let error = Error::coded(ErrorCode::InsufficientFunds, "Insufficient funds");
+        renamed.write_error_json(&ErrorRecord::rejection(3, 2, 7, &error), &mut json)?;
+        assert!(String::from_utf8(json)
+            .unwrap()
+            .contains(&format!("\"client\":\"{}\"", two)));
+        let mut mapping = vec![];
+        renamed.write_mapping(&mut mapping)?;
+        assert_eq!(
+            String::from_utf8(mapping).unwrap(),
+            format!("pseudonym,client\n{},1\n{},2\n", one, two)
+        );
+        Ok(())
+    }
+}
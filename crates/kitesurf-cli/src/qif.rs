@@ -0,0 +1,141 @@
+use std::io::Read;
+
+use crate::ofx::signed_transfer;
+use crate::{AmountFormat, ClientId, Error, ErrorCode, Tx, TxRefs};
+
+fn import_error(line: usize, message: &str) -> Error {
+    Error::coded(
+        ErrorCode::Import,
+        &format!("QIF Error on line {}: {}", line, message),
+    )
+    .with_line(line as u64)
+}
+
+/// Fields of a transaction being read, up to its closing `^`.
+#[derive(Default)]
+struct Record<'a> {
+    amount: Option<&'a str>,
+    number: Option<&'a str>,
+    payee: Option<&'a str>,
+    memo: Option<&'a str>,
+    category: Option<&'a str>,
+}
+
+/// Read the bank, cash and credit card transactions of a QIF export as
+/// deposits (positive amounts) and withdrawals (negative ones) of
+/// `client_id`, as QIF doesn't identify accounts. Transactions have no ids
+/// either: each is interned into `refs` by its check number if it has one,
+/// or else its position in the file. The payee is kept as the merchant,
+/// the memo as the description and the category as is.
+pub fn read_qif<R: Read>(
+    mut buf: R,
+    client_id: ClientId,
+    refs: &mut TxRefs,
+) -> Result<Vec<Tx>, Error> {
+    let mut qif = String::new();
+    buf.read_to_string(&mut qif)?;
+    let mut txs = vec![];
+    // `!Account` blocks describe an account, not transactions
+    let mut in_account = false;
+    let mut record = Record::default();
+    for (index, line) in qif.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim_end();
+        if let Some(header) = line.strip_prefix('!') {
+            match header.trim() {
+                "Account" => in_account = true,
+                "Type:Bank" | "Type:Cash" | "Type:CCard" | "Type:Oth A" | "Type:Oth L" => {
+                    in_account = false
+                }
+                header if header.starts_with("Option:") || header.starts_with("Clear:") => {}
+                header => {
+                    return Err(import_error(
+                        line_number,
+                        &format!("unsupported section {}", header),
+                    ))
+                }
+            }
+            continue;
+        }
+        let (code, value) = line.split_at(line.chars().next().map_or(0, char::len_utf8));
+        let value = value.trim();
+        match code {
+            "^" if in_account => in_account = false,
+            "^" => {
+                let done = std::mem::take(&mut record);
+                let raw = done
+                    .amount
+                    .ok_or_else(|| import_error(line_number, "transaction without an amount"))?;
+                let amount = AmountFormat::default()
+                    .parse(raw)
+                    .ok_or_else(|| import_error(line_number, &format!("invalid amount {}", raw)))?;
+                let tx_ref = done
+                    .number
+                    .map_or_else(|| format!("#{}", txs.len() + 1), str::to_string);
+                let mut tx = signed_transfer(client_id, refs.intern(&tx_ref)?, amount)
+                    .map_err(|err| err.with_line(line_number as u64))?;
+                tx.description = done.memo.map(str::to_string);
+                tx.merchant = done.payee.map(str::to_string);
+                tx.category = done.category.map(str::to_string);
+                txs.push(tx);
+            }
+            _ if in_account => {}
+            "T" | "U" => record.amount = Some(value),
+            "N" if !value.is_empty() => record.number = Some(value),
+            "P" => record.payee = Some(value),
+            "M" => record.memo = Some(value),
+            "L" => record.category = Some(value),
+            // date, cleared status, address, splits...
+            _ => {}
+        }
+    }
+    Ok(txs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TxType;
+
+    #[test]
+    fn bank_transactions_of_the_given_client() -> Result<(), Error> {
+        let qif = "!Account
+NChecking
+TBank
+^
+!Type:Bank
+D01/05/2024
+T1,200.00
+PACME Payroll
+LSalary
+^
+D01/07/2024
+U-45.10
+T-45.10
+N1043
+PGrocer
+MWeekly shop
+LFood:Groceries
+^
+";
+        let mut refs = TxRefs::new();
+        let txs = read_qif(qif.as_bytes(), 9, &mut refs)?;
+        let mut payroll = signed_transfer(9, 0, 1200.0)?;
+        payroll.merchant = Some("ACME Payroll".to_string());
+        payroll.category = Some("Salary".to_string());
+        let mut groceries = signed_transfer(9, 1, -45.1)?;
+        groceries.description = Some("Weekly shop".to_string());
+        groceries.merchant = Some("Grocer".to_string());
+        groceries.category = Some("Food:Groceries".to_string());
+        assert_eq!(txs, vec![payroll, groceries]);
+        assert_eq!(txs[1].type_, TxType::Withdrawal);
+        assert_eq!(refs.resolve(1), Some("1043"));
+        Ok(())
+    }
+
+    #[test]
+    fn investment_accounts_are_rejected() {
+        let err = read_qif("!Type:Invst\n^\n".as_bytes(), 1, &mut TxRefs::new()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::Import);
+    }
+}
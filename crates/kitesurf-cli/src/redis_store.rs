@@ -0,0 +1,185 @@
+use redis::Commands;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::{
+    AccountRecord, ClientAccount, ClientId, Compat, Engine, Error, LockedPolicy, Snapshot,
+    StateStore, Tx,
+};
+
+/// `StateStore` keeping accounts and transaction states in Redis, so several
+/// processor instances can share them.
+///
+/// Each transaction WATCHes the client's account key and the transaction key,
+/// applies the transaction with an `Engine` holding just those two and writes
+/// the result back in a MULTI/EXEC
+/// block. If another instance changed either key in the meantime the EXEC is
+/// discarded and the transaction is retried against the fresh state.
+///
+/// WATCH holds for the whole connection, so each call takes a connection of
+/// its own from a pool of idle ones instead of sharing a multiplexed one.
+pub struct RedisStore {
+    client: redis::Client,
+    idle: Mutex<Vec<redis::Connection>>,
+    prefix: String,
+    locked_policy: LockedPolicy,
+}
+
+impl RedisStore {
+    /// Keys are `<prefix>account:<client>` and `<prefix>tx:<tx>`, JSON values.
+    pub fn new(url: &str, prefix: &str) -> Result<Self, Error> {
+        Ok(Self {
+            client: redis::Client::open(url)?,
+            idle: Mutex::new(vec![]),
+            prefix: prefix.to_string(),
+            locked_policy: LockedPolicy::default(),
+        })
+    }
+
+    pub fn with_locked_policy(mut self, locked_policy: LockedPolicy) -> Self {
+        self.locked_policy = locked_policy;
+        self
+    }
+
+    fn account_key(&self, client_id: ClientId) -> String {
+        format!("{}account:{}", self.prefix, client_id)
+    }
+
+    /// Runs `f` on an idle connection, or a new one when all are in use, and
+    /// returns the connection to the pool unless it broke.
+    fn with_connection<T>(
+        &self,
+        f: impl FnOnce(&mut redis::Connection) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let idle = self
+            .idle
+            .lock()
+            .map_err(|_| Error::new("Redis connection pool lock poisoned"))?
+            .pop();
+        let mut con = match idle {
+            Some(con) => con,
+            None => self.client.get_connection()?,
+        };
+        let result = f(&mut con);
+        // an error can leave keys watched
+        let reusable = result.is_ok() || redis::cmd("UNWATCH").query::<()>(&mut con).is_ok();
+        if reusable {
+            if let Ok(mut idle) = self.idle.lock() {
+                idle.push(con);
+            }
+        }
+        result
+    }
+}
+
+impl StateStore for RedisStore {
+    fn apply(&self, tx: Tx) -> Result<(), Error> {
+        let account_key = self.account_key(tx.client_id);
+        let tx_key = format!("{}tx:{}", self.prefix, tx.tx_id);
+        self.with_connection(|con| loop {
+            redis::cmd("WATCH")
+                .arg(&account_key)
+                .arg(&tx_key)
+                .query::<()>(con)?;
+            let (account, tx_state): (Option<String>, Option<String>) = redis::cmd("MGET")
+                .arg(&account_key)
+                .arg(&tx_key)
+                .query(con)?;
+
+            let mut storage = Snapshot::default();
+            if let Some(account) = account {
+                let account: ClientAccount = serde_json::from_str(&account)?;
+                storage.accounts.insert(tx.client_id, account);
+            }
+            if let Some(tx_state) = tx_state {
+                storage
+                    .tx_states
+                    .insert(tx.tx_id, serde_json::from_str(&tx_state)?);
+            }
+            let mut engine = Engine::builder()
+                .storage(storage)
+                .compat(Compat::V2)
+                .locked_policy(self.locked_policy)
+                .build()?;
+            engine.process(tx.clone())?;
+
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            if let Some(account) = engine.account(tx.client_id) {
+                let account = AccountRecord::from(account);
+                pipe.set(&account_key, serde_json::to_string(&account)?)
+                    .ignore();
+            }
+            if let Some(tx_state) = engine.tx_state(tx.tx_id) {
+                pipe.set(&tx_key, serde_json::to_string(tx_state)?).ignore();
+            }
+            // EXEC answers nil when a watched key changed
+            let committed: Option<()> = pipe.query(con)?;
+            if committed.is_some() {
+                return Ok(());
+            }
+        })
+    }
+
+    fn account(&self, client_id: ClientId) -> Result<Option<ClientAccount>, Error> {
+        let account: Option<String> = self.with_connection(|con| {
+            Ok(redis::cmd("GET")
+                .arg(self.account_key(client_id))
+                .query(con)?)
+        })?;
+        Ok(match account {
+            Some(account) => Some(serde_json::from_str::<ClientAccount>(&account)?),
+            None => None,
+        })
+    }
+
+    fn accounts(&self) -> Result<HashMap<ClientId, ClientAccount>, Error> {
+        self.with_connection(|con| {
+            let keys: Vec<String> = con
+                .scan_match(format!("{}account:*", self.prefix))?
+                .collect();
+            let mut accounts = HashMap::new();
+            for key in keys {
+                let account: Option<String> = redis::cmd("GET").arg(&key).query(con)?;
+                if let Some(account) = account {
+                    let account: ClientAccount = serde_json::from_str(&account)?;
+                    accounts.insert(account.client, account);
+                }
+            }
+            Ok(accounts)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Amount, TxType};
+
+    /// Needs a disposable Redis server, e.g.
+    /// `REDIS_URL=redis://127.0.0.1/ cargo test --features redis -- --ignored`
+    #[test]
+    #[ignore]
+    fn apply_against_redis() -> Result<(), Error> {
+        let url = std::env::var("REDIS_URL").unwrap_or("redis://127.0.0.1/".to_string());
+        let store = RedisStore::new(&url, "transaction-resolver-test:")?;
+        let client_id = 7;
+        let mut con = store.client.get_connection()?;
+        redis::cmd("DEL")
+            .arg(store.account_key(client_id))
+            .arg("transaction-resolver-test:tx:70")
+            .query::<()>(&mut con)?;
+
+        // more decimals than the CSV output keeps
+        store.apply(Tx::new(TxType::Deposit, client_id, 70).with_amount(Amount::new(3.00005)?))?;
+        store.apply(Tx::new(TxType::Dispute, client_id, 70))?;
+        let account = store.account(client_id)?.unwrap();
+        assert_eq!(
+            (account.available().value(), account.held()?.value()),
+            (0.0, 3.00005)
+        );
+        // the calls took turns on one connection
+        assert_eq!(store.idle.lock().unwrap().len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+/// Spaces out calls to `wait` to a fixed rate. Slots are computed from the
+/// start time, so time spent processing between calls doesn't add drift.
+#[derive(Debug)]
+pub struct Pacer {
+    interval: Duration,
+    start: Option<Instant>,
+    ticks: u32,
+}
+
+impl Pacer {
+    pub fn new(rate_per_sec: f64) -> Result<Self, Error> {
+        if !(rate_per_sec.is_finite() && rate_per_sec > 0.0) {
+            return Err(Error::new(&format!(
+                "Replay rate must be a positive number, got {}",
+                rate_per_sec
+            )));
+        }
+        Ok(Self {
+            interval: Duration::from_secs_f64(1.0 / rate_per_sec),
+            start: None,
+            ticks: 0,
+        })
+    }
+
+    /// Block until the next slot. The first call returns immediately.
+    pub fn wait(&mut self) {
+        let start = *self.start.get_or_insert_with(Instant::now);
+        let due = start + self.interval * self.ticks;
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+        self.ticks += 1;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn paces_calls_to_the_rate() -> Result<(), Error> {
+        let mut pacer = Pacer::new(1000.0)?;
+        let start = Instant::now();
+        for _ in 0..51 {
+            pacer.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_rate_is_error() {
+        assert!(Pacer::new(0.0).is_err());
+        assert!(Pacer::new(-1.0).is_err());
+        assert!(Pacer::new(f64::NAN).is_err());
+    }
+}
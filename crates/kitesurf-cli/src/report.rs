@@ -0,0 +1,190 @@
+use serde::Serialize;
+use std::io::Write;
+
+use crate::{
+    AuditEntry, AuditEvent, CategoryTotal, ClientId, Error, HoldReview, LedgerLine, LockLine,
+    Payout, PendingTotal, ProbeAttempt, RecoveryLine, RevenueLine, RiskFlag, SuspenseEntry, TxId,
+};
+
+/// Write `rows` as CSV, with a header from the fields of `T`.
+fn write_rows<T: Serialize>(rows: &[T], output: &mut impl Write) -> Result<(), Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b',')
+        .has_headers(true)
+        .from_writer(output);
+
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_audit(entries: &[AuditEntry], output: &mut impl Write) -> Result<(), Error> {
+    write_rows(entries, output)
+}
+
+pub fn write_category_report(
+    totals: &[CategoryTotal],
+    output: &mut impl Write,
+) -> Result<(), Error> {
+    write_rows(totals, output)
+}
+
+pub fn write_recoveries(lines: &[RecoveryLine], output: &mut impl Write) -> Result<(), Error> {
+    write_rows(lines, output)
+}
+
+pub fn write_locks(lines: &[LockLine], output: &mut impl Write) -> Result<(), Error> {
+    write_rows(lines, output)
+}
+
+pub fn write_revenue_report(lines: &[RevenueLine], output: &mut impl Write) -> Result<(), Error> {
+    write_rows(lines, output)
+}
+
+pub fn write_hold_reviews(reviews: &[HoldReview], output: &mut impl Write) -> Result<(), Error> {
+    write_rows(reviews, output)
+}
+
+pub fn write_ledger(lines: &[LedgerLine], output: &mut impl Write) -> Result<(), Error> {
+    write_rows(lines, output)
+}
+
+pub fn write_payouts(payouts: &[Payout], output: &mut impl Write) -> Result<(), Error> {
+    write_rows(payouts, output)
+}
+
+pub fn write_pending(totals: &[PendingTotal], output: &mut impl Write) -> Result<(), Error> {
+    write_rows(totals, output)
+}
+
+pub fn write_risk_report(flags: &[RiskFlag], output: &mut impl Write) -> Result<(), Error> {
+    write_rows(flags, output)
+}
+
+pub fn write_suspense(entries: &[SuspenseEntry], output: &mut impl Write) -> Result<(), Error> {
+    write_rows(entries, output)
+}
+
+/// A `ProbeAttempt`, with the number of attempts its client made.
+#[derive(Serialize)]
+struct ProbeRow {
+    index: u64,
+    event: AuditEvent,
+    client: ClientId,
+    tx: TxId,
+    owner: ClientId,
+    client_attempts: usize,
+}
+
+/// Write the cross-client `attempts`, each with the total of its client.
+pub fn write_security_report(
+    attempts: &[ProbeAttempt],
+    output: &mut impl Write,
+) -> Result<(), Error> {
+    let counts = ProbeAttempt::counts(attempts);
+    let rows: Vec<ProbeRow> = attempts
+        .iter()
+        .map(|attempt| ProbeRow {
+            index: attempt.index,
+            event: attempt.event.clone(),
+            client: attempt.client,
+            tx: attempt.tx,
+            owner: attempt.owner,
+            client_attempts: counts[&attempt.client],
+        })
+        .collect();
+    write_rows(&rows, output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CategorySpend, LedgerEvent, Tx, TxType};
+
+    #[test]
+    fn write_audit_csv() -> Result<(), Error> {
+        let mut tx = Tx::new(TxType::Withdrawal, 1, 2);
+        tx.merchant = Some("Corner Shop".to_string());
+        tx.category = Some("groceries".to_string());
+        let entries = vec![AuditEntry::new(
+            3,
+            &tx,
+            &Err(Error::new(
+                "Withdrawal transaction expected to have an amount",
+            )),
+        )];
+        let mut output: Vec<u8> = Vec::new();
+        write_audit(&entries, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "index,event,client,tx,amount,error,description,merchant,category,\
+             currency,original_amount\n\
+             3,withdrawal,1,2,,Withdrawal transaction expected to have an amount,,\
+             Corner Shop,groceries,,\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn write_category_report_csv() -> Result<(), Error> {
+        let mut spend = CategorySpend::new();
+        spend.record(2, "travel", 10.0);
+        spend.record(1, "groceries", 1.25);
+        spend.record(1, "groceries", 2.5);
+        let mut output: Vec<u8> = Vec::new();
+        write_category_report(&spend.totals(), &mut output)?;
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,category,spend\n1,groceries,3.75\n2,travel,10.0\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn write_ledger_csv() -> Result<(), Error> {
+        let line = |account: &str, debit: f64, credit: f64| LedgerLine {
+            entry: 1,
+            event: LedgerEvent::Deposit,
+            tx: Some(1),
+            client: 1,
+            account: account.to_string(),
+            debit,
+            credit,
+        };
+        let mut out = vec![];
+        write_ledger(
+            &[line("cash", 10.0, 0.0), line("client:1", 0.0, 10.0)],
+            &mut out,
+        )?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "entry,event,tx,client,account,debit,credit\n\
+             1,deposit,1,1,cash,10.0,0.0\n\
+             1,deposit,1,1,client:1,0.0,10.0\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn write_security_report_csv() -> Result<(), Error> {
+        let attempt = |index: u64, client: ClientId| ProbeAttempt {
+            index,
+            event: AuditEvent::Dispute,
+            client,
+            tx: 1,
+            owner: 9,
+        };
+        let mut out = vec![];
+        write_security_report(&[attempt(2, 3), attempt(5, 4), attempt(7, 3)], &mut out)?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "index,event,client,tx,owner,client_attempts\n\
+             2,dispute,3,1,9,2\n\
+             5,dispute,4,1,9,1\n\
+             7,dispute,3,1,9,2\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::io::csv_reader;
+use crate::{AccountRecord, ClientAccount, ClientId, Error, RoundingMode};
+
+/// Balance an `OutputField` computes with.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Operand {
+    Available,
+    Held,
+    Total,
+    Number(f64),
+}
+
+impl Operand {
+    fn value(self, account: &ClientAccount) -> f64 {
+        match self {
+            Operand::Available => account.available().value(),
+            Operand::Held => AccountRecord::from(account).held,
+            Operand::Total => account.total().value(),
+            Operand::Number(number) => number,
+        }
+    }
+}
+
+impl FromStr for Operand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "available" => Ok(Operand::Available),
+            "held" => Ok(Operand::Held),
+            "total" => Ok(Operand::Total),
+            number => number
+                .parse()
+                .map(Operand::Number)
+                .map_err(|_| Error::new(&format!("Unknown output operand: {}", number))),
+        }
+    }
+}
+
+/// What a column of an `OutputSchema` holds: a field of the account,
+/// `status` (`active`, `locked` or `closed`), or two balances or numbers
+/// combined with `+`, `-`, `*` or `/`, e.g. `total*100` for cents.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputField {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    Closed,
+    Status,
+    Computed(Operand, char, Operand),
+}
+
+impl FromStr for OutputField {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let field = match s.trim() {
+            "client" => OutputField::Client,
+            "available" => OutputField::Available,
+            "held" => OutputField::Held,
+            "total" => OutputField::Total,
+            "locked" => OutputField::Locked,
+            "closed" => OutputField::Closed,
+            "status" => OutputField::Status,
+            expression => {
+                // past the first character, which may be a sign
+                let at = expression
+                    .char_indices()
+                    .skip(1)
+                    .find(|(_, c)| matches!(c, '+' | '-' | '*' | '/'))
+                    .map(|(at, _)| at)
+                    .ok_or_else(|| Error::new(&format!("Unknown output field: {}", expression)))?;
+                let op = expression[at..].chars().next().unwrap_or('+');
+                OutputField::Computed(expression[..at].parse()?, op, expression[at + 1..].parse()?)
+            }
+        };
+        Ok(field)
+    }
+}
+
+/// Value of a cell, serialized as the default output does.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Cell {
+    Client(ClientId),
+    Amount(f64),
+    Flag(bool),
+    Text(&'static str),
+}
+
+impl OutputField {
+    fn cell(self, account: &ClientAccount, rounding: RoundingMode) -> Cell {
+        match self {
+            OutputField::Client => Cell::Client(account.client),
+            OutputField::Available => {
+                Cell::Amount(rounding.round(Operand::Available.value(account)))
+            }
+            OutputField::Held => Cell::Amount(rounding.round(Operand::Held.value(account))),
+            OutputField::Total => Cell::Amount(rounding.round(Operand::Total.value(account))),
+            OutputField::Locked => Cell::Flag(account.locked),
+            OutputField::Closed => Cell::Flag(account.closed),
+            OutputField::Status if account.closed => Cell::Text("closed"),
+            OutputField::Status if account.locked => Cell::Text("locked"),
+            OutputField::Status => Cell::Text("active"),
+            OutputField::Computed(a, op, b) => {
+                let (a, b) = (a.value(account), b.value(account));
+                let value = match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    _ => a / b,
+                };
+                Cell::Amount(rounding.round(value))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SchemaRecord {
+    column: String,
+    field: String,
+}
+
+/// Columns of the accounts output, in order, with their names and
+/// contents, see `--output-schema`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct OutputSchema {
+    columns: Vec<(String, OutputField)>,
+}
+
+impl OutputSchema {
+    pub fn new(columns: Vec<(String, OutputField)>) -> Self {
+        Self { columns }
+    }
+
+    /// Names of the columns holding the client id.
+    pub fn client_columns(&self) -> impl Iterator<Item = &str> {
+        self.columns
+            .iter()
+            .filter(|(_, field)| *field == OutputField::Client)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Write `accounts` by client, with a header of the column names.
+    pub fn write(
+        &self,
+        accounts: HashMap<ClientId, ClientAccount>,
+        rounding: RoundingMode,
+        output: &mut impl Write,
+    ) -> Result<(), Error> {
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(b',')
+            .has_headers(false)
+            .from_writer(output);
+        writer.write_record(self.columns.iter().map(|(name, _)| name))?;
+
+        let mut accounts: Vec<ClientAccount> = accounts.into_values().collect();
+        accounts.sort_by_key(|account| account.client);
+        for account in accounts {
+            let cells: Vec<Cell> = self
+                .columns
+                .iter()
+                .map(|(_, field)| field.cell(&account, rounding))
+                .collect();
+            writer.serialize(cells)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Read a `column,field` table, one row per output column in order.
+pub fn read_output_schema<R: std::io::Read>(buf: R) -> Result<OutputSchema, Error> {
+    let mut columns = vec![];
+    for result in csv_reader(buf).deserialize() {
+        let record: SchemaRecord = result?;
+        columns.push((record.column, record.field.parse()?));
+    }
+    if columns.is_empty() {
+        return Err(Error::new("Output schema without columns"));
+    }
+    Ok(OutputSchema::new(columns))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn columns_are_picked_renamed_and_computed() -> Result<(), Error> {
+        let schema = "\
+column,field
+customer_id,client
+balance_cents,total*100
+frozen,locked
+state,status
+free,available-held
+";
+        let schema = read_output_schema(schema.as_bytes())?;
+        let accounts = HashMap::from([
+            (
+                2,
+                ClientAccount::try_from(AccountRecord {
+                    client: 2,
+                    available: 1.5,
+                    held: 0.25,
+                    total: 1.75,
+                    locked: true,
+                    closed: false,
+                    lock: None,
+                })?,
+            ),
+            (1, ClientAccount::new(1)),
+        ]);
+        let mut out = vec![];
+        schema.write(accounts, RoundingMode::default(), &mut out)?;
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "customer_id,balance_cents,frozen,state,free\n\
+             1,0.0,false,active,0.0\n\
+             2,175.0,true,locked,1.25\n"
+        );
+
+        assert_eq!(
+            "-1*held".parse::<OutputField>()?,
+            OutputField::Computed(Operand::Number(-1.0), '*', Operand::Held)
+        );
+        assert!("balance".parse::<OutputField>().is_err());
+        assert!("total%2".parse::<OutputField>().is_err());
+        assert!(read_output_schema("column,field\n".as_bytes()).is_err());
+        Ok(())
+    }
+}
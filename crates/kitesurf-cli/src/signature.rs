@@ -0,0 +1,74 @@
+use minisign_verify::{PublicKey, Signature};
+use std::io::Read;
+
+use crate::{Error, ErrorCode};
+
+fn signature_error(message: &str) -> Error {
+    Error::coded(
+        ErrorCode::Signature,
+        &format!("Signature Error: {}", message),
+    )
+}
+
+/// Check `input` against a detached minisign `signature` made with the
+/// secret key of `public_key`, both given as the content of their files.
+/// The input is streamed, except for the legacy signatures of older
+/// minisign versions, which sign the whole content.
+pub fn verify_signature<R: Read>(
+    mut input: R,
+    signature: &str,
+    public_key: &str,
+) -> Result<(), Error> {
+    let public_key = PublicKey::decode(public_key)
+        .map_err(|err| signature_error(&format!("invalid public key, {}", err)))?;
+    let signature = Signature::decode(signature)
+        .map_err(|err| signature_error(&format!("invalid signature, {}", err)))?;
+    let mut verifier = match public_key.verify_stream(&signature) {
+        Ok(verifier) => verifier,
+        Err(minisign_verify::Error::UnsupportedLegacyMode) => {
+            let mut data = vec![];
+            input.read_to_end(&mut data)?;
+            return public_key
+                .verify(&data, &signature, true)
+                .map_err(|err| signature_error(&err.to_string()));
+        }
+        Err(err) => return Err(signature_error(&err.to_string())),
+    };
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let read = input.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        verifier.update(&buf[..read]);
+    }
+    verifier
+        .finalize()
+        .map_err(|err| signature_error(&err.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    fn fixture(name: &str) -> String {
+        fs::read_to_string(format!("tests/data/{}", name)).unwrap()
+    }
+
+    #[test]
+    fn signed_input_verifies() -> Result<(), Error> {
+        let (input, signature, key) = (
+            fixture("basic.csv"),
+            fixture("basic.csv.minisig"),
+            fixture("partner.pub"),
+        );
+        verify_signature(input.as_bytes(), &signature, &key)?;
+
+        let tampered = input.replacen("100.0", "900.0", 1);
+        let err = verify_signature(tampered.as_bytes(), &signature, &key).unwrap_err();
+        assert_eq!(err.code, ErrorCode::Signature);
+        assert!(verify_signature(input.as_bytes(), &signature, "not a key").is_err());
+        Ok(())
+    }
+}
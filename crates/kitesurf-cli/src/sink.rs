@@ -0,0 +1,98 @@
+use std::fs;
+use std::io::Write;
+
+use crate::{parse_s3_url, Error};
+
+/// Where a report or the account summary goes at the end of a run.
+pub trait ReportSink {
+    /// Store the complete `report`.
+    fn put(&self, report: &[u8]) -> Result<(), Error>;
+}
+
+pub struct FileSink {
+    pub path: String,
+}
+
+impl ReportSink for FileSink {
+    fn put(&self, report: &[u8]) -> Result<(), Error> {
+        fs::write(&self.path, report)
+            .map_err(|err| Error::new(&format!("Unable to write {}: {}", self.path, err)))
+    }
+}
+
+pub struct StdoutSink;
+
+impl ReportSink for StdoutSink {
+    fn put(&self, report: &[u8]) -> Result<(), Error> {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(report)?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// POSTs the report as `text/csv`. Anything but a 2xx response is an error.
+#[cfg(feature = "http")]
+pub struct HttpSink {
+    pub url: String,
+}
+
+#[cfg(feature = "http")]
+impl ReportSink for HttpSink {
+    fn put(&self, report: &[u8]) -> Result<(), Error> {
+        ureq::post(&self.url)
+            .set("Content-Type", "text/csv")
+            .send_bytes(report)
+            .map_err(|err| Error::new(&format!("Unable to post to {}: {}", self.url, err)))?;
+        Ok(())
+    }
+}
+
+/// Uploads the report, replacing the object if it exists.
+#[cfg(feature = "s3")]
+pub struct S3Sink {
+    pub bucket: String,
+    pub key: String,
+}
+
+#[cfg(feature = "s3")]
+impl ReportSink for S3Sink {
+    fn put(&self, report: &[u8]) -> Result<(), Error> {
+        use object_store::ObjectStore;
+
+        let payload = object_store::PutPayload::from(report.to_vec());
+        crate::source::with_s3_object(&self.bucket, &self.key, |store, path| async move {
+            store.put(&path, payload).await
+        })?;
+        Ok(())
+    }
+}
+
+/// Sink for an output argument, with the same forms as `source`: `-` for
+/// stdout, an `http(s)://` URL to POST to, `s3://bucket/key`, or a file path.
+pub fn sink(target: &str) -> Result<Box<dyn ReportSink>, Error> {
+    if target == "-" {
+        return Ok(Box::new(StdoutSink));
+    }
+    if target.starts_with("s3://") {
+        let (_bucket, _key) = parse_s3_url(target)?;
+        #[cfg(feature = "s3")]
+        return Ok(Box::new(S3Sink {
+            bucket: _bucket,
+            key: _key,
+        }));
+        #[cfg(not(feature = "s3"))]
+        return Err(Error::new("s3:// outputs need the s3 feature"));
+    }
+    if target.starts_with("http://") || target.starts_with("https://") {
+        #[cfg(feature = "http")]
+        return Ok(Box::new(HttpSink {
+            url: target.to_string(),
+        }));
+        #[cfg(not(feature = "http"))]
+        return Err(Error::new("http(s):// outputs need the http feature"));
+    }
+    Ok(Box::new(FileSink {
+        path: target.to_string(),
+    }))
+}
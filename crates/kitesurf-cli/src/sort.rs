@@ -0,0 +1,304 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::Error;
+
+/// Rows `ExternalSort` holds in memory by default.
+pub const SORT_CHUNK_ROWS: usize = 100_000;
+
+/// Orders the rows of a CSV input by some of its columns before it's read,
+/// for inputs that arrive unsorted but carry e.g. a `timestamp`. Rows are
+/// sorted in chunks of `chunk_rows`, spilled to temporary files and merged,
+/// so memory stays bounded whatever the size of the input. Values that
+/// parse as numbers are compared as numbers, others byte by byte (which
+/// orders ISO 8601 timestamps), and rows with equal keys keep their input
+/// order.
+#[derive(Debug, Clone)]
+pub struct ExternalSort {
+    keys: Vec<String>,
+    chunk_rows: usize,
+    dir: PathBuf,
+}
+
+impl ExternalSort {
+    pub fn new(keys: Vec<String>) -> Self {
+        Self {
+            keys,
+            chunk_rows: SORT_CHUNK_ROWS,
+            dir: std::env::temp_dir(),
+        }
+    }
+
+    pub fn with_chunk_rows(mut self, chunk_rows: usize) -> Self {
+        self.chunk_rows = chunk_rows.max(1);
+        self
+    }
+
+    /// Directory the temporary files are created in, the system's one by
+    /// default.
+    pub fn with_dir(mut self, dir: PathBuf) -> Self {
+        self.dir = dir;
+        self
+    }
+
+    /// Sort `input`, header first, into a temporary file read back by the
+    /// returned `SortedInput`.
+    pub fn sort<R: Read>(&self, input: R) -> Result<SortedInput, Error> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.subsec_nanos());
+        let dir = self
+            .dir
+            .join(format!("kitesurf-sort-{}-{}", std::process::id(), nanos));
+        fs::create_dir_all(&dir)?;
+        // removes the directory if sorting fails
+        let mut sorted = SortedInput { file: None, dir };
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .from_reader(input);
+        let headers = reader.byte_headers()?.clone();
+        let keys = self
+            .keys
+            .iter()
+            .map(|key| {
+                headers
+                    .iter()
+                    .position(|header| column_name(header).eq_ignore_ascii_case(key.trim()))
+                    .ok_or_else(|| Error::new(&format!("Unknown sort column: {}", key)))
+            })
+            .collect::<Result<Vec<usize>, Error>>()?;
+
+        let mut runs = vec![];
+        let mut chunk: Vec<(u64, csv::ByteRecord)> = Vec::new();
+        let mut index = 0;
+        let mut record = csv::ByteRecord::new();
+        while reader.read_byte_record(&mut record)? {
+            chunk.push((index, record.clone()));
+            index += 1;
+            if chunk.len() == self.chunk_rows {
+                runs.push(sorted.spill(&mut chunk, &keys, runs.len())?);
+            }
+        }
+        if !chunk.is_empty() {
+            runs.push(sorted.spill(&mut chunk, &keys, runs.len())?);
+        }
+
+        let path = sorted.dir.join("sorted.csv");
+        let mut writer = csv::WriterBuilder::new()
+            .flexible(true)
+            .from_writer(BufWriter::new(fs::File::create(&path)?));
+        writer.write_byte_record(&headers)?;
+        let mut readers = runs
+            .iter()
+            .map(|run| {
+                Ok(csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .from_reader(BufReader::new(fs::File::open(run)?)))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut heads = BinaryHeap::new();
+        for (run, reader) in readers.iter_mut().enumerate() {
+            if let Some(head) = RunHead::read(reader, run, &keys)? {
+                heads.push(head);
+            }
+        }
+        while let Some(head) = heads.pop() {
+            writer.write_byte_record(&head.record)?;
+            if let Some(next) = RunHead::read(&mut readers[head.run], head.run, &keys)? {
+                heads.push(next);
+            }
+        }
+        writer.flush()?;
+        drop(writer);
+        for run in runs {
+            fs::remove_file(run)?;
+        }
+        sorted.file = Some(BufReader::new(fs::File::open(path)?));
+        Ok(sorted)
+    }
+}
+
+/// Header without a UTF-8 byte order mark and surrounding spaces.
+fn column_name(header: &[u8]) -> &str {
+    let header = header.strip_prefix(b"\xef\xbb\xbf").unwrap_or(header);
+    std::str::from_utf8(header).unwrap_or_default().trim()
+}
+
+fn compare_keys(keys: &[usize], a: &csv::ByteRecord, b: &csv::ByteRecord) -> Ordering {
+    let number = |field: &[u8]| -> Option<f64> { std::str::from_utf8(field).ok()?.parse().ok() };
+    for &at in keys {
+        let a = a.get(at).unwrap_or_default().trim_ascii();
+        let b = b.get(at).unwrap_or_default().trim_ascii();
+        let ordering = match (number(a), number(b)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            _ => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Next row of a sorted run, ordered for the min-heap merging the runs.
+struct RunHead {
+    keys: Vec<usize>,
+    index: u64,
+    run: usize,
+    record: csv::ByteRecord,
+}
+
+impl RunHead {
+    fn read<R: Read>(
+        reader: &mut csv::Reader<R>,
+        run: usize,
+        keys: &[usize],
+    ) -> Result<Option<Self>, Error> {
+        let mut record = csv::ByteRecord::new();
+        if !reader.read_byte_record(&mut record)? {
+            return Ok(None);
+        }
+        // runs lead each row with its index in the input
+        let index = std::str::from_utf8(record.get(0).unwrap_or_default())
+            .ok()
+            .and_then(|index| index.parse().ok())
+            .ok_or_else(|| Error::new("Corrupt sort run"))?;
+        Ok(Some(Self {
+            keys: keys.to_vec(),
+            index,
+            run,
+            record: record.iter().skip(1).collect(),
+        }))
+    }
+}
+
+impl Ord for RunHead {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed, BinaryHeap pops the largest
+        compare_keys(&self.keys, &other.record, &self.record)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for RunHead {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RunHead {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RunHead {}
+
+/// Output of `ExternalSort::sort`, deleting its temporary files once
+/// dropped.
+#[derive(Debug)]
+pub struct SortedInput {
+    file: Option<BufReader<fs::File>>,
+    dir: PathBuf,
+}
+
+impl SortedInput {
+    /// Sort `chunk` and write it to the `run`th temporary file, each row led
+    /// by its index in the input.
+    fn spill(
+        &self,
+        chunk: &mut Vec<(u64, csv::ByteRecord)>,
+        keys: &[usize],
+        run: usize,
+    ) -> Result<PathBuf, Error> {
+        chunk.sort_by(|(a_index, a), (b_index, b)| {
+            compare_keys(keys, a, b).then_with(|| a_index.cmp(b_index))
+        });
+        let path = self.dir.join(format!("run-{}.csv", run));
+        let mut writer = csv::WriterBuilder::new()
+            .flexible(true)
+            .from_writer(BufWriter::new(fs::File::create(&path)?));
+        for (index, record) in chunk.drain(..) {
+            let index = index.to_string();
+            let row = std::iter::once(index.as_bytes()).chain(record.iter());
+            writer.write_record(row)?;
+        }
+        writer.flush()?;
+        Ok(path)
+    }
+}
+
+impl Read for SortedInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.file.as_mut() {
+            Some(file) => file.read(buf),
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for SortedInput {
+    fn drop(&mut self) {
+        self.file = None;
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sort(input: &str, keys: &[&str], chunk_rows: usize) -> Result<String, Error> {
+        let keys = keys.iter().map(|key| key.to_string()).collect();
+        let mut sorted = ExternalSort::new(keys)
+            .with_chunk_rows(chunk_rows)
+            .sort(input.as_bytes())?;
+        let mut output = String::new();
+        sorted.read_to_string(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn rows_are_merged_in_key_order() -> Result<(), Error> {
+        let input = "type,client,tx,amount,timestamp\n\
+                     withdrawal,1,3,1.0,2024-01-03T10:00:00Z\n\
+                     deposit,1,1,2.0,2024-01-01T10:00:00Z\n\
+                     deposit,2,4,\"1,5\",2024-01-02T10:00:00Z\n\
+                     deposit,1,2,3.0,2024-01-01T10:00:00Z\n\
+                     dispute,1,1,,2024-01-02T09:00:00Z\n";
+        let expected = "type,client,tx,amount,timestamp\n\
+                        deposit,1,1,2.0,2024-01-01T10:00:00Z\n\
+                        deposit,1,2,3.0,2024-01-01T10:00:00Z\n\
+                        dispute,1,1,,2024-01-02T09:00:00Z\n\
+                        deposit,2,4,\"1,5\",2024-01-02T10:00:00Z\n\
+                        withdrawal,1,3,1.0,2024-01-03T10:00:00Z\n";
+        for chunk_rows in [1, 2, 100] {
+            assert_eq!(sort(input, &["timestamp"], chunk_rows)?, expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn numbers_are_compared_as_numbers() -> Result<(), Error> {
+        let input = "type,client,tx,amount,seq\n\
+                     deposit,2,1,1.0,10\n\
+                     deposit,1,2,1.0,9\n\
+                     deposit,1,3,1.0,10\n";
+        assert_eq!(
+            sort(input, &["client", "seq"], 2)?,
+            "type,client,tx,amount,seq\n\
+             deposit,1,2,1.0,9\n\
+             deposit,1,3,1.0,10\n\
+             deposit,2,1,1.0,10\n"
+        );
+        assert!(sort(input, &["timestamp"], 2).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::Read;
+
+use crate::{open_file, Error};
+
+/// Where the input transactions come from.
+pub trait TxSource {
+    /// Open the input for reading, from the start.
+    fn open(&self) -> Result<Box<dyn Read>, Error>;
+}
+
+pub struct FileSource {
+    pub path: String,
+}
+
+impl TxSource for FileSource {
+    fn open(&self) -> Result<Box<dyn Read>, Error> {
+        Ok(Box::new(open_file(&self.path)?))
+    }
+}
+
+pub struct StdinSource;
+
+impl TxSource for StdinSource {
+    fn open(&self) -> Result<Box<dyn Read>, Error> {
+        Ok(Box::new(std::io::stdin()))
+    }
+}
+
+/// Streams the body of a GET request.
+#[cfg(feature = "http")]
+pub struct HttpSource {
+    pub url: String,
+}
+
+#[cfg(feature = "http")]
+impl TxSource for HttpSource {
+    fn open(&self) -> Result<Box<dyn Read>, Error> {
+        let response = ureq::get(&self.url)
+            .call()
+            .map_err(|err| Error::new(&format!("Unable to fetch {}: {}", self.url, err)))?;
+        Ok(Box::new(response.into_reader()))
+    }
+}
+
+/// Downloads the object into memory.
+#[cfg(feature = "s3")]
+pub struct S3Source {
+    pub bucket: String,
+    pub key: String,
+}
+
+#[cfg(feature = "s3")]
+impl TxSource for S3Source {
+    fn open(&self) -> Result<Box<dyn Read>, Error> {
+        use object_store::ObjectStore;
+
+        let bytes = with_s3_object(&self.bucket, &self.key, |store, path| async move {
+            store.get(&path).await?.bytes().await
+        })?;
+        Ok(Box::new(std::io::Cursor::new(bytes)))
+    }
+}
+
+/// Run `operation` on the object at `key` in `bucket` to completion, with
+/// credentials and region from the `AWS_*` environment variables.
+#[cfg(feature = "s3")]
+pub(crate) fn with_s3_object<T, F>(
+    bucket: &str,
+    key: &str,
+    operation: impl FnOnce(object_store::aws::AmazonS3, object_store::path::Path) -> F,
+) -> Result<T, Error>
+where
+    F: std::future::Future<Output = Result<T, object_store::Error>>,
+{
+    let s3_error =
+        |err: object_store::Error| Error::new(&format!("s3://{}/{}: {}", bucket, key, err));
+    let store = object_store::aws::AmazonS3Builder::from_env()
+        .with_bucket_name(bucket)
+        .build()
+        .map_err(s3_error)?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime
+        .block_on(operation(store, object_store::path::Path::from(key)))
+        .map_err(s3_error)
+}
+
+/// Split `s3://bucket/key` into its bucket and key.
+pub fn parse_s3_url(url: &str) -> Result<(String, String), Error> {
+    url.strip_prefix("s3://")
+        .and_then(|location| location.split_once('/'))
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+        .map(|(bucket, key)| (bucket.to_string(), key.to_string()))
+        .ok_or_else(|| Error::new(&format!("Expected s3://bucket/key, got {}", url)))
+}
+
+/// Source for an input argument: `-` for stdin, an `http(s)://` URL (with
+/// the `http` feature), `s3://bucket/key` (with the `s3` feature), or else a
+/// file path.
+pub fn source(input: &str) -> Result<Box<dyn TxSource>, Error> {
+    if input == "-" {
+        return Ok(Box::new(StdinSource));
+    }
+    if input.starts_with("s3://") {
+        let (_bucket, _key) = parse_s3_url(input)?;
+        #[cfg(feature = "s3")]
+        return Ok(Box::new(S3Source {
+            bucket: _bucket,
+            key: _key,
+        }));
+        #[cfg(not(feature = "s3"))]
+        return Err(Error::new("s3:// inputs need the s3 feature"));
+    }
+    if input.starts_with("http://") || input.starts_with("https://") {
+        #[cfg(feature = "http")]
+        return Ok(Box::new(HttpSource {
+            url: input.to_string(),
+        }));
+        #[cfg(not(feature = "http"))]
+        return Err(Error::new("http(s):// inputs need the http feature"));
+    }
+    Ok(Box::new(FileSource {
+        path: input.to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_s3_urls() -> Result<(), Error> {
+        assert_eq!(
+            parse_s3_url("s3://bucket/daily/txs.csv")?,
+            ("bucket".to_string(), "daily/txs.csv".to_string())
+        );
+        assert!(parse_s3_url("s3://bucket").is_err());
+        assert!(parse_s3_url("s3:///txs.csv").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn file_source_reads_the_file() -> Result<(), Error> {
+        let mut content = String::new();
+        source("tests/data/basic.csv")?
+            .open()?
+            .read_to_string(&mut content)?;
+        assert!(content.starts_with("type,"));
+        assert!(source("tests/data/missing.csv")?.open().is_err());
+        Ok(())
+    }
+}
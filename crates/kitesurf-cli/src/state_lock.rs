@@ -0,0 +1,114 @@
+use std::fs::{self, File, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::{Error, ErrorCode};
+
+/// Lock file created in each state directory.
+const LOCK_FILE: &str = ".kitesurf.lock";
+
+/// Advisory lock on a directory holding persistent state (checkpoints,
+/// snapshots, the state of `watch`), held until dropped. A second run
+/// locking the same directory, e.g. a cron job started before the previous
+/// one finished, fails right away instead of interleaving its writes. The
+/// lock is released by the OS when the process exits, even if it crashes,
+/// so a stale lock file doesn't block the next run.
+#[derive(Debug)]
+pub struct StateLock {
+    dir: PathBuf,
+    // locked for as long as it's open
+    _file: File,
+}
+
+impl StateLock {
+    /// Lock `dir`, failing with `ErrorCode::StateLocked` if another process
+    /// holds it.
+    pub fn acquire(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(LOCK_FILE);
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                // written by the holder, only there to help whoever reads this
+                let holder = match fs::read_to_string(&path) {
+                    Ok(pid) if !pid.trim().is_empty() => format!(" (pid {})", pid.trim()),
+                    _ => String::new(),
+                };
+                return Err(Error::coded(
+                    ErrorCode::StateLocked,
+                    &format!(
+                        "State directory {} is in use by another run{}",
+                        dir.display(),
+                        holder
+                    ),
+                ));
+            }
+            Err(TryLockError::Error(err)) => return Err(err.into()),
+        }
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            _file: file,
+        })
+    }
+
+    /// Lock the directory of each of `files`, once per directory.
+    pub fn acquire_for<'a>(files: impl IntoIterator<Item = &'a str>) -> Result<Vec<Self>, Error> {
+        let mut locks: Vec<Self> = vec![];
+        for file in files {
+            let dir = match Path::new(file).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            let dir = dir.canonicalize()?;
+            if !locks.iter().any(|lock| lock.dir == dir) {
+                locks.push(Self::acquire(&dir)?);
+            }
+        }
+        Ok(locks)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn second_lock_fails_until_the_first_is_dropped() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("state-lock-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let lock = StateLock::acquire(&dir)?;
+        let err = StateLock::acquire(&dir).unwrap_err();
+        assert_eq!(err.code, ErrorCode::StateLocked);
+        assert!(
+            err.message.ends_with(&format!(
+                "in use by another run (pid {})",
+                std::process::id()
+            )),
+            "{}",
+            err.message
+        );
+        // files of the same directory take a single lock
+        let file = dir.join("checkpoint.json");
+        let err = StateLock::acquire_for([file.to_str().unwrap()]).unwrap_err();
+        assert_eq!(err.code, ErrorCode::StateLocked);
+
+        drop(lock);
+        let locks = StateLock::acquire_for([file.to_str().unwrap(), file.to_str().unwrap()])?;
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].dir(), dir.canonicalize()?);
+        drop(locks);
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::Write;
+
+use crate::generate::Rng;
+use crate::{
+    generate, process_parallel, read_csv, ClientId, Compat, Engine, Error, ErrorCode,
+    GeneratorConfig, Rejection,
+};
+
+/// Settings of the `stress` subcommand.
+#[derive(Debug, PartialEq, Clone)]
+pub struct StressConfig {
+    /// Seed of the first run, each next run taking the next one.
+    pub seed: u64,
+    pub runs: usize,
+    /// Transactions per run.
+    pub rows: usize,
+    /// Most clients in a run.
+    pub clients: ClientId,
+    /// Threads of the parallel engine compared with the serial one.
+    pub threads: usize,
+}
+
+impl Default for StressConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            runs: 100,
+            rows: 1000,
+            clients: 20,
+            threads: 4,
+        }
+    }
+}
+
+fn engine() -> Engine {
+    Engine::new()
+        .with_compat(Compat::V2)
+        .with_audit()
+        .with_suspense()
+        .with_last_activity()
+        .with_client_stats()
+}
+
+/// Generate the transactions of one run from `seed`, with client count and
+/// dispute and fraud rates drawn from it too, and check the result of
+/// processing them: the invariants of `Engine::check_invariants` hold, and
+/// `process_parallel` ends with the same state, reports and rejections as
+/// processing them one by one.
+pub fn stress_run(config: &StressConfig, seed: u64) -> Result<(), Error> {
+    let mut rng = Rng(seed);
+    #[allow(clippy::unnecessary_cast)] // ClientId is u64 with wide-client-ids
+    let clients = rng.below(config.clients as u64) as ClientId + 1;
+    let generator = GeneratorConfig {
+        seed,
+        clients,
+        rows: config.rows,
+        dispute_rate: rng.below(30) as f64 / 100.0,
+        fraud_rate: rng.below(5) as f64 / 100.0,
+    };
+    let mut csv = vec![];
+    generate(&generator, &mut csv)?;
+    let txs = read_csv(csv.as_slice())?;
+
+    let mut serial = engine();
+    let mut rejections = vec![];
+    for (index, tx) in (1..).zip(&txs) {
+        if let Err(error) = serial.process(tx.clone()) {
+            rejections.push(Rejection {
+                index,
+                client: tx.client_id,
+                tx: tx.tx_id,
+                error,
+            });
+        }
+    }
+    serial.check_invariants()?;
+
+    let parallel = process_parallel(engine(), txs, config.threads)?;
+    let differs = |what: &str| Err(Error::new(&format!("Parallel engine differs in {}", what)));
+    if parallel.rejections != rejections {
+        return differs("rejections");
+    }
+    if parallel.engine.snapshot() != serial.snapshot() {
+        return differs("accounts or transaction states");
+    }
+    if parallel.engine.audit_trail() != serial.audit_trail() {
+        return differs("audit trail");
+    }
+    if parallel.engine.suspense_ledger() != serial.suspense_ledger() {
+        return differs("suspense ledger");
+    }
+    if parallel.engine.last_activity() != serial.last_activity() {
+        return differs("last activity");
+    }
+    if parallel.engine.client_stats() != serial.client_stats() {
+        return differs("client stats");
+    }
+    Ok(())
+}
+
+/// `stress_run` for `config.runs` seeds in a row, writing a line per run to
+/// `progress`. Stops at the first failing run, with an error naming its
+/// seed, which `--seed <seed> --runs 1` reproduces.
+pub fn stress(config: &StressConfig, progress: &mut impl Write) -> Result<(), Error> {
+    for seed in (config.seed..).take(config.runs) {
+        if let Err(err) = stress_run(config, seed) {
+            return Err(Error::coded(
+                ErrorCode::Invariants,
+                &format!(
+                    "Stress run with seed {} failed: {}; reproduce with `stress --seed {} --runs 1`",
+                    seed, err.message, seed
+                ),
+            ));
+        }
+        writeln!(progress, "seed {}: ok", seed)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn runs_pass_and_print_their_seeds() -> Result<(), Error> {
+        let config = StressConfig {
+            seed: 7,
+            runs: 3,
+            rows: 300,
+            ..StressConfig::default()
+        };
+        let mut progress = vec![];
+        stress(&config, &mut progress)?;
+        assert_eq!(
+            String::from_utf8(progress).unwrap(),
+            "seed 7: ok\nseed 8: ok\nseed 9: ok\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::{
+    AccountRecord, ClientAccount, ClientId, Compat, Engine, Error, LockedPolicy, Snapshot,
+    StateStore, Tx, TxId, TxState,
+};
+
+/// Accounts in memory, with when they were last used.
+#[derive(Default)]
+struct Hot {
+    accounts: HashMap<ClientId, (ClientAccount, u64)>,
+    /// Clients by the tick they were last used, least recent first.
+    by_use: BTreeMap<u64, ClientId>,
+    tick: u64,
+    tx_states: HashMap<TxId, TxState>,
+}
+
+impl Hot {
+    fn touch(&mut self, client_id: ClientId, account: ClientAccount) {
+        self.tick += 1;
+        if let Some((_, used)) = self.accounts.insert(client_id, (account, self.tick)) {
+            self.by_use.remove(&used);
+        }
+        self.by_use.insert(self.tick, client_id);
+    }
+
+    /// Take out the least recently used account.
+    fn pop_dormant(&mut self) -> Option<ClientAccount> {
+        let (_, client_id) = self.by_use.pop_first()?;
+        self.accounts.remove(&client_id).map(|(account, _)| account)
+    }
+}
+
+/// `StateStore` keeping at most `capacity` accounts in memory, the most
+/// recently used ones. Dormant accounts are spilled to a sled database on
+/// disk and loaded back when a transaction touches them, so runs with many
+/// more clients than fit in memory need a bounded amount of it for
+/// accounts. Transaction states, needed for disputes, stay in memory.
+///
+/// Transactions are applied one at a time, under a single lock.
+pub struct TieredStore {
+    hot: Mutex<Hot>,
+    cold: sled::Db,
+    capacity: usize,
+    locked_policy: LockedPolicy,
+}
+
+impl std::fmt::Debug for TieredStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TieredStore")
+            .field("capacity", &self.capacity)
+            .field("cold", &self.cold.len())
+            .finish()
+    }
+}
+
+impl TieredStore {
+    /// Spill to a database in the `path` directory, cleared first: it only
+    /// holds the accounts of this store.
+    pub fn open(path: impl AsRef<Path>, capacity: usize) -> Result<Self, Error> {
+        let cold = sled::open(path)?;
+        cold.clear()?;
+        Ok(Self::with_db(cold, capacity))
+    }
+
+    /// Spill to a temporary database, removed when the store is dropped.
+    pub fn temporary(capacity: usize) -> Result<Self, Error> {
+        Ok(Self::with_db(
+            sled::Config::new().temporary(true).open()?,
+            capacity,
+        ))
+    }
+
+    fn with_db(cold: sled::Db, capacity: usize) -> Self {
+        Self {
+            hot: Mutex::new(Hot::default()),
+            cold,
+            capacity: capacity.max(1),
+            locked_policy: LockedPolicy::default(),
+        }
+    }
+
+    pub fn with_locked_policy(mut self, locked_policy: LockedPolicy) -> Self {
+        self.locked_policy = locked_policy;
+        self
+    }
+
+    /// Number of accounts spilled to disk.
+    pub fn cold_len(&self) -> usize {
+        self.cold.len()
+    }
+
+    fn hot(&self) -> Result<MutexGuard<'_, Hot>, Error> {
+        self.hot
+            .lock()
+            .map_err(|_| Error::new("Tiered store lock poisoned"))
+    }
+
+    fn load(&self, client_id: ClientId) -> Result<Option<ClientAccount>, Error> {
+        Ok(match self.cold.get(key(client_id))? {
+            Some(account) => Some(serde_json::from_slice::<ClientAccount>(&account)?),
+            None => None,
+        })
+    }
+
+    /// Spill the least recently used accounts until `capacity` are left.
+    fn evict(&self, hot: &mut Hot) -> Result<(), Error> {
+        while hot.accounts.len() > self.capacity {
+            if let Some(account) = hot.pop_dormant() {
+                self.cold.insert(
+                    key(account.client),
+                    serde_json::to_vec(&AccountRecord::from(&account))?,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Big-endian, so the database iterates in client order.
+#[allow(clippy::unnecessary_cast)] // ClientId is u64 with wide-client-ids
+fn key(client_id: ClientId) -> [u8; 8] {
+    (client_id as u64).to_be_bytes()
+}
+
+impl StateStore for TieredStore {
+    fn apply(&self, tx: Tx) -> Result<(), Error> {
+        let mut hot = self.hot()?;
+        let client_id = tx.client_id;
+        let tx_id = tx.tx_id;
+        let mut storage = Snapshot::default();
+        let mut from_cold = false;
+        match hot.accounts.get(&client_id) {
+            Some((account, _)) => {
+                storage.accounts.insert(client_id, account.clone());
+            }
+            None => {
+                if let Some(account) = self.load(client_id)? {
+                    storage.accounts.insert(client_id, account);
+                    from_cold = true;
+                }
+            }
+        }
+        if let Some(tx_state) = hot.tx_states.get(&tx_id) {
+            storage.tx_states.insert(tx_id, tx_state.clone());
+        }
+
+        let mut engine = Engine::builder()
+            .storage(storage)
+            .compat(Compat::V2)
+            .locked_policy(self.locked_policy)
+            .build()?;
+        engine.process(tx)?;
+
+        if let Some(tx_state) = engine.tx_state(tx_id) {
+            hot.tx_states.insert(tx_id, tx_state.clone());
+        }
+        if let Some(account) = engine.into_accounts().remove(&client_id) {
+            hot.touch(client_id, account);
+            if from_cold {
+                self.cold.remove(key(client_id))?;
+            }
+        }
+        self.evict(&mut hot)
+    }
+
+    fn account(&self, client_id: ClientId) -> Result<Option<ClientAccount>, Error> {
+        if let Some((account, _)) = self.hot()?.accounts.get(&client_id) {
+            return Ok(Some(account.clone()));
+        }
+        self.load(client_id)
+    }
+
+    fn accounts(&self) -> Result<HashMap<ClientId, ClientAccount>, Error> {
+        let hot = self.hot()?;
+        let mut accounts: HashMap<ClientId, ClientAccount> = hot
+            .accounts
+            .iter()
+            .map(|(client_id, (account, _))| (*client_id, account.clone()))
+            .collect();
+        for entry in self.cold.iter() {
+            let (_, account) = entry?;
+            let account: ClientAccount = serde_json::from_slice(&account)?;
+            accounts.insert(account.client, account);
+        }
+        Ok(accounts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::TxBuilder;
+
+    #[test]
+    fn dormant_accounts_are_spilled_and_reloaded() -> Result<(), Error> {
+        let store = TieredStore::temporary(2)?;
+        for (client_id, tx_id) in (1..=5).zip(1..) {
+            store.apply(TxBuilder::deposit(client_id).tx(tx_id).amount(2.0).build())?;
+        }
+        assert_eq!(store.cold_len(), 3);
+        assert_eq!(store.account(1)?.unwrap().total().value(), 2.0);
+
+        // client 1 is loaded back, pushing out client 4
+        store.apply(TxBuilder::dispute(1).tx(1).build())?;
+        assert_eq!(store.cold_len(), 3);
+        let account = store.account(1)?.unwrap();
+        assert_eq!(
+            (account.available().value(), account.held()?.value()),
+            (0.0, 2.0)
+        );
+
+        // a rejected transaction leaves the spilled account in place
+        assert!(store.apply(TxBuilder::withdrawal(2).tx(6).build()).is_err());
+        assert_eq!(store.cold_len(), 3);
+
+        let accounts = store.accounts()?;
+        assert_eq!(accounts.len(), 5);
+        assert!(accounts
+            .values()
+            .all(|account| account.total().value() == 2.0));
+        Ok(())
+    }
+}
@@ -0,0 +1,379 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::HashMap;
+
+use crate::{AccountRecord, ClientAccount, ClientId, Error, Snapshot, TxId, TxState};
+
+/// Column the accounts of an `AccountView` are sorted by.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum SortKey {
+    #[default]
+    Client,
+    Available,
+    Held,
+    Total,
+}
+
+impl SortKey {
+    fn next(self) -> Self {
+        match self {
+            SortKey::Client => SortKey::Available,
+            SortKey::Available => SortKey::Held,
+            SortKey::Held => SortKey::Total,
+            SortKey::Total => SortKey::Client,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SortKey::Client => "client",
+            SortKey::Available => "available",
+            SortKey::Held => "held",
+            SortKey::Total => "total",
+        }
+    }
+}
+
+/// Accounts an `AccountView` lists.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum ViewFilter {
+    #[default]
+    All,
+    Locked,
+    /// With held funds, i.e. open disputes.
+    Held,
+    /// With negative available funds or total.
+    Negative,
+}
+
+impl ViewFilter {
+    fn next(self) -> Self {
+        match self {
+            ViewFilter::All => ViewFilter::Locked,
+            ViewFilter::Locked => ViewFilter::Held,
+            ViewFilter::Held => ViewFilter::Negative,
+            ViewFilter::Negative => ViewFilter::All,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            ViewFilter::All => "all",
+            ViewFilter::Locked => "locked",
+            ViewFilter::Held => "held",
+            ViewFilter::Negative => "negative",
+        }
+    }
+
+    fn matches(self, account: &ClientAccount) -> bool {
+        match self {
+            ViewFilter::All => true,
+            ViewFilter::Locked => account.locked,
+            ViewFilter::Held => AccountRecord::from(account).held != 0.0,
+            ViewFilter::Negative => {
+                account.available().value() < 0.0 || account.total().value() < 0.0
+            }
+        }
+    }
+}
+
+/// State of the `view` subcommand: the accounts of a snapshot or output
+/// file, browsed with the keyboard. `s` cycles the sort column and `r`
+/// reverses it, `f` cycles the filter, `/` searches client ids, Enter
+/// shows the transactions of the selected client and `q` quits.
+#[derive(Debug, Default)]
+pub struct AccountView {
+    accounts: Vec<ClientAccount>,
+    /// Transactions the snapshot still knows about, per client, by id.
+    /// Empty for output files.
+    history: HashMap<ClientId, Vec<(TxId, TxState)>>,
+    pub sort: SortKey,
+    pub descending: bool,
+    pub filter: ViewFilter,
+    /// Part of the client ids listed.
+    pub search: String,
+    searching: bool,
+    selected: usize,
+    /// Client whose transactions are shown.
+    detail: Option<ClientId>,
+}
+
+impl AccountView {
+    pub fn new(snapshot: Snapshot) -> Self {
+        let mut history: HashMap<ClientId, Vec<(TxId, TxState)>> = HashMap::new();
+        for (tx_id, state) in snapshot.tx_states {
+            history
+                .entry(state.client_id)
+                .or_default()
+                .push((tx_id, state));
+        }
+        for txs in history.values_mut() {
+            txs.sort_by_key(|(tx_id, _)| *tx_id);
+        }
+        Self {
+            accounts: snapshot.accounts.into_values().collect(),
+            history,
+            ..Self::default()
+        }
+    }
+
+    /// Accounts matching the filter and search, in display order.
+    pub fn visible(&self) -> Vec<&ClientAccount> {
+        let mut accounts: Vec<&ClientAccount> = self
+            .accounts
+            .iter()
+            .filter(|account| self.filter.matches(account))
+            .filter(|account| account.client.to_string().contains(&self.search))
+            .collect();
+        let key = |account: &ClientAccount| match self.sort {
+            SortKey::Client => account.client as f64,
+            SortKey::Available => account.available().value(),
+            SortKey::Held => AccountRecord::from(account).held,
+            SortKey::Total => account.total().value(),
+        };
+        accounts.sort_by(|a, b| {
+            key(a)
+                .total_cmp(&key(b))
+                .then_with(|| a.client.cmp(&b.client))
+        });
+        if self.descending {
+            accounts.reverse();
+        }
+        accounts
+    }
+
+    pub fn selected(&self) -> Option<ClientId> {
+        self.visible()
+            .get(self.selected)
+            .map(|account| account.client)
+    }
+
+    pub fn history(&self, client: ClientId) -> &[(TxId, TxState)] {
+        self.history.get(&client).map_or(&[], Vec::as_slice)
+    }
+
+    /// Apply a key press, false once the viewer should quit.
+    pub fn handle_key(&mut self, key: KeyCode) -> bool {
+        if self.searching {
+            match key {
+                KeyCode::Char(c) => self.search.push(c),
+                KeyCode::Backspace => {
+                    self.search.pop();
+                }
+                KeyCode::Esc => {
+                    self.search.clear();
+                    self.searching = false;
+                }
+                KeyCode::Enter => self.searching = false,
+                _ => {}
+            }
+            self.selected = 0;
+            return true;
+        }
+        if self.detail.is_some() {
+            match key {
+                KeyCode::Char('q') => return false,
+                KeyCode::Esc | KeyCode::Enter | KeyCode::Backspace => self.detail = None,
+                _ => {}
+            }
+            return true;
+        }
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Down | KeyCode::Char('j') => {
+                let last = self.visible().len().saturating_sub(1);
+                self.selected = (self.selected + 1).min(last);
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('s') => self.sort = self.sort.next(),
+            KeyCode::Char('r') => self.descending = !self.descending,
+            KeyCode::Char('f') => {
+                self.filter = self.filter.next();
+                self.selected = 0;
+            }
+            KeyCode::Char('/') => self.searching = true,
+            KeyCode::Enter => self.detail = self.selected(),
+            _ => {}
+        }
+        true
+    }
+
+    pub fn draw(&self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let highlight = Style::default().add_modifier(Modifier::REVERSED);
+        let header = Style::default().add_modifier(Modifier::BOLD);
+        match self.detail {
+            Some(client) => {
+                let rows = self.history(client).iter().map(|(tx_id, state)| {
+                    Row::new(vec![
+                        tx_id.to_string(),
+                        format!("{:?}", state.type_).to_lowercase(),
+                        format!("{:.4}", state.amount),
+                        state.disputed.to_string(),
+                        state.charged_back.to_string(),
+                    ])
+                });
+                let table = Table::new(rows, [Constraint::Length(12); 5])
+                    .header(
+                        Row::new(vec!["tx", "type", "amount", "disputed", "charged back"])
+                            .style(header),
+                    )
+                    .block(
+                        Block::default()
+                            .borders(Borders::ALL)
+                            .title(format!("Client {} transactions", client)),
+                    );
+                frame.render_widget(table, main);
+                frame.render_widget(Paragraph::new("Esc back, q quit"), status);
+            }
+            None => {
+                let rows = self.visible().into_iter().map(|account| {
+                    let record = AccountRecord::from(account);
+                    Row::new(vec![
+                        account.client.to_string(),
+                        format!("{:.4}", record.available),
+                        format!("{:.4}", record.held),
+                        format!("{:.4}", record.total),
+                        account.locked.to_string(),
+                        account.closed.to_string(),
+                    ])
+                });
+                let table = Table::new(rows, [Constraint::Length(12); 6])
+                    .header(
+                        Row::new(vec![
+                            "client",
+                            "available",
+                            "held",
+                            "total",
+                            "locked",
+                            "closed",
+                        ])
+                        .style(header),
+                    )
+                    .row_highlight_style(highlight)
+                    .block(Block::default().borders(Borders::ALL).title("Accounts"));
+                let mut state = TableState::default().with_selected(Some(self.selected));
+                frame.render_stateful_widget(table, main, &mut state);
+                let search = if self.searching {
+                    format!("/{}", self.search)
+                } else {
+                    self.search.clone()
+                };
+                let line = format!(
+                    "sort {}{} | filter {} | search {} | s sort, r reverse, f filter, / search, Enter transactions, q quit",
+                    self.sort.name(),
+                    if self.descending { " desc" } else { "" },
+                    self.filter.name(),
+                    search,
+                );
+                frame.render_widget(Paragraph::new(line), status);
+            }
+        }
+    }
+}
+
+/// Browse `view` in the terminal until the user quits.
+pub fn run_view(mut view: AccountView) -> Result<(), Error> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut view);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, view: &mut AccountView) -> Result<(), Error> {
+    loop {
+        terminal.draw(|frame| view.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !view.handle_key(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn account(client: ClientId, available: f64, held: f64, locked: bool) -> ClientAccount {
+        ClientAccount::try_from(AccountRecord {
+            client,
+            available,
+            held,
+            total: available + held,
+            locked,
+            closed: false,
+            lock: None,
+        })
+        .unwrap()
+    }
+
+    fn view() -> AccountView {
+        let accounts = [
+            account(1, 5.0, 0.0, false),
+            account(2, -1.0, 0.0, true),
+            account(12, 2.0, 3.0, false),
+        ];
+        AccountView::new(Snapshot {
+            accounts: accounts.into_iter().map(|a| (a.client, a)).collect(),
+            tx_states: HashMap::new(),
+        })
+    }
+
+    fn clients(view: &AccountView) -> Vec<ClientId> {
+        view.visible()
+            .iter()
+            .map(|account| account.client)
+            .collect()
+    }
+
+    #[test]
+    fn accounts_are_sorted_filtered_and_searched() {
+        let mut view = view();
+        assert_eq!(clients(&view), vec![1, 2, 12]);
+        view.handle_key(KeyCode::Char('s'));
+        view.handle_key(KeyCode::Char('r'));
+        assert_eq!(clients(&view), vec![1, 12, 2]);
+        view.handle_key(KeyCode::Char('f'));
+        assert_eq!(clients(&view), vec![2]);
+        view.handle_key(KeyCode::Char('f'));
+        view.handle_key(KeyCode::Char('f'));
+        view.handle_key(KeyCode::Char('f'));
+        for key in [KeyCode::Char('/'), KeyCode::Char('1'), KeyCode::Enter] {
+            view.handle_key(key);
+        }
+        assert_eq!(clients(&view), vec![1, 12]);
+        assert!(!view.handle_key(KeyCode::Char('q')));
+    }
+
+    #[test]
+    fn selected_client_history_is_drawn() -> Result<(), Error> {
+        let mut snapshot = Snapshot::default();
+        snapshot.accounts.insert(3, account(3, 2.0, 0.0, false));
+        let tx_states = r#"{"7": {"amount": 2.0, "type_": "Deposit", "client_id": 3,
+            "disputed": false, "charged_back": false}}"#;
+        snapshot.tx_states = serde_json::from_str(tx_states)?;
+        let mut view = AccountView::new(snapshot);
+        view.handle_key(KeyCode::Enter);
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 6))?;
+        terminal.draw(|frame| view.draw(frame))?;
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("Client 3 transactions"));
+        assert!(screen.contains("deposit"));
+        assert!(screen.contains("2.0000"));
+        Ok(())
+    }
+}
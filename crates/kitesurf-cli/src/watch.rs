@@ -0,0 +1,286 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    output_to_stdout, read_csv, write_error_json, Engine, EngineBuilder, Error, ErrorRecord,
+    Snapshot, StateFiles, StateLock, TransactionProcessor,
+};
+
+/// Engine state kept across files, in the output directory.
+const STATE_FILE: &str = "state.json";
+
+/// What processing one file did, see `WatchDir::process_file`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct FileReport {
+    pub processed: usize,
+    pub rejected: usize,
+}
+
+/// Processes the transaction files dropped into `input` one at a time, on
+/// a single engine whose state is saved to `output` after each file, so a
+/// restart goes on where the last run stopped. For `name.csv`, writes the
+/// accounts after it to `name.accounts.csv` in `output` and its rejections,
+/// if any, to `name.errors.jsonl`. Files with an accounts report are taken
+/// as done. The output directory is locked while open, see `StateLock`.
+/// Runs on an `Engine` unless opened with another processor, see
+/// `WatchDir::open_with`.
+#[derive(Debug)]
+pub struct WatchDir<P = Engine> {
+    input: PathBuf,
+    output: PathBuf,
+    engine: P,
+    done: HashSet<PathBuf>,
+    state: StateFiles,
+    _lock: StateLock,
+}
+
+impl WatchDir {
+    /// Watch `input` with an engine configured by `builder`, starting from
+    /// the state saved in `output` by an earlier run, if any. The state is
+    /// read and written through `state`.
+    pub fn open(
+        input: &Path,
+        output: &Path,
+        builder: EngineBuilder,
+        state: StateFiles,
+    ) -> Result<Self, Error> {
+        Self::open_with(input, output, state, |snapshot| match snapshot {
+            Some(snapshot) => builder.storage(snapshot).build(),
+            None => builder.build(),
+        })
+    }
+}
+
+impl<P: TransactionProcessor> WatchDir<P> {
+    /// `open`, watching with the processor `restore` makes from the state
+    /// saved by an earlier run, if any.
+    pub fn open_with(
+        input: &Path,
+        output: &Path,
+        state: StateFiles,
+        restore: impl FnOnce(Option<Snapshot>) -> Result<P, Error>,
+    ) -> Result<Self, Error> {
+        if !input.is_dir() {
+            return Err(Error::new(&format!(
+                "{} isn't a directory",
+                input.display()
+            )));
+        }
+        // events name files under the canonical path
+        let input = input.canonicalize()?;
+        fs::create_dir_all(output)?;
+        let lock = StateLock::acquire(output)?;
+        let state_path = output.join(STATE_FILE);
+        let saved = match state_path.exists() {
+            true => Some(state.read_snapshot(&state_path.to_string_lossy())?),
+            false => None,
+        };
+        Ok(Self {
+            input,
+            output: output.to_path_buf(),
+            engine: restore(saved)?,
+            done: HashSet::new(),
+            state,
+            _lock: lock,
+        })
+    }
+
+    pub fn engine(&self) -> &P {
+        &self.engine
+    }
+
+    fn report_path(&self, file: &Path, suffix: &str) -> PathBuf {
+        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+        self.output.join(format!("{}.{}", stem, suffix))
+    }
+
+    /// Whether `file` is a transaction file of the input directory that
+    /// wasn't processed yet.
+    pub fn is_pending(&self, file: &Path) -> bool {
+        file.parent() == Some(self.input.as_path())
+            && file.extension().is_some_and(|extension| extension == "csv")
+            && file.is_file()
+            && !self.done.contains(file)
+            && !self.report_path(file, "accounts.csv").exists()
+    }
+
+    /// Files of the input directory not processed yet, by name.
+    pub fn pending(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut files = vec![];
+        for entry in fs::read_dir(&self.input)? {
+            let path = entry?.path();
+            if self.is_pending(&path) {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+
+    /// Process every transaction of `file` and write its reports and the
+    /// engine state. A file that can't be read is left out entirely, as
+    /// it's read before any of it is processed, and won't be tried again.
+    pub fn process_file(&mut self, file: &Path) -> Result<FileReport, Error> {
+        self.done.insert(file.to_path_buf());
+        let txs = read_csv(BufReader::new(fs::File::open(file)?)).map_err(|err| {
+            Error::coded(err.code, &format!("{}: {}", file.display(), err.message))
+        })?;
+        let mut report = FileReport::default();
+        let mut errors = vec![];
+        for (index, tx) in (1..).zip(txs) {
+            let (client, tx_id) = (tx.client_id, tx.tx_id);
+            report.processed += 1;
+            if let Err(err) = self.engine.apply(tx) {
+                report.rejected += 1;
+                write_error_json(
+                    &ErrorRecord::rejection(index, client, tx_id, &err),
+                    &mut errors,
+                )?;
+            }
+        }
+
+        let state = self.output.join(STATE_FILE);
+        let tmp_state = self.output.join(format!("{}.tmp", STATE_FILE));
+        self.state
+            .write_snapshot(&tmp_state.to_string_lossy(), &self.engine.snapshot())?;
+        fs::rename(&tmp_state, &state)?;
+        if !errors.is_empty() {
+            fs::write(self.report_path(file, "errors.jsonl"), errors)?;
+        }
+        // written last, as it marks the file as done
+        let mut out = BufWriter::new(fs::File::create(self.report_path(file, "accounts.csv"))?);
+        output_to_stdout(self.engine.report(), &mut out)?;
+        out.flush()?;
+        Ok(report)
+    }
+
+    /// Process `file` if it's pending, printing how it went to `progress`.
+    /// Errors of the file itself are printed rather than returned, so
+    /// watching goes on.
+    pub fn process_if_pending(
+        &mut self,
+        file: &Path,
+        progress: &mut impl Write,
+    ) -> Result<(), Error> {
+        if !self.is_pending(file) {
+            return Ok(());
+        }
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        match self.process_file(file) {
+            Ok(report) => writeln!(
+                progress,
+                "{}: {} transactions, {} rejected",
+                name, report.processed, report.rejected
+            )?,
+            Err(err) => writeln!(progress, "{}: {}", name, err.message)?,
+        }
+        Ok(())
+    }
+}
+
+/// Process the pending files of `dir`, then each file written into it or
+/// moved there, until `stop` returns true. Files are picked up once closed
+/// after writing or when moved in, so a file being copied isn't read
+/// half-way; on platforms without close events, write files elsewhere and
+/// move them in.
+#[cfg(feature = "watch")]
+pub fn watch<P: TransactionProcessor>(
+    dir: &mut WatchDir<P>,
+    stop: impl Fn() -> bool,
+    progress: &mut impl Write,
+) -> Result<(), Error> {
+    use notify::event::{AccessKind, AccessMode, ModifyKind};
+    use notify::{EventKind, RecursiveMode, Watcher};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+    watcher
+        .watch(&dir.input, RecursiveMode::NonRecursive)
+        .map_err(watch_error)?;
+    for file in dir.pending()? {
+        dir.process_if_pending(&file, progress)?;
+    }
+    while !stop() {
+        let event = match events.recv_timeout(Duration::from_millis(200)) {
+            Ok(event) => event.map_err(watch_error)?,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        if matches!(
+            event.kind,
+            EventKind::Access(AccessKind::Close(AccessMode::Write))
+                | EventKind::Modify(ModifyKind::Name(_))
+        ) {
+            for file in &event.paths {
+                dir.process_if_pending(file, progress)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "watch")]
+fn watch_error(err: notify::Error) -> Error {
+    Error::coded(crate::ErrorCode::Io, &format!("Watch Error: {}", err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn files_are_processed_once_on_persistent_state() -> Result<(), Error> {
+        let root = std::env::temp_dir().join(format!("watch-{}", std::process::id()));
+        let (input, output) = (root.join("in"), root.join("out"));
+        fs::create_dir_all(&input)?;
+        fs::write(
+            input.join("a.csv"),
+            "type,client,tx,amount\ndeposit,1,1,5.0\n",
+        )?;
+        fs::write(input.join("notes.txt"), "not transactions")?;
+
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|file| file.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        let mut dir = WatchDir::open(&input, &output, Engine::builder(), StateFiles::default())?;
+        let pending = dir.pending()?;
+        assert_eq!(names(pending.clone()), ["a.csv"]);
+        let mut progress = vec![];
+        dir.process_if_pending(&pending[0], &mut progress)?;
+        assert!(dir.pending()?.is_empty());
+        let err =
+            WatchDir::open(&input, &output, Engine::builder(), StateFiles::default()).unwrap_err();
+        assert_eq!(err.code, crate::ErrorCode::StateLocked);
+        drop(dir);
+
+        // a restart picks up the state and only processes the new file
+        fs::write(
+            input.join("b.csv"),
+            "type,client,tx,amount\nwithdrawal,1,2,2.0\nwithdrawal,1,3,\n",
+        )?;
+        let mut dir = WatchDir::open(&input, &output, Engine::builder(), StateFiles::default())?;
+        let pending = dir.pending()?;
+        assert_eq!(names(pending.clone()), ["b.csv"]);
+        dir.process_if_pending(&pending[0], &mut progress)?;
+        assert_eq!(
+            String::from_utf8(progress).unwrap(),
+            "a.csv: 1 transactions, 0 rejected\nb.csv: 2 transactions, 1 rejected\n"
+        );
+        assert_eq!(dir.engine().account(1).unwrap().available().value(), 3.0);
+        assert_eq!(
+            fs::read_to_string(output.join("b.accounts.csv"))?,
+            "client,available,held,total,locked\n1,3.0,0.0,3.0,false\n"
+        );
+        assert!(fs::read_to_string(output.join("b.errors.jsonl"))?.contains("missing_amount"));
+        assert!(!output.join("a.errors.jsonl").exists());
+        fs::remove_dir_all(&root)?;
+        Ok(())
+    }
+}
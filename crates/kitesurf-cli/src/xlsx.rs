@@ -0,0 +1,103 @@
+use calamine::{open_workbook_from_rs, Reader, Xlsx, XlsxError};
+use std::io::{Cursor, Read};
+
+use crate::{read_csv, Error, ErrorCode, Tx};
+
+fn import_error(message: &str) -> Error {
+    Error::coded(ErrorCode::Import, &format!("XLSX Error: {}", message))
+}
+
+/// Convert the worksheet `sheet` of an Excel workbook, or its first one, to
+/// CSV for the usual readers. Its first row is the header, as in a CSV
+/// input; whole numbers are written without decimals, so ids entered as
+/// numbers read back as ids.
+pub fn xlsx_to_csv<R: Read>(mut buf: R, sheet: Option<&str>) -> Result<Vec<u8>, Error> {
+    let mut data = vec![];
+    buf.read_to_end(&mut data)?;
+    let mut workbook: Xlsx<_> = open_workbook_from_rs(Cursor::new(data))
+        .map_err(|err: XlsxError| import_error(&err.to_string()))?;
+    let range = match sheet {
+        Some(name) => workbook.worksheet_range(name),
+        None => workbook
+            .worksheet_range_at(0)
+            .ok_or_else(|| import_error("workbook without worksheets"))?,
+    }
+    .map_err(|err| import_error(&err.to_string()))?;
+
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for row in range.rows() {
+        writer.write_record(row.iter().map(|cell| cell.to_string()))?;
+    }
+    writer
+        .into_inner()
+        .map_err(|err| import_error(&err.to_string()))
+}
+
+/// Like `read_csv`, for the worksheet `sheet` of an Excel workbook, see
+/// `xlsx_to_csv`.
+pub fn read_xlsx<R: Read>(buf: R, sheet: Option<&str>) -> Result<Vec<Tx>, Error> {
+    read_csv(Cursor::new(xlsx_to_csv(buf, sheet)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{SignedAmount, TxType};
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    /// Workbook with a `notes` sheet followed by a `transactions` one, whose
+    /// cells are inline strings and numbers.
+    fn workbook() -> Vec<u8> {
+        let row = |index: usize, cells: &[&str]| {
+            let cells: String = cells
+                .iter()
+                .map(|cell| match cell.parse::<f64>() {
+                    Ok(_) => format!("<c><v>{}</v></c>", cell),
+                    Err(_) => format!("<c t=\"inlineStr\"><is><t>{}</t></is></c>", cell),
+                })
+                .collect();
+            format!("<row r=\"{}\">{}</row>", index + 1, cells)
+        };
+        let sheet = |rows: &[&[&str]]| {
+            let rows: String = rows.iter().enumerate().map(|(i, r)| row(i, r)).collect();
+            format!(
+                "<worksheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\"><sheetData>{}</sheetData></worksheet>",
+                rows
+            )
+        };
+        let files = [
+            ("[Content_Types].xml", "<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\"><Default Extension=\"xml\" ContentType=\"application/xml\"/><Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/><Override PartName=\"/xl/workbook.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml\"/></Types>".to_string()),
+            ("_rels/.rels", "<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\"><Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"xl/workbook.xml\"/></Relationships>".to_string()),
+            ("xl/workbook.xml", "<workbook xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\" xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\"><sheets><sheet name=\"notes\" sheetId=\"1\" r:id=\"rId1\"/><sheet name=\"transactions\" sheetId=\"2\" r:id=\"rId2\"/></sheets></workbook>".to_string()),
+            ("xl/_rels/workbook.xml.rels", "<Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\"><Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet\" Target=\"worksheets/sheet1.xml\"/><Relationship Id=\"rId2\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet\" Target=\"worksheets/sheet2.xml\"/></Relationships>".to_string()),
+            ("xl/worksheets/sheet1.xml", sheet(&[&["exported by ops"]])),
+            ("xl/worksheets/sheet2.xml", sheet(&[
+                &["type", "client", "tx", "amount"],
+                &["deposit", "1", "1", "2.5"],
+                &["withdrawal", "1", "2", "1"],
+            ])),
+        ];
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in files {
+            zip.start_file(name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn named_worksheet_is_read_as_csv() -> Result<(), Error> {
+        let txs = read_xlsx(&workbook()[..], Some("transactions"))?;
+        assert_eq!(txs.len(), 2);
+        assert_eq!(
+            (txs[1].type_.clone(), txs[1].tx_id, txs[1].amount()),
+            (TxType::Withdrawal, 2, Some(SignedAmount::new(1.0)?))
+        );
+        // the first sheet, with a lone header
+        assert!(read_xlsx(&workbook()[..], None)?.is_empty());
+        let missing = read_xlsx(&workbook()[..], Some("sheet9")).unwrap_err();
+        assert_eq!(missing.code, ErrorCode::Import);
+        Ok(())
+    }
+}
@@ -0,0 +1,1159 @@
+use assert_cmd::Command;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+const DATA_DIR: &str = "tests/data";
+
+const BIN: &str = "transaction-resolver";
+
+/// Header followed by the account rows sorted by client, since the output
+/// order is arbitrary.
+fn sorted(output: &str) -> Vec<String> {
+    let mut lines: Vec<String> = output.lines().map(str::to_string).collect();
+    if lines.len() > 1 {
+        lines[1..].sort_by_key(|line| {
+            let client = line.split(',').next().unwrap_or_default();
+            client.parse::<u64>().unwrap_or(u64::MAX)
+        });
+    }
+    lines
+}
+
+fn run(input: &Path) -> Command {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg(input);
+    cmd
+}
+
+fn assert_golden(name: &str) {
+    assert_golden_with(name, &[]);
+}
+
+fn assert_golden_with(name: &str, args: &[&str]) {
+    let input = Path::new(DATA_DIR).join(format!("{}.csv", name));
+    let expected =
+        fs::read_to_string(Path::new(DATA_DIR).join(format!("{}.expected.csv", name))).unwrap();
+    let output = run(&input)
+        .args(args)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        sorted(&String::from_utf8(output).unwrap()),
+        sorted(&expected),
+        "output of {} doesn't match its golden file",
+        name
+    );
+}
+
+#[test]
+fn basic() {
+    assert_golden("basic");
+}
+
+#[test]
+fn disputes() {
+    assert_golden("disputes");
+}
+
+#[test]
+fn precision() {
+    assert_golden("precision");
+}
+
+#[test]
+fn large_generated_input() {
+    let clients = 1000;
+    let mut input = String::from("type,client,tx,amount\n");
+    let mut expected = String::from("client,available,held,total,locked\n");
+    for client in 1..=clients {
+        let tx = client * 3;
+        writeln!(input, "deposit,{},{},10.0", client, tx).unwrap();
+        writeln!(input, "withdrawal,{},{},2.5", client, tx + 1).unwrap();
+        writeln!(input, "deposit,{},{},1.25", client, tx + 2).unwrap();
+        writeln!(input, "dispute,{},{},", client, tx + 2).unwrap();
+        writeln!(expected, "{},7.5,1.25,8.75,false", client).unwrap();
+    }
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("large_generated_input.csv");
+    fs::write(&path, input).unwrap();
+
+    let output = run(&path).assert().success().get_output().stdout.clone();
+    assert_eq!(
+        sorted(&String::from_utf8(output).unwrap()),
+        sorted(&expected)
+    );
+}
+
+#[test]
+fn malformed_inputs_fail() {
+    for name in [
+        "malformed_type",
+        "malformed_columns",
+        "malformed_amount",
+        "malformed_client",
+    ] {
+        let input = Path::new(DATA_DIR).join(format!("{}.csv", name));
+        let assert = run(&input).assert().failure();
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("CSV Error"), "{}: {}", name, stderr);
+    }
+}
+
+#[test]
+fn output_is_partitioned_by_client() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("partitions");
+    let dir_arg = dir.to_str().unwrap();
+    run(&Path::new(DATA_DIR).join("basic.csv"))
+        .args(["--output-dir", dir_arg, "--partitions", "4"])
+        .assert()
+        .success()
+        .stdout("");
+
+    let mut rows = vec![];
+    for index in 0..4 {
+        let path = dir.join(format!("accounts-{:03}.csv", index));
+        let content = fs::read_to_string(path).unwrap();
+        rows.extend(content.lines().skip(1).map(str::to_string));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+    let expected = fs::read_to_string(Path::new(DATA_DIR).join("basic.expected.csv")).unwrap();
+    let mut expected: Vec<String> = expected.lines().skip(1).map(str::to_string).collect();
+    rows.sort();
+    expected.sort();
+    assert_eq!(rows, expected);
+}
+
+#[test]
+fn interrupted_run_resumes_from_checkpoint() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("resume");
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    let checkpoint = dir.join("checkpoint.json");
+    let checkpoint_arg = checkpoint.to_str().unwrap();
+    let full = fs::read_to_string(Path::new(DATA_DIR).join("basic.csv")).unwrap();
+
+    // the first run stops after a dispute it never saw charged back
+    let partial: Vec<&str> = full.lines().take(7).collect();
+    fs::write(&input, partial.join("\n")).unwrap();
+    run(&input)
+        .args(["--checkpoint", checkpoint_arg])
+        .assert()
+        .success();
+
+    fs::write(&input, &full).unwrap();
+    let manifest = dir.join("manifest.json");
+    let output = run(&input)
+        .args(["--resume", checkpoint_arg])
+        .args(["--manifest", manifest.to_str().unwrap()])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let resumed: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+    run(&input)
+        .args(["--manifest", manifest.to_str().unwrap()])
+        .assert()
+        .success();
+    let uninterrupted: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+    assert_eq!(resumed["tx_chain"], uninterrupted["tx_chain"]);
+    fs::remove_dir_all(&dir).unwrap();
+    let expected = fs::read_to_string(Path::new(DATA_DIR).join("basic.expected.csv")).unwrap();
+    assert_eq!(
+        sorted(&String::from_utf8(output).unwrap()),
+        sorted(&expected)
+    );
+}
+
+#[test]
+fn resumed_run_ends_like_a_single_pass() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("resume-state");
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    let checkpoint = dir.join("checkpoint.json");
+    let pending = dir.join("pending.csv");
+    // cut with a withdrawal pending, a dispute timing out and a chargeback
+    // held for its dispute
+    let full = "\
+type,client,tx,amount
+deposit,1,1,10.0
+withdrawal,1,2,15.0
+dispute,1,1,
+deposit,2,4,3.0
+chargeback,2,4,
+dispute,2,4,
+deposit,1,6,6.0
+deposit,2,7,1.0
+deposit,1,8,1.0
+";
+    let options = [
+        "--dispute-timeout",
+        "3",
+        "--reorder-window",
+        "3",
+        "--pending",
+        pending.to_str().unwrap(),
+    ];
+    let partial: Vec<&str> = full.lines().take(6).collect();
+    fs::write(&input, partial.join("\n")).unwrap();
+    run(&input)
+        .args(options)
+        .args(["--checkpoint", checkpoint.to_str().unwrap()])
+        .assert()
+        .success();
+
+    fs::write(&input, full).unwrap();
+    let resumed = run(&input)
+        .args(options)
+        .args(["--resume", checkpoint.to_str().unwrap()])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let single = run(&input)
+        .args(options)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        sorted(&String::from_utf8(resumed).unwrap()),
+        sorted(&String::from_utf8(single).unwrap())
+    );
+}
+
+#[test]
+fn concurrent_run_on_the_same_state_fails_fast() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("state-lock");
+    fs::create_dir_all(&dir).unwrap();
+    let checkpoint = dir.join("checkpoint.json");
+    let input = Path::new(DATA_DIR).join("basic.csv");
+
+    // another run holding the directory
+    let lock = fs::File::create(dir.join(".kitesurf.lock")).unwrap();
+    lock.try_lock().unwrap();
+    let assert = run(&input)
+        .args([
+            "--checkpoint",
+            checkpoint.to_str().unwrap(),
+            "--errors-json",
+        ])
+        .assert()
+        .failure();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("\"code\":\"state_locked\""), "{}", stderr);
+    assert!(!checkpoint.exists());
+
+    drop(lock);
+    run(&input)
+        .args(["--checkpoint", checkpoint.to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(checkpoint.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupted_run_flushes_checkpoint() {
+    // a directory of its own, as runs lock the directory of their checkpoint
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("interrupted");
+    fs::create_dir_all(&dir).unwrap();
+    let checkpoint = dir.join("checkpoint.json");
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin(BIN))
+        .args(["replay", "--rate", "10", "--checkpoint"])
+        .arg(&checkpoint)
+        .arg(Path::new(DATA_DIR).join("basic.csv"))
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    let content = fs::read_to_string(&checkpoint).unwrap();
+    fs::remove_file(&checkpoint).unwrap();
+
+    assert_eq!(output.status.code(), Some(130));
+    assert!(output.stdout.is_empty());
+    let checkpoint: serde_json::Value = serde_json::from_str(&content).unwrap();
+    let records = checkpoint["records"].as_u64().unwrap();
+    assert!(records > 0 && records < 21, "{}", records);
+}
+
+#[test]
+fn accounts_are_written_to_output() {
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join("accounts.csv");
+    run(&Path::new(DATA_DIR).join("basic.csv"))
+        .args(["--output", output.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout("");
+    let expected = fs::read_to_string(Path::new(DATA_DIR).join("basic.expected.csv")).unwrap();
+    assert_eq!(
+        sorted(&fs::read_to_string(&output).unwrap()),
+        sorted(&expected)
+    );
+}
+
+#[test]
+fn manifest_traces_the_run() {
+    let input = Path::new(DATA_DIR).join("basic.csv");
+    let manifest_with = |name: &str, extra: &[&str]| -> serde_json::Value {
+        let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+        run(&input)
+            .args(["--manifest", path.to_str().unwrap()])
+            .args(extra)
+            .assert()
+            .success();
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap()
+    };
+    let manifest = |name: &str| manifest_with(name, &[]);
+    let first = manifest("manifest-1.json");
+    let input_bytes = fs::metadata(&input).unwrap().len();
+    assert_eq!(first["inputs"][0]["bytes"], input_bytes);
+    assert_eq!(first["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(first["rows"]["read"], 21);
+    assert_eq!(first["rows"]["accounts"], 13);
+    assert_eq!(first["interrupted"], false);
+
+    // the manifest path is part of the configuration
+    let second = manifest("manifest-1.json");
+    assert_eq!(first["report_id"], second["report_id"]);
+    let third = manifest("manifest-2.json");
+    assert_ne!(first["report_id"], third["report_id"]);
+
+    // same transactions applied, whatever the configuration
+    let chain = first["tx_chain"].as_str().unwrap();
+    assert_eq!(chain.len(), 64);
+    assert_eq!(third["tx_chain"], chain);
+    let threaded = manifest_with("manifest-3.json", &["--threads", "2"]);
+    assert_eq!(threaded["tx_chain"], chain);
+    let limited = manifest_with("manifest-4.json", &["--max-amount", "1"]);
+    assert_ne!(limited["tx_chain"], chain);
+}
+
+#[test]
+fn errors_are_reported_as_json() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let input = dir.join("rejections.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,\n",
+    )
+    .unwrap();
+    let assert = run(&input).arg("--errors-json").assert().success();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    let error: serde_json::Value = serde_json::from_str(stderr.trim()).unwrap();
+    assert_eq!(error["severity"], "rejection");
+    assert_eq!(error["code"], "missing_amount");
+    assert_eq!(error["index"], 2);
+    assert_eq!(error["client"], 1);
+    assert_eq!(error["tx"], 2);
+
+    let errors = dir.join("errors.jsonl");
+    run(Path::new("tests/data/does-not-exist.csv"))
+        .args(["--errors-json", "--errors-out", errors.to_str().unwrap()])
+        .assert()
+        .failure();
+    let error: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&errors).unwrap()).unwrap();
+    assert_eq!(error["severity"], "fatal");
+    assert_eq!(error["code"], "io");
+}
+
+#[test]
+fn output_is_the_same_whatever_the_thread_count() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    // tx ids are reused across clients, and disputes refer to any of them
+    let mut input = String::from("type,client,tx,amount\n");
+    for row in 1..=3000u32 {
+        let mixed = row.wrapping_mul(2654435761);
+        let (client, tx) = (mixed % 37 + 1, mixed % row + 1);
+        let amount = f64::from(mixed % 1000) / 100.0;
+        match mixed % 7 {
+            0..=2 => writeln!(input, "deposit,{},{},{}", client, tx, amount),
+            3..=4 => writeln!(input, "withdrawal,{},{},{}", client, tx, amount),
+            // rejected, no amount
+            _ if row % 50 == 0 => writeln!(input, "withdrawal,{},{},", client, tx),
+            5 => writeln!(input, "dispute,{},{},", client, tx),
+            _ => writeln!(input, "chargeback,{},{},", client, tx),
+        }
+        .unwrap();
+    }
+    let path = dir.join("threads.csv");
+    fs::write(&path, input).unwrap();
+
+    let output = |extra: &[&str]| {
+        let audit = dir.join(format!("threads-audit-{}.csv", extra.len()));
+        let output = run(&path)
+            .args(["--audit", audit.to_str().unwrap(), "--errors-json"])
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        (output.stdout, output.stderr, fs::read(audit).unwrap())
+    };
+    let sequential = output(&[]);
+    assert!(!sequential.1.is_empty());
+    for threads in ["1", "4"] {
+        assert!(
+            output(&["--threads", threads]) == sequential,
+            "{} threads",
+            threads
+        );
+    }
+    // same accounts, in the order the partitions finished
+    let (stdout, stderr, audit) = output(&["--threads", "4", "--stream-output"]);
+    let text = |bytes: &[u8]| String::from_utf8(bytes.to_vec()).unwrap();
+    assert_eq!(sorted(&text(&stdout)), sorted(&text(&sequential.0)));
+    assert!((stderr, audit) == (sequential.1, sequential.2));
+}
+
+#[test]
+fn closed_accounts_are_paid_out() {
+    let payouts = Path::new(env!("CARGO_TARGET_TMPDIR")).join("payouts.csv");
+    assert_golden_with("close_account", &["--payouts", payouts.to_str().unwrap()]);
+    assert_eq!(
+        fs::read_to_string(&payouts).unwrap(),
+        "client,tx,amount\n1,4,3.5\n"
+    );
+}
+
+#[test]
+fn output_is_checked_against_expected_accounts() {
+    let input = Path::new(DATA_DIR).join("basic.csv");
+    let expected = Path::new(DATA_DIR).join("basic.expected.csv");
+    run(&input)
+        .args(["--expect", expected.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let wrong = Path::new(env!("CARGO_TARGET_TMPDIR")).join("wrong.expected.csv");
+    let content = fs::read_to_string(&expected).unwrap();
+    fs::write(
+        &wrong,
+        content.replace("125,100.0,0.0,100.0", "125,99.0,0.0,99.0"),
+    )
+    .unwrap();
+    let assert = run(&input)
+        .args(["--expect", wrong.to_str().unwrap()])
+        .assert()
+        .failure();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(
+        stderr.contains("client 125: available 100, expected 99; total 100, expected 99"),
+        "{}",
+        stderr
+    );
+    assert!(!assert.get_output().stdout.is_empty());
+}
+
+#[test]
+fn empty_accounts_are_skipped() {
+    assert_golden_with("empty_accounts", &["--skip-empty-accounts"]);
+}
+
+/// Compares the output byte for byte with a golden file written by the original
+/// binary, its rows put in client order (it wrote them in hash map order).
+fn assert_golden_bytes(name: &str, args: &[&str]) {
+    let input = Path::new(DATA_DIR).join(format!("{}.csv", name));
+    let expected =
+        fs::read_to_string(Path::new(DATA_DIR).join(format!("{}.expected.csv", name))).unwrap();
+    run(&input).args(args).assert().success().stdout(expected);
+}
+
+#[test]
+fn compat_v1_keeps_the_original_behavior() {
+    assert_golden_bytes("compat", &["--compat", "v1"]);
+    // negative withdrawals credit the account, disputes of negative deposits
+    // hold a negative amount
+    assert_golden_bytes("compat_negative", &["--compat", "v1"]);
+    // disputes open the accounts of unknown clients and move the funds of
+    // the client referring to another's transaction
+    assert_golden_bytes("compat_other_clients", &["--compat", "v1"]);
+    let output = run(&Path::new(DATA_DIR).join("compat_other_clients.csv"))
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        sorted(&String::from_utf8(output).unwrap()),
+        vec![
+            "client,available,held,total,locked",
+            "1,10.0,0.0,10.0,false",
+            "2,5.0,0.0,5.0,false",
+        ]
+    );
+    let output = run(&Path::new(DATA_DIR).join("compat.csv"))
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        sorted(&String::from_utf8(output).unwrap()),
+        vec![
+            "client,available,held,total,locked",
+            "1,10.0,0.0,10.0,false",
+            "2,0.0,1.0,1.0,true",
+        ]
+    );
+}
+
+#[test]
+fn html_report_summarizes_the_run() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("report.html");
+    let expected = fs::read_to_string(Path::new(DATA_DIR).join("disputes.expected.csv")).unwrap();
+    let output = run(&Path::new(DATA_DIR).join("disputes.csv"))
+        .args(["--report-html", path.to_str().unwrap()])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    // the stats behind the dispute funnel aren't added to the output
+    assert_eq!(
+        sorted(&String::from_utf8(output).unwrap()),
+        sorted(&expected)
+    );
+    let html = fs::read_to_string(&path).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("Transactions<b>15</b>"), "{}", html);
+    assert!(html.contains("<td>chargeback of tx 2</td>"), "{}", html);
+    assert!(!html.contains("{{"));
+}
+
+#[test]
+fn pseudonymized_output_maps_back_to_clients() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("pseudonyms");
+    fs::create_dir_all(&dir).unwrap();
+    let (locks, map) = (dir.join("locks.csv"), dir.join("map.csv"));
+    let output = run(&Path::new(DATA_DIR).join("basic.csv"))
+        .env("KITESURF_PSEUDONYM_KEY", "a secret of the operator")
+        .args(["--pseudonymize", "--locks", locks.to_str().unwrap()])
+        .args(["--pseudonym-map", map.to_str().unwrap()])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let output = String::from_utf8(output).unwrap();
+    let locks = fs::read_to_string(&locks).unwrap();
+    let map = fs::read_to_string(&map).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    // the pseudonyms replace the ids, and the mapping turns them back
+    let mut clients = std::collections::HashMap::new();
+    for row in map.lines().skip(1) {
+        let (pseudonym, client) = row.split_once(',').unwrap();
+        assert_eq!(pseudonym.len(), 16);
+        clients.insert(pseudonym.to_string(), client.to_string());
+    }
+    let unmapped = |report: &str| -> String {
+        let mut lines: Vec<String> = report.lines().map(str::to_string).collect();
+        for line in &mut lines[1..] {
+            let (pseudonym, rest) = line.split_once(',').unwrap();
+            *line = format!("{},{}", clients[pseudonym], rest);
+        }
+        lines.join("\n") + "\n"
+    };
+    assert!(!output.contains("\n1,"));
+    let expected = fs::read_to_string(Path::new(DATA_DIR).join("basic.expected.csv")).unwrap();
+    assert_eq!(sorted(&unmapped(&output)), sorted(&expected));
+    assert!(unmapped(&locks).starts_with("client,reason,tx,tx_index\n1,chargeback,"));
+
+    run(&Path::new(DATA_DIR).join("basic.csv"))
+        .env_remove("KITESURF_PSEUDONYM_KEY")
+        .arg("--pseudonymize")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn accounts_are_filtered() {
+    let output = run(&Path::new(DATA_DIR).join("basic.csv"))
+        .args(["--clients", "1-2,125", "--min-balance", "1"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    assert_eq!(
+        sorted(&String::from_utf8(output).unwrap()),
+        vec![
+            "client,available,held,total,locked",
+            "2,0.0,2.1235,2.1235,false",
+            "125,100.0,0.0,100.0,false",
+        ]
+    );
+    run(&Path::new(DATA_DIR).join("basic.csv"))
+        .arg("--only-locked")
+        .assert()
+        .success()
+        .stdout("client,available,held,total,locked\n1,0.6,0.0,0.6,true\n");
+}
+
+#[test]
+fn messy_input_is_repaired_when_lenient() {
+    run(&Path::new(DATA_DIR).join("messy.csv"))
+        .assert()
+        .failure();
+    assert_golden_with("messy", &["--lenient"]);
+
+    let assert = run(&Path::new(DATA_DIR).join("messy.csv"))
+        .arg("--lenient")
+        .assert()
+        .success();
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(
+        stderr.contains("Read 8 rows: 5 repaired, 3 skipped"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn malformed_inputs_are_skipped_when_lenient() {
+    for name in [
+        "malformed_type",
+        "malformed_columns",
+        "malformed_amount",
+        "malformed_client",
+    ] {
+        let input = Path::new(DATA_DIR).join(format!("{}.csv", name));
+        let assert = run(&input).arg("--lenient").assert().success();
+        let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+        assert!(stderr.contains("skipped"), "{}: {}", name, stderr);
+    }
+}
+
+#[test]
+fn missing_input_file_fails() {
+    run(Path::new("tests/data/does-not-exist.csv"))
+        .assert()
+        .failure();
+}
+
+#[test]
+fn output_schema_shapes_the_output() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let schema = dir.join("output-schema.csv");
+    fs::write(
+        &schema,
+        "column,field\n\
+         account_id,client\n\
+         balance_cents,total*100\n\
+         status,status\n",
+    )
+    .unwrap();
+    let input = dir.join("output-schema-input.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount\n\
+         deposit,2,1,2.5\n\
+         deposit,1,2,1.25\n\
+         dispute,1,2,\n\
+         chargeback,1,2,\n",
+    )
+    .unwrap();
+
+    let output = run(&input)
+        .args(["--output-schema", schema.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "account_id,balance_cents,status\n\
+         1,0.0,locked\n\
+         2,250.0,active\n"
+    );
+}
+
+#[test]
+fn delta_only_writes_the_changed_accounts() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let initial = dir.join("delta-initial.csv");
+    fs::write(
+        &initial,
+        "client,available,held,total,locked\n\
+         1,10.0,0.0,10.0,false\n\
+         2,5.0,0.0,5.0,false\n\
+         3,1.0,0.0,1.0,false\n",
+    )
+    .unwrap();
+    let input = dir.join("delta.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount\n\
+         deposit,1,1,2.5\n\
+         withdrawal,2,2,50.0\n\
+         deposit,4,3,1.0\n",
+    )
+    .unwrap();
+
+    let output = run(&input)
+        .args([
+            "--initial-accounts",
+            initial.to_str().unwrap(),
+            "--delta-only",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        sorted(&String::from_utf8(output.stdout).unwrap()),
+        vec![
+            "client,available,held,total,locked",
+            "1,12.5,0.0,12.5,false",
+            "4,1.0,0.0,1.0,false"
+        ]
+    );
+}
+
+#[test]
+fn client_stats_are_appended_to_the_output() {
+    let output = run(Path::new(&format!("{}/disputes.csv", DATA_DIR)))
+        .arg("--client-stats")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        sorted(&String::from_utf8(output.stdout).unwrap()),
+        vec![
+            "client,available,held,total,locked,deposits,withdrawals,open_disputes,resolved_disputes,chargebacks",
+            "1,10.0,0.0,10.0,true,2,0,0,1,1",
+            "2,-1.5,3.0,1.5,false,1,1,1,0,0",
+            "3,1.0,0.0,1.0,false,1,0,0,0,0"
+        ]
+    );
+}
+
+#[test]
+fn alerts_are_logged_mid_run() {
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("alerts.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount\n\
+         deposit,1,1,50.0\n\
+         dispute,1,1,\n\
+         chargeback,1,1,\n\
+         deposit,2,2,5.0\n",
+    )
+    .unwrap();
+
+    let output = run(&input)
+        .args(["--alert", "held>10", "--alert", "chargebacks>0"])
+        .args(["--alert", "rejections>0"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Alert: held>10 at transaction 2: client 1 at 50\n\
+         Alert: chargebacks>0 at transaction 3: 1 so far\n"
+    );
+}
+
+#[test]
+fn circuit_breaker_stops_a_bad_feed() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("bad-feed");
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("bad-feed.csv");
+    let mut data = "type,client,tx,amount\ndeposit,1,1,1.0\n".to_string();
+    for tx in 2..20 {
+        writeln!(data, "deposit,1,{},", tx).unwrap();
+    }
+    fs::write(&input, data).unwrap();
+    let checkpoint = dir.join("bad-feed.checkpoint.json");
+
+    let output = run(&input)
+        .args(["--max-rejection-rate", "50", "--rejection-window", "4"])
+        .args([
+            "--checkpoint",
+            checkpoint.to_str().unwrap(),
+            "--errors-json",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(r#""code":"circuit_breaker""#));
+    let saved: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&checkpoint).unwrap()).unwrap();
+    assert_eq!(saved["records"], 4);
+}
+
+#[test]
+fn resource_limits_abort_the_run() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let input = dir.join("many-clients.csv");
+    let mut data = "type,client,tx,amount\n".to_string();
+    for tx in 1..20 {
+        writeln!(data, "deposit,{},{},1.0", tx, tx).unwrap();
+    }
+    fs::write(&input, data).unwrap();
+
+    let output = run(&input)
+        .args(["--max-accounts", "10", "--errors-json"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(r#""code":"resource_limit""#));
+    run(&input)
+        .args(["--max-tx-states", "19"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn transactions_are_filtered_by_client_lists() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let allow = dir.join("clients-allow.csv");
+    fs::write(&allow, "client\n1\n2\n").unwrap();
+    let deny = dir.join("clients-deny.csv");
+    fs::write(&deny, "client\n2\n").unwrap();
+
+    let assert = run(Path::new("tests/data/basic.csv"))
+        .args(["--clients-allow", allow.to_str().unwrap()])
+        .args(["--clients-deny", deny.to_str().unwrap()])
+        .assert()
+        .success();
+    let output = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let clients: Vec<&str> = output
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert_eq!(clients, vec!["1"]);
+    let stderr = String::from_utf8(assert.get_output().stderr.clone()).unwrap();
+    assert!(stderr.contains("Filtered out 14 transactions by client"));
+}
+
+#[test]
+fn tenants_have_separate_clients() {
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("tenants.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount,tenant\n\
+         deposit,1,1,5.0,shop-a\n\
+         deposit,1,2,3.0,shop-b\n\
+         withdrawal,1,3,4.0,shop-b\n\
+         dispute,1,1,,shop-a\n",
+    )
+    .unwrap();
+
+    let output = run(&input).arg("--tenants").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "tenant,client,available,held,total,locked\n\
+         shop-a,1,0.0,5.0,5.0,false\n\
+         shop-b,1,3.0,0.0,3.0,false\n"
+    );
+}
+
+#[test]
+fn input_is_sorted_by_timestamp() {
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("unsorted.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount,timestamp\n\
+         withdrawal,1,2,4.0,2024-03-02T08:00:00Z\n\
+         deposit,1,1,5.0,2024-03-01T08:00:00Z\n\
+         deposit,2,3,1.0,2024-03-01T09:00:00Z\n",
+    )
+    .unwrap();
+
+    let unsorted = run(&input).output().unwrap();
+    assert!(unsorted.status.success());
+    assert!(String::from_utf8(unsorted.stdout)
+        .unwrap()
+        .contains("1,5.0,0.0,5.0,false"));
+    let output = run(&input)
+        .args(["--sort-by", "timestamp", "--sort-buffer", "2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        sorted(&String::from_utf8(output.stdout).unwrap()),
+        vec![
+            "client,available,held,total,locked",
+            "1,1.0,0.0,1.0,false",
+            "2,1.0,0.0,1.0,false"
+        ]
+    );
+    run(&input).args(["--sort-by", "date"]).assert().failure();
+}
+
+#[test]
+fn amounts_are_converted_with_rates() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let input = dir.join("currencies.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount,currency\n\
+         deposit,1,1,10.0,EUR\n\
+         deposit,1,2,1.0,\n\
+         withdrawal,1,3,2.0,usd\n\
+         deposit,2,4,5.0,GBP\n",
+    )
+    .unwrap();
+    let rates = dir.join("rates.csv");
+    fs::write(&rates, "currency,rate\nUSD,1.0\nEUR,1.1\n").unwrap();
+    let audit = dir.join("currencies-audit.csv");
+
+    let output = run(&input)
+        .args(["--rates", rates.to_str().unwrap()])
+        .args(["--audit", audit.to_str().unwrap(), "--errors-json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        sorted(&String::from_utf8(output.stdout).unwrap()),
+        vec![
+            "client,available,held,total,locked",
+            "1,10.0,0.0,10.0,false"
+        ]
+    );
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["code"], "unknown_currency");
+    let audit = fs::read_to_string(audit).unwrap();
+    assert!(audit.contains("deposit,1,1,11.0,,,,,EUR,10.0"), "{}", audit);
+}
+
+#[test]
+fn timestamps_are_kept_on_tx_states() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("timestamps");
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("timestamps.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount,timestamp\n\
+         deposit,1,1,2.0,2024-01-02T13:00:00+01:00\n\
+         deposit,1,2,1.0,1704200400\n\
+         deposit,1,3,1.0,\n",
+    )
+    .unwrap();
+    let snapshot = dir.join("timestamps.json");
+    run(&input)
+        .args(["--snapshot-out", snapshot.to_str().unwrap()])
+        .assert()
+        .success();
+    let snapshot: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&snapshot).unwrap()).unwrap();
+    let states = &snapshot["tx_states"];
+    assert_eq!(states["1"]["timestamp"], 1_704_196_800_000_i64);
+    assert_eq!(states["2"]["timestamp"], 1_704_200_400_000_i64);
+    assert!(states["3"].get("timestamp").is_none());
+
+    run(&input)
+        .args(["--timestamp-format", "rfc3339"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn explain_dry_runs_a_transaction() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("explain");
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("explain.csv");
+    fs::write(
+        &input,
+        "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,1.0\n",
+    )
+    .unwrap();
+    let snapshot = dir.join("explain.json");
+    run(&input)
+        .args(["--snapshot-out", snapshot.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let explain = |row: &str| {
+        let output = Command::cargo_bin(BIN)
+            .unwrap()
+            .args(["explain", "--snapshot", snapshot.to_str().unwrap(), row])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let other_client = explain("dispute,2,1,");
+    assert!(
+        other_client.contains("outcome: ignored: Dispute doesn't apply to transaction 1"),
+        "{}",
+        other_client
+    );
+    let dispute = explain("dispute,1,1,");
+    assert!(dispute.contains("outcome: applied"), "{}", dispute);
+    assert!(
+        dispute.contains("account after: available 0, held 5, total 5"),
+        "{}",
+        dispute
+    );
+    // the snapshot is left as it was
+    assert!(explain("dispute,1,1,").contains("outcome: applied"));
+}
+
+#[cfg(feature = "signatures")]
+#[test]
+fn tampered_input_is_refused() {
+    let signature = Path::new(DATA_DIR).join("basic.csv.minisig");
+    let pubkey = Path::new(DATA_DIR).join("partner.pub");
+    let verify = [
+        "--verify",
+        signature.to_str().unwrap(),
+        "--pubkey",
+        pubkey.to_str().unwrap(),
+    ];
+    assert_golden_with("basic", &verify);
+
+    let tampered = Path::new(env!("CARGO_TARGET_TMPDIR")).join("tampered.csv");
+    let basic = fs::read_to_string(Path::new(DATA_DIR).join("basic.csv")).unwrap();
+    fs::write(&tampered, basic.replacen("100.0", "900.0", 1)).unwrap();
+    let output = run(&tampered)
+        .args(verify)
+        .arg("--errors-json")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["code"], "signature");
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn snapshots_are_encrypted_at_rest() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("encrypted");
+    fs::create_dir_all(&dir).unwrap();
+    let key = "2b".repeat(32);
+    let key_file = dir.join("state.key");
+    fs::write(&key_file, &key).unwrap();
+    let snapshot = dir.join("snapshot.json");
+    run(&Path::new(DATA_DIR).join("basic.csv"))
+        .args(["--snapshot-out", snapshot.to_str().unwrap()])
+        .args(["--state-key-file", key_file.to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(serde_json::from_slice::<serde_json::Value>(&fs::read(&snapshot).unwrap()).is_err());
+
+    let explain = || {
+        let mut cmd = Command::cargo_bin(BIN).unwrap();
+        cmd.args([
+            "explain",
+            "--snapshot",
+            snapshot.to_str().unwrap(),
+            "dispute,1,1,",
+        ]);
+        cmd
+    };
+    // the key can come from the environment too
+    explain().env("KITESURF_STATE_KEY", &key).assert().success();
+    let output = explain()
+        .env_remove("KITESURF_STATE_KEY")
+        .arg("--errors-json")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["code"], "encryption");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn mmap_input_may_start_with_a_bom() {
+    let input = Path::new(env!("CARGO_TARGET_TMPDIR")).join("bom.csv");
+    let mut content = b"\xEF\xBB\xBF".to_vec();
+    content.extend(fs::read(Path::new(DATA_DIR).join("basic.csv")).unwrap());
+    fs::write(&input, content).unwrap();
+    let expected = fs::read_to_string(Path::new(DATA_DIR).join("basic.expected.csv")).unwrap();
+    let output = run(&input).arg("--mmap").assert().success();
+    assert_eq!(
+        sorted(&String::from_utf8(output.get_output().stdout.clone()).unwrap()),
+        sorted(&expected)
+    );
+}
+
+#[cfg(all(unix, feature = "grpc"))]
+#[test]
+fn terminated_server_writes_its_snapshot() {
+    use kitesurf_cli::proto::{
+        transaction_processor_client::TransactionProcessorClient, Transaction, TransactionType,
+    };
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("terminated-server");
+    fs::create_dir_all(&dir).unwrap();
+    let snapshot = dir.join("snapshot.json");
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin(BIN))
+        .args([
+            "serve-grpc",
+            "--addr",
+            &addr.to_string(),
+            "--queue-capacity",
+            "10",
+        ])
+        .arg("--snapshot-out")
+        .arg(&snapshot)
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let url = format!("http://{}", addr);
+        let mut client = loop {
+            match TransactionProcessorClient::connect(url.clone()).await {
+                Ok(client) => break client,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(50)).await,
+            }
+        };
+        for (r#type, amount) in [
+            (TransactionType::Deposit, Some(3.0)),
+            (TransactionType::Dispute, None),
+        ] {
+            let tx = Transaction {
+                r#type: r#type as i32,
+                client: 7,
+                tx: 70,
+                amount,
+                seq: None,
+            };
+            client.submit_transaction(tx).await.unwrap();
+        }
+    });
+    std::process::Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(output.status.code(), Some(130));
+
+    // the queued transactions were applied before writing
+    let state = kitesurf_cli::read_snapshot(snapshot.to_str().unwrap()).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let account = &state.accounts[&7];
+    assert_eq!(
+        (account.available().value(), account.held().unwrap().value()),
+        (0.0, 3.0)
+    );
+    assert!(state.tx_states[&70].disputed);
+}
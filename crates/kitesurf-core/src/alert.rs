@@ -0,0 +1,216 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{ClientId, Engine, Error, Tx, TxId, TxType};
+
+/// Condition an `AlertMonitor` watches for, written `held>1000`,
+/// `available<-50`, `chargebacks>5` or `rejections>100`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AlertRule {
+    /// An account holds more than this.
+    HeldAbove(f64),
+    /// An account's available funds drop below this.
+    AvailableBelow(f64),
+    /// More than this many chargebacks took effect so far.
+    ChargebacksAbove(u64),
+    /// More than this many transactions were rejected so far.
+    RejectionsAbove(u64),
+}
+
+impl FromStr for AlertRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::new(&format!("Invalid alert rule: {}", s));
+        let (metric, limit) = s.split_once(['>', '<']).ok_or_else(invalid)?;
+        let above = s.as_bytes()[metric.len()] == b'>';
+        let amount = || limit.trim().parse::<f64>().map_err(|_| invalid());
+        let count = || limit.trim().parse::<u64>().map_err(|_| invalid());
+        match (metric.trim(), above) {
+            ("held", true) => Ok(AlertRule::HeldAbove(amount()?)),
+            ("available", false) => Ok(AlertRule::AvailableBelow(amount()?)),
+            ("chargebacks", true) => Ok(AlertRule::ChargebacksAbove(count()?)),
+            ("rejections", true) => Ok(AlertRule::RejectionsAbove(count()?)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AlertRule::HeldAbove(limit) => write!(f, "held>{}", limit),
+            AlertRule::AvailableBelow(limit) => write!(f, "available<{}", limit),
+            AlertRule::ChargebacksAbove(limit) => write!(f, "chargebacks>{}", limit),
+            AlertRule::RejectionsAbove(limit) => write!(f, "rejections>{}", limit),
+        }
+    }
+}
+
+/// A rule that started to hold at the `index`th transaction.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Alert {
+    pub index: u64,
+    pub rule: AlertRule,
+    /// Account the rule is about, none for the totals of the run.
+    pub client: Option<ClientId>,
+    /// The balance or count that crossed the limit.
+    pub value: f64,
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at transaction {}: ", self.rule, self.index)?;
+        match self.client {
+            Some(client) => write!(f, "client {} at {}", client, self.value),
+            None => write!(f, "{} so far", self.value),
+        }
+    }
+}
+
+/// Checks `AlertRule`s after each transaction, so that operators hear of a
+/// bad batch while it's processed rather than from the final reports. An
+/// account rule fires once when an account starts to match it, and again
+/// only after the account stopped matching; a rule on the totals fires
+/// once.
+#[derive(Debug, Default, Clone)]
+pub struct AlertMonitor {
+    rules: Vec<AlertRule>,
+    chargebacks: u64,
+    rejections: u64,
+    /// Transactions whose chargeback was counted.
+    charged_back: HashSet<TxId>,
+    /// Rules, by position, currently matching each account or the totals.
+    firing: HashSet<(usize, Option<ClientId>)>,
+}
+
+impl AlertMonitor {
+    pub fn new(rules: Vec<AlertRule>) -> Self {
+        Self {
+            rules,
+            ..Self::default()
+        }
+    }
+
+    pub fn rules(&self) -> &[AlertRule] {
+        &self.rules
+    }
+
+    /// Alerts raised by `tx`, the `index`th transaction, which `engine`
+    /// just processed with `result`.
+    pub fn observe(
+        &mut self,
+        index: u64,
+        tx: &Tx,
+        result: &Result<(), Error>,
+        engine: &Engine,
+    ) -> Vec<Alert> {
+        match result {
+            Err(_) => self.rejections += 1,
+            Ok(()) if tx.type_ == TxType::Chargeback => {
+                let applied = engine
+                    .tx_states
+                    .get(&tx.tx_id)
+                    .is_some_and(|state| state.charged_back && state.client_id == tx.client_id);
+                if applied && self.charged_back.insert(tx.tx_id) {
+                    self.chargebacks += 1;
+                }
+            }
+            Ok(()) => {}
+        }
+        let account = engine.accounts.get(&tx.client_id);
+        let mut alerts = vec![];
+        for (at, rule) in self.rules.iter().enumerate() {
+            let (client, value, matches) = match *rule {
+                AlertRule::HeldAbove(limit) => {
+                    let held = account.map_or(0.0, |account| account.held);
+                    (Some(tx.client_id), held, held > limit)
+                }
+                AlertRule::AvailableBelow(limit) => {
+                    let available = account.map_or(0.0, |account| account.available);
+                    (Some(tx.client_id), available, available < limit)
+                }
+                AlertRule::ChargebacksAbove(limit) => {
+                    (None, self.chargebacks as f64, self.chargebacks > limit)
+                }
+                AlertRule::RejectionsAbove(limit) => {
+                    (None, self.rejections as f64, self.rejections > limit)
+                }
+            };
+            if !matches {
+                // totals only grow, so rules on them stay fired
+                if client.is_some() {
+                    self.firing.remove(&(at, client));
+                }
+            } else if self.firing.insert((at, client)) {
+                alerts.push(Alert {
+                    index,
+                    rule: *rule,
+                    client,
+                    value,
+                });
+            }
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::TxBuilder;
+
+    #[test]
+    fn parse_rules() -> Result<(), Error> {
+        assert_eq!(
+            "held>1000".parse::<AlertRule>()?,
+            AlertRule::HeldAbove(1000.0)
+        );
+        assert_eq!(
+            "available < -50".parse::<AlertRule>()?,
+            AlertRule::AvailableBelow(-50.0)
+        );
+        assert_eq!(
+            "chargebacks>5".parse::<AlertRule>()?,
+            AlertRule::ChargebacksAbove(5)
+        );
+        assert_eq!(AlertRule::RejectionsAbove(9).to_string(), "rejections>9");
+        for invalid in ["held<5", "chargebacks>1.5", "total>1", "held"] {
+            assert!(invalid.parse::<AlertRule>().is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn alerts_fire_as_rules_start_to_match() {
+        let mut monitor = AlertMonitor::new(vec![
+            AlertRule::HeldAbove(5.0),
+            AlertRule::ChargebacksAbove(0),
+        ]);
+        let mut engine = Engine::new();
+        let mut fired = vec![];
+        let txs = [
+            TxBuilder::deposit(1).tx(1).amount(10.0).build(),
+            TxBuilder::dispute(1).tx(1).build(),
+            TxBuilder::deposit(1).tx(2).amount(1.0).build(),
+            TxBuilder::resolve(1).tx(1).build(),
+            TxBuilder::dispute(1).tx(1).build(),
+            TxBuilder::chargeback(1).tx(1).build(),
+            TxBuilder::chargeback(1).tx(1).build(),
+        ];
+        for (index, tx) in (1..).zip(txs) {
+            let result = engine.process(tx.clone());
+            fired.extend(monitor.observe(index, &tx, &result, &engine));
+        }
+        let fired: Vec<String> = fired.iter().map(Alert::to_string).collect();
+        assert_eq!(
+            fired,
+            vec![
+                "held>5 at transaction 2: client 1 at 10",
+                "held>5 at transaction 5: client 1 at 10",
+                "chargebacks>0 at transaction 6: 1 so far",
+            ]
+        );
+    }
+}
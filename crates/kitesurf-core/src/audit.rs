@@ -0,0 +1,71 @@
+use serde::Serialize;
+
+use crate::{ClientId, Error, Tx, TxId, TxType};
+
+#[derive(Debug, Serialize, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    CloseAccount,
+    Adjustment,
+    /// Dispute resolved by the engine after the configured timeout.
+    AutoResolve,
+    /// A `TxType::Custom`, written as its name.
+    Custom(String),
+}
+
+impl From<&TxType> for AuditEvent {
+    fn from(type_: &TxType) -> Self {
+        match type_ {
+            TxType::Deposit => AuditEvent::Deposit,
+            TxType::Withdrawal => AuditEvent::Withdrawal,
+            TxType::Dispute => AuditEvent::Dispute,
+            TxType::Resolve => AuditEvent::Resolve,
+            TxType::Chargeback => AuditEvent::Chargeback,
+            TxType::CloseAccount => AuditEvent::CloseAccount,
+            TxType::Adjustment => AuditEvent::Adjustment,
+            TxType::Custom(name) => AuditEvent::Custom(name.clone()),
+        }
+    }
+}
+
+/// One line of the audit trail. `index` is the position of the input
+/// transaction that triggered the event, `error` is set when it was rejected.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct AuditEntry {
+    pub index: u64,
+    pub event: AuditEvent,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub amount: Option<f64>,
+    pub error: Option<String>,
+    pub description: Option<String>,
+    pub merchant: Option<String>,
+    pub category: Option<String>,
+    /// Currency the transaction came in, and its amount in that currency
+    /// when a `CurrencyConverter` converted it.
+    pub currency: Option<String>,
+    pub original_amount: Option<f64>,
+}
+
+impl AuditEntry {
+    pub fn new(index: u64, tx: &Tx, result: &Result<(), Error>) -> Self {
+        Self {
+            index,
+            event: AuditEvent::from(&tx.type_),
+            client: tx.client_id,
+            tx: tx.tx_id,
+            amount: tx.amount,
+            error: result.as_ref().err().map(|err| err.message.clone()),
+            description: tx.description.clone(),
+            merchant: tx.merchant.clone(),
+            category: tx.category.clone(),
+            currency: tx.currency.clone(),
+            original_amount: tx.original_amount,
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::{Error, ErrorCode, Tx, TxType};
+
+/// Stops a run fed a corrupted or hostile input before it does more harm
+/// downstream: it trips once too many of the last transactions were
+/// rejected, or too many chargebacks came in too short a time. Nothing trips
+/// until a limit is set.
+#[derive(Debug, Default, Clone)]
+pub struct CircuitBreaker {
+    /// Highest share of rejections, between 0 and 1, over the last `window`
+    /// transactions.
+    rejection_rate: Option<(f64, usize)>,
+    /// Most chargebacks within any `period`.
+    chargeback_rate: Option<(usize, Duration)>,
+    /// Whether each of the last `window` transactions was rejected.
+    outcomes: VecDeque<bool>,
+    rejections: usize,
+    chargebacks: VecDeque<Instant>,
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trip when more than `max_rate` (e.g. 0.5 for half) of the last
+    /// `window` transactions were rejected. Only full windows count, so a
+    /// few rejections at the start of a run don't trip it.
+    pub fn with_rejection_rate(mut self, max_rate: f64, window: usize) -> Self {
+        self.rejection_rate = Some((max_rate, window.max(1)));
+        self
+    }
+
+    /// Trip when more than `max` chargebacks were processed within `period`.
+    pub fn with_chargeback_rate(mut self, max: usize, period: Duration) -> Self {
+        self.chargeback_rate = Some((max, period));
+        self
+    }
+
+    /// Record that `tx` was processed with `result` at `now`, erroring once
+    /// the breaker trips.
+    pub fn record(
+        &mut self,
+        tx: &Tx,
+        result: &Result<(), Error>,
+        now: Instant,
+    ) -> Result<(), Error> {
+        if let Some((max_rate, window)) = self.rejection_rate {
+            self.outcomes.push_back(result.is_err());
+            self.rejections += usize::from(result.is_err());
+            if self.outcomes.len() > window && self.outcomes.pop_front() == Some(true) {
+                self.rejections -= 1;
+            }
+            let rate = self.rejections as f64 / window as f64;
+            if self.outcomes.len() == window && rate > max_rate {
+                return Err(Error::coded(
+                    ErrorCode::CircuitBreaker,
+                    &format!(
+                        "Circuit breaker tripped: {} of the last {} transactions rejected",
+                        self.rejections, window
+                    ),
+                ));
+            }
+        }
+        if let Some((max, period)) = self.chargeback_rate {
+            if tx.type_ == TxType::Chargeback && result.is_ok() {
+                self.chargebacks.push_back(now);
+            }
+            while self
+                .chargebacks
+                .front()
+                .is_some_and(|at| now.duration_since(*at) >= period)
+            {
+                self.chargebacks.pop_front();
+            }
+            if self.chargebacks.len() > max {
+                return Err(Error::coded(
+                    ErrorCode::CircuitBreaker,
+                    &format!(
+                        "Circuit breaker tripped: {} chargebacks within {:?}",
+                        self.chargebacks.len(),
+                        period
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::TxBuilder;
+
+    #[test]
+    fn trips_on_a_window_of_rejections() {
+        let mut breaker = CircuitBreaker::new().with_rejection_rate(0.5, 4);
+        let now = Instant::now();
+        let rejected = Err(Error::new("Nope"));
+        let deposit = TxBuilder::deposit(1).tx(1).build();
+        // 2 of 2, the window isn't full yet
+        assert!(breaker.record(&deposit, &rejected, now).is_ok());
+        assert!(breaker.record(&deposit, &rejected, now).is_ok());
+        assert!(breaker.record(&deposit, &Ok(()), now).is_ok());
+        assert!(breaker.record(&deposit, &Ok(()), now).is_ok());
+        // 2 of 4, at the limit
+        assert!(breaker.record(&deposit, &rejected, now).is_ok());
+        assert!(breaker.record(&deposit, &rejected, now).is_ok());
+        let err = breaker.record(&deposit, &rejected, now).unwrap_err();
+        assert_eq!(err.code, ErrorCode::CircuitBreaker);
+    }
+
+    #[test]
+    fn trips_on_a_burst_of_chargebacks() {
+        let mut breaker = CircuitBreaker::new().with_chargeback_rate(2, Duration::from_secs(60));
+        let start = Instant::now();
+        let chargeback = TxBuilder::chargeback(1).tx(1).build();
+        assert!(breaker.record(&chargeback, &Ok(()), start).is_ok());
+        assert!(breaker.record(&chargeback, &Ok(()), start).is_ok());
+        // the first two are a minute old by then
+        let later = start + Duration::from_secs(60);
+        assert!(breaker.record(&chargeback, &Ok(()), later).is_ok());
+        assert!(breaker
+            .record(&chargeback, &Err(Error::new("Nope")), later)
+            .is_ok());
+        assert!(breaker.record(&chargeback, &Ok(()), later).is_ok());
+        assert!(breaker.record(&chargeback, &Ok(()), later).is_err());
+    }
+}
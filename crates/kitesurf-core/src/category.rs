@@ -0,0 +1,60 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{ClientId, RoundingMode};
+
+/// Amounts withdrawn per client and `category`, for withdrawals that were
+/// applied. Withdrawals without a category are not counted.
+#[derive(Debug, Default, Clone)]
+pub struct CategorySpend {
+    spend: BTreeMap<ClientId, BTreeMap<String, f64>>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct CategoryTotal {
+    pub client: ClientId,
+    pub category: String,
+    pub spend: f64,
+}
+
+impl CategorySpend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, client_id: ClientId, category: &str, amount: f64) {
+        *self
+            .spend
+            .entry(client_id)
+            .or_default()
+            .entry(category.to_string())
+            .or_default() += amount;
+    }
+
+    pub(crate) fn client(&self, client_id: ClientId) -> Option<BTreeMap<String, f64>> {
+        self.spend.get(&client_id).cloned()
+    }
+
+    pub(crate) fn restore(&mut self, client_id: ClientId, spend: Option<BTreeMap<String, f64>>) {
+        match spend {
+            Some(spend) => self.spend.insert(client_id, spend),
+            None => self.spend.remove(&client_id),
+        };
+    }
+
+    /// Ordered by client, then category.
+    pub fn totals(&self) -> Vec<CategoryTotal> {
+        self.spend
+            .iter()
+            .flat_map(|(&client, categories)| {
+                categories
+                    .iter()
+                    .map(move |(category, &spend)| CategoryTotal {
+                        client,
+                        category: category.clone(),
+                        spend: RoundingMode::default().round(spend),
+                    })
+            })
+            .collect()
+    }
+}
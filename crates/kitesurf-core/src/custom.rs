@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{
+    Amount, Available, Balance, ClientAccount, ClientId, Error, ErrorCode, LockInfo, LockReason,
+    LockedPolicy, Total, Tx, TxId, TxType,
+};
+
+/// Changes a `CustomTx` can make to an account. Every operation keeps
+/// `total = available + held` and fails without changing anything if it
+/// would take available funds below zero. Amounts are `Amount`s, so they
+/// can't be negative, and held funds can't go below zero either.
+#[derive(Debug)]
+pub struct AccountOps<'a> {
+    account: &'a mut ClientAccount,
+    tx: TxId,
+}
+
+impl AccountOps<'_> {
+    pub fn client(&self) -> ClientId {
+        self.account.client
+    }
+
+    pub fn available(&self) -> Balance<Available> {
+        self.account.available()
+    }
+
+    pub fn held(&self) -> Result<Amount, Error> {
+        self.account.held()
+    }
+
+    pub fn total(&self) -> Balance<Total> {
+        self.account.total()
+    }
+
+    pub fn locked(&self) -> bool {
+        self.account.locked
+    }
+
+    /// Add `amount` to the available funds, e.g. a bonus.
+    pub fn credit(&mut self, amount: Amount) -> Result<(), Error> {
+        self.account.deposit(amount)
+    }
+
+    /// Take `amount` out of the available funds.
+    pub fn debit(&mut self, amount: Amount) -> Result<(), Error> {
+        self.account.withdraw(amount)
+    }
+
+    /// Move `amount` from the available to the held funds.
+    pub fn hold(&mut self, amount: Amount) -> Result<(), Error> {
+        if !self.available().covers(amount) {
+            return Err(Error::coded(
+                ErrorCode::InsufficientFunds,
+                "Insufficient funds",
+            ));
+        }
+        self.account.hold(amount)
+    }
+
+    /// Move `amount` from the held back to the available funds.
+    pub fn release(&mut self, amount: Amount) -> Result<(), Error> {
+        self.account.release(amount)
+    }
+
+    pub fn lock(&mut self) {
+        if !self.account.locked {
+            self.account.lock = Some(LockInfo {
+                reason: LockReason::Custom,
+                tx: self.tx,
+                tx_index: None,
+            });
+        }
+        self.account.locked = true;
+    }
+}
+
+/// A transaction type the engine doesn't know about, handled by the
+/// `CustomTxRegistry` it's registered in. Custom transactions can't be
+/// disputed, and are ignored on closed accounts and, unless the locked
+/// policy is `AllowAdmin`, on locked ones.
+pub trait CustomTx: Send + Sync {
+    /// Apply `tx` to its client's account. When this returns an error, the
+    /// changes it made are discarded.
+    fn apply(&self, tx: &Tx, account: &mut AccountOps) -> Result<(), Error>;
+
+    /// Whether a transaction for an unknown client opens its account, as a
+    /// deposit does. Otherwise it's ignored.
+    fn opens_account(&self) -> bool {
+        false
+    }
+}
+
+/// Handlers of custom transaction types, by the name in the `type` column.
+#[derive(Default, Clone)]
+pub struct CustomTxRegistry {
+    handlers: BTreeMap<String, Arc<dyn CustomTx>>,
+}
+
+impl fmt::Debug for CustomTxRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.handlers.keys()).finish()
+    }
+}
+
+impl CustomTxRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle transactions of type `name` with `handler`. `name` is made of
+    /// lowercase letters, digits and underscores, and can't be one of the
+    /// built-in types or registered twice.
+    pub fn register(&mut self, name: &str, handler: impl CustomTx + 'static) -> Result<(), Error> {
+        if name.is_empty()
+            || !name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+        {
+            return Err(Error::new(&format!(
+                "Invalid transaction type name {:?}, expected lowercase letters, digits and underscores",
+                name
+            )));
+        }
+        if name.parse::<TxType>().is_ok() || self.handlers.contains_key(name) {
+            return Err(Error::new(&format!(
+                "Transaction type {} is already defined",
+                name
+            )));
+        }
+        self.handlers.insert(name.to_string(), Arc::new(handler));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn CustomTx> {
+        self.handlers.get(name).map(|handler| handler.as_ref())
+    }
+
+    /// The built-in type called `name`, or the custom one registered as
+    /// `name`.
+    pub fn tx_type(&self, name: &str) -> Result<TxType, Error> {
+        match name.parse() {
+            Err(_) if self.handlers.contains_key(name) => Ok(TxType::Custom(name.to_string())),
+            result => result,
+        }
+    }
+
+    /// Apply a `TxType::Custom` transaction through its handler.
+    pub(crate) fn apply(
+        &self,
+        tx: &Tx,
+        accounts: &mut HashMap<ClientId, ClientAccount>,
+        locked_policy: LockedPolicy,
+    ) -> Result<(), Error> {
+        let handler = match &tx.type_ {
+            TxType::Custom(name) => self.get(name).ok_or_else(|| unknown_type(name))?,
+            _ => return Err(Error::new("Not a custom transaction")),
+        };
+        let mut account = match accounts.get(&tx.client_id) {
+            Some(account) => account.clone(),
+            None if handler.opens_account() => ClientAccount::new(tx.client_id),
+            None => return Ok(()),
+        };
+        if account.closed || account.locked && !locked_policy.allows(&tx.type_) {
+            return Ok(());
+        }
+        handler.apply(
+            tx,
+            &mut AccountOps {
+                account: &mut account,
+                tx: tx.tx_id,
+            },
+        )?;
+        accounts.insert(tx.client_id, account);
+        Ok(())
+    }
+}
+
+pub(crate) fn unknown_type(name: &str) -> Error {
+    Error::coded(
+        ErrorCode::UnknownTxType,
+        &format!("Unknown transaction type {}", name),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::TxBuilder;
+    use crate::Engine;
+
+    /// Credits the amount, opening the account if needed.
+    struct Bonus;
+
+    impl CustomTx for Bonus {
+        fn apply(&self, tx: &Tx, account: &mut AccountOps) -> Result<(), Error> {
+            account.credit(Amount::new(tx.amount.unwrap_or_default())?)
+        }
+
+        fn opens_account(&self) -> bool {
+            true
+        }
+    }
+
+    fn custom(name: &str) -> TxType {
+        TxType::Custom(name.to_string())
+    }
+
+    #[test]
+    fn custom_types_go_through_their_handler() -> Result<(), Error> {
+        let mut registry = CustomTxRegistry::new();
+        registry.register("bonus", Bonus)?;
+        assert!(registry.register("bonus", Bonus).is_err());
+        assert!(registry.register("deposit", Bonus).is_err());
+
+        let mut engine = Engine::new().with_custom_types(registry);
+        engine.process(TxBuilder::new(custom("bonus"), 1).tx(1).amount(2.5).build())?;
+        assert!(engine
+            .process(
+                TxBuilder::new(custom("bonus"), 1)
+                    .tx(1)
+                    .amount(-1.0)
+                    .build()
+            )
+            .is_err());
+        assert_eq!(
+            engine.process(TxBuilder::new(custom("reversal"), 1).tx(1).build()),
+            Err(Error::coded(
+                ErrorCode::UnknownTxType,
+                "Unknown transaction type reversal"
+            ))
+        );
+
+        let account = &engine.accounts[&1];
+        assert_eq!((account.available, account.total), (2.5, 2.5));
+        engine.check_invariants()
+    }
+
+    #[test]
+    fn custom_types_need_a_registry() -> Result<(), Error> {
+        let mut engine = Engine::new();
+        engine.process(TxBuilder::deposit(1).tx(1).amount(1.0).build())?;
+        assert!(engine
+            .process(TxBuilder::new(custom("bonus"), 1).tx(1).amount(1.0).build())
+            .is_err());
+        assert_eq!(engine.accounts[&1].available, 1.0);
+        Ok(())
+    }
+}
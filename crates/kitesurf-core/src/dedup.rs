@@ -0,0 +1,241 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+
+use crate::{Error, TxId};
+
+/// Ids `TxIdSet::new` sizes its bloom filters for.
+const EXPECTED_IDS: usize = 1 << 20;
+/// Bloom filter bits per expected id, for about 1% false positives.
+const BITS_PER_ID: usize = 12;
+/// Bits set per transaction id, all in the same word, so that they're set
+/// at once.
+const BLOOM_HASHES: u32 = 3;
+
+struct Shard {
+    bloom: Vec<AtomicU64>,
+    /// Ids inserted without the lock, moved into `exact` by the next thread
+    /// taking it.
+    claimed: Sender<TxId>,
+    /// Inserts between setting their bits and sending their id.
+    claiming: AtomicUsize,
+    exact: Mutex<Exact>,
+}
+
+struct Exact {
+    ids: HashSet<TxId>,
+    claimed: Receiver<TxId>,
+}
+
+impl Shard {
+    fn new(words: usize) -> Self {
+        let (claimed, receiver) = mpsc::channel();
+        Self {
+            bloom: (0..words.max(1)).map(|_| AtomicU64::new(0)).collect(),
+            claimed,
+            claiming: AtomicUsize::new(0),
+            exact: Mutex::new(Exact {
+                ids: HashSet::new(),
+                claimed: receiver,
+            }),
+        }
+    }
+
+    fn bits(&self, hash: u64) -> (&AtomicU64, u64) {
+        let word = &self.bloom[mix(hash) as usize % self.bloom.len()];
+        let mask = (0..BLOOM_HASHES).fold(0, |mask, i| mask | 1 << ((hash >> (i * 6)) & 63));
+        (word, mask)
+    }
+
+    fn may_contain(&self, hash: u64) -> bool {
+        let (word, mask) = self.bits(hash);
+        word.load(Ordering::SeqCst) & mask == mask
+    }
+
+    /// Sets the bits of `hash`, returning whether they weren't all set yet:
+    /// then no id with them was inserted before, and every later insert of
+    /// it finds them set.
+    fn mark(&self, hash: u64) -> bool {
+        let (word, mask) = self.bits(hash);
+        word.fetch_or(mask, Ordering::SeqCst) & mask != mask
+    }
+
+    /// The exact set, with the ids inserted without the lock so far.
+    fn exact(&self) -> Result<MutexGuard<'_, Exact>, Error> {
+        let mut exact = self
+            .exact
+            .lock()
+            .map_err(|_| Error::new("Transaction id set lock poisoned"))?;
+        // an id whose bits were set before ours is on its way
+        while self.claiming.load(Ordering::SeqCst) > 0 {
+            thread::yield_now();
+        }
+        let Exact { ids, claimed } = &mut *exact;
+        ids.extend(claimed.try_iter());
+        Ok(exact)
+    }
+}
+
+/// Concurrent set of transaction ids, for ids that must be unique across
+/// the shards of a `SharedEngine`. Ids are spread over their own shards,
+/// each an exact set behind its own lock, so inserts of different ids
+/// rarely contend. A bloom filter per shard answers inserts and lookups of
+/// most new ids without taking the lock.
+///
+/// The bloom filters are never cleared, as removed ids keep their bits, so
+/// size them for the ids expected over the set's lifetime with
+/// `with_capacity`. Past that, more new ids go through the lock.
+pub struct TxIdSet {
+    shards: Vec<Shard>,
+    lock_free_inserts: AtomicUsize,
+}
+
+impl std::fmt::Debug for TxIdSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TxIdSet")
+            .field("shards", &self.shards.len())
+            .finish()
+    }
+}
+
+impl TxIdSet {
+    pub fn new(num_shards: usize) -> Self {
+        Self::with_capacity(num_shards, EXPECTED_IDS)
+    }
+
+    /// A set whose bloom filters are sized for `expected_ids`.
+    pub fn with_capacity(num_shards: usize, expected_ids: usize) -> Self {
+        let num_shards = num_shards.max(1);
+        let words = expected_ids.saturating_mul(BITS_PER_ID) / num_shards / 64;
+        Self {
+            shards: (0..num_shards).map(|_| Shard::new(words)).collect(),
+            lock_free_inserts: AtomicUsize::new(0),
+        }
+    }
+
+    #[allow(clippy::unnecessary_cast)] // TxId is u64 with wide-tx-ids
+    fn shard(&self, tx_id: TxId) -> (&Shard, u64) {
+        let hash = mix(tx_id as u64);
+        (
+            &self.shards[(hash >> 32) as usize % self.shards.len()],
+            hash,
+        )
+    }
+
+    /// Add `tx_id`, returning whether it wasn't in the set yet. Takes the
+    /// lock only when the bloom filter already has its bits.
+    pub fn insert(&self, tx_id: TxId) -> Result<bool, Error> {
+        let (shard, hash) = self.shard(tx_id);
+        shard.claiming.fetch_add(1, Ordering::SeqCst);
+        let new = shard.mark(hash);
+        if new {
+            // the receiver lives as long as the shard
+            let _ = shard.claimed.send(tx_id);
+        }
+        shard.claiming.fetch_sub(1, Ordering::SeqCst);
+        if new {
+            self.lock_free_inserts.fetch_add(1, Ordering::Relaxed);
+            return Ok(true);
+        }
+        Ok(shard.exact()?.ids.insert(tx_id))
+    }
+
+    pub fn contains(&self, tx_id: TxId) -> Result<bool, Error> {
+        let (shard, hash) = self.shard(tx_id);
+        if !shard.may_contain(hash) {
+            return Ok(false);
+        }
+        Ok(shard.exact()?.ids.contains(&tx_id))
+    }
+
+    /// How many inserts didn't take a lock.
+    pub fn lock_free_inserts(&self) -> usize {
+        self.lock_free_inserts.load(Ordering::Relaxed)
+    }
+
+    /// Forget `tx_id`, e.g. when the transaction that claimed it didn't go
+    /// through. The bloom filter keeps its bits, so later lookups of the id
+    /// go to the exact set.
+    pub fn remove(&self, tx_id: TxId) -> Result<bool, Error> {
+        let (shard, _) = self.shard(tx_id);
+        Ok(shard.exact()?.ids.remove(&tx_id))
+    }
+
+    pub fn len(&self) -> Result<usize, Error> {
+        let mut len = 0;
+        for shard in &self.shards {
+            len += shard.exact()?.ids.len();
+        }
+        Ok(len)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.len()? == 0)
+    }
+}
+
+/// splitmix64 finalizer, so consecutive ids spread over shards and bits.
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn each_id_is_inserted_once_across_threads() -> Result<(), Error> {
+        let set = Arc::new(TxIdSet::new(8));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let set = Arc::clone(&set);
+                thread::spawn(move || -> Result<usize, Error> {
+                    let mut inserted = 0;
+                    for tx_id in 0..1000 {
+                        if set.insert(tx_id)? {
+                            inserted += 1;
+                        }
+                    }
+                    Ok(inserted)
+                })
+            })
+            .collect();
+        let mut inserted = 0;
+        for handle in handles {
+            inserted += handle.join().unwrap()?;
+        }
+        assert_eq!(inserted, 1000);
+        assert_eq!(set.len()?, 1000);
+
+        assert!(set.contains(999)?);
+        assert!(!set.contains(1000)?);
+        assert!(set.remove(999)?);
+        assert!(!set.contains(999)?);
+        assert!(set.insert(999)?);
+        Ok(())
+    }
+
+    #[test]
+    fn new_ids_skip_the_lock() -> Result<(), Error> {
+        let set = TxIdSet::with_capacity(2, 10_000);
+        for tx_id in 0..10_000 {
+            assert!(set.insert(tx_id)?);
+        }
+        // false positives of the bloom filter only
+        assert!(set.lock_free_inserts() > 9_900);
+        assert_eq!(set.len()?, 10_000);
+
+        // repeated and removed ids go through the exact set
+        assert!(!set.insert(42)?);
+        assert!(set.remove(42)?);
+        assert!(set.insert(42)?);
+        assert!(set.lock_free_inserts() < 10_000);
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{ClientAccount, ClientId, RoundingMode};
+
+/// Difference between the computed accounts and the expected ones, see
+/// `diff_accounts`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum AccountDiff {
+    /// Expected, but no such account was computed.
+    Missing(ClientAccount),
+    /// Computed, but not expected.
+    Unexpected(ClientAccount),
+    Changed {
+        expected: ClientAccount,
+        actual: ClientAccount,
+    },
+}
+
+impl AccountDiff {
+    pub fn client(&self) -> ClientId {
+        match self {
+            AccountDiff::Missing(account) | AccountDiff::Unexpected(account) => account.client,
+            AccountDiff::Changed { actual, .. } => actual.client,
+        }
+    }
+}
+
+impl fmt::Display for AccountDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountDiff::Missing(expected) => write!(
+                f,
+                "client {}: missing, expected available {}, held {}, total {}",
+                expected.client, expected.available, expected.held, expected.total
+            ),
+            AccountDiff::Unexpected(actual) => write!(
+                f,
+                "client {}: unexpected, got available {}, held {}, total {}",
+                actual.client, actual.available, actual.held, actual.total
+            ),
+            AccountDiff::Changed { expected, actual } => {
+                let mut fields = vec![];
+                let mut field =
+                    |name: &str, actual: &dyn fmt::Display, expected: &dyn fmt::Display| {
+                        let (actual, expected) = (actual.to_string(), expected.to_string());
+                        if actual != expected {
+                            fields.push(format!("{} {}, expected {}", name, actual, expected));
+                        }
+                    };
+                field("available", &actual.available, &expected.available);
+                field("held", &actual.held, &expected.held);
+                field("total", &actual.total, &expected.total);
+                field("locked", &actual.locked, &expected.locked);
+                field("closed", &actual.closed, &expected.closed);
+                write!(f, "client {}: {}", actual.client, fields.join("; "))
+            }
+        }
+    }
+}
+
+/// Compare `actual` to `expected` accounts, with balances rounded by
+/// `rounding` as in the output. Differences are ordered by client.
+pub fn diff_accounts(
+    actual: &HashMap<ClientId, ClientAccount>,
+    expected: &HashMap<ClientId, ClientAccount>,
+    rounding: RoundingMode,
+) -> Vec<AccountDiff> {
+    let rounded = |account: &ClientAccount| ClientAccount {
+        available: rounding.round(account.available),
+        held: rounding.round(account.held),
+        total: rounding.round(account.total),
+        // not in the CSV the expected accounts come from
+        lock: None,
+        ..account.clone()
+    };
+    let mut diffs = vec![];
+    for (client_id, expected) in expected {
+        let expected = rounded(expected);
+        match actual.get(client_id).map(rounded) {
+            None => diffs.push(AccountDiff::Missing(expected)),
+            Some(actual) if actual != expected => {
+                diffs.push(AccountDiff::Changed { expected, actual })
+            }
+            Some(_) => {}
+        }
+    }
+    for (client_id, actual) in actual {
+        if !expected.contains_key(client_id) {
+            diffs.push(AccountDiff::Unexpected(rounded(actual)));
+        }
+    }
+    diffs.sort_by_key(AccountDiff::client);
+    diffs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn account(client: ClientId, available: f64, locked: bool) -> ClientAccount {
+        ClientAccount {
+            client,
+            available,
+            held: 0.0,
+            total: available,
+            locked,
+            closed: false,
+            lock: None,
+        }
+    }
+
+    #[test]
+    fn differences_are_listed_by_client() {
+        let actual = HashMap::from([
+            (1, account(1, 1.00001, false)),
+            (2, account(2, 2.5, true)),
+            (4, account(4, 0.0, false)),
+        ]);
+        let expected = HashMap::from([
+            (1, account(1, 1.0, false)),
+            (2, account(2, 2.0, false)),
+            (3, account(3, 3.0, false)),
+        ]);
+        let diffs = diff_accounts(&actual, &expected, RoundingMode::default());
+        let lines: Vec<String> = diffs.iter().map(AccountDiff::to_string).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "client 2: available 2.5, expected 2; total 2.5, expected 2; locked true, expected false",
+                "client 3: missing, expected available 3, held 0, total 3",
+                "client 4: unexpected, got available 0, held 0, total 0",
+            ]
+        );
+        assert!(diff_accounts(&expected, &expected, RoundingMode::default()).is_empty());
+    }
+}
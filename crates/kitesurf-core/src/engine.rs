@@ -0,0 +1,3118 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+use crate::custom::unknown_type;
+use crate::rounding::tolerance;
+
+use crate::{
+    process_tx_with_policies, AccountCreation, Amount, AuditEntry, AuditEvent, CancellationToken,
+    CategorySpend, ClientAccount, ClientActivity, ClientId, ClientStats, Compat, CustomTxRegistry,
+    DisputeHoldPolicy, EngineState, Error, ErrorCode, Explanation, FeeSchedule, HoldReview,
+    LockedPolicy, MiddlewareChain, Outcome, Payout, PendingWithdrawals, ProbeAttempt, Recovery,
+    RecoveryLedger, ReorderBuffer, RevenueLedger, RiskMonitor, RoundingMode, RunSummary,
+    SequenceChecker, SignedAmount, Snapshot, SuspenseEntry, SuspenseReason, TransactionProcessor,
+    Tx, TxId, TxIdSet, TxState, TxStateType, TxType, ZeroAmountPolicy, DECIMALS,
+};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BatchResult {
+    /// Number of transactions applied, the whole batch.
+    Applied(usize),
+    /// Nothing was applied because the transaction at `index` failed.
+    Rejected { index: usize, error: Error },
+}
+
+/// What one `Engine::process` call changed, holding the previous value of
+/// everything it touched so `Engine::rollback` can put it back.
+#[derive(Debug, Default, Clone)]
+struct Delta {
+    /// Client of the transaction, whose sequence number, pending withdrawals,
+    /// risk activity, category spend, fees and recovery are kept below.
+    client_id: ClientId,
+    accounts: Vec<(ClientId, Option<ClientAccount>)>,
+    tx_states: Vec<(TxId, Option<TxState>)>,
+    last_seq: Option<u64>,
+    pending: Option<VecDeque<Tx>>,
+    activity: Option<ClientActivity>,
+    spend: Option<BTreeMap<String, f64>>,
+    fees: Option<f64>,
+    recovery: Option<Recovery>,
+    last_activity: Option<u64>,
+    /// Stats of the clients whose transactions changed state, auto-resolved
+    /// disputes included.
+    stats: Vec<(ClientId, Option<ClientStats>)>,
+    audit_len: usize,
+    payouts_len: usize,
+    hold_reviews_len: usize,
+    suspense_len: usize,
+    probes_len: usize,
+    opened_dispute: bool,
+    expired_disputes: Vec<(u64, TxId)>,
+    dispute_opened_at: Vec<(TxId, Option<u64>)>,
+    reorder: Option<ReorderBuffer>,
+}
+
+impl Delta {
+    fn remember_account(
+        &mut self,
+        client_id: ClientId,
+        accounts: &HashMap<ClientId, ClientAccount>,
+    ) {
+        if self.accounts.iter().all(|(client, _)| *client != client_id) {
+            self.accounts
+                .push((client_id, accounts.get(&client_id).cloned()));
+        }
+    }
+
+    fn remember_tx_state(&mut self, tx_id: TxId, tx_states: &HashMap<TxId, TxState>) {
+        if self.tx_states.iter().all(|(id, _)| *id != tx_id) {
+            self.tx_states.push((tx_id, tx_states.get(&tx_id).cloned()));
+        }
+    }
+
+    fn remember_stats(&mut self, client_id: ClientId, stats: &HashMap<ClientId, ClientStats>) {
+        if self.stats.iter().all(|(client, _)| *client != client_id) {
+            self.stats.push((client_id, stats.get(&client_id).copied()));
+        }
+    }
+
+    fn remember_dispute(&mut self, tx_id: TxId, opened_at: &HashMap<TxId, u64>) {
+        if self.dispute_opened_at.iter().all(|(id, _)| *id != tx_id) {
+            self.dispute_opened_at
+                .push((tx_id, opened_at.get(&tx_id).copied()));
+        }
+    }
+}
+
+/// What happens to disputes nobody resolves or charges back.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum DisputePolicy {
+    /// They stay open, holding the funds.
+    #[default]
+    Manual,
+    /// They're resolved after this many further transactions, see
+    /// `Engine::with_dispute_timeout`.
+    AutoResolveAfter(u64),
+}
+
+/// Fluent configuration of an `Engine`, see `Engine::builder`. Features
+/// with their own state (audit trail, fees, risk monitor...) are still
+/// enabled with the `Engine::with_*` methods on the built engine.
+#[derive(Debug, Default, Clone)]
+pub struct EngineBuilder {
+    storage: Snapshot,
+    dispute_policy: DisputePolicy,
+    locked_policy: LockedPolicy,
+    dispute_holds: DisputeHoldPolicy,
+    zero_amounts: ZeroAmountPolicy,
+    account_creation: AccountCreation,
+    compat: Compat,
+    rounding: Option<RoundingMode>,
+    precision: Option<u32>,
+    strict: bool,
+}
+
+impl EngineBuilder {
+    /// Accounts and transaction states to start from, e.g. a snapshot of
+    /// an earlier run. Empty by default.
+    pub fn storage(mut self, storage: Snapshot) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    pub fn dispute_policy(mut self, dispute_policy: DisputePolicy) -> Self {
+        self.dispute_policy = dispute_policy;
+        self
+    }
+
+    pub fn locked_policy(mut self, locked_policy: LockedPolicy) -> Self {
+        self.locked_policy = locked_policy;
+        self
+    }
+
+    pub fn dispute_holds(mut self, dispute_holds: DisputeHoldPolicy) -> Self {
+        self.dispute_holds = dispute_holds;
+        self
+    }
+
+    pub fn zero_amounts(mut self, zero_amounts: ZeroAmountPolicy) -> Self {
+        self.zero_amounts = zero_amounts;
+        self
+    }
+
+    pub fn account_creation(mut self, account_creation: AccountCreation) -> Self {
+        self.account_creation = account_creation;
+        self
+    }
+
+    /// Semantics to follow, `Compat::V1` by default. Doesn't change the
+    /// locked policy, see `Compat::locked_policy` for the one that goes
+    /// with it.
+    pub fn compat(mut self, compat: Compat) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    /// Round incoming amounts with `rounding`, to `precision` places or
+    /// `DECIMALS` without one. Amounts are taken as they come otherwise.
+    pub fn rounding(mut self, rounding: RoundingMode) -> Self {
+        self.rounding = Some(rounding);
+        self
+    }
+
+    /// Round incoming amounts to `places` decimals, at most `DECIMALS`,
+    /// with the default `RoundingMode` unless one is given.
+    pub fn precision(mut self, places: u32) -> Self {
+        self.precision = Some(places);
+        self
+    }
+
+    /// Reject transactions that `Engine::process` would otherwise ignore:
+    /// insufficient funds, duplicate ids, locked or closed accounts, and
+    /// disputes, resolves and chargebacks that don't apply. Withdrawals
+    /// short of funds are rejected rather than kept pending.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn build(self) -> Result<Engine, Error> {
+        let mut engine = Engine::from_snapshot(self.storage)
+            .with_locked_policy(self.locked_policy)
+            .with_dispute_holds(self.dispute_holds)
+            .with_zero_amounts(self.zero_amounts)
+            .with_account_creation(self.account_creation)
+            .with_compat(self.compat);
+        if let DisputePolicy::AutoResolveAfter(timeout) = self.dispute_policy {
+            engine = engine.with_dispute_timeout(timeout);
+        }
+        if self.rounding.is_some() || self.precision.is_some() {
+            let decimals = match self.precision {
+                None => DECIMALS,
+                Some(places) => i32::try_from(places)
+                    .ok()
+                    .filter(|places| *places <= DECIMALS)
+                    .ok_or_else(|| {
+                        Error::new(&format!("Precision is at most {} decimals", DECIMALS))
+                    })?,
+            };
+            engine.input_rounding = Some((self.rounding.unwrap_or_default(), decimals));
+        }
+        engine.strict = self.strict;
+        Ok(engine)
+    }
+}
+
+/// Single-threaded processing state: client accounts and the transactions
+/// that can later be disputed.
+#[derive(Debug, Default, Clone)]
+pub struct Engine {
+    pub(crate) accounts: HashMap<ClientId, ClientAccount>,
+    pub(crate) tx_states: HashMap<TxId, TxState>,
+    sequences: Option<SequenceChecker>,
+    /// Number of transactions passed to `process` so far.
+    processed: u64,
+    audit: Option<Vec<AuditEntry>>,
+    payouts: Vec<Payout>,
+    hold_policy: DisputeHoldPolicy,
+    hold_reviews: Vec<HoldReview>,
+    zero_amounts: ZeroAmountPolicy,
+    account_creation: AccountCreation,
+    compat: Compat,
+    /// Take negative deposits as adjustments, see
+    /// `Engine::with_negative_deposit_adjustments`.
+    adjust_negative_deposits: bool,
+    suspense: Option<Vec<SuspenseEntry>>,
+    probes: Option<Vec<ProbeAttempt>>,
+    dispute_timeout: Option<u64>,
+    /// Disputes in the order they were opened, with the index that opened them.
+    open_disputes: VecDeque<(u64, TxId)>,
+    dispute_opened_at: HashMap<TxId, u64>,
+    pending: Option<PendingWithdrawals>,
+    reorder: Option<ReorderBuffer>,
+    risk: Option<RiskMonitor>,
+    category_spend: Option<CategorySpend>,
+    fees: Option<FeeSchedule>,
+    revenue: Option<RevenueLedger>,
+    recoveries: Option<RecoveryLedger>,
+    /// Mode and decimals incoming amounts are rounded to.
+    input_rounding: Option<(RoundingMode, i32)>,
+    /// Reject transactions that wouldn't take effect, see
+    /// `EngineBuilder::strict`.
+    strict: bool,
+    max_amount: Option<Amount>,
+    max_balance: Option<Amount>,
+    /// Most accounts and stored transaction states, see
+    /// `Engine::with_max_accounts` and `Engine::with_max_tx_states`.
+    max_accounts: Option<usize>,
+    max_tx_states: Option<usize>,
+    custom_types: Option<CustomTxRegistry>,
+    middleware: MiddlewareChain,
+    /// Index of the last transaction that changed each account.
+    last_activity: Option<HashMap<ClientId, u64>>,
+    stats: Option<HashMap<ClientId, ClientStats>>,
+    locked_policy: LockedPolicy,
+    /// Changes of the last `rollback_depth` transactions, oldest first.
+    undo: Option<VecDeque<Delta>>,
+    rollback_depth: usize,
+    /// Changes of the transaction being processed.
+    recording: Option<Delta>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    /// Reject transactions whose `seq` doesn't follow the previous one of the
+    /// same client.
+    pub fn with_sequence_check(mut self) -> Self {
+        self.sequences = Some(SequenceChecker::new());
+        self
+    }
+
+    pub fn sequence_checker(&self) -> Option<&SequenceChecker> {
+        self.sequences.as_ref()
+    }
+
+    pub fn with_locked_policy(mut self, locked_policy: LockedPolicy) -> Self {
+        self.locked_policy = locked_policy;
+        self
+    }
+
+    /// What disputes of deposits the client already spent hold.
+    pub fn with_dispute_holds(mut self, hold_policy: DisputeHoldPolicy) -> Self {
+        self.hold_policy = hold_policy;
+        self
+    }
+
+    /// What deposits and withdrawals of a zero amount do.
+    pub fn with_zero_amounts(mut self, zero_amounts: ZeroAmountPolicy) -> Self {
+        self.zero_amounts = zero_amounts;
+        self
+    }
+
+    /// Which transactions open the account of a new client.
+    pub fn with_account_creation(mut self, account_creation: AccountCreation) -> Self {
+        self.account_creation = account_creation;
+        self
+    }
+
+    /// Follow the semantics of `compat` instead of the original ones.
+    pub fn with_compat(mut self, compat: Compat) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    /// Process deposits of a negative amount as `TxType::Adjustment`s
+    /// decreasing the balance, instead of deposits of the absolute amount.
+    pub fn with_negative_deposit_adjustments(mut self) -> Self {
+        self.adjust_negative_deposits = true;
+        self
+    }
+
+    /// Disputes that left negative available funds, in order, under
+    /// `DisputeHoldPolicy::FlagForReview`.
+    pub fn hold_reviews(&self) -> &[HoldReview] {
+        &self.hold_reviews
+    }
+
+    /// Record every processed transaction, see `Engine::audit_trail`.
+    pub fn with_audit(mut self) -> Self {
+        self.audit = Some(vec![]);
+        self
+    }
+
+    /// Automatically resolve disputes that are still open after `timeout`
+    /// further transactions, releasing the held funds.
+    pub fn with_dispute_timeout(mut self, timeout: u64) -> Self {
+        self.dispute_timeout = Some(timeout);
+        self
+    }
+
+    /// Hold resolves and chargebacks of a deposit that isn't disputed for
+    /// up to `window` further transactions, and apply them right after the
+    /// dispute if it arrives in time. They're ignored as usual otherwise.
+    pub fn with_reorder_window(mut self, window: u64) -> Self {
+        self.reorder = Some(ReorderBuffer::new(window));
+        self
+    }
+
+    pub fn reorder_buffer(&self) -> Option<&ReorderBuffer> {
+        self.reorder.as_ref()
+    }
+
+    /// Keep withdrawals exceeding the available funds pending instead of
+    /// dropping them, and retry them once the client's funds are sufficient.
+    pub fn with_pending_withdrawals(mut self) -> Self {
+        self.pending = Some(PendingWithdrawals::new());
+        self
+    }
+
+    pub fn pending_withdrawals(&self) -> Option<&PendingWithdrawals> {
+        self.pending.as_ref()
+    }
+
+    /// Track per-client activity for risk scoring, see `RiskMonitor::flagged`.
+    pub fn with_risk_monitor(mut self) -> Self {
+        self.risk = Some(RiskMonitor::new());
+        self
+    }
+
+    pub fn risk_monitor(&self) -> Option<&RiskMonitor> {
+        self.risk.as_ref()
+    }
+
+    /// Round amounts with more than `DECIMALS` places when they come in,
+    /// instead of carrying the extra precision through the balances.
+    pub fn with_input_rounding(mut self, rounding: RoundingMode) -> Self {
+        self.input_rounding = Some((rounding, DECIMALS));
+        self
+    }
+
+    /// Charge withdrawals the fee of the client's tier on top of their
+    /// amount, collected in `Engine::revenue`.
+    pub fn with_fees(mut self, fees: FeeSchedule) -> Self {
+        self.fees = Some(fees);
+        self.revenue = Some(RevenueLedger::new());
+        self
+    }
+
+    pub fn fee_schedule(&self) -> Option<&FeeSchedule> {
+        self.fees.as_ref()
+    }
+
+    pub fn revenue(&self) -> Option<&RevenueLedger> {
+        self.revenue.as_ref()
+    }
+
+    /// Track negative balances left by chargebacks of deposits the client
+    /// already spent, and later deposits paying them back, collected in
+    /// `Engine::recoveries`.
+    pub fn with_recoveries(mut self) -> Self {
+        self.recoveries = Some(RecoveryLedger::new());
+        self
+    }
+
+    pub fn recoveries(&self) -> Option<&RecoveryLedger> {
+        self.recoveries.as_ref()
+    }
+
+    /// Apply `TxType::Custom` transactions through their handler in
+    /// `registry`. Without one they're rejected as unknown.
+    pub fn with_custom_types(mut self, registry: CustomTxRegistry) -> Self {
+        self.custom_types = Some(registry);
+        self
+    }
+
+    /// Pass every transaction through `chain` first. Transactions it drops
+    /// or rejects aren't processed at all: they aren't counted, audited or
+    /// rolled back.
+    pub fn with_middleware(mut self, chain: MiddlewareChain) -> Self {
+        self.middleware = chain;
+        self
+    }
+
+    /// Reject deposits and withdrawals larger than `max_amount`.
+    pub fn with_max_amount(mut self, max_amount: Amount) -> Self {
+        self.max_amount = Some(max_amount);
+        self
+    }
+
+    /// Reject deposits that would take an account's total above
+    /// `max_balance`.
+    pub fn with_max_balance(mut self, max_balance: Amount) -> Self {
+        self.max_balance = Some(max_balance);
+        self
+    }
+
+    /// Fail transactions that would open an account beyond the first
+    /// `max_accounts` with `ErrorCode::ResourceLimit`, for callers to abort
+    /// the run instead of running out of memory on a corrupt input.
+    pub fn with_max_accounts(mut self, max_accounts: usize) -> Self {
+        self.max_accounts = Some(max_accounts);
+        self
+    }
+
+    /// Fail deposits and withdrawals that would store a transaction state
+    /// beyond the first `max_tx_states` with `ErrorCode::ResourceLimit`,
+    /// like `Engine::with_max_accounts`.
+    pub fn with_max_tx_states(mut self, max_tx_states: usize) -> Self {
+        self.max_tx_states = Some(max_tx_states);
+        self
+    }
+
+    /// Sum applied withdrawals per client and `category`.
+    pub fn with_category_spend(mut self) -> Self {
+        self.category_spend = Some(CategorySpend::new());
+        self
+    }
+
+    pub fn category_spend(&self) -> Option<&CategorySpend> {
+        self.category_spend.as_ref()
+    }
+
+    /// Track the last transaction that changed each account, see
+    /// `Engine::last_activity`.
+    pub fn with_last_activity(mut self) -> Self {
+        self.last_activity = Some(HashMap::new());
+        self
+    }
+
+    /// Index of the last transaction, counting from 1 as `process` is
+    /// called, that changed the client's balances or status. Disputes
+    /// auto-resolved by a timeout aren't counted, as no transaction of the
+    /// client caused them.
+    pub fn last_activity(&self) -> Option<&HashMap<ClientId, u64>> {
+        self.last_activity.as_ref()
+    }
+
+    /// Count the deposits, withdrawals, disputes and chargebacks that took
+    /// effect on each account, see `Engine::client_stats`.
+    pub fn with_client_stats(mut self) -> Self {
+        self.stats = Some(HashMap::new());
+        self
+    }
+
+    pub fn client_stats(&self) -> Option<&HashMap<ClientId, ClientStats>> {
+        self.stats.as_ref()
+    }
+
+    /// Keep what the last `depth` transactions changed, see `Engine::rollback`.
+    pub fn with_rollback(mut self, depth: usize) -> Self {
+        self.undo = Some(VecDeque::new());
+        self.rollback_depth = depth;
+        self
+    }
+
+    /// Record disputes, resolves and chargebacks of unknown transactions,
+    /// see `Engine::suspense_ledger`.
+    pub fn with_suspense(mut self) -> Self {
+        self.suspense = Some(vec![]);
+        self
+    }
+
+    pub fn suspense_ledger(&self) -> &[SuspenseEntry] {
+        self.suspense.as_deref().unwrap_or_default()
+    }
+
+    /// Record disputes, resolves and chargebacks referring to the
+    /// transactions of other clients, whether rejected or ignored, see
+    /// `Engine::probes`.
+    pub fn with_probe_log(mut self) -> Self {
+        self.probes = Some(vec![]);
+        self
+    }
+
+    pub fn probes(&self) -> &[ProbeAttempt] {
+        self.probes.as_deref().unwrap_or_default()
+    }
+
+    /// Funds paid out by `close_account` transactions, in order.
+    pub fn payouts(&self) -> &[Payout] {
+        &self.payouts
+    }
+
+    pub fn audit_trail(&self) -> &[AuditEntry] {
+        self.audit.as_deref().unwrap_or_default()
+    }
+
+    /// Start from existing balances, e.g. the previous run's closing positions.
+    /// Only the balances are known, so earlier deposits can't be disputed.
+    pub fn seed_accounts(
+        &mut self,
+        accounts: HashMap<ClientId, ClientAccount>,
+    ) -> Result<(), Error> {
+        for (client_id, account) in accounts {
+            if self.accounts.contains_key(&client_id) {
+                return Err(Error::new(&format!(
+                    "Client {} already has an account",
+                    client_id
+                )));
+            }
+            self.accounts.insert(client_id, account);
+        }
+        Ok(())
+    }
+
+    /// Open empty accounts for `clients` that don't have one, e.g. the
+    /// registered clients under `AccountCreation::Never`.
+    pub fn register_accounts(&mut self, clients: impl IntoIterator<Item = ClientId>) {
+        for client_id in clients {
+            self.accounts
+                .entry(client_id)
+                .or_insert_with(|| ClientAccount::new(client_id));
+        }
+    }
+
+    /// Restore the state exported by `Engine::snapshot`.
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        Self {
+            accounts: snapshot.accounts,
+            tx_states: snapshot.tx_states,
+            ..Self::default()
+        }
+    }
+
+    /// Export the state processing goes on from, see `EngineState`.
+    pub fn state(&self) -> EngineState {
+        let mut open_disputes: Vec<(u64, TxId)> = self
+            .dispute_opened_at
+            .iter()
+            .map(|(tx_id, opened_at)| (*opened_at, *tx_id))
+            .collect();
+        open_disputes.sort_unstable();
+        EngineState {
+            accounts: self.accounts.clone(),
+            tx_states: self.tx_states.clone(),
+            processed: self.processed,
+            open_disputes,
+            sequences: self.sequences.clone(),
+            pending: self.pending.clone(),
+            reorder: self.reorder.clone(),
+        }
+    }
+
+    /// Restore the state exported by `Engine::state`, replacing the
+    /// engine's. Sequence checks, pending withdrawals and the reorder
+    /// window are on if they were when it was exported; other features and
+    /// settings are configured as usual.
+    pub fn with_state(mut self, state: EngineState) -> Self {
+        self.accounts = state.accounts;
+        self.tx_states = state.tx_states;
+        self.processed = state.processed;
+        self.dispute_opened_at = state
+            .open_disputes
+            .iter()
+            .map(|(opened_at, tx_id)| (*tx_id, *opened_at))
+            .collect();
+        self.open_disputes = state.open_disputes.into();
+        self.sequences = state.sequences;
+        self.pending = state.pending;
+        self.reorder = state.reorder;
+        self
+    }
+
+    /// Accounts, in no particular order. Use `Engine::accounts_sorted` for
+    /// an order that's the same from one run to the next.
+    pub fn accounts(&self) -> impl ExactSizeIterator<Item = &ClientAccount> {
+        self.accounts.values()
+    }
+
+    /// Accounts by client.
+    pub fn accounts_sorted(&self) -> impl ExactSizeIterator<Item = &ClientAccount> {
+        let mut accounts: Vec<&ClientAccount> = self.accounts.values().collect();
+        accounts.sort_by_key(|account| account.client);
+        accounts.into_iter()
+    }
+
+    pub fn account_count(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn account(&self, client_id: ClientId) -> Option<&ClientAccount> {
+        self.accounts.get(&client_id)
+    }
+
+    /// Accounts keyed by client, e.g. for `diff_accounts` or `lock_report`.
+    pub fn accounts_by_client(&self) -> &HashMap<ClientId, ClientAccount> {
+        &self.accounts
+    }
+
+    /// Keep only the accounts `keep` is true of, e.g. to leave filtered
+    /// out accounts out of the output.
+    pub fn retain_accounts(&mut self, mut keep: impl FnMut(&ClientAccount) -> bool) {
+        self.accounts.retain(|_, account| keep(account));
+    }
+
+    /// The accounts, once processing is done.
+    pub fn into_accounts(self) -> HashMap<ClientId, ClientAccount> {
+        self.accounts
+    }
+
+    /// Transactions that can still be disputed or were, in no particular
+    /// order. Use `Engine::tx_states_sorted` for a stable one.
+    pub fn tx_states(&self) -> impl ExactSizeIterator<Item = (TxId, &TxState)> {
+        self.tx_states.iter().map(|(tx_id, state)| (*tx_id, state))
+    }
+
+    /// Transaction states by id.
+    pub fn tx_states_sorted(&self) -> impl ExactSizeIterator<Item = (TxId, &TxState)> {
+        let mut states: Vec<(TxId, &TxState)> = self.tx_states().collect();
+        states.sort_by_key(|(tx_id, _)| *tx_id);
+        states.into_iter()
+    }
+
+    pub fn tx_state_count(&self) -> usize {
+        self.tx_states.len()
+    }
+
+    pub fn tx_state(&self, tx_id: TxId) -> Option<&TxState> {
+        self.tx_states.get(&tx_id)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            accounts: self.accounts.clone(),
+            tx_states: self.tx_states.clone(),
+        }
+    }
+
+    /// Check that every account has `total == available + held`, no
+    /// negative `held`, no negative `total` unless it's locked (a
+    /// chargeback can leave a locked account in debt), and no funds left on
+    /// closed accounts. Balances are compared
+    /// at output precision. All violations are listed in the error, ordered
+    /// by client.
+    pub fn check_invariants(&self) -> Result<(), Error> {
+        let tolerance = tolerance();
+        let mut clients: Vec<&ClientAccount> = self.accounts.values().collect();
+        clients.sort_by_key(|account| account.client);
+        let mut violations = vec![];
+        for account in clients {
+            if (account.total - (account.available + account.held)).abs() >= tolerance {
+                violations.push(format!(
+                    "client {}: total {} != available {} + held {}",
+                    account.client, account.total, account.available, account.held
+                ));
+            }
+            if account.held <= -tolerance {
+                violations.push(format!(
+                    "client {}: negative held {}",
+                    account.client, account.held
+                ));
+            }
+            if account.closed && (account.total.abs() >= tolerance || account.held != 0.0) {
+                violations.push(format!(
+                    "client {}: closed account holds {}",
+                    account.client, account.total
+                ));
+            }
+            if !account.locked && account.total <= -tolerance {
+                violations.push(format!(
+                    "client {}: negative total {} on an unlocked account",
+                    account.client, account.total
+                ));
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::coded(
+                ErrorCode::Invariants,
+                &format!("Invariants violated: {}", violations.join("; ")),
+            ))
+        }
+    }
+
+    pub fn process(&mut self, tx: Tx) -> Result<(), Error> {
+        let tx = match self.prepare(tx)? {
+            Some(tx) => tx,
+            None => return Ok(()),
+        };
+        if self.undo.is_some() {
+            self.recording = Some(Delta {
+                client_id: tx.client_id,
+                last_seq: self
+                    .sequences
+                    .as_ref()
+                    .and_then(|sequences| sequences.last_seq(tx.client_id)),
+                pending: self
+                    .pending
+                    .as_ref()
+                    .and_then(|pending| pending.queue(tx.client_id)),
+                activity: self
+                    .risk
+                    .as_ref()
+                    .and_then(|risk| risk.activity(tx.client_id).cloned()),
+                spend: self
+                    .category_spend
+                    .as_ref()
+                    .and_then(|spend| spend.client(tx.client_id)),
+                fees: self
+                    .revenue
+                    .as_ref()
+                    .and_then(|revenue| revenue.client(tx.client_id)),
+                recovery: self
+                    .recoveries
+                    .as_ref()
+                    .and_then(|recoveries| recoveries.client(tx.client_id)),
+                last_activity: self
+                    .last_activity
+                    .as_ref()
+                    .and_then(|last_activity| last_activity.get(&tx.client_id).copied()),
+                audit_len: self.audit_trail().len(),
+                payouts_len: self.payouts.len(),
+                hold_reviews_len: self.hold_reviews.len(),
+                suspense_len: self.suspense_ledger().len(),
+                probes_len: self.probes().len(),
+                reorder: self.reorder.clone(),
+                ..Delta::default()
+            });
+        }
+        self.processed += 1;
+        let recorded_tx = (self.audit.is_some() || self.risk.is_some()).then(|| tx.clone());
+        let tx_id = tx.tx_id;
+        let client_id = tx.client_id;
+        let account_before = self
+            .last_activity
+            .as_ref()
+            .map(|_| self.accounts.get(&client_id).cloned());
+        let total_before = self.recoveries.as_ref().map(|_| {
+            let total = self
+                .accounts
+                .get(&client_id)
+                .map_or(0.0, |account| account.total);
+            (tx.type_ == TxType::Chargeback, total)
+        });
+        let state_before = self
+            .stats
+            .as_ref()
+            .map(|_| self.tx_states.get(&tx_id).cloned());
+        let opens_dispute = tx.type_ == TxType::Dispute
+            && self.tx_states.get(&tx_id).map(|state| state.disputed) == Some(false);
+        let orphan = match (&tx.type_, self.tx_states.get(&tx_id)) {
+            _ if self.suspense.is_none() => None,
+            (TxType::Dispute | TxType::Resolve | TxType::Chargeback, None) => {
+                Some(SuspenseReason::UnknownTx)
+            }
+            // `Compat::V1` applies them to the referring client
+            (TxType::Dispute | TxType::Resolve | TxType::Chargeback, Some(state))
+                if state.client_id != tx.client_id && self.compat == Compat::V2 =>
+            {
+                Some(SuspenseReason::OtherClient)
+            }
+            _ => None,
+        }
+        .map(|reason| SuspenseEntry {
+            index: self.processed,
+            event: AuditEvent::from(&tx.type_),
+            client: tx.client_id,
+            tx: tx_id,
+            reason,
+        });
+        let probe = match (&tx.type_, self.tx_states.get(&tx_id)) {
+            _ if self.probes.is_none() => None,
+            (TxType::Dispute | TxType::Resolve | TxType::Chargeback, Some(state))
+                if state.client_id != tx.client_id =>
+            {
+                Some(ProbeAttempt {
+                    index: self.processed,
+                    event: AuditEvent::from(&tx.type_),
+                    client: tx.client_id,
+                    tx: tx_id,
+                    owner: state.client_id,
+                })
+            }
+            _ => None,
+        };
+        let review = match (
+            self.hold_policy,
+            self.tx_states.get(&tx_id),
+            self.accounts.get(&client_id),
+        ) {
+            (DisputeHoldPolicy::FlagForReview, Some(state), Some(account))
+                if opens_dispute && state.amount.value() > account.available =>
+            {
+                Some(HoldReview {
+                    index: self.processed,
+                    client: client_id,
+                    tx: tx_id,
+                    held: state.amount.value(),
+                    available: account.available,
+                })
+            }
+            _ => None,
+        };
+
+        let result = self.apply(tx);
+        if let Some(before) = state_before {
+            self.record_stats(tx_id, before);
+        }
+        if let (Some(last_activity), Some(before), Ok(())) =
+            (self.last_activity.as_mut(), account_before, &result)
+        {
+            if self.accounts.get(&client_id) != before.as_ref() {
+                last_activity.insert(client_id, self.processed);
+            }
+        }
+        if let (Some(account), Ok(())) = (self.accounts.get_mut(&client_id), &result) {
+            if let Some(lock) = account.lock.as_mut().filter(|lock| lock.tx == tx_id) {
+                lock.tx_index.get_or_insert(self.processed);
+            }
+        }
+        if let (Some(recoveries), Some((chargeback, before)), Ok(())) =
+            (self.recoveries.as_mut(), total_before, &result)
+        {
+            if let Some(account) = self.accounts.get(&client_id) {
+                recoveries.record(client_id, chargeback, before, account.total);
+            }
+        }
+        if let (Some(suspense), Some(entry), Ok(())) = (self.suspense.as_mut(), orphan, &result) {
+            suspense.push(entry);
+        }
+        if let (Some(probes), Some(probe)) = (self.probes.as_mut(), probe) {
+            probes.push(probe);
+        }
+        // unless the dispute didn't apply, e.g. to another client's deposit
+        if let (Some(review), Ok(())) = (review, &result) {
+            if self.tx_states.get(&tx_id).map(|state| state.disputed) == Some(true) {
+                self.hold_reviews.push(review);
+            }
+        }
+        if let Some(tx) = recorded_tx {
+            if let Some(audit) = self.audit.as_mut() {
+                audit.push(AuditEntry::new(self.processed, &tx, &result));
+            }
+            if let (Some(risk), Ok(())) = (self.risk.as_mut(), &result) {
+                risk.record(self.processed, &tx);
+            }
+        }
+        if self.dispute_timeout.is_some()
+            && opens_dispute
+            && self.tx_states.get(&tx_id).map(|state| state.disputed) == Some(true)
+        {
+            if let Some(delta) = self.recording.as_mut() {
+                delta.opened_dispute = true;
+                delta.remember_dispute(tx_id, &self.dispute_opened_at);
+            }
+            self.open_disputes.push_back((self.processed, tx_id));
+            self.dispute_opened_at.insert(tx_id, self.processed);
+        }
+        self.expire_disputes();
+        if let Some(reorder) = self.reorder.as_mut() {
+            reorder.expire(self.processed);
+        }
+        if let (Some(undo), Some(delta)) = (self.undo.as_mut(), self.recording.take()) {
+            undo.push_back(delta);
+            if undo.len() > self.rollback_depth {
+                undo.pop_front();
+            }
+        }
+        result
+    }
+
+    /// `tx` as `process` takes it: through the middleware, rounded and
+    /// with negative deposits turned into adjustments as configured.
+    fn prepare(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        match self.middleware.handle(tx)? {
+            Some(tx) => Ok(Some(self.normalize(tx))),
+            None => Ok(None),
+        }
+    }
+
+    /// Input rounding and negative deposit adjustments, after middleware.
+    fn normalize(&self, mut tx: Tx) -> Tx {
+        if let Some((rounding, decimals)) = self.input_rounding {
+            tx.amount = tx.amount.map(|amount| rounding.round_to(amount, decimals));
+        }
+        if self.adjust_negative_deposits
+            && tx.type_ == TxType::Deposit
+            && tx.amount.is_some_and(|amount| amount < 0.0)
+        {
+            tx.type_ = TxType::Adjustment;
+        }
+        tx
+    }
+
+    /// What processing `tx` would do, without changing the engine: the
+    /// outcome and why, the settings it depends on, and the account and
+    /// transaction state before and after. Middleware only checks the
+    /// transaction (`TxMiddleware::check`), so e.g. `DedupTxs` doesn't
+    /// remember it and a later `process` still applies it.
+    pub fn explain(&self, tx: Tx) -> Explanation {
+        let account_before = self.accounts.get(&tx.client_id).cloned();
+        let tx_state_before = self.tx_states.get(&tx.tx_id).cloned();
+        let explanation = |tx, outcome, engine: Option<&Engine>| Explanation {
+            policies: self.policies_for(&tx),
+            account_after: engine.map_or(account_before.clone(), |engine| {
+                engine.accounts.get(&tx.client_id).cloned()
+            }),
+            tx_state_after: engine.map_or(tx_state_before.clone(), |engine| {
+                engine.tx_states.get(&tx.tx_id).cloned()
+            }),
+            account_before: account_before.clone(),
+            tx_state_before: tx_state_before.clone(),
+            outcome,
+            tx,
+        };
+        let prepared = match self.middleware.check(tx.clone()) {
+            Ok(Some(checked)) => self.normalize(checked),
+            Ok(None) => return explanation(tx, Outcome::Dropped, None),
+            Err(err) => return explanation(tx, Outcome::Rejected(err), None),
+        };
+        let mut engine = self.clone();
+        engine.middleware = MiddlewareChain::new();
+        let pending = |engine: &Engine| {
+            engine
+                .pending
+                .as_ref()
+                .and_then(|pending| pending.queue(prepared.client_id))
+                .map_or(0, |queue| queue.len())
+        };
+        let held = |engine: &Engine| engine.reorder.as_ref().map_or(0, ReorderBuffer::len);
+        let outcome = match engine.process(prepared.clone()) {
+            Err(err) => Outcome::Rejected(err),
+            Ok(()) if pending(&engine) > pending(self) => {
+                Outcome::Held("Short of funds, pending until deposits cover it".to_string())
+            }
+            Ok(()) if held(&engine) > held(self) => {
+                Outcome::Held("Not disputed yet, held until its dispute arrives".to_string())
+            }
+            Ok(())
+                if engine.accounts.get(&prepared.client_id) != account_before.as_ref()
+                    || engine.tx_states.get(&prepared.tx_id) != tx_state_before.as_ref() =>
+            {
+                Outcome::Applied
+            }
+            Ok(()) => Outcome::Ignored(match self.check_takes_effect(&prepared) {
+                Err(err) => err.message,
+                Ok(()) if prepared.amount == Some(0.0) => "Zero amount".to_string(),
+                Ok(()) => "No effect".to_string(),
+            }),
+        };
+        explanation(prepared, outcome, Some(&engine))
+    }
+
+    /// Settings of the engine that decide what `tx` does.
+    fn policies_for(&self, tx: &Tx) -> Vec<String> {
+        let mut policies = vec![];
+        if !self.middleware.is_empty() {
+            policies.push(format!("{} middleware steps", self.middleware.len()));
+        }
+        match self.accounts.get(&tx.client_id) {
+            Some(account) if account.locked => {
+                policies.push(format!("locked policy {:?}", self.locked_policy));
+            }
+            None if self.account_creation != AccountCreation::Implicit => {
+                policies.push(format!(
+                    "account creation policy {:?}",
+                    self.account_creation
+                ));
+            }
+            _ => {}
+        }
+        match tx.type_ {
+            TxType::Deposit | TxType::Withdrawal => {
+                if tx.amount == Some(0.0) {
+                    policies.push(format!("zero amount policy {:?}", self.zero_amounts));
+                }
+                if let Some(max_amount) = self.max_amount {
+                    policies.push(format!("max amount {}", max_amount));
+                }
+                if let Some(max_balance) = self.max_balance {
+                    policies.push(format!("max balance {}", max_balance));
+                }
+            }
+            TxType::Dispute => {
+                policies.push(format!("dispute hold policy {:?}", self.hold_policy));
+                if let Some(timeout) = self.dispute_timeout {
+                    policies.push(format!("disputes auto-resolved after {}", timeout));
+                }
+            }
+            TxType::Resolve | TxType::Chargeback if self.reorder.is_some() => {
+                policies.push("reorder window".to_string());
+            }
+            _ => {}
+        }
+        if tx.type_ == TxType::Withdrawal {
+            let fee = self.fee_for(tx);
+            if fee > 0.0 {
+                policies.push(format!("fee {}", fee));
+            }
+            if self.pending.is_some() {
+                policies.push("pending withdrawals".to_string());
+            }
+        }
+        if self.adjust_negative_deposits && tx.type_ == TxType::Adjustment {
+            policies.push("negative deposits as adjustments".to_string());
+        }
+        if self.compat != Compat::V1 {
+            policies.push(format!("compat {:?}", self.compat));
+        }
+        if self.strict {
+            policies.push("strict".to_string());
+        }
+        policies
+    }
+
+    /// Revert the last `n` transactions passed to `process`, including
+    /// disputes they auto-resolved and pending withdrawals they released.
+    /// To go further back than the configured depth, restore a snapshot
+    /// with `Engine::from_snapshot` instead.
+    pub fn rollback(&mut self, n: usize) -> Result<(), Error> {
+        let undo = self
+            .undo
+            .as_mut()
+            .ok_or_else(|| Error::new("Rollback is not enabled"))?;
+        if n > undo.len() {
+            return Err(Error::new(&format!(
+                "Can't roll back {} transactions, only {} are kept",
+                n,
+                undo.len()
+            )));
+        }
+        let deltas: Vec<Delta> = undo.drain(undo.len() - n..).rev().collect();
+        for delta in deltas {
+            self.revert(delta);
+        }
+        Ok(())
+    }
+
+    fn revert(&mut self, delta: Delta) {
+        for (client_id, account) in delta.accounts.into_iter().rev() {
+            match account {
+                Some(account) => self.accounts.insert(client_id, account),
+                None => self.accounts.remove(&client_id),
+            };
+        }
+        for (tx_id, state) in delta.tx_states.into_iter().rev() {
+            match state {
+                Some(state) => self.tx_states.insert(tx_id, state),
+                None => self.tx_states.remove(&tx_id),
+            };
+        }
+        if let Some(sequences) = self.sequences.as_mut() {
+            sequences.restore(delta.client_id, delta.last_seq);
+        }
+        if let Some(pending) = self.pending.as_mut() {
+            pending.restore(delta.client_id, delta.pending);
+        }
+        if let Some(risk) = self.risk.as_mut() {
+            risk.restore(delta.client_id, delta.activity);
+        }
+        if let Some(spend) = self.category_spend.as_mut() {
+            spend.restore(delta.client_id, delta.spend);
+        }
+        if let Some(revenue) = self.revenue.as_mut() {
+            revenue.restore(delta.client_id, delta.fees);
+        }
+        if let Some(recoveries) = self.recoveries.as_mut() {
+            recoveries.restore(delta.client_id, delta.recovery);
+        }
+        if self.reorder.is_some() {
+            self.reorder = delta.reorder;
+        }
+        if let Some(last_activity) = self.last_activity.as_mut() {
+            match delta.last_activity {
+                Some(index) => last_activity.insert(delta.client_id, index),
+                None => last_activity.remove(&delta.client_id),
+            };
+        }
+        if let Some(stats) = self.stats.as_mut() {
+            for (client_id, client_stats) in delta.stats.into_iter().rev() {
+                match client_stats {
+                    Some(client_stats) => stats.insert(client_id, client_stats),
+                    None => stats.remove(&client_id),
+                };
+            }
+        }
+        if let Some(audit) = self.audit.as_mut() {
+            audit.truncate(delta.audit_len);
+        }
+        self.payouts.truncate(delta.payouts_len);
+        self.hold_reviews.truncate(delta.hold_reviews_len);
+        if let Some(suspense) = self.suspense.as_mut() {
+            suspense.truncate(delta.suspense_len);
+        }
+        if let Some(probes) = self.probes.as_mut() {
+            probes.truncate(delta.probes_len);
+        }
+        if delta.opened_dispute {
+            self.open_disputes.pop_back();
+        }
+        for expired in delta.expired_disputes.into_iter().rev() {
+            self.open_disputes.push_front(expired);
+        }
+        for (tx_id, opened_at) in delta.dispute_opened_at.into_iter().rev() {
+            match opened_at {
+                Some(opened_at) => self.dispute_opened_at.insert(tx_id, opened_at),
+                None => self.dispute_opened_at.remove(&tx_id),
+            };
+        }
+        self.processed -= 1;
+    }
+
+    /// Refuse engines whose results depend on the order of transactions
+    /// across clients, which `process_parallel` doesn't keep: dispute
+    /// timeouts and reorder windows count every transaction, pending
+    /// withdrawals are retried on later ones, and `Compat::V1` disputes of
+    /// another client's transaction move the funds of the disputing one.
+    pub(crate) fn check_parallel(&self) -> Result<(), Error> {
+        if self.compat == Compat::V1 {
+            return Err(Error::new(
+                "Compat::V1 disputes reach across clients and need sequential processing",
+            ));
+        }
+        if self.dispute_timeout.is_some()
+            || self.reorder.is_some()
+            || self.pending.is_some()
+            || self.undo.is_some()
+        {
+            return Err(Error::new(
+                "Dispute timeouts, reorder windows, pending withdrawals and rollback need sequential processing",
+            ));
+        }
+        if self.processed > 0 {
+            return Err(Error::new(
+                "Parallel processing needs an engine without processed transactions",
+            ));
+        }
+        Ok(())
+    }
+
+    /// `process` as the `index`th transaction of the input, counting from 1,
+    /// which reports refer to.
+    pub(crate) fn process_at(&mut self, index: u64, tx: Tx) -> Result<(), Error> {
+        self.processed = index - 1;
+        self.process(tx)
+    }
+
+    /// Learn the state of a transaction of a client processed elsewhere, so
+    /// its id is taken as in a sequential run.
+    pub(crate) fn know_tx_state(&mut self, tx_id: TxId, state: TxState) {
+        self.tx_states.entry(tx_id).or_insert(state);
+    }
+
+    /// Take over the state of `clients` from `worker`, a copy of this
+    /// engine that processed all their transactions. Its report entries are
+    /// appended, see `Engine::order_by_index`.
+    pub(crate) fn absorb(&mut self, worker: Engine, clients: &HashSet<ClientId>) {
+        for &client_id in clients {
+            if let Some(account) = worker.accounts.get(&client_id) {
+                self.accounts.insert(client_id, account.clone());
+            }
+            if let (Some(sequences), Some(theirs)) = (self.sequences.as_mut(), &worker.sequences) {
+                sequences.restore(client_id, theirs.last_seq(client_id));
+            }
+            if let (Some(risk), Some(theirs)) = (self.risk.as_mut(), &worker.risk) {
+                risk.restore(client_id, theirs.activity(client_id).cloned());
+            }
+            if let (Some(spend), Some(theirs)) =
+                (self.category_spend.as_mut(), &worker.category_spend)
+            {
+                spend.restore(client_id, theirs.client(client_id));
+            }
+            if let (Some(revenue), Some(theirs)) = (self.revenue.as_mut(), &worker.revenue) {
+                revenue.restore(client_id, theirs.client(client_id));
+            }
+            if let (Some(recoveries), Some(theirs)) = (self.recoveries.as_mut(), &worker.recoveries)
+            {
+                recoveries.restore(client_id, theirs.client(client_id));
+            }
+            if let (Some(last_activity), Some(theirs)) =
+                (self.last_activity.as_mut(), &worker.last_activity)
+            {
+                if let Some(&index) = theirs.get(&client_id) {
+                    last_activity.insert(client_id, index);
+                }
+            }
+            if let (Some(stats), Some(theirs)) = (self.stats.as_mut(), &worker.stats) {
+                if let Some(&client_stats) = theirs.get(&client_id) {
+                    stats.insert(client_id, client_stats);
+                }
+            }
+        }
+        self.tx_states.extend(
+            worker
+                .tx_states
+                .into_iter()
+                .filter(|(_, state)| clients.contains(&state.client_id)),
+        );
+        if let (Some(audit), Some(theirs)) = (self.audit.as_mut(), worker.audit) {
+            audit.extend(theirs);
+        }
+        if let (Some(suspense), Some(theirs)) = (self.suspense.as_mut(), worker.suspense) {
+            suspense.extend(theirs);
+        }
+        if let (Some(probes), Some(theirs)) = (self.probes.as_mut(), worker.probes) {
+            probes.extend(theirs);
+        }
+        self.payouts.extend(worker.payouts);
+        self.hold_reviews.extend(worker.hold_reviews);
+    }
+
+    /// Once every worker is absorbed, put the report entries in input order
+    /// as a sequential run of `processed` transactions would have them.
+    /// `payout_indices` are the indices of the transactions that made the
+    /// payouts.
+    pub(crate) fn order_by_index(&mut self, payout_indices: &[u64], processed: u64) {
+        // stable, keeping the order of the entries of one transaction
+        if let Some(audit) = self.audit.as_mut() {
+            audit.sort_by_key(|entry| entry.index);
+        }
+        if let Some(suspense) = self.suspense.as_mut() {
+            suspense.sort_by_key(|entry| entry.index);
+        }
+        if let Some(probes) = self.probes.as_mut() {
+            probes.sort_by_key(|probe| probe.index);
+        }
+        self.hold_reviews.sort_by_key(|review| review.index);
+        let mut payouts: Vec<(u64, Payout)> = payout_indices
+            .iter()
+            .copied()
+            .zip(self.payouts.drain(..))
+            .collect();
+        payouts.sort_by_key(|(index, _)| *index);
+        self.payouts = payouts.into_iter().map(|(_, payout)| payout).collect();
+        self.processed = processed;
+    }
+
+    /// Apply related transactions, e.g. a transfer and its fee, all or
+    /// nothing: they're processed against a copy of the engine, which only
+    /// replaces this one once every transaction succeeded.
+    ///
+    /// Unlike `process`, a transaction that would be silently ignored
+    /// (insufficient funds, locked account, unknown or duplicate tx id)
+    /// rejects the batch, as would a withdrawal left pending.
+    pub fn apply_batch(&mut self, txs: &[Tx]) -> BatchResult {
+        let mut batch = self.clone();
+        if let Err((index, error)) = batch.process_batch(txs.iter().enumerate()) {
+            return BatchResult::Rejected { index, error };
+        }
+        *self = batch;
+        BatchResult::Applied(txs.len())
+    }
+
+    /// `process` each transaction of `txs` until they run out or `cancel`
+    /// is cancelled, which is checked before each one. A cancelled stream
+    /// leaves the engine as it was after the last transaction processed, so
+    /// its accounts and the returned summary are a consistent partial
+    /// result, and processing can go on with the rest of the stream later.
+    pub fn process_stream(
+        &mut self,
+        txs: impl IntoIterator<Item = Tx>,
+        cancel: &CancellationToken,
+    ) -> RunSummary {
+        TransactionProcessor::process_stream(self, txs, cancel)
+    }
+
+    /// Process the batch's transactions, stopping at the first that fails
+    /// with its index. Leaves the engine half-way on error, so it must run
+    /// on a copy.
+    fn process_batch<'a>(
+        &mut self,
+        txs: impl IntoIterator<Item = (usize, &'a Tx)>,
+    ) -> Result<(), (usize, Error)> {
+        for (index, tx) in txs {
+            self.check_takes_effect(tx)
+                .and_then(|()| self.process(tx.clone()))
+                .map_err(|error| (index, error.with_tx(tx.tx_id)))?;
+        }
+        Ok(())
+    }
+
+    /// Reject what `Compat::V1` took silently: negative deposits and
+    /// withdrawals, disputes of negative deposits and reused transaction
+    /// ids.
+    fn check_compat(&self, tx: &Tx) -> Result<(), Error> {
+        if tx.type_ == TxType::Dispute
+            && self.tx_states.get(&tx.tx_id).is_some_and(|state| {
+                state.type_ == TxStateType::Deposit
+                    && state.client_id == tx.client_id
+                    && state.amount.is_negative()
+            })
+        {
+            return Err(Error::coded(
+                ErrorCode::NotApplicable,
+                "Dispute of a negative deposit",
+            ));
+        }
+        if !matches!(
+            tx.type_,
+            TxType::Deposit | TxType::Withdrawal | TxType::Adjustment
+        ) {
+            return Ok(());
+        }
+        if self.tx_states.contains_key(&tx.tx_id) {
+            return Err(Error::coded(
+                ErrorCode::DuplicateTx,
+                "Duplicate transaction id",
+            ));
+        }
+        if matches!(tx.type_, TxType::Deposit | TxType::Withdrawal)
+            && tx.amount.is_some_and(|amount| amount < 0.0)
+        {
+            return Err(Error::coded(
+                ErrorCode::NegativeAmount,
+                &format!("{:?} of a negative amount", tx.type_),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_takes_effect(&self, tx: &Tx) -> Result<(), Error> {
+        let account = self.accounts.get(&tx.client_id);
+        if account.map(|account| account.closed) == Some(true) {
+            return Err(Error::coded(ErrorCode::AccountClosed, "Account is closed"));
+        }
+        if account.map(|account| account.locked) == Some(true)
+            && !self.locked_policy.allows(&tx.type_)
+        {
+            return Err(Error::coded(ErrorCode::AccountLocked, "Account is locked"));
+        }
+        if tx.type_ == TxType::CloseAccount {
+            return match account {
+                Some(account) if account.held == 0.0 => Ok(()),
+                Some(_) => Err(Error::coded(
+                    ErrorCode::NotApplicable,
+                    "Account with held funds can't be closed",
+                )),
+                None => Err(Error::coded(
+                    ErrorCode::NotApplicable,
+                    "Unknown account can't be closed",
+                )),
+            };
+        }
+        if let TxType::Custom(name) = &tx.type_ {
+            return match self.custom_types.as_ref().and_then(|types| types.get(name)) {
+                Some(_) => Ok(()),
+                None => Err(unknown_type(name)),
+            };
+        }
+        let state = self.tx_states.get(&tx.tx_id);
+        match (&tx.type_, state) {
+            (TxType::Deposit, None) => Ok(()),
+            (TxType::Withdrawal, None) => {
+                let available = account.map(|account| account.available).unwrap_or(0.0);
+                match tx.amount {
+                    Some(amount) if amount + self.fee_for(tx) > available => Err(Error::coded(
+                        ErrorCode::InsufficientFunds,
+                        "Insufficient funds",
+                    )),
+                    _ => Ok(()),
+                }
+            }
+            (TxType::Adjustment, None) if account.is_some() => Ok(()),
+            (TxType::Adjustment, None) => Err(Error::coded(
+                ErrorCode::NotApplicable,
+                "Unknown account can't be adjusted",
+            )),
+            (TxType::Deposit | TxType::Withdrawal | TxType::Adjustment, Some(_)) => Err(
+                Error::coded(ErrorCode::DuplicateTx, "Duplicate transaction id"),
+            ),
+            (TxType::Dispute, Some(state))
+                if state.type_ == TxStateType::Deposit
+                    && !state.amount.is_negative()
+                    && !state.disputed
+                    && state.client_id == tx.client_id =>
+            {
+                Ok(())
+            }
+            (TxType::Resolve | TxType::Chargeback, Some(state))
+                if state.type_ == TxStateType::Deposit
+                    && state.disputed
+                    && state.client_id == tx.client_id =>
+            {
+                Ok(())
+            }
+            (
+                TxType::Dispute
+                | TxType::Resolve
+                | TxType::Chargeback
+                | TxType::CloseAccount
+                | TxType::Custom(_),
+                _,
+            ) => Err(Error::coded(
+                ErrorCode::NotApplicable,
+                &format!("{:?} doesn't apply to transaction {}", tx.type_, tx.tx_id),
+            )),
+        }
+    }
+
+    fn apply(&mut self, tx: Tx) -> Result<(), Error> {
+        if let Some(sequences) = self.sequences.as_mut() {
+            sequences.check(&tx)?;
+        }
+        self.check_limits(&tx)?;
+        self.check_account_creation(&tx)?;
+        self.check_resources(&tx)?;
+        let zero_amount = matches!(tx.type_, TxType::Deposit | TxType::Withdrawal)
+            && tx.amount == Some(0.0)
+            && !self.tx_states.contains_key(&tx.tx_id);
+        match self.zero_amounts {
+            ZeroAmountPolicy::Ignore if zero_amount => return Ok(()),
+            ZeroAmountPolicy::Reject if zero_amount => {
+                return Err(Error::coded(
+                    ErrorCode::ZeroAmount,
+                    &format!("{:?} of a zero amount", tx.type_),
+                ))
+            }
+            _ => {}
+        }
+        if self.settles_before_dispute(&tx) {
+            if let Some(reorder) = self.reorder.as_mut() {
+                reorder.hold(self.processed, tx);
+            }
+            return Ok(());
+        }
+        if self.compat == Compat::V2 {
+            self.check_compat(&tx)?;
+        }
+        if self.strict {
+            self.check_takes_effect(&tx)?;
+        }
+        if self.pending.is_some() && self.is_short_of_funds(&tx) {
+            self.pending
+                .get_or_insert_with(PendingWithdrawals::new)
+                .push(tx);
+            return Ok(());
+        }
+        let (client_id, tx_id) = (tx.client_id, tx.tx_id);
+        let dispute = tx.type_ == TxType::Dispute;
+        self.process_tx(tx)?;
+        let settle = match self.reorder.as_mut() {
+            Some(reorder)
+                if dispute && self.tx_states.get(&tx_id).map(|s| s.disputed) == Some(true) =>
+            {
+                reorder.take(tx_id)
+            }
+            _ => None,
+        };
+        if let Some(settle) = settle {
+            self.process_tx(settle)?;
+        }
+        self.retry_pending(client_id);
+        Ok(())
+    }
+
+    /// Reject `tx` if its client has no account and it can't open one.
+    fn check_account_creation(&self, tx: &Tx) -> Result<(), Error> {
+        if self.accounts.contains_key(&tx.client_id) {
+            return Ok(());
+        }
+        let opens = match self.account_creation {
+            AccountCreation::Implicit => true,
+            AccountCreation::FirstDeposit => tx.type_ == TxType::Deposit,
+            AccountCreation::Never => false,
+        };
+        if opens {
+            return Ok(());
+        }
+        Err(Error::coded(
+            ErrorCode::UnknownClient,
+            &format!("Client {} has no account", tx.client_id),
+        ))
+    }
+
+    /// Whether `tx` opens an account for a client without one: a deposit or
+    /// withdrawal, any transaction under `Compat::V1`, or a custom one whose
+    /// handler opens accounts.
+    fn opens_account(&self, tx: &Tx) -> bool {
+        match (&tx.type_, self.compat) {
+            (TxType::Custom(name), _) => self
+                .custom_types
+                .as_ref()
+                .and_then(|custom_types| custom_types.get(name))
+                .is_some_and(|handler| handler.opens_account()),
+            (_, Compat::V1) => true,
+            (TxType::Deposit | TxType::Withdrawal, Compat::V2) => true,
+            (_, Compat::V2) => false,
+        }
+    }
+
+    /// Fail `tx` if it could open an account or store a transaction state
+    /// past the `max_accounts` or `max_tx_states` ones.
+    fn check_resources(&self, tx: &Tx) -> Result<(), Error> {
+        if let Some(max_accounts) = self.max_accounts {
+            if self.opens_account(tx)
+                && !self.accounts.contains_key(&tx.client_id)
+                && self.accounts.len() >= max_accounts
+            {
+                return Err(Error::coded(
+                    ErrorCode::ResourceLimit,
+                    &format!(
+                        "Client {} would exceed the maximum of {} accounts",
+                        tx.client_id, max_accounts
+                    ),
+                ));
+            }
+        }
+        if let Some(max_tx_states) = self.max_tx_states {
+            if matches!(tx.type_, TxType::Deposit | TxType::Withdrawal)
+                && !self.tx_states.contains_key(&tx.tx_id)
+                && self.tx_states.len() >= max_tx_states
+            {
+                return Err(Error::coded(
+                    ErrorCode::ResourceLimit,
+                    &format!(
+                        "Transaction {} would exceed the maximum of {} stored transactions",
+                        tx.tx_id, max_tx_states
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `tx` is a resolve or chargeback of the client's deposit that
+    /// isn't disputed (yet), to hold with `Engine::with_reorder_window`.
+    fn settles_before_dispute(&self, tx: &Tx) -> bool {
+        self.reorder.is_some()
+            && matches!(tx.type_, TxType::Resolve | TxType::Chargeback)
+            && self.tx_states.get(&tx.tx_id).is_some_and(|state| {
+                state.type_ == TxStateType::Deposit
+                    && !state.disputed
+                    && state.client_id == tx.client_id
+            })
+    }
+
+    fn process_tx(&mut self, tx: Tx) -> Result<(), Error> {
+        if let Some(delta) = self.recording.as_mut() {
+            delta.remember_account(tx.client_id, &self.accounts);
+            delta.remember_tx_state(tx.tx_id, &self.tx_states);
+        }
+        let spend = match (&tx.type_, &tx.category, tx.amount) {
+            (TxType::Withdrawal, Some(category), Some(amount))
+                if self.category_spend.is_some() && !self.tx_states.contains_key(&tx.tx_id) =>
+            {
+                Some((tx.client_id, tx.tx_id, category.clone(), amount))
+            }
+            _ => None,
+        };
+        let closing = match (&tx.type_, self.accounts.get(&tx.client_id)) {
+            (TxType::CloseAccount, Some(account)) if !account.closed => Some(Payout {
+                client: tx.client_id,
+                tx: tx.tx_id,
+                amount: account.available,
+            }),
+            _ => None,
+        };
+        let client_id = tx.client_id;
+        let tx_id = tx.tx_id;
+        let fee = match tx.type_ {
+            TxType::Withdrawal if !self.tx_states.contains_key(&tx_id) => self.fee_for(&tx),
+            _ => 0.0,
+        };
+        if fee > 0.0 {
+            let amount = tx.amount.unwrap_or_default();
+            match self.accounts.get(&client_id) {
+                Some(account) if amount + fee <= account.available => {}
+                // can't pay the fee on top, same as insufficient funds
+                _ => return Ok(()),
+            }
+        }
+        match (&tx.type_, &self.custom_types) {
+            (TxType::Custom(_), Some(custom_types)) => {
+                custom_types.apply(&tx, &mut self.accounts, self.locked_policy)?
+            }
+            _ => process_tx_with_policies(
+                tx,
+                &mut self.accounts,
+                &mut self.tx_states,
+                self.locked_policy,
+                self.hold_policy,
+                self.compat,
+            )?,
+        }
+        if fee > 0.0 && self.tx_states.contains_key(&tx_id) {
+            if let Some(account) = self.accounts.get_mut(&client_id) {
+                account.adjust(-SignedAmount::new(fee)?)?;
+            }
+            self.revenue
+                .get_or_insert_with(RevenueLedger::new)
+                .record(client_id, fee);
+        }
+        if let Some(payout) = closing {
+            if self.accounts[&client_id].closed {
+                self.payouts.push(payout);
+            }
+        }
+        // insufficient funds and locked accounts leave no transaction state
+        if let Some((client_id, tx_id, category, amount)) = spend {
+            if self.tx_states.contains_key(&tx_id) {
+                self.category_spend
+                    .get_or_insert_with(CategorySpend::new)
+                    .record(client_id, &category, amount);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_limits(&self, tx: &Tx) -> Result<(), Error> {
+        let amount = match (&tx.type_, tx.amount) {
+            (TxType::Deposit | TxType::Withdrawal, Some(amount)) => amount,
+            _ => return Ok(()),
+        };
+        if let Some(max_amount) = self.max_amount {
+            if amount > max_amount.value() {
+                return Err(Error::coded(
+                    ErrorCode::LimitExceeded,
+                    &format!("Amount {} exceeds the maximum of {}", amount, max_amount),
+                ));
+            }
+        }
+        if let (TxType::Deposit, Some(max_balance)) = (&tx.type_, self.max_balance) {
+            let total = self
+                .accounts
+                .get(&tx.client_id)
+                .map_or(0.0, |account| account.total);
+            if total + amount > max_balance.value() {
+                return Err(Error::coded(
+                    ErrorCode::LimitExceeded,
+                    &format!(
+                        "Deposit would take the balance to {}, above the maximum of {}",
+                        total + amount,
+                        max_balance
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fee of a withdrawal, none for other transactions.
+    fn fee_for(&self, tx: &Tx) -> f64 {
+        match (&tx.type_, tx.amount, self.fees.as_ref()) {
+            (TxType::Withdrawal, Some(amount), Some(fees)) => fees.fee(tx.client_id, amount),
+            _ => 0.0,
+        }
+    }
+
+    /// New withdrawal on an unlocked account that exceeds the available
+    /// funds, fee included.
+    fn is_short_of_funds(&self, tx: &Tx) -> bool {
+        let amount = match (&tx.type_, tx.amount) {
+            (TxType::Withdrawal, Some(amount)) => amount + self.fee_for(tx),
+            _ => return false,
+        };
+        if self.tx_states.contains_key(&tx.tx_id) {
+            return false;
+        }
+        match self.accounts.get(&tx.client_id) {
+            Some(account) => !account.locked && amount > account.available,
+            None => amount > 0.0,
+        }
+    }
+
+    fn retry_pending(&mut self, client_id: ClientId) {
+        let (pending, account) = match (self.pending.as_mut(), self.accounts.get(&client_id)) {
+            (Some(pending), Some(account)) if !account.locked => (pending, account),
+            _ => return,
+        };
+        let fees = self.fees.as_ref();
+        let cost = |amount| amount + fees.map_or(0.0, |fees| fees.fee(client_id, amount));
+        for tx in pending.take_ready_with(client_id, account.available, cost) {
+            let _result = self.process_tx(tx);
+        }
+    }
+
+    /// Count the change of `tx_id`'s state from `before` in the stats of
+    /// its client.
+    fn record_stats(&mut self, tx_id: TxId, before: Option<TxState>) {
+        let after = self.tx_states.get(&tx_id);
+        let (Some(stats), Some(client_id)) = (
+            self.stats.as_mut(),
+            after.or(before.as_ref()).map(|state| state.client_id),
+        ) else {
+            return;
+        };
+        if before.as_ref() == after {
+            return;
+        }
+        if let Some(delta) = self.recording.as_mut() {
+            delta.remember_stats(client_id, stats);
+        }
+        stats
+            .entry(client_id)
+            .or_default()
+            .record(before.as_ref(), after);
+    }
+
+    fn expire_disputes(&mut self) {
+        let timeout = match self.dispute_timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+        while let Some(&(opened_at, tx_id)) = self.open_disputes.front() {
+            if opened_at + timeout > self.processed {
+                break;
+            }
+            self.open_disputes.pop_front();
+            if let Some(delta) = self.recording.as_mut() {
+                delta.expired_disputes.push((opened_at, tx_id));
+                delta.remember_dispute(tx_id, &self.dispute_opened_at);
+            }
+            // skip disputes that were closed, and reopened since
+            if self.dispute_opened_at.get(&tx_id) != Some(&opened_at) {
+                continue;
+            }
+            self.dispute_opened_at.remove(&tx_id);
+            let (client_id, amount) = match self.tx_states.get(&tx_id) {
+                Some(state) if state.disputed => (state.client_id, state.amount.value()),
+                _ => continue,
+            };
+            let resolve = Tx::new(TxType::Resolve, client_id, tx_id);
+            let state_before = self
+                .stats
+                .as_ref()
+                .map(|_| self.tx_states.get(&tx_id).cloned());
+            let result = self.process_tx(resolve);
+            if let Some(before) = state_before {
+                self.record_stats(tx_id, before);
+            }
+            if let Some(audit) = self.audit.as_mut() {
+                audit.push(AuditEntry {
+                    index: self.processed,
+                    event: AuditEvent::AutoResolve,
+                    client: client_id,
+                    tx: tx_id,
+                    amount: Some(amount),
+                    error: result.err().map(|err| err.message),
+                    description: None,
+                    merchant: None,
+                    category: None,
+                    currency: None,
+                    original_amount: None,
+                });
+            }
+        }
+    }
+}
+
+/// Engine that can be shared between worker threads.
+///
+/// Clients are spread over a fixed number of shards, each behind its own
+/// `RwLock`, so transactions for clients living in different shards are
+/// applied in parallel while transactions for the same client are serialized.
+/// Disputes and the like are tracked within the shard of the client that
+/// issued the transaction, while the ids of deposits and withdrawals are
+/// also claimed in a `TxIdSet`, so an id already used in another shard is
+/// rejected as a duplicate.
+///
+/// Reads go through copies of each shard's accounts, which writers update
+/// when done and readers share, copy-on-write, so reads never wait on the
+/// shards being written.
+#[derive(Debug)]
+pub struct SharedEngine {
+    shards: Vec<RwLock<Engine>>,
+    /// The accounts of each shard as of its last write, updated together for
+    /// a batch.
+    published: Mutex<Vec<ShardAccounts>>,
+    tx_ids: TxIdSet,
+}
+
+type ShardAccounts = Arc<HashMap<ClientId, ClientAccount>>;
+
+/// Whether `tx` takes a new transaction id, unless its shard knows it.
+fn claims_tx_id(tx: &Tx, engine: &Engine) -> bool {
+    matches!(
+        tx.type_,
+        TxType::Deposit | TxType::Withdrawal | TxType::Adjustment
+    ) && !engine.tx_states.contains_key(&tx.tx_id)
+}
+
+impl SharedEngine {
+    /// Shards in `Compat::V2` with its locked policy, see `with_builder`.
+    pub fn new(num_shards: usize) -> Self {
+        let shards = (0..num_shards.max(1))
+            .map(|_| {
+                Engine::new()
+                    .with_compat(Compat::V2)
+                    .with_locked_policy(Compat::V2.locked_policy())
+            })
+            .collect();
+        Self::from_shards(shards)
+    }
+
+    /// Shards built by `builder`, whose storage is spread over them by
+    /// client. `Compat::V1` is refused: its disputes of another client's
+    /// transaction would reach into another shard.
+    pub fn with_builder(num_shards: usize, mut builder: EngineBuilder) -> Result<Self, Error> {
+        if builder.compat == Compat::V1 {
+            return Err(Error::new(
+                "Compat::V1 disputes reach across clients, which a SharedEngine keeps apart",
+            ));
+        }
+        let num_shards = num_shards.max(1);
+        let storage = std::mem::take(&mut builder.storage);
+        let mut shards: Vec<Snapshot> = (0..num_shards).map(|_| Snapshot::default()).collect();
+        for (client_id, account) in storage.accounts {
+            shards[client_id as usize % num_shards]
+                .accounts
+                .insert(client_id, account);
+        }
+        for (tx_id, tx_state) in storage.tx_states {
+            shards[tx_state.client_id as usize % num_shards]
+                .tx_states
+                .insert(tx_id, tx_state);
+        }
+        let shards = shards
+            .into_iter()
+            .map(|storage| builder.clone().storage(storage).build())
+            .collect::<Result<Vec<Engine>, Error>>()?;
+        let mut engine = Self::from_shards(shards);
+        engine.claim_stored_tx_ids()?;
+        Ok(engine)
+    }
+
+    fn from_shards(shards: Vec<Engine>) -> Self {
+        let published = shards
+            .iter()
+            .map(|engine| Arc::new(engine.accounts.clone()))
+            .collect();
+        Self {
+            tx_ids: TxIdSet::new(shards.len()),
+            published: Mutex::new(published),
+            shards: shards.into_iter().map(RwLock::new).collect(),
+        }
+    }
+
+    /// Sizes the filter of the claimed transaction ids for `expected` ids
+    /// over the engine's lifetime, see `TxIdSet::with_capacity`.
+    pub fn with_expected_tx_ids(mut self, expected: usize) -> Result<Self, Error> {
+        self.tx_ids = TxIdSet::with_capacity(self.shards.len(), expected);
+        self.claim_stored_tx_ids()?;
+        Ok(self)
+    }
+
+    /// Claims the ids of the transactions the shards start with.
+    fn claim_stored_tx_ids(&mut self) -> Result<(), Error> {
+        for shard in &mut self.shards {
+            let engine = shard
+                .get_mut()
+                .map_err(|_| Error::new("Engine shard lock poisoned"))?;
+            for tx_id in engine.tx_states.keys() {
+                self.tx_ids.insert(*tx_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn shard_index(&self, client_id: ClientId) -> usize {
+        client_id as usize % self.shards.len()
+    }
+
+    fn published(&self) -> Result<MutexGuard<'_, Vec<ShardAccounts>>, Error> {
+        self.published
+            .lock()
+            .map_err(|_| Error::new("Engine accounts lock poisoned"))
+    }
+
+    /// Publishes the accounts of the `(shard, client)` pairs as written to
+    /// `engines`, all at once. Copies a shard's accounts only while a reader
+    /// still holds them.
+    fn publish<'a>(
+        &self,
+        changes: impl IntoIterator<Item = (&'a Engine, usize, ClientId)>,
+    ) -> Result<(), Error> {
+        let mut published = self.published()?;
+        for (engine, shard, client_id) in changes {
+            let accounts = Arc::make_mut(&mut published[shard]);
+            match engine.accounts.get(&client_id) {
+                Some(account) => accounts.insert(client_id, account.clone()),
+                None => accounts.remove(&client_id),
+            };
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, tx: Tx) -> Result<(), Error> {
+        let (shard, client_id) = (self.shard_index(tx.client_id), tx.client_id);
+        let mut engine = self.shards[shard]
+            .write()
+            .map_err(|_| Error::new("Engine shard lock poisoned"))?;
+        let result = self.apply_to(&mut engine, tx);
+        self.publish([(&*engine, shard, client_id)])?;
+        result
+    }
+
+    fn apply_to(&self, engine: &mut Engine, tx: Tx) -> Result<(), Error> {
+        if !claims_tx_id(&tx, engine) {
+            return TransactionProcessor::apply(engine, tx);
+        }
+        let tx_id = tx.tx_id;
+        if !self.tx_ids.insert(tx_id)? {
+            return Err(Error::coded(
+                ErrorCode::DuplicateTx,
+                "Duplicate transaction id",
+            ));
+        }
+        let result = TransactionProcessor::apply(engine, tx);
+        // e.g. insufficient funds, the id can be used again
+        if !engine.tx_states.contains_key(&tx_id) {
+            self.tx_ids.remove(tx_id)?;
+        }
+        result
+    }
+
+    /// `Engine::apply_batch` across shards. The shards of the batch's clients
+    /// are write-locked together, and the batch is applied to copies of them
+    /// that replace the originals only once every transaction succeeded.
+    pub fn apply_batch(&self, txs: &[Tx]) -> Result<BatchResult, Error> {
+        let mut by_shard: BTreeMap<usize, Vec<(usize, &Tx)>> = BTreeMap::new();
+        for (index, tx) in txs.iter().enumerate() {
+            by_shard
+                .entry(self.shard_index(tx.client_id))
+                .or_default()
+                .push((index, tx));
+        }
+        // locked in shard order, so concurrent batches can't deadlock
+        let mut locked = vec![];
+        for &shard in by_shard.keys() {
+            let engine = self.shards[shard]
+                .write()
+                .map_err(|_| Error::new("Engine shard lock poisoned"))?;
+            locked.push(engine);
+        }
+        // claim the new ids, each for the first shard using it in the batch
+        let shards: Vec<usize> = by_shard.keys().copied().collect();
+        let mut claimed: HashMap<TxId, usize> = HashMap::new();
+        let mut rejected: Option<(usize, Error)> = None;
+        for (index, tx) in txs.iter().enumerate() {
+            let shard = self.shard_index(tx.client_id);
+            let engine = &locked[shards.partition_point(|&locked| locked < shard)];
+            if !claims_tx_id(tx, engine) {
+                continue;
+            }
+            let duplicate = match claimed.get(&tx.tx_id) {
+                Some(&claimed_by) => claimed_by != shard,
+                None if self.tx_ids.insert(tx.tx_id)? => {
+                    claimed.insert(tx.tx_id, shard);
+                    false
+                }
+                None => true,
+            };
+            if duplicate {
+                rejected = Some((
+                    index,
+                    Error::coded(ErrorCode::DuplicateTx, "Duplicate transaction id"),
+                ));
+                break;
+            }
+        }
+
+        let mut staged = vec![];
+        for (engine, shard_txs) in locked.iter().zip(by_shard.into_values()) {
+            let mut copy = Engine::clone(engine);
+            if let Err((index, error)) = copy.process_batch(shard_txs) {
+                // report the first failing transaction of the whole batch
+                if rejected.as_ref().is_none_or(|(first, _)| index < *first) {
+                    rejected = Some((index, error));
+                }
+            }
+            staged.push(copy);
+        }
+        if let Some((index, error)) = rejected {
+            for tx_id in claimed.into_keys() {
+                self.tx_ids.remove(tx_id)?;
+            }
+            return Ok(BatchResult::Rejected { index, error });
+        }
+        for (engine, copy) in locked.iter_mut().zip(staged) {
+            **engine = copy;
+        }
+        self.publish(txs.iter().map(|tx| {
+            let shard = self.shard_index(tx.client_id);
+            let engine = &locked[shards.partition_point(|&locked| locked < shard)];
+            (&**engine, shard, tx.client_id)
+        }))?;
+        for (tx_id, shard) in claimed {
+            let engine = &locked[shards.partition_point(|&locked| locked < shard)];
+            if !engine.tx_states.contains_key(&tx_id) {
+                self.tx_ids.remove(tx_id)?;
+            }
+        }
+        Ok(BatchResult::Applied(txs.len()))
+    }
+
+    pub fn account(&self, client_id: ClientId) -> Result<Option<ClientAccount>, Error> {
+        let published = self.published()?;
+        Ok(published[self.shard_index(client_id)]
+            .get(&client_id)
+            .cloned())
+    }
+
+    /// Consistent copy of every account: batches are published at once, so
+    /// the result never contains half of one. Copied from the published
+    /// accounts, without waiting on the shards.
+    pub fn accounts(&self) -> Result<HashMap<ClientId, ClientAccount>, Error> {
+        let shards = self.published()?.clone();
+        let mut accounts = HashMap::new();
+        for shard in shards {
+            accounts.extend(
+                shard
+                    .iter()
+                    .map(|(&client_id, account)| (client_id, account.clone())),
+            );
+        }
+        Ok(accounts)
+    }
+
+    /// Accounts and transaction states of every shard, e.g. to save before
+    /// shutting down. Waits on each shard being written in turn.
+    pub fn snapshot(&self) -> Result<Snapshot, Error> {
+        let mut snapshot = Snapshot::default();
+        for shard in &self.shards {
+            let engine = shard
+                .read()
+                .map_err(|_| Error::new("Engine shard lock poisoned"))?;
+            let Snapshot {
+                accounts,
+                tx_states,
+            } = TransactionProcessor::snapshot(&*engine);
+            snapshot.accounts.extend(accounts);
+            snapshot.tx_states.extend(tx_states);
+        }
+        Ok(snapshot)
+    }
+
+    pub fn into_accounts(self) -> Result<HashMap<ClientId, ClientAccount>, Error> {
+        let mut accounts = HashMap::new();
+        for shard in self.shards {
+            let engine = shard
+                .into_inner()
+                .map_err(|_| Error::new("Engine shard lock poisoned"))?;
+            accounts.extend(engine.accounts);
+        }
+        Ok(accounts)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::TxBuilder;
+    use crate::{ClientAllowlist, DedupTxs, FeeRule, LockInfo, LockReason, TxType, DEFAULT_TIER};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn disputes_of_spent_deposits_are_flagged_for_review() -> Result<(), Error> {
+        let mut engine = Engine::new().with_dispute_holds(DisputeHoldPolicy::FlagForReview);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::deposit(1).tx(2).amount(1.0).build())?;
+        engine.process(TxBuilder::withdrawal(1).tx(3).amount(4.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(2).build())?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        assert_eq!(
+            engine.hold_reviews(),
+            &[HoldReview {
+                index: 5,
+                client: 1,
+                tx: 1,
+                held: 5.0,
+                available: 1.0,
+            }]
+        );
+        assert_eq!(engine.accounts[&1].available, -4.0);
+        Ok(())
+    }
+
+    #[test]
+    fn chargeback_exposure_is_recovered_by_deposits() -> Result<(), Error> {
+        let mut engine = Engine::new()
+            .with_recoveries()
+            .with_locked_policy(LockedPolicy::AllowAdmin)
+            .with_rollback(1);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::withdrawal(1).tx(2).amount(4.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        engine.process(TxBuilder::chargeback(1).tx(1).build())?;
+        engine.process(TxBuilder::deposit(1).tx(3).amount(1.5).build())?;
+        let recovered = |engine: &Engine| engine.recoveries().map(|r| r.lines()[0].recovered);
+        assert_eq!(recovered(&engine), Some(1.5));
+        assert_eq!(
+            engine.recoveries().map(RecoveryLedger::outstanding),
+            Some(2.5)
+        );
+        engine.rollback(1)?;
+        assert_eq!(recovered(&engine), Some(0.0));
+        Ok(())
+    }
+
+    #[test]
+    fn early_chargeback_is_applied_once_disputed() -> Result<(), Error> {
+        let mut engine = Engine::new().with_reorder_window(2).with_rollback(1);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::deposit(1).tx(2).amount(3.0).build())?;
+        engine.process(TxBuilder::chargeback(1).tx(1).build())?;
+        engine.process(TxBuilder::resolve(1).tx(2).build())?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        let account = &engine.accounts[&1];
+        assert_eq!(
+            (account.total, account.held, account.locked),
+            (3.0, 0.0, true)
+        );
+        engine.rollback(1)?;
+        assert_eq!(engine.reorder_buffer().map(ReorderBuffer::len), Some(2));
+        engine.process(TxBuilder::deposit(1).tx(3).amount(1.0).build())?;
+        engine.process(TxBuilder::deposit(1).tx(4).amount(1.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(2).build())?;
+        // the resolve expired, so the dispute stays open
+        assert_eq!(engine.accounts[&1].held, 3.0);
+        Ok(())
+    }
+
+    #[test]
+    fn cancelled_stream_stops_between_transactions() {
+        let cancel = CancellationToken::new();
+        let txs = vec![
+            TxBuilder::deposit(1).tx(1).amount(5.0).build(),
+            // rejected without an amount
+            TxBuilder::deposit(1).tx(2).build(),
+            TxBuilder::deposit(1).tx(3).amount(3.0).build(),
+            TxBuilder::deposit(1).tx(4).amount(4.0).build(),
+        ];
+        let mut engine = Engine::new();
+        let stream = txs.into_iter().inspect(|tx| {
+            // as if cancelled from another thread while the third is read
+            if tx.tx_id == 3 {
+                cancel.cancel();
+            }
+        });
+        let summary = engine.process_stream(stream, &cancel);
+        assert!(summary.cancelled);
+        assert_eq!(summary.processed, 2);
+        assert_eq!(summary.rejections.len(), 1);
+        assert_eq!(summary.rejections[0].index, 2);
+        assert_eq!(
+            summary.rejections[0].error.tx,
+            Some(summary.rejections[0].tx)
+        );
+        assert_eq!(engine.accounts[&1].total, 5.0);
+        assert_eq!(summary.tx_types[&TxType::Deposit], 2);
+        assert_eq!(summary.rejected_by_reason[&ErrorCode::MissingAmount], 1);
+        assert_eq!(summary.accounts_touched, HashSet::from([1]));
+
+        let summary = engine.process_stream(
+            vec![TxBuilder::deposit(1).tx(5).amount(1.0).build()],
+            &CancellationToken::new(),
+        );
+        assert!(!summary.cancelled);
+        assert_eq!(engine.accounts[&1].total, 6.0);
+    }
+
+    #[test]
+    fn lock_records_the_first_chargeback() -> Result<(), Error> {
+        let mut engine = Engine::new().with_locked_policy(LockedPolicy::AllowDisputes);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::deposit(1).tx(2).amount(3.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(2).build())?;
+        engine.process(TxBuilder::chargeback(1).tx(2).build())?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        engine.process(TxBuilder::chargeback(1).tx(1).build())?;
+        let lock = LockInfo {
+            reason: LockReason::Chargeback,
+            tx: 2,
+            tx_index: Some(4),
+        };
+        assert_eq!(engine.accounts[&1].lock, Some(lock));
+        Ok(())
+    }
+
+    #[test]
+    fn zero_amounts_are_ignored_or_rejected() -> Result<(), Error> {
+        let mut engine = Engine::new()
+            .with_compat(Compat::V2)
+            .with_zero_amounts(ZeroAmountPolicy::Ignore);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(0.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        assert!(engine.tx_states.is_empty());
+        assert!(engine.accounts.is_empty());
+
+        let mut engine = Engine::new().with_zero_amounts(ZeroAmountPolicy::Reject);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(2.0).build())?;
+        let err = engine
+            .process(TxBuilder::withdrawal(1).tx(2).amount(0.0).build())
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::ZeroAmount);
+        assert_eq!(engine.tx_states.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_clients_open_accounts_per_policy() -> Result<(), Error> {
+        let mut engine = Engine::builder()
+            .account_creation(AccountCreation::FirstDeposit)
+            .build()?;
+        let err = engine
+            .process(TxBuilder::withdrawal(1).tx(1).amount(1.0).build())
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::UnknownClient);
+        assert!(engine.accounts.is_empty());
+        engine.process(TxBuilder::deposit(1).tx(2).amount(3.0).build())?;
+        engine.process(TxBuilder::withdrawal(1).tx(3).amount(1.0).build())?;
+        assert_eq!(engine.accounts[&1].available, 2.0);
+
+        let mut engine = Engine::new().with_account_creation(AccountCreation::Never);
+        engine.register_accounts([2]);
+        let err = engine
+            .process(TxBuilder::deposit(1).tx(1).amount(3.0).build())
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::UnknownClient);
+        engine.process(TxBuilder::deposit(2).tx(2).amount(3.0).build())?;
+        assert_eq!(engine.accounts.keys().collect::<Vec<_>>(), vec![&2]);
+        assert_eq!(engine.accounts[&2].available, 3.0);
+        assert_eq!("any".parse::<AccountCreation>()?, AccountCreation::Implicit);
+        assert!("sometimes".parse::<AccountCreation>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn negative_deposits_are_adjustments() -> Result<(), Error> {
+        let mut engine = Engine::new()
+            .with_compat(Compat::V2)
+            .with_negative_deposit_adjustments()
+            .with_audit();
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::deposit(1).tx(2).amount(-1.5).build())?;
+        engine.process(TxBuilder::dispute(1).tx(2).build())?;
+        let account = &engine.accounts[&1];
+        assert_eq!((account.available, account.held), (3.5, 0.0));
+        assert_eq!(engine.audit_trail()[1].event, AuditEvent::Adjustment);
+
+        // unknown clients can't be adjusted
+        engine.process(TxBuilder::adjustment(2).tx(3).amount(-1.0).build())?;
+        assert!(!engine.accounts.contains_key(&2));
+        Ok(())
+    }
+
+    #[test]
+    fn compat_v2_rejects_what_v1_takes() -> Result<(), Error> {
+        let txs = [
+            TxBuilder::deposit(1).tx(1).amount(5.0).build(),
+            TxBuilder::deposit(1).tx(2).amount(-1.5).build(),
+            TxBuilder::deposit(1).tx(1).amount(3.0).build(),
+        ];
+        let mut v1 = Engine::new();
+        for tx in txs.clone() {
+            v1.process(tx)?;
+        }
+        assert_eq!(v1.accounts[&1].available, 6.5);
+
+        let mut v2 = Engine::new().with_compat(Compat::V2);
+        let codes: Vec<_> = txs
+            .into_iter()
+            .filter_map(|tx| v2.process(tx).err())
+            .map(|err| err.code)
+            .collect();
+        assert_eq!(codes, [ErrorCode::NegativeAmount, ErrorCode::DuplicateTx]);
+        assert_eq!(v2.accounts[&1].available, 5.0);
+        assert_eq!(Compat::V2.locked_policy(), LockedPolicy::AllowDisputes);
+        Ok(())
+    }
+
+    #[test]
+    fn compat_v1_credits_negative_withdrawals() -> Result<(), Error> {
+        let mut v1 = Engine::new();
+        v1.process(TxBuilder::deposit(1).tx(1).amount(10.0).build())?;
+        v1.process(TxBuilder::withdrawal(1).tx(2).amount(-5.0).build())?;
+        assert_eq!(
+            (v1.accounts[&1].available, v1.accounts[&1].total),
+            (15.0, 15.0)
+        );
+        assert_eq!(v1.tx_states[&2].amount.value(), 5.0);
+
+        let mut v2 = Engine::new().with_compat(Compat::V2);
+        v2.process(TxBuilder::deposit(1).tx(1).amount(10.0).build())?;
+        let err = v2
+            .process(TxBuilder::withdrawal(1).tx(2).amount(-5.0).build())
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::NegativeAmount);
+        assert_eq!(v2.accounts[&1].total, 10.0);
+        Ok(())
+    }
+
+    #[test]
+    fn compat_v1_disputes_of_negative_deposits_hold_their_amount() -> Result<(), Error> {
+        let mut v1 = Engine::new();
+        v1.process(TxBuilder::deposit(1).tx(1).amount(-4.0).build())?;
+        v1.process(TxBuilder::dispute(1).tx(1).build())?;
+        let account = &v1.accounts[&1];
+        assert_eq!(
+            (account.available, account.held, account.total),
+            (8.0, -4.0, 4.0)
+        );
+        v1.process(TxBuilder::resolve(1).tx(1).build())?;
+        let account = &v1.accounts[&1];
+        assert_eq!(
+            (account.available, account.held, account.total),
+            (4.0, 0.0, 4.0)
+        );
+        v1.process(TxBuilder::dispute(1).tx(1).build())?;
+        v1.process(TxBuilder::chargeback(1).tx(1).build())?;
+        let account = &v1.accounts[&1];
+        assert_eq!(
+            (
+                account.available,
+                account.held,
+                account.total,
+                account.locked
+            ),
+            (8.0, 0.0, 8.0, true)
+        );
+
+        // as restored from a snapshot of a v1 run
+        let mut deposited = Engine::new();
+        deposited.process(TxBuilder::deposit(1).tx(1).amount(-4.0).build())?;
+        let mut v2 = Engine::from_snapshot(deposited.snapshot()).with_compat(Compat::V2);
+        let err = v2.process(TxBuilder::dispute(1).tx(1).build()).unwrap_err();
+        assert_eq!(err.code, ErrorCode::NotApplicable);
+        Ok(())
+    }
+
+    #[test]
+    fn state_is_iterated_in_order() -> Result<(), Error> {
+        let mut engine = Engine::new();
+        for (client_id, tx_id) in [(3, 9), (1, 2), (2, 5)] {
+            engine.process(TxBuilder::deposit(client_id).tx(tx_id).amount(1.0).build())?;
+        }
+        let clients: Vec<ClientId> = engine.accounts_sorted().map(|a| a.client).collect();
+        assert_eq!(clients, [1, 2, 3]);
+        let tx_ids: Vec<TxId> = engine.tx_states_sorted().map(|(tx_id, _)| tx_id).collect();
+        assert_eq!(tx_ids, [2, 5, 9]);
+        assert_eq!(engine.accounts().len(), engine.account_count());
+        assert_eq!(engine.tx_states().len(), engine.tx_state_count());
+        Ok(())
+    }
+
+    #[test]
+    fn state_is_restored() -> Result<(), Error> {
+        let mut engine = Engine::new()
+            .with_dispute_timeout(3)
+            .with_pending_withdrawals()
+            .with_sequence_check();
+        let seq = |seq: u64, tx: Tx| Tx {
+            seq: Some(seq),
+            ..tx
+        };
+        engine.process(seq(1, TxBuilder::deposit(1).tx(1).amount(5.0).build()))?;
+        engine.process(seq(2, TxBuilder::dispute(1).tx(1).build()))?;
+        engine.process(seq(3, TxBuilder::withdrawal(1).tx(2).amount(1.0).build()))?;
+        let state = engine.state();
+        assert_eq!(state.open_disputes, [(2, 1)]);
+
+        let json = serde_json::to_string(&state).unwrap();
+        let mut restored = Engine::new()
+            .with_dispute_timeout(3)
+            .with_state(serde_json::from_str(&json).unwrap());
+        assert_eq!(restored.state(), state);
+        // still sequence checked, and the dispute times out on schedule
+        assert!(restored
+            .process(TxBuilder::deposit(1).tx(3).amount(1.0).build())
+            .is_err());
+        assert!(engine
+            .process(TxBuilder::deposit(1).tx(3).amount(1.0).build())
+            .is_err());
+        for (seq_no, tx_id) in [(4, 3), (5, 4)] {
+            restored.process(seq(
+                seq_no,
+                TxBuilder::deposit(1).tx(tx_id).amount(1.0).build(),
+            ))?;
+            engine.process(seq(
+                seq_no,
+                TxBuilder::deposit(1).tx(tx_id).amount(1.0).build(),
+            ))?;
+        }
+        assert_eq!(restored.state(), engine.state());
+        assert_eq!(restored.accounts[&1].held, 0.0);
+        Ok(())
+    }
+
+    #[test]
+    fn middleware_runs_before_processing() -> Result<(), Error> {
+        let chain = MiddlewareChain::new()
+            .with(ClientAllowlist::new([1]))
+            .with(|tx: Tx| {
+                Ok(Some(Tx {
+                    amount: tx.amount.map(|amount| amount * 2.0),
+                    ..tx
+                }))
+            });
+        let mut engine = Engine::new().with_middleware(chain).with_audit();
+        engine.process(TxBuilder::deposit(1).tx(1).amount(1.5).build())?;
+        engine.process(TxBuilder::deposit(2).tx(2).amount(1.0).build())?;
+        assert_eq!(engine.accounts[&1].total, 3.0);
+        assert!(!engine.accounts.contains_key(&2));
+        assert_eq!(engine.audit_trail().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn locked_account_settles_disputes_of_earlier_deposits() -> Result<(), Error> {
+        let mut engine = Engine::builder()
+            .locked_policy(LockedPolicy::AllowDisputes)
+            .strict(true)
+            .build()?;
+        for tx_id in 1..=3 {
+            engine.process(TxBuilder::deposit(1).tx(tx_id).amount(2.0).build())?;
+        }
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        engine.process(TxBuilder::chargeback(1).tx(1).build())?;
+        assert!(engine.accounts[&1].locked);
+
+        engine.process(TxBuilder::dispute(1).tx(2).build())?;
+        engine.process(TxBuilder::chargeback(1).tx(2).build())?;
+        engine.process(TxBuilder::dispute(1).tx(3).build())?;
+        engine.process(TxBuilder::resolve(1).tx(3).build())?;
+        let account = &engine.accounts[&1];
+        assert_eq!(
+            (account.available, account.held, account.total),
+            (2.0, 0.0, 2.0)
+        );
+        assert_eq!(account.lock.as_ref().map(|lock| lock.tx), Some(1));
+        assert!(engine.tx_states[&2].charged_back);
+
+        let unknown = engine
+            .process(TxBuilder::dispute(1).tx(9).build())
+            .unwrap_err();
+        assert_eq!(unknown.code, ErrorCode::NotApplicable);
+        let withdrawal = engine.process(TxBuilder::withdrawal(1).tx(4).amount(1.0).build());
+        assert_eq!(withdrawal.unwrap_err().code, ErrorCode::AccountLocked);
+        Ok(())
+    }
+
+    #[test]
+    fn explain_leaves_the_engine_unchanged() -> Result<(), Error> {
+        let mut engine = Engine::new()
+            .with_compat(Compat::V2)
+            .with_locked_policy(LockedPolicy::AllowDisputes);
+        engine.process(TxBuilder::deposit(2).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::deposit(2).tx(2).amount(1.0).build())?;
+        engine.process(TxBuilder::dispute(2).tx(2).build())?;
+        engine.process(TxBuilder::chargeback(2).tx(2).build())?;
+
+        let explanation = engine.explain(TxBuilder::dispute(2).tx(1).build());
+        assert_eq!(explanation.outcome, Outcome::Applied);
+        assert_eq!(explanation.account_after.map(|a| a.held), Some(5.0));
+        assert_eq!(explanation.tx_state_after.map(|s| s.disputed), Some(true));
+        assert!(explanation
+            .policies
+            .contains(&"locked policy AllowDisputes".to_string()));
+
+        let other_client = engine.explain(TxBuilder::dispute(3).tx(1).build());
+        assert_eq!(
+            other_client.outcome,
+            Outcome::Ignored("Dispute doesn't apply to transaction 1".to_string())
+        );
+        let locked = engine.explain(TxBuilder::deposit(2).tx(3).amount(1.0).build());
+        assert_eq!(
+            locked.outcome,
+            Outcome::Ignored("Account is locked".to_string())
+        );
+        assert_eq!(locked.account_before, locked.account_after);
+        assert_eq!(engine.accounts[&2].held, 0.0);
+        assert!(!engine.tx_states.contains_key(&3));
+        Ok(())
+    }
+
+    #[test]
+    fn explain_doesnt_record_the_transaction_in_middleware() -> Result<(), Error> {
+        let allowlist = ClientAllowlist::new([1]);
+        let chain = MiddlewareChain::new()
+            .with(DedupTxs::new())
+            .with(allowlist.clone());
+        let mut engine = Engine::new().with_middleware(chain);
+        let deposit = TxBuilder::deposit(1).tx(1).amount(2.0).build();
+        assert_eq!(engine.explain(deposit.clone()).outcome, Outcome::Applied);
+        let other_client = TxBuilder::deposit(2).tx(2).amount(1.0).build();
+        assert_eq!(engine.explain(other_client).outcome, Outcome::Dropped);
+        assert_eq!(allowlist.dropped(), 0);
+
+        engine.process(deposit.clone())?;
+        assert_eq!(engine.accounts[&1].total, 2.0);
+        assert_eq!(engine.explain(deposit).outcome, Outcome::Dropped);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_is_auto_resolved_after_timeout() -> Result<(), Error> {
+        let mut engine = Engine::new().with_audit().with_dispute_timeout(2);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        engine.process(TxBuilder::deposit(1).tx(2).amount(1.0).build())?;
+        assert_eq!(engine.accounts[&1].held, 5.0);
+        engine.process(TxBuilder::deposit(1).tx(3).amount(1.0).build())?;
+
+        let account = &engine.accounts[&1];
+        assert_eq!(account.held, 0.0);
+        assert_eq!(account.available, 7.0);
+        let last = engine.audit_trail().last().unwrap();
+        assert_eq!(last.event, AuditEvent::AutoResolve);
+        assert_eq!((last.index, last.tx, last.amount), (4, 1, Some(5.0)));
+        Ok(())
+    }
+
+    #[test]
+    fn closed_dispute_is_not_auto_resolved() -> Result<(), Error> {
+        let mut engine = Engine::new().with_audit().with_dispute_timeout(2);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        engine.process(TxBuilder::resolve(1).tx(1).build())?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        engine.process(TxBuilder::deposit(1).tx(2).amount(1.0).build())?;
+
+        assert_eq!(engine.accounts[&1].held, 5.0);
+        assert!(engine
+            .audit_trail()
+            .iter()
+            .all(|entry| entry.event != AuditEvent::AutoResolve));
+        Ok(())
+    }
+
+    #[test]
+    fn pending_withdrawal_is_retried_after_deposit() -> Result<(), Error> {
+        let mut engine = Engine::new().with_pending_withdrawals();
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::withdrawal(1).tx(2).amount(8.0).build())?;
+        assert_eq!(engine.accounts[&1].available, 5.0);
+        assert_eq!(engine.pending_withdrawals().unwrap().totals()[0].total, 8.0);
+
+        engine.process(TxBuilder::deposit(1).tx(3).amount(4.0).build())?;
+        assert_eq!(engine.accounts[&1].available, 1.0);
+        assert_eq!(engine.accounts[&1].total, 1.0);
+        assert!(engine.pending_withdrawals().unwrap().totals().is_empty());
+        assert!(engine.tx_states.contains_key(&2));
+        Ok(())
+    }
+
+    #[test]
+    fn batch_is_applied_all_or_nothing() -> Result<(), Error> {
+        let mut engine = Engine::new().with_audit();
+        engine.process(TxBuilder::deposit(1).tx(1).amount(10.0).build())?;
+
+        let rejected = engine.apply_batch(&[
+            TxBuilder::withdrawal(1).tx(2).amount(6.0).build(),
+            TxBuilder::withdrawal(1).tx(3).amount(6.0).build(),
+        ]);
+        assert_eq!(
+            rejected,
+            BatchResult::Rejected {
+                index: 1,
+                error: Error::coded(ErrorCode::InsufficientFunds, "Insufficient funds").with_tx(3)
+            }
+        );
+        assert_eq!(engine.accounts[&1].available, 10.0);
+        assert!(!engine.tx_states.contains_key(&2));
+        assert_eq!(engine.audit_trail().len(), 1);
+
+        let applied = engine.apply_batch(&[
+            TxBuilder::withdrawal(1).tx(2).amount(6.0).build(),
+            TxBuilder::withdrawal(1).tx(3).amount(1.0).build(),
+        ]);
+        assert_eq!(applied, BatchResult::Applied(2));
+        assert_eq!(engine.accounts[&1].available, 3.0);
+        assert_eq!(engine.audit_trail().len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn rollback_restores_earlier_state() -> Result<(), Error> {
+        let mut engine = Engine::new()
+            .with_audit()
+            .with_pending_withdrawals()
+            .with_dispute_timeout(2)
+            .with_rollback(10);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        let snapshot = engine.snapshot();
+
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        engine.process(TxBuilder::withdrawal(1).tx(2).amount(3.0).build())?;
+        // releases the pending withdrawal and auto-resolves the dispute
+        engine.process(TxBuilder::deposit(1).tx(3).amount(4.0).build())?;
+        assert_eq!(engine.accounts[&1].available, 6.0);
+        assert!(engine.pending_withdrawals().unwrap().totals().is_empty());
+
+        engine.rollback(1)?;
+        assert_eq!(engine.accounts[&1].held, 5.0);
+        assert_eq!(engine.pending_withdrawals().unwrap().totals()[0].total, 3.0);
+        assert!(engine.rollback(4).is_err());
+
+        engine.rollback(2)?;
+        assert_eq!(engine.snapshot(), snapshot);
+        assert_eq!(engine.audit_trail().len(), 1);
+
+        // the dispute can be opened again and still times out
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        engine.process(TxBuilder::deposit(1).tx(2).amount(1.0).build())?;
+        engine.process(TxBuilder::deposit(1).tx(3).amount(1.0).build())?;
+        assert_eq!(engine.accounts[&1].held, 0.0);
+        assert_eq!(engine.accounts[&1].available, 7.0);
+        Ok(())
+    }
+
+    #[test]
+    fn last_activity_is_the_last_change_to_the_account() -> Result<(), Error> {
+        let mut engine = Engine::new().with_last_activity().with_rollback(5);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::deposit(2).tx(2).amount(1.0).build())?;
+        // insufficient funds, and a dispute of an unknown transaction
+        engine.process(TxBuilder::withdrawal(1).tx(3).amount(10.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(9).build())?;
+        assert_eq!(engine.last_activity().unwrap()[&1], 1);
+        assert_eq!(engine.last_activity().unwrap()[&2], 2);
+
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        assert_eq!(engine.last_activity().unwrap()[&1], 5);
+        engine.rollback(1)?;
+        assert_eq!(engine.last_activity().unwrap()[&1], 1);
+        assert!(Engine::new().last_activity().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn client_stats_count_what_took_effect() -> Result<(), Error> {
+        let mut engine = Engine::new()
+            .with_client_stats()
+            .with_dispute_timeout(3)
+            .with_rollback(5);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::deposit(1).tx(2).amount(5.0).build())?;
+        // insufficient funds
+        engine.process(TxBuilder::withdrawal(1).tx(3).amount(20.0).build())?;
+        engine.process(TxBuilder::withdrawal(1).tx(4).amount(1.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        engine.process(TxBuilder::resolve(1).tx(1).build())?;
+        engine.process(TxBuilder::dispute(1).tx(2).build())?;
+        engine.process(TxBuilder::chargeback(1).tx(2).build())?;
+        let stats = engine.client_stats().unwrap()[&1];
+        assert_eq!(
+            stats,
+            ClientStats {
+                deposits: 2,
+                withdrawals: 1,
+                open_disputes: 0,
+                resolved_disputes: 1,
+                chargebacks: 1,
+            }
+        );
+
+        // resolved by the timeout three transactions later
+        engine.process(TxBuilder::deposit(2).tx(10).amount(1.0).build())?;
+        engine.process(TxBuilder::dispute(2).tx(10).build())?;
+        assert_eq!(engine.client_stats().unwrap()[&2].open_disputes, 1);
+        for _ in 0..3 {
+            engine.process(TxBuilder::dispute(1).tx(9).build())?;
+        }
+        let stats_2 = engine.client_stats().unwrap()[&2];
+        assert_eq!((stats_2.open_disputes, stats_2.resolved_disputes), (0, 1));
+        engine.rollback(1)?;
+        let stats_2 = engine.client_stats().unwrap()[&2];
+        assert_eq!((stats_2.open_disputes, stats_2.resolved_disputes), (1, 0));
+        assert_eq!(engine.client_stats().unwrap()[&1], stats);
+        Ok(())
+    }
+
+    #[test]
+    fn category_spend_counts_applied_withdrawals() -> Result<(), Error> {
+        let mut engine = Engine::new().with_category_spend();
+        let withdrawal = |tx_id: TxId, amount: f64| {
+            TxBuilder::withdrawal(1)
+                .tx(tx_id)
+                .amount(amount)
+                .category("travel")
+                .build()
+        };
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(withdrawal(2, 2.0))?;
+        engine.process(withdrawal(3, 10.0))?;
+        engine.process(withdrawal(2, 2.0))?;
+
+        let totals = engine.category_spend().unwrap().totals();
+        assert_eq!(totals.len(), 1);
+        assert_eq!(
+            (totals[0].category.as_str(), totals[0].spend),
+            ("travel", 2.0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn over_precise_inputs_are_rounded() -> Result<(), Error> {
+        let mut engine = Engine::new().with_input_rounding(RoundingMode::Down);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(1.00009).build())?;
+        engine.process(TxBuilder::deposit(1).tx(2).amount(1.00009).build())?;
+        assert_eq!(engine.accounts[&1].available, 2.0);
+        assert_eq!(engine.tx_states[&1].amount.value(), 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn builder_configures_the_engine() -> Result<(), Error> {
+        let mut engine = Engine::builder()
+            .dispute_policy(DisputePolicy::AutoResolveAfter(1))
+            .precision(2)
+            .strict(true)
+            .build()?;
+        engine.process(TxBuilder::deposit(1).tx(1).amount(1.0051).build())?;
+        assert_eq!(engine.accounts[&1].available, 1.01);
+        assert_eq!(
+            engine.process(TxBuilder::withdrawal(1).tx(2).amount(5.0).build()),
+            Err(Error::coded(
+                ErrorCode::InsufficientFunds,
+                "Insufficient funds"
+            ))
+        );
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        assert!(engine.process(TxBuilder::dispute(1).tx(3).build()).is_err());
+        // auto-resolved by the rejected dispute, which still counts
+        assert_eq!(engine.accounts[&1].held, 0.0);
+
+        let restored = Engine::builder().storage(engine.snapshot()).build()?;
+        assert_eq!(restored.snapshot(), engine.snapshot());
+        assert!(Engine::builder().precision(5).build().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invariants_hold_after_processing() -> Result<(), Error> {
+        let mut engine = Engine::new();
+        engine.process(TxBuilder::deposit(1).tx(1).amount(0.1).build())?;
+        engine.process(TxBuilder::deposit(1).tx(2).amount(0.2).build())?;
+        engine.process(TxBuilder::withdrawal(1).tx(3).amount(0.3).build())?;
+        engine.process(TxBuilder::deposit(1).tx(4).amount(2.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(4).build())?;
+        engine.check_invariants()?;
+
+        engine.accounts.get_mut(&1).unwrap().held = -1.0;
+        let error = engine.check_invariants().unwrap_err();
+        assert!(
+            error.message.contains("client 1: total"),
+            "{}",
+            error.message
+        );
+        assert!(error.message.contains("negative held"), "{}", error.message);
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_over_the_limits_are_rejected() -> Result<(), Error> {
+        let mut engine = Engine::new()
+            .with_audit()
+            .with_max_amount(Amount::new(100.0)?)
+            .with_max_balance(Amount::new(150.0)?);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(100.0).build())?;
+        assert!(engine
+            .process(TxBuilder::deposit(1).tx(2).amount(100.01).build())
+            .is_err());
+        assert!(engine
+            .process(TxBuilder::deposit(1).tx(3).amount(60.0).build())
+            .is_err());
+        engine.process(TxBuilder::deposit(1).tx(4).amount(50.0).build())?;
+        assert!(engine
+            .process(TxBuilder::withdrawal(1).tx(5).amount(120.0).build())
+            .is_err());
+        assert_eq!(engine.accounts[&1].total, 150.0);
+        let rejected: Vec<TxId> = engine
+            .audit_trail()
+            .iter()
+            .filter(|entry| entry.error.is_some())
+            .map(|entry| entry.tx)
+            .collect();
+        assert_eq!(rejected, vec![2, 3, 5]);
+        Ok(())
+    }
+
+    #[test]
+    fn resource_limits_fail_new_accounts_and_tx_states() -> Result<(), Error> {
+        let mut engine = Engine::new().with_max_accounts(1).with_max_tx_states(2);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        let err = engine
+            .process(TxBuilder::deposit(2).tx(2).amount(1.0).build())
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::ResourceLimit);
+        assert_eq!(engine.accounts.len(), 1);
+        engine.process(TxBuilder::withdrawal(1).tx(3).amount(1.0).build())?;
+        let err = engine
+            .process(TxBuilder::deposit(1).tx(4).amount(1.0).build())
+            .unwrap_err();
+        assert_eq!(err.code, ErrorCode::ResourceLimit);
+        // transactions on stored states are still processed
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        assert_eq!(engine.accounts[&1].held, 5.0);
+        // only transactions that open an account count towards the limit
+        let dispute = Tx::new(TxType::Dispute, 2, 1);
+        let mut v2 = Engine::new().with_compat(Compat::V2).with_max_accounts(0);
+        v2.process(dispute.clone())?;
+        let mut v1 = Engine::new().with_compat(Compat::V1).with_max_accounts(0);
+        assert_eq!(
+            v1.process(dispute).unwrap_err().code,
+            ErrorCode::ResourceLimit
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn closing_an_account_pays_out_its_funds() -> Result<(), Error> {
+        let mut engine = Engine::new().with_rollback(4);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::withdrawal(1).tx(2).amount(1.5).build())?;
+        engine.process(TxBuilder::close_account(1).tx(3).build())?;
+        engine.process(TxBuilder::deposit(1).tx(4).amount(2.0).build())?;
+        assert_eq!(
+            engine.payouts(),
+            &[Payout {
+                client: 1,
+                tx: 3,
+                amount: 3.5
+            }]
+        );
+        assert!(engine.accounts[&1].closed);
+        assert_eq!(engine.accounts[&1].total, 0.0);
+        engine.check_invariants()?;
+
+        engine.rollback(2)?;
+        assert!(engine.payouts().is_empty());
+        assert!(!engine.accounts[&1].closed);
+        assert_eq!(engine.accounts[&1].available, 3.5);
+        Ok(())
+    }
+
+    #[test]
+    fn withdrawals_are_charged_their_fee() -> Result<(), Error> {
+        let fees = FeeSchedule::new().with_tier(
+            DEFAULT_TIER,
+            FeeRule {
+                flat: 0.5,
+                percent: 1.0,
+            },
+        );
+        let mut engine = Engine::new()
+            .with_fees(fees)
+            .with_pending_withdrawals()
+            .with_rollback(1);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(10.0).build())?;
+        // 4.0 plus 0.54 of fee doesn't fit in the 4.45 left, until a deposit
+        engine.process(TxBuilder::withdrawal(1).tx(2).amount(5.0).build())?;
+        engine.process(TxBuilder::withdrawal(1).tx(3).amount(4.0).build())?;
+        assert_eq!(engine.accounts[&1].available, 4.45);
+        assert_eq!(engine.revenue().map(RevenueLedger::total), Some(0.55));
+
+        engine.process(TxBuilder::deposit(1).tx(4).amount(0.1).build())?;
+        assert_eq!(
+            RoundingMode::default().round(engine.accounts[&1].total),
+            0.01
+        );
+        assert_eq!(engine.revenue().map(RevenueLedger::total), Some(1.09));
+        engine.rollback(1)?;
+        assert_eq!(engine.revenue().map(RevenueLedger::total), Some(0.55));
+        Ok(())
+    }
+
+    #[test]
+    fn orphaned_disputes_go_to_suspense() -> Result<(), Error> {
+        let mut engine = Engine::new()
+            .with_compat(Compat::V2)
+            .with_suspense()
+            .with_rollback(1);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        engine.process(TxBuilder::chargeback(1).tx(7).build())?;
+        engine.process(TxBuilder::resolve(2).tx(1).build())?;
+        let orphans: Vec<(TxId, SuspenseReason)> = engine
+            .suspense_ledger()
+            .iter()
+            .map(|entry| (entry.tx, entry.reason))
+            .collect();
+        assert_eq!(
+            orphans,
+            vec![
+                (7, SuspenseReason::UnknownTx),
+                (1, SuspenseReason::OtherClient)
+            ]
+        );
+        assert_eq!(engine.suspense_ledger()[0].index, 3);
+        engine.rollback(1)?;
+        assert_eq!(engine.suspense_ledger().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn cross_client_references_are_logged_as_probes() -> Result<(), Error> {
+        let mut engine = Engine::new().with_probe_log().with_rollback(1);
+        engine.process(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        let other = |type_: TxType| TxBuilder::new(type_, 2).tx(1).build();
+        engine.process(other(TxType::Dispute))?;
+        engine.process(other(TxType::Chargeback))?;
+        engine.process(TxBuilder::dispute(1).tx(1).build())?;
+        // unknown transactions aren't probes
+        engine.process(TxBuilder::dispute(2).tx(9).build())?;
+        let probes: Vec<(u64, ClientId, ClientId)> = engine
+            .probes()
+            .iter()
+            .map(|probe| (probe.index, probe.client, probe.owner))
+            .collect();
+        assert_eq!(probes, vec![(2, 2, 1), (3, 2, 1)]);
+        assert_eq!(ProbeAttempt::counts(engine.probes())[&2], 2);
+
+        engine.process(other(TxType::Resolve))?;
+        assert_eq!(engine.probes().len(), 3);
+        engine.rollback(1)?;
+        assert_eq!(engine.probes().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_accounts_keep_their_balances() -> Result<(), Error> {
+        let mut engine = Engine::new();
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            1,
+            ClientAccount {
+                client: 1,
+                available: 10.0,
+                held: 0.0,
+                total: 10.0,
+                locked: false,
+                closed: false,
+                lock: None,
+            },
+        );
+        engine.seed_accounts(accounts.clone())?;
+        engine.process(TxBuilder::withdrawal(1).tx(1).amount(4.0).build())?;
+        assert_eq!(engine.accounts[&1].available, 6.0);
+        assert!(engine.seed_accounts(accounts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn shared_engine_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedEngine>();
+    }
+
+    #[test]
+    fn apply_from_multiple_threads() -> Result<(), Error> {
+        let engine = Arc::new(SharedEngine::new(4));
+        let handles: Vec<_> = (1..=8 as ClientId)
+            .map(|client_id| {
+                let engine = Arc::clone(&engine);
+                thread::spawn(move || {
+                    for i in 0..100 as TxId {
+                        let tx = Tx {
+                            amount: Some(1.0),
+                            ..Tx::new(TxType::Deposit, client_id, client_id as TxId * 1000 + i)
+                        };
+                        engine.apply(tx).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(engine.account(3)?.unwrap().total, 100.0);
+        let engine = Arc::try_unwrap(engine).unwrap();
+        let accounts = engine.into_accounts()?;
+        assert_eq!(accounts.len(), 8);
+        assert!(accounts.values().all(|account| account.available == 100.0));
+        Ok(())
+    }
+
+    #[test]
+    fn shards_start_from_the_builder_storage() -> Result<(), Error> {
+        let mut sequential = Engine::new().with_compat(Compat::V2);
+        for (client_id, tx_id) in (1..=5).zip(1..) {
+            sequential.process(TxBuilder::deposit(client_id).tx(tx_id).amount(2.0).build())?;
+        }
+        let builder = Engine::builder()
+            .storage(sequential.snapshot())
+            .compat(Compat::V2)
+            .locked_policy(LockedPolicy::AllowAdmin);
+        let engine = SharedEngine::with_builder(3, builder.clone())?;
+        assert_eq!(engine.accounts()?, sequential.accounts);
+        // the stored deposits keep their ids and can be disputed
+        assert!(engine
+            .apply(TxBuilder::deposit(1).tx(4).amount(1.0).build())
+            .is_err());
+        engine.apply(TxBuilder::dispute(4).tx(4).build())?;
+        assert_eq!(engine.account(4)?.map(|account| account.held), Some(2.0));
+
+        assert_eq!(engine.snapshot()?.tx_states.len(), 5);
+        assert!(SharedEngine::with_builder(3, builder.compat(Compat::V1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tx_ids_are_unique_across_shards() -> Result<(), Error> {
+        let engine = SharedEngine::new(4).with_expected_tx_ids(100)?;
+        let of = |client_id: ClientId, tx: Tx| Tx { client_id, ..tx };
+        engine.apply(of(1, TxBuilder::deposit(1).tx(1).amount(5.0).build()))?;
+        assert_eq!(
+            engine.apply(of(2, TxBuilder::deposit(1).tx(1).amount(5.0).build())),
+            Err(Error::coded(
+                ErrorCode::DuplicateTx,
+                "Duplicate transaction id"
+            ))
+        );
+        assert_eq!(engine.account(2)?, None);
+
+        // rejected withdrawals leave their id free
+        engine.apply(of(2, TxBuilder::withdrawal(1).tx(2).amount(1.0).build()))?;
+        engine.apply(of(3, TxBuilder::deposit(1).tx(2).amount(1.0).build()))?;
+
+        let batch = [
+            of(1, TxBuilder::deposit(1).tx(3).amount(1.0).build()),
+            of(2, TxBuilder::deposit(1).tx(3).amount(1.0).build()),
+        ];
+        assert_eq!(
+            engine.apply_batch(&batch)?,
+            BatchResult::Rejected {
+                index: 1,
+                error: Error::coded(ErrorCode::DuplicateTx, "Duplicate transaction id")
+            }
+        );
+        assert_eq!(engine.apply_batch(&batch[..1])?, BatchResult::Applied(1));
+        assert_eq!(engine.account(1)?.unwrap().total, 6.0);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_never_see_half_a_batch() -> Result<(), Error> {
+        let engine = Arc::new(SharedEngine::new(4));
+        engine.apply(TxBuilder::deposit(1).tx(1).amount(100.0).build())?;
+        let transfer = |from: ClientId, to: ClientId, tx_id: TxId, amount: f64| {
+            [
+                TxBuilder::withdrawal(from).tx(tx_id).amount(amount).build(),
+                TxBuilder::deposit(to)
+                    .tx(tx_id + 10_000)
+                    .amount(amount)
+                    .build(),
+            ]
+        };
+
+        let writer = {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || {
+                for tx_id in 2..200 {
+                    let result = engine.apply_batch(&transfer(1, 2, tx_id, 0.5)).unwrap();
+                    assert_eq!(result, BatchResult::Applied(2));
+                }
+            })
+        };
+        while !writer.is_finished() {
+            let total: f64 = engine
+                .accounts()?
+                .values()
+                .map(|account| account.total)
+                .sum();
+            assert_eq!(total, 100.0);
+        }
+        writer.join().unwrap();
+        assert_eq!(engine.account(2)?.unwrap().total, 99.0);
+
+        let result = engine.apply_batch(&transfer(2, 1, 200, 1000.0))?;
+        assert!(matches!(result, BatchResult::Rejected { index: 0, .. }));
+        assert_eq!(engine.account(1)?.unwrap().total, 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_dont_wait_for_the_shards() -> Result<(), Error> {
+        let engine = SharedEngine::new(2);
+        engine.apply(TxBuilder::deposit(1).tx(1).amount(5.0).build())?;
+        let snapshot = engine.accounts()?;
+
+        let _writing = engine.shards[1].write().unwrap();
+        assert_eq!(engine.accounts()?, snapshot);
+        assert_eq!(engine.account(1)?.unwrap().total, 5.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::TxId;
+
+/// Stable, machine-readable category of an `Error`, see `--errors-json`.
+/// Codes are only ever added, never renamed.
+#[derive(Debug, Default, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    #[default]
+    Other,
+    /// Invalid command line.
+    Usage,
+    Io,
+    Csv,
+    Json,
+    Redis,
+    /// On-disk accounts of a `TieredStore`.
+    Storage,
+    /// Deposit or withdrawal without an amount.
+    MissingAmount,
+    InsufficientFunds,
+    DuplicateTx,
+    UnknownTxType,
+    /// Dispute, resolve, chargeback or close of a transaction or account it
+    /// doesn't apply to.
+    NotApplicable,
+    AccountLocked,
+    AccountClosed,
+    /// `--max-amount` or `--max-balance` exceeded.
+    LimitExceeded,
+    /// Missing, out-of-order or skipped sequence number.
+    Sequence,
+    /// `--self-check` failed.
+    Invariants,
+    /// `--expect` found accounts that differ from the expected ones.
+    Mismatch,
+    /// Input in another format than CSV that can't be converted to
+    /// transactions, see `--format`.
+    Import,
+    /// Deposit or withdrawal of a zero amount, see `--zero-amounts`.
+    ZeroAmount,
+    /// Deposit or withdrawal of a negative amount. Negative deposits are
+    /// only rejected under `--compat v2`.
+    NegativeAmount,
+    /// Amount in a currency without a rate, see `--rates`.
+    UnknownCurrency,
+    /// Input that doesn't match its signature, see `--verify`.
+    Signature,
+    /// Run stopped by its `CircuitBreaker`, see `--max-rejection-rate`.
+    CircuitBreaker,
+    /// State directory in use by another run, see `--checkpoint`.
+    StateLocked,
+    /// Encrypted snapshot or checkpoint that can't be read, e.g. without
+    /// its key or with another one, see `--state-key-file`.
+    Encryption,
+    /// Transaction of a client without an account that it can't open, see
+    /// `--account-creation`.
+    UnknownClient,
+    /// `--max-accounts` or `--max-tx-states` reached, which aborts the run.
+    ResourceLimit,
+}
+
+/// An error with a stable `code`, and optionally the transaction and input
+/// line it's about and the error that caused it. `{}` displays the message
+/// and `{:#}` the context before it, e.g. `line 4, tx 7: Insufficient
+/// funds`. Errors converted from those of libraries keep them as their
+/// `source()`, for embedders to inspect, e.g. the `ErrorKind` of an IO
+/// error; their message already includes its text.
+#[derive(Clone)]
+pub struct Error {
+    pub message: String,
+    pub code: ErrorCode,
+    /// Transaction the error is about, see `with_tx`.
+    pub tx: Option<TxId>,
+    /// Line of the input the error is about, counting from 1, see
+    /// `with_line`.
+    pub line: Option<u64>,
+    source: Option<Arc<dyn std::error::Error + Send + Sync>>,
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn std::error::Error + 'static))
+    }
+}
+impl Error {
+    pub fn new(message: &str) -> Error {
+        Self::coded(ErrorCode::Other, message)
+    }
+
+    pub fn coded(code: ErrorCode, message: &str) -> Error {
+        Error {
+            message: message.to_string(),
+            code,
+            tx: None,
+            line: None,
+            source: None,
+        }
+    }
+
+    /// This error, about transaction `tx`.
+    pub fn with_tx(mut self, tx: TxId) -> Error {
+        self.tx = Some(tx);
+        self
+    }
+
+    /// This error, about line `line` of the input.
+    pub fn with_line(mut self, line: u64) -> Error {
+        self.line = Some(line);
+        self
+    }
+
+    /// This error, caused by `source`.
+    pub fn with_source(mut self, source: impl std::error::Error + Send + Sync + 'static) -> Error {
+        self.source = Some(Arc::new(source));
+        self
+    }
+}
+// sources aren't comparable, errors are equal by what they say
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.message, self.code, self.tx, self.line)
+            == (&other.message, other.code, other.tx, other.line)
+    }
+}
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = f.debug_struct("Error");
+        debug
+            .field("message", &self.message)
+            .field("code", &self.code);
+        if let Some(tx) = self.tx {
+            debug.field("tx", &tx);
+        }
+        if let Some(line) = self.line {
+            debug.field("line", &line);
+        }
+        if let Some(source) = &self.source {
+            debug.field("source", source);
+        }
+        debug.finish()
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if f.alternate() {
+            match (self.line, self.tx) {
+                (Some(line), Some(tx)) => write!(f, "line {}, tx {}: ", line, tx)?,
+                (Some(line), None) => write!(f, "line {}: ", line)?,
+                (None, Some(tx)) => write!(f, "tx {}: ", tx)?,
+                (None, None) => {}
+            }
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// `Error::with_tx` and `Error::with_line` on the error of a result, e.g.
+/// `engine.process(tx).with_tx(tx_id)`.
+pub trait ErrorContext {
+    fn with_tx(self, tx: TxId) -> Self;
+    fn with_line(self, line: u64) -> Self;
+}
+impl<T> ErrorContext for Result<T, Error> {
+    fn with_tx(self, tx: TxId) -> Self {
+        self.map_err(|err| err.with_tx(tx))
+    }
+
+    fn with_line(self, line: u64) -> Self {
+        self.map_err(|err| err.with_line(line))
+    }
+}
+#[cfg(feature = "csv")]
+impl From<csv::Error> for Error {
+    fn from(err: csv::Error) -> Self {
+        let line = err.position().map(|position| position.line());
+        let error = Self::coded(ErrorCode::Csv, &format!("CSV Error: {}", err));
+        let error = match line {
+            Some(line) => error.with_line(line),
+            None => error,
+        };
+        error.with_source(err)
+    }
+}
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::coded(ErrorCode::Io, &format!("IO Error: {}", err)).with_source(err)
+    }
+}
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::coded(ErrorCode::Json, &format!("JSON Error: {}", err)).with_source(err)
+    }
+}
+#[cfg(feature = "redis")]
+impl From<redis::RedisError> for Error {
+    fn from(err: redis::RedisError) -> Self {
+        Self::coded(ErrorCode::Redis, &format!("Redis Error: {}", err)).with_source(err)
+    }
+}
+#[cfg(feature = "sled")]
+impl From<sled::Error> for Error {
+    fn from(err: sled::Error) -> Self {
+        Self::coded(ErrorCode::Storage, &format!("Storage Error: {}", err)).with_source(err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn context_and_source_are_kept() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let err = Error::from(io).with_line(4);
+        assert_eq!(err.to_string(), "IO Error: no such file");
+        assert_eq!(format!("{:#}", err), "line 4: IO Error: no such file");
+        let source = err
+            .source()
+            .and_then(|source| source.downcast_ref::<std::io::Error>());
+        assert_eq!(
+            source.map(|io| io.kind()),
+            Some(std::io::ErrorKind::NotFound)
+        );
+
+        let rejected: Result<(), Error> = Err(Error::coded(ErrorCode::InsufficientFunds, "No"));
+        let err = rejected.with_tx(7).with_line(2).unwrap_err();
+        assert_eq!(format!("{:#}", err), "line 2, tx 7: No");
+        assert_eq!(err.tx, Some(7));
+        assert!(err.source().is_none());
+        assert_ne!(err, Error::coded(ErrorCode::InsufficientFunds, "No"));
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+use crate::{ClientAccount, Error, Tx, TxState};
+
+/// What processing a transaction does, see `Engine::explain`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Outcome {
+    /// Changed the account or the state of the transaction it refers to.
+    Applied,
+    /// Processed without effect, for this reason.
+    Ignored(String),
+    /// Rejected with this error, as `Engine::process` returns it.
+    Rejected(Error),
+    /// Kept aside to be applied later, for this reason.
+    Held(String),
+    /// Dropped by the middleware.
+    Dropped,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Applied => write!(f, "applied"),
+            Outcome::Ignored(reason) => write!(f, "ignored: {}", reason),
+            Outcome::Rejected(err) => write!(f, "rejected ({:?}): {}", err.code, err),
+            Outcome::Held(reason) => write!(f, "held: {}", reason),
+            Outcome::Dropped => write!(f, "dropped by the middleware"),
+        }
+    }
+}
+
+/// Dry run of one transaction by `Engine::explain`, written out by its
+/// `Display` for the `explain` subcommand.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Explanation {
+    /// The transaction as the engine takes it, after the middleware.
+    pub tx: Tx,
+    pub outcome: Outcome,
+    /// Settings of the engine the outcome depends on, e.g. the locked
+    /// policy for a locked account.
+    pub policies: Vec<String>,
+    pub account_before: Option<ClientAccount>,
+    pub account_after: Option<ClientAccount>,
+    /// State of the transaction `tx` refers to, or creates.
+    pub tx_state_before: Option<TxState>,
+    pub tx_state_after: Option<TxState>,
+}
+
+fn write_account(
+    f: &mut fmt::Formatter,
+    label: &str,
+    account: &Option<ClientAccount>,
+) -> fmt::Result {
+    match account {
+        Some(account) => writeln!(
+            f,
+            "account {}: available {}, held {}, total {}, locked {}, closed {}",
+            label, account.available, account.held, account.total, account.locked, account.closed
+        ),
+        None => writeln!(f, "account {}: none", label),
+    }
+}
+
+fn write_tx_state(f: &mut fmt::Formatter, label: &str, state: &Option<TxState>) -> fmt::Result {
+    match state {
+        Some(state) => writeln!(
+            f,
+            "transaction {}: {:?} of {} by client {}, disputed {}, charged back {}",
+            label, state.type_, state.amount, state.client_id, state.disputed, state.charged_back
+        ),
+        None => writeln!(f, "transaction {}: unknown", label),
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tx = &self.tx;
+        write!(f, "{:?} {} of client {}", tx.type_, tx.tx_id, tx.client_id)?;
+        match tx.amount {
+            Some(amount) => writeln!(f, ", amount {}", amount)?,
+            None => writeln!(f)?,
+        }
+        writeln!(f, "outcome: {}", self.outcome)?;
+        if self.policies.is_empty() {
+            writeln!(f, "policies: defaults")?;
+        } else {
+            writeln!(f, "policies: {}", self.policies.join(", "))?;
+        }
+        write_account(f, "before", &self.account_before)?;
+        write_account(f, "after", &self.account_after)?;
+        write_tx_state(f, "before", &self.tx_state_before)?;
+        write_tx_state(f, "after", &self.tx_state_after)
+    }
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{ClientId, Error, RoundingMode};
+
+/// Tier of clients that aren't assigned one.
+pub const DEFAULT_TIER: &str = "default";
+
+/// Fee of a withdrawal: `flat` plus `percent` of its amount.
+#[derive(Debug, Default, Deserialize, PartialEq, Clone, Copy)]
+pub struct FeeRule {
+    pub flat: f64,
+    pub percent: f64,
+}
+
+impl FeeRule {
+    pub fn fee(&self, amount: f64) -> f64 {
+        RoundingMode::default().round(self.flat + amount * self.percent / 100.0)
+    }
+}
+
+/// Fee rules per tier, and the tier of each client. Clients without a tier
+/// pay the `default` one's fees, or none if there's no such tier.
+#[derive(Debug, Default, Clone)]
+pub struct FeeSchedule {
+    tiers: HashMap<String, FeeRule>,
+    clients: HashMap<ClientId, String>,
+}
+
+impl FeeSchedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_tier(mut self, tier: &str, rule: FeeRule) -> Self {
+        self.tiers.insert(tier.to_string(), rule);
+        self
+    }
+
+    /// Assign `client_id` to `tier`, which must have been added already.
+    pub fn assign(&mut self, client_id: ClientId, tier: &str) -> Result<(), Error> {
+        if !self.tiers.contains_key(tier) {
+            return Err(Error::new(&format!(
+                "Client {} is assigned to unknown fee tier {}",
+                client_id, tier
+            )));
+        }
+        self.clients.insert(client_id, tier.to_string());
+        Ok(())
+    }
+
+    pub fn tier(&self, client_id: ClientId) -> &str {
+        self.clients
+            .get(&client_id)
+            .map_or(DEFAULT_TIER, String::as_str)
+    }
+
+    /// Fee charged to `client_id` for withdrawing `amount`.
+    pub fn fee(&self, client_id: ClientId, amount: f64) -> f64 {
+        self.tiers
+            .get(self.tier(client_id))
+            .map_or(0.0, |rule| rule.fee(amount))
+    }
+}
+
+/// Withdrawal fees collected per client.
+#[derive(Debug, Default, Clone)]
+pub struct RevenueLedger {
+    fees: BTreeMap<ClientId, f64>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RevenueLine {
+    pub client: ClientId,
+    pub tier: String,
+    pub fees: f64,
+}
+
+impl RevenueLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, client_id: ClientId, fee: f64) {
+        *self.fees.entry(client_id).or_default() += fee;
+    }
+
+    pub(crate) fn client(&self, client_id: ClientId) -> Option<f64> {
+        self.fees.get(&client_id).copied()
+    }
+
+    pub(crate) fn restore(&mut self, client_id: ClientId, fees: Option<f64>) {
+        match fees {
+            Some(fees) => self.fees.insert(client_id, fees),
+            None => self.fees.remove(&client_id),
+        };
+    }
+
+    pub fn total(&self) -> f64 {
+        RoundingMode::default().round(self.fees.values().sum())
+    }
+
+    /// Ordered by client.
+    pub fn lines(&self, schedule: &FeeSchedule) -> Vec<RevenueLine> {
+        self.fees
+            .iter()
+            .map(|(&client, &fees)| RevenueLine {
+                client,
+                tier: schedule.tier(client).to_string(),
+                fees: RoundingMode::default().round(fees),
+            })
+            .collect()
+    }
+}
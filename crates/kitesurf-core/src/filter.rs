@@ -0,0 +1,124 @@
+use std::str::FromStr;
+
+use crate::{ClientAccount, ClientId, Error};
+
+/// Client ids given as a comma-separated list of ids and inclusive ranges,
+/// e.g. `1,5,9-20`.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ClientRanges {
+    ranges: Vec<(ClientId, ClientId)>,
+}
+
+impl ClientRanges {
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        self.ranges
+            .iter()
+            .any(|(first, last)| (*first..=*last).contains(&client_id))
+    }
+}
+
+impl FromStr for ClientRanges {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::new(&format!("Invalid client list: {}", s));
+        let id = |id: &str| id.trim().parse::<ClientId>().map_err(|_| invalid());
+        let mut ranges = vec![];
+        for part in s.split(',') {
+            let range = match part.split_once('-') {
+                Some((first, last)) => (id(first)?, id(last)?),
+                None => (id(part)?, id(part)?),
+            };
+            if range.0 > range.1 {
+                return Err(invalid());
+            }
+            ranges.push(range);
+        }
+        Ok(Self { ranges })
+    }
+}
+
+/// Which accounts make it to the output. Every condition that is set must
+/// hold.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct AccountFilter {
+    pub only_locked: bool,
+    /// Minimum total, at output precision.
+    pub min_balance: Option<f64>,
+    pub clients: Option<ClientRanges>,
+    /// Leave out accounts whose available, held and total funds are all 0.
+    pub nonzero_only: bool,
+}
+
+impl AccountFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn matches(&self, account: &ClientAccount) -> bool {
+        let rounding = crate::RoundingMode::default();
+        (!self.only_locked || account.locked)
+            && self
+                .min_balance
+                .is_none_or(|min| rounding.round(account.total) >= min)
+            && self
+                .clients
+                .as_ref()
+                .is_none_or(|clients| clients.contains(account.client))
+            && (!self.nonzero_only
+                || [account.available, account.held, account.total]
+                    .iter()
+                    .any(|funds| rounding.round(*funds) != 0.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn account(client: ClientId, total: f64, locked: bool) -> ClientAccount {
+        ClientAccount {
+            client,
+            available: total,
+            held: 0.0,
+            total,
+            locked,
+            closed: false,
+            lock: None,
+        }
+    }
+
+    #[test]
+    fn parse_client_ranges() -> Result<(), Error> {
+        let clients: ClientRanges = "1,5,9-20".parse()?;
+        assert!([1, 5, 9, 15, 20].iter().all(|id| clients.contains(*id)));
+        assert!([0, 2, 8, 21].iter().all(|id| !clients.contains(*id)));
+        assert!("".parse::<ClientRanges>().is_err());
+        assert!("3-1".parse::<ClientRanges>().is_err());
+        assert!("1,x".parse::<ClientRanges>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn all_conditions_must_hold() -> Result<(), Error> {
+        let filter = AccountFilter {
+            only_locked: true,
+            min_balance: Some(10.0),
+            clients: Some("1-3".parse()?),
+            nonzero_only: true,
+        };
+        assert!(filter.matches(&account(2, 10.0, true)));
+        assert!(!filter.matches(&account(2, 10.0, false)));
+        assert!(!filter.matches(&account(2, 9.99, true)));
+        assert!(!filter.matches(&account(4, 10.0, true)));
+
+        let nonzero = AccountFilter {
+            nonzero_only: true,
+            ..AccountFilter::default()
+        };
+        assert!(!nonzero.matches(&account(1, 0.00001, true)));
+        assert!(nonzero.matches(&account(1, -1.0, true)));
+        assert!(AccountFilter::default().matches(&account(1, 0.0, false)));
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::transaction::round_serialize;
+use crate::{ClientId, RoundingMode, Snapshot, TxId, TxStateType};
+
+/// Funds deposited with the processor, debited by deposits and credited by
+/// withdrawals.
+pub const CASH_ACCOUNT: &str = "cash";
+/// Held funds paid back to the card network by chargebacks.
+pub const CHARGEBACK_WRITEOFF_ACCOUNT: &str = "chargeback_writeoff";
+/// Balances that the snapshot's transactions don't explain: initial
+/// accounts, fees, payouts and custom transactions.
+pub const ADJUSTMENTS_ACCOUNT: &str = "adjustments";
+
+/// What a journal entry records.
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerEvent {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Chargeback,
+    Adjustment,
+}
+
+/// One side of a journal entry. Each entry has a debit line followed by a
+/// credit line for the same amount.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct LedgerLine {
+    pub entry: usize,
+    pub event: LedgerEvent,
+    pub tx: Option<TxId>,
+    pub client: ClientId,
+    pub account: String,
+    #[serde(serialize_with = "round_serialize")]
+    pub debit: f64,
+    #[serde(serialize_with = "round_serialize")]
+    pub credit: f64,
+}
+
+/// The client's available funds, owed to it by the processor.
+pub fn client_account(client_id: ClientId) -> String {
+    format!("client:{}", client_id)
+}
+
+/// The client's held funds, while a dispute is open.
+pub fn holds_account(client_id: ClientId) -> String {
+    format!("holds:{}", client_id)
+}
+
+#[derive(Default)]
+struct Journal {
+    lines: Vec<LedgerLine>,
+}
+
+impl Journal {
+    fn post(
+        &mut self,
+        event: LedgerEvent,
+        tx: Option<TxId>,
+        client: ClientId,
+        (debit, credit): (String, String),
+        amount: f64,
+    ) {
+        let entry = self.lines.len() / 2 + 1;
+        let line = |account, debit, credit| LedgerLine {
+            entry,
+            event,
+            tx,
+            client,
+            account,
+            debit,
+            credit,
+        };
+        self.lines.push(line(debit, amount, 0.0));
+        self.lines.push(line(credit, 0.0, amount));
+    }
+
+    /// Bring `account` from `implied` to `actual` against the adjustments
+    /// account.
+    fn adjust(&mut self, client: ClientId, account: String, implied: f64, actual: f64) {
+        let difference = RoundingMode::default().round(actual - implied);
+        if difference > 0.0 {
+            let accounts = (ADJUSTMENTS_ACCOUNT.to_string(), account);
+            self.post(LedgerEvent::Adjustment, None, client, accounts, difference);
+        } else if difference < 0.0 {
+            let accounts = (account, ADJUSTMENTS_ACCOUNT.to_string());
+            self.post(LedgerEvent::Adjustment, None, client, accounts, -difference);
+        }
+    }
+}
+
+/// Double-entry journal of a snapshot, so its balances can be reconciled
+/// in accounting terms. Every transaction the snapshot still knows about
+/// is posted in id order, against the client's account (`client:<id>`),
+/// its holds account (`holds:<id>`), `cash` and `chargeback_writeoff`.
+/// Entries on `adjustments` then bring each client's accounts to the
+/// snapshot's balances, e.g. for initial balances or fees.
+pub fn ledger(snapshot: &Snapshot) -> Vec<LedgerLine> {
+    let mut journal = Journal::default();
+    // available and held funds the transactions account for, per client
+    let mut implied: BTreeMap<ClientId, (f64, f64)> = BTreeMap::new();
+    let tx_states: BTreeMap<_, _> = snapshot.tx_states.iter().collect();
+    for (tx_id, state) in tx_states {
+        let client = state.client_id;
+        let (available, held) = implied.entry(client).or_default();
+        let amount = state.amount.magnitude().value();
+        let tx = Some(*tx_id);
+        match state.type_ {
+            TxStateType::Deposit => {
+                let accounts = (CASH_ACCOUNT.to_string(), client_account(client));
+                journal.post(LedgerEvent::Deposit, tx, client, accounts, amount);
+                *available += amount;
+                let held_amount = state.held_amount();
+                if state.disputed || state.charged_back {
+                    let accounts = (client_account(client), holds_account(client));
+                    journal.post(LedgerEvent::Dispute, tx, client, accounts, held_amount);
+                    *available -= held_amount;
+                    *held += held_amount;
+                }
+                if state.charged_back {
+                    let accounts = (
+                        holds_account(client),
+                        CHARGEBACK_WRITEOFF_ACCOUNT.to_string(),
+                    );
+                    journal.post(LedgerEvent::Chargeback, tx, client, accounts, held_amount);
+                    *held -= held_amount;
+                }
+            }
+            TxStateType::Withdrawal => {
+                let accounts = (client_account(client), CASH_ACCOUNT.to_string());
+                journal.post(LedgerEvent::Withdrawal, tx, client, accounts, amount);
+                *available -= amount;
+            }
+            // left to the entries on `adjustments`
+            TxStateType::Adjustment => {}
+        }
+    }
+
+    let clients: BTreeSet<ClientId> = snapshot
+        .accounts
+        .keys()
+        .chain(implied.keys())
+        .copied()
+        .collect();
+    for client in clients {
+        let (implied_available, implied_held) = implied.get(&client).copied().unwrap_or_default();
+        let (available, held) = snapshot
+            .accounts
+            .get(&client)
+            .map_or((0.0, 0.0), |account| (account.available, account.held));
+        journal.adjust(client, client_account(client), implied_available, available);
+        journal.adjust(client, holds_account(client), implied_held, held);
+    }
+    journal.lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::TxBuilder;
+    use crate::{ClientAccount, Engine, Error};
+
+    #[test]
+    fn ledger_balances_and_matches_the_accounts() -> Result<(), Error> {
+        let mut engine = Engine::new();
+        engine.seed_accounts(std::collections::HashMap::from([(
+            2,
+            ClientAccount {
+                client: 2,
+                available: 3.0,
+                held: 0.0,
+                total: 3.0,
+                locked: false,
+                closed: false,
+                lock: None,
+            },
+        )]))?;
+        engine.process(TxBuilder::deposit(1).tx(1).amount(10.0).build())?;
+        engine.process(TxBuilder::withdrawal(1).tx(2).amount(4.0).build())?;
+        engine.process(TxBuilder::deposit(1).tx(3).amount(2.0).build())?;
+        engine.process(TxBuilder::dispute(1).tx(3).build())?;
+        engine.process(TxBuilder::deposit(2).tx(4).amount(1.0).build())?;
+        engine.process(TxBuilder::dispute(2).tx(4).build())?;
+        engine.process(TxBuilder::chargeback(2).tx(4).build())?;
+
+        let lines = ledger(&engine.snapshot());
+        assert_eq!(lines.len() % 2, 0);
+        let debits: f64 = lines.iter().map(|line| line.debit).sum();
+        let credits: f64 = lines.iter().map(|line| line.credit).sum();
+        assert_eq!(debits, credits);
+
+        // credit balance of each account
+        let balance = |account: &str| -> f64 {
+            lines
+                .iter()
+                .filter(|line| line.account == account)
+                .map(|line| line.credit - line.debit)
+                .sum()
+        };
+        for (client, account) in &engine.accounts {
+            assert_eq!(balance(&client_account(*client)), account.available);
+            assert_eq!(balance(&holds_account(*client)), account.held);
+        }
+        assert_eq!(balance(CHARGEBACK_WRITEOFF_ACCOUNT), 1.0);
+        assert_eq!(balance(ADJUSTMENTS_ACCOUNT), -3.0);
+        assert_eq!(balance(CASH_ACCOUNT), -9.0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+//! Transaction processing without IO: the engine, its accounts and
+//! transaction states, and the reports it keeps. Reading and writing files
+//! is left to `kitesurf-cli`.
+
+mod alert;
+mod audit;
+mod breaker;
+mod cancel;
+mod category;
+mod custom;
+mod dedup;
+mod diff;
+mod engine;
+mod error;
+mod explain;
+mod fee;
+mod filter;
+mod ledger;
+mod lock;
+mod middleware;
+mod money;
+mod parallel;
+mod payout;
+mod pending;
+pub mod prelude;
+mod probe;
+mod processor;
+mod queue;
+mod recovery;
+mod reorder;
+mod review;
+mod risk;
+mod rounding;
+mod sequence;
+mod snapshot;
+mod stats;
+mod store;
+mod summary;
+mod suspense;
+mod tenants;
+#[cfg(any(test, feature = "test-util"))]
+pub mod testkit;
+mod timestamp;
+mod transaction;
+mod tx_refs;
+
+pub use crate::alert::{Alert, AlertMonitor, AlertRule};
+pub use crate::audit::{AuditEntry, AuditEvent};
+pub use crate::breaker::CircuitBreaker;
+pub use crate::cancel::CancellationToken;
+pub use crate::category::{CategorySpend, CategoryTotal};
+pub use crate::custom::{AccountOps, CustomTx, CustomTxRegistry};
+pub use crate::dedup::TxIdSet;
+pub use crate::diff::{diff_accounts, AccountDiff};
+pub use crate::engine::{BatchResult, DisputePolicy, Engine, EngineBuilder, SharedEngine};
+pub use crate::error::{Error, ErrorCode, ErrorContext};
+pub use crate::explain::{Explanation, Outcome};
+pub use crate::fee::{FeeRule, FeeSchedule, RevenueLedger, RevenueLine, DEFAULT_TIER};
+pub use crate::filter::{AccountFilter, ClientRanges};
+pub use crate::ledger::{
+    client_account, holds_account, ledger, LedgerEvent, LedgerLine, ADJUSTMENTS_ACCOUNT,
+    CASH_ACCOUNT, CHARGEBACK_WRITEOFF_ACCOUNT,
+};
+pub use crate::lock::{lock_report, LockInfo, LockLine, LockReason};
+pub use crate::middleware::{
+    ClientAllowlist, ClientDenylist, CurrencyConverter, DedupTxs, MiddlewareChain, TxMiddleware,
+};
+pub use crate::money::{Amount, Available, Balance, SignedAmount, Total};
+pub use crate::parallel::{process_parallel, process_parallel_streaming, ParallelRun, Rejection};
+pub use crate::payout::Payout;
+pub use crate::pending::{PendingTotal, PendingWithdrawals};
+pub use crate::probe::ProbeAttempt;
+pub use crate::processor::TransactionProcessor;
+pub use crate::queue::{
+    ingest_queue, Drained, IngestReceiver, IngestSender, Submit, DEFAULT_QUEUE_CAPACITY,
+};
+pub use crate::recovery::{Recovery, RecoveryLedger, RecoveryLine};
+pub use crate::reorder::ReorderBuffer;
+pub use crate::review::HoldReview;
+pub use crate::risk::{
+    ClientActivity, DefaultRiskScorer, RiskFlag, RiskMonitor, RiskScore, RiskScorer,
+};
+pub use crate::rounding::{RoundingMode, DECIMALS};
+pub use crate::sequence::SequenceChecker;
+pub use crate::snapshot::{AccountRecord, EngineState, Snapshot};
+pub use crate::stats::ClientStats;
+pub use crate::store::StateStore;
+pub use crate::summary::RunSummary;
+pub use crate::suspense::{SuspenseEntry, SuspenseReason};
+pub use crate::tenants::Tenants;
+pub use crate::timestamp::{deserialize_timestamp, format_timestamp, Timestamp, TimestampFormat};
+pub use crate::transaction::{
+    AccountCreation, ClientAccount, ClientId, Compat, DisputeHoldPolicy, LockedPolicy, Tx, TxId,
+    TxState, TxStateType, TxType, ZeroAmountPolicy,
+};
+pub use crate::tx_refs::TxRefs;
+
+#[allow(deprecated)]
+pub use crate::transaction::process_tx;
+pub(crate) use crate::transaction::process_tx_with_policies;
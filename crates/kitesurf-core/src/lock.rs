@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{ClientAccount, ClientId, TxId};
+
+/// Why an account was locked.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum LockReason {
+    Chargeback,
+    /// By the handler of a custom transaction type, see `AccountOps::lock`.
+    Custom,
+}
+
+/// Why and when an account was locked, kept on `ClientAccount::lock`.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct LockInfo {
+    pub reason: LockReason,
+    /// Transaction that locked the account.
+    pub tx: TxId,
+    /// Index of that transaction in the input, as in the audit trail, not
+    /// when it was processed. Not known to the stores applying transactions
+    /// one by one.
+    #[serde(alias = "at")]
+    pub tx_index: Option<u64>,
+}
+
+/// Row of the locks report.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LockLine {
+    pub client: ClientId,
+    pub reason: LockReason,
+    pub tx: TxId,
+    pub tx_index: Option<u64>,
+}
+
+/// Locks of the locked `accounts`, ordered by client. Accounts locked
+/// before locks were recorded, e.g. loaded from an older snapshot, have
+/// none.
+pub fn lock_report(accounts: &HashMap<ClientId, ClientAccount>) -> Vec<LockLine> {
+    let mut lines: Vec<LockLine> = accounts
+        .values()
+        .filter(|account| account.locked)
+        .filter_map(|account| {
+            let lock = account.lock?;
+            Some(LockLine {
+                client: account.client,
+                reason: lock.reason,
+                tx: lock.tx,
+                tx_index: lock.tx_index,
+            })
+        })
+        .collect();
+    lines.sort_by_key(|line| line.client);
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report_lists_locked_accounts_by_client() {
+        let lock = LockInfo {
+            reason: LockReason::Custom,
+            tx: 7,
+            tx_index: Some(3),
+        };
+        let mut locked = ClientAccount::new(2);
+        locked.locked = true;
+        locked.lock = Some(lock);
+        let mut legacy = ClientAccount::new(1);
+        legacy.locked = true;
+        let accounts = HashMap::from([(1, legacy), (2, locked), (3, ClientAccount::new(3))]);
+        assert_eq!(
+            lock_report(&accounts),
+            vec![LockLine {
+                client: 2,
+                reason: LockReason::Custom,
+                tx: 7,
+                tx_index: Some(3),
+            }]
+        );
+    }
+
+    #[test]
+    fn older_snapshots_name_the_index_at() -> Result<(), serde_json::Error> {
+        let lock: LockInfo = serde_json::from_str(r#"{"reason":"chargeback","tx":7,"at":3}"#)?;
+        assert_eq!(lock.tx_index, Some(3));
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{ClientId, Error, ErrorCode, Tx, TxId};
+
+/// Preprocessing step of a `MiddlewareChain`, run on every transaction
+/// passed to `Engine::process` before anything else. Steps are shared by
+/// the clones of an engine, e.g. the threads of `process_parallel`, so the
+/// ones keeping state do it behind a lock, keyed by client to stay
+/// deterministic.
+pub trait TxMiddleware: Send + Sync {
+    /// The transaction to process instead of `tx`, `None` to drop it, or an
+    /// error to reject it.
+    fn handle(&self, tx: Tx) -> Result<Option<Tx>, Error>;
+
+    /// What `handle` would return for `tx`, without recording it, for
+    /// `Engine::explain`. Steps keeping state override it.
+    fn check(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        self.handle(tx)
+    }
+}
+
+impl<F> TxMiddleware for F
+where
+    F: Fn(Tx) -> Result<Option<Tx>, Error> + Send + Sync,
+{
+    fn handle(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        self(tx)
+    }
+}
+
+/// Middleware run in the order they were added, see
+/// `Engine::with_middleware`. A transaction dropped or rejected by one
+/// doesn't reach the next.
+#[derive(Default, Clone)]
+pub struct MiddlewareChain {
+    steps: Vec<Arc<dyn TxMiddleware>>,
+}
+
+impl fmt::Debug for MiddlewareChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MiddlewareChain")
+            .field("steps", &self.steps.len())
+            .finish()
+    }
+}
+
+impl MiddlewareChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `step` at the end of the chain.
+    pub fn with(mut self, step: impl TxMiddleware + 'static) -> Self {
+        self.steps.push(Arc::new(step));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn handle(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        self.steps.iter().try_fold(Some(tx), |tx, step| match tx {
+            Some(tx) => step.handle(tx),
+            None => Ok(None),
+        })
+    }
+
+    /// Like `handle`, through `TxMiddleware::check`.
+    pub fn check(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        self.steps.iter().try_fold(Some(tx), |tx, step| match tx {
+            Some(tx) => step.check(tx),
+            None => Ok(None),
+        })
+    }
+}
+
+/// Drops the transactions of clients that aren't listed. Clones share the
+/// count of dropped transactions, so one kept aside reads it after the run.
+#[derive(Debug, Clone)]
+pub struct ClientAllowlist {
+    clients: HashSet<ClientId>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl ClientAllowlist {
+    pub fn new(clients: impl IntoIterator<Item = ClientId>) -> Self {
+        Self {
+            clients: clients.into_iter().collect(),
+            dropped: Arc::default(),
+        }
+    }
+
+    /// Transactions dropped so far.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl TxMiddleware for ClientAllowlist {
+    fn handle(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        let tx = self.check(tx)?;
+        if tx.is_none() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(tx)
+    }
+
+    fn check(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        Ok(self.clients.contains(&tx.client_id).then_some(tx))
+    }
+}
+
+/// Drops the transactions of the listed clients, counting them like
+/// `ClientAllowlist`.
+#[derive(Debug, Clone)]
+pub struct ClientDenylist {
+    clients: HashSet<ClientId>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl ClientDenylist {
+    pub fn new(clients: impl IntoIterator<Item = ClientId>) -> Self {
+        Self {
+            clients: clients.into_iter().collect(),
+            dropped: Arc::default(),
+        }
+    }
+
+    /// Transactions dropped so far.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl TxMiddleware for ClientDenylist {
+    fn handle(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        let tx = self.check(tx)?;
+        if tx.is_none() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(tx)
+    }
+
+    fn check(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        Ok((!self.clients.contains(&tx.client_id)).then_some(tx))
+    }
+}
+
+/// Type, client and id of a transaction, as `DedupTxs` tells repeated ones.
+type DedupKey = (String, ClientId, TxId);
+
+/// Drops transactions of the same type, client and id as one already seen,
+/// e.g. rows an upstream feed sent twice.
+#[derive(Debug, Default)]
+pub struct DedupTxs {
+    seen: Mutex<HashSet<DedupKey>>,
+}
+
+impl DedupTxs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(tx: &Tx) -> DedupKey {
+        (format!("{:?}", tx.type_), tx.client_id, tx.tx_id)
+    }
+
+    fn seen(&self) -> Result<MutexGuard<'_, HashSet<DedupKey>>, Error> {
+        self.seen
+            .lock()
+            .map_err(|_| Error::new("Dedup middleware lock poisoned"))
+    }
+}
+
+impl TxMiddleware for DedupTxs {
+    fn handle(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        Ok(self.seen()?.insert(Self::key(&tx)).then_some(tx))
+    }
+
+    fn check(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        Ok((!self.seen()?.contains(&Self::key(&tx))).then_some(tx))
+    }
+}
+
+/// Converts the amounts of transactions in another currency into the base
+/// one, keeping the amount as it came in `Tx::original_amount`.
+/// Transactions without a currency are taken to be in the base currency,
+/// the ones in a currency without a rate are rejected.
+#[derive(Debug, Default, Clone)]
+pub struct CurrencyConverter {
+    /// Value of one unit of each currency, by uppercase code, in the base
+    /// currency.
+    rates: HashMap<String, f64>,
+}
+
+impl CurrencyConverter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Convert amounts in `currency` at `rate` base currency units per unit.
+    /// The base currency itself is listed with a rate of 1.
+    pub fn with_rate(mut self, currency: &str, rate: f64) -> Self {
+        self.rates.insert(currency.to_uppercase(), rate);
+        self
+    }
+
+    pub fn rate(&self, currency: &str) -> Option<f64> {
+        self.rates.get(&currency.to_uppercase()).copied()
+    }
+}
+
+impl TxMiddleware for CurrencyConverter {
+    fn handle(&self, tx: Tx) -> Result<Option<Tx>, Error> {
+        let (Some(currency), Some(amount)) = (&tx.currency, tx.amount) else {
+            return Ok(Some(tx));
+        };
+        let rate = self.rate(currency).ok_or_else(|| {
+            Error::coded(
+                ErrorCode::UnknownCurrency,
+                &format!(
+                    "Transaction {} in currency {} without a rate",
+                    tx.tx_id, currency
+                ),
+            )
+        })?;
+        Ok(Some(Tx {
+            amount: Some(amount * rate),
+            original_amount: Some(amount),
+            ..tx
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::TxBuilder;
+
+    #[test]
+    fn steps_run_in_order() -> Result<(), Error> {
+        let chain = MiddlewareChain::new()
+            .with(DedupTxs::new())
+            .with(ClientAllowlist::new([1, 2]))
+            .with(|tx: Tx| {
+                Ok(Some(Tx {
+                    category: Some("imported".to_string()),
+                    ..tx
+                }))
+            });
+        let deposit = chain.handle(TxBuilder::deposit(1).tx(1).amount(1.0).build())?;
+        assert_eq!(
+            deposit.and_then(|tx| tx.category).as_deref(),
+            Some("imported")
+        );
+        assert_eq!(
+            chain.handle(TxBuilder::deposit(1).tx(1).amount(1.0).build())?,
+            None
+        );
+        assert!(chain.handle(TxBuilder::dispute(1).tx(1).build())?.is_some());
+        assert_eq!(
+            chain.handle(TxBuilder::deposit(3).tx(2).amount(1.0).build())?,
+            None
+        );
+
+        let rejecting = chain.with(|_: Tx| Err(Error::new("Nope")));
+        assert!(rejecting
+            .handle(TxBuilder::deposit(2).tx(3).amount(1.0).build())
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn client_lists_count_the_dropped_transactions() -> Result<(), Error> {
+        let allow = ClientAllowlist::new([1, 2]);
+        let deny = ClientDenylist::new([2]);
+        let chain = MiddlewareChain::new()
+            .with(allow.clone())
+            .with(deny.clone());
+        let passed: Vec<ClientId> = [1, 2, 3, 1, 2]
+            .into_iter()
+            .zip(1..)
+            .filter_map(|(client_id, tx_id)| {
+                chain
+                    .handle(TxBuilder::deposit(client_id).tx(tx_id).amount(1.0).build())
+                    .transpose()
+            })
+            .map(|tx| tx.map(|tx| tx.client_id))
+            .collect::<Result<_, Error>>()?;
+        assert_eq!(passed, vec![1, 1]);
+        assert_eq!((allow.dropped(), deny.dropped()), (1, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn amounts_are_converted_to_the_base_currency() -> Result<(), Error> {
+        let converter = CurrencyConverter::new()
+            .with_rate("USD", 1.0)
+            .with_rate("eur", 1.25);
+        let in_currency = |currency: Option<&str>| Tx {
+            currency: currency.map(str::to_string),
+            ..TxBuilder::deposit(1).tx(1).amount(2.0).build()
+        };
+
+        let converted = converter.handle(in_currency(Some("EUR")))?.unwrap();
+        assert_eq!(converted.amount, Some(2.5));
+        assert_eq!(converted.original_amount, Some(2.0));
+        assert_eq!(converted.currency.as_deref(), Some("EUR"));
+        let base = converter.handle(in_currency(Some("usd")))?.unwrap();
+        assert_eq!((base.amount, base.original_amount), (Some(2.0), Some(2.0)));
+        let unspecified = converter.handle(in_currency(None))?.unwrap();
+        assert_eq!(unspecified, in_currency(None));
+
+        let err = converter.handle(in_currency(Some("GBP"))).unwrap_err();
+        assert_eq!(err.code, ErrorCode::UnknownCurrency);
+        let dispute = Tx {
+            currency: Some("GBP".to_string()),
+            ..TxBuilder::dispute(1).tx(1).build()
+        };
+        assert!(converter.handle(dispute)?.is_some());
+        Ok(())
+    }
+}
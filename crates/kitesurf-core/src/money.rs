@@ -0,0 +1,339 @@
+use serde::{Deserialize, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::str::FromStr;
+
+use crate::Error;
+
+/// A finite, non-negative amount of money, e.g. what a transaction moves or
+/// an account holds. Arithmetic that could go below zero is checked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Serialize)]
+#[serde(transparent)]
+pub struct Amount(f64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0.0);
+
+    pub fn new(value: f64) -> Result<Self, Error> {
+        if value.is_finite() && value >= 0.0 {
+            // no negative zero
+            Ok(Amount(value + 0.0))
+        } else {
+            Err(Error::new(&format!("Invalid amount {}", value)))
+        }
+    }
+
+    pub fn value(self) -> f64 {
+        self.0
+    }
+
+    /// `self - other`, or `None` when `other` is larger.
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        (other.0 <= self.0).then_some(Amount(self.0 - other.0))
+    }
+}
+
+impl TryFrom<f64> for Amount {
+    type Error = Error;
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        Amount::new(value)
+    }
+}
+
+impl From<Amount> for f64 {
+    fn from(amount: Amount) -> Self {
+        amount.0
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        Amount(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        self.0 += other.0;
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value: f64 = s
+            .parse()
+            .map_err(|_| Error::new(&format!("Invalid amount {}", s)))?;
+        Amount::new(value)
+    }
+}
+
+/// A finite amount of money of either sign, e.g. what an adjustment moves
+/// or a withdrawal's transaction state records. Its magnitude is an
+/// `Amount`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(try_from = "f64", into = "f64")]
+pub struct SignedAmount(f64);
+
+impl SignedAmount {
+    pub const ZERO: SignedAmount = SignedAmount(0.0);
+
+    pub fn new(value: f64) -> Result<Self, Error> {
+        if value.is_finite() {
+            Ok(SignedAmount(value + 0.0))
+        } else {
+            Err(Error::new(&format!("Invalid amount {}", value)))
+        }
+    }
+
+    /// `value`, already known to be finite, e.g. the amount of a `Tx`,
+    /// checked when it was set.
+    pub(crate) fn finite(value: f64) -> Self {
+        debug_assert!(value.is_finite());
+        SignedAmount(value + 0.0)
+    }
+
+    pub fn value(self) -> f64 {
+        self.0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0.0
+    }
+
+    pub fn magnitude(self) -> Amount {
+        Amount(self.0.abs())
+    }
+}
+
+impl TryFrom<f64> for SignedAmount {
+    type Error = Error;
+
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        SignedAmount::new(value)
+    }
+}
+
+impl From<SignedAmount> for f64 {
+    fn from(amount: SignedAmount) -> Self {
+        amount.0
+    }
+}
+
+impl From<Amount> for SignedAmount {
+    fn from(amount: Amount) -> Self {
+        SignedAmount(amount.0)
+    }
+}
+
+impl Neg for SignedAmount {
+    type Output = SignedAmount;
+
+    fn neg(self) -> SignedAmount {
+        SignedAmount(-self.0 + 0.0)
+    }
+}
+
+impl fmt::Display for SignedAmount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Marker of `Balance<Available>`, funds the client can withdraw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Available {}
+
+/// Marker of `Balance<Total>`, available and held funds together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Total {}
+
+/// A signed balance of kind `K`, which can go negative, e.g. available funds
+/// after a dispute of a spent deposit. Balances only add up with balances of
+/// the same kind, so available funds can't be mixed with total ones by
+/// accident, while any `Amount` can be added or taken out. Held funds can't
+/// go negative and are an `Amount`.
+pub struct Balance<K> {
+    value: f64,
+    kind: PhantomData<K>,
+}
+
+impl<K> Balance<K> {
+    pub fn new(value: f64) -> Self {
+        Balance {
+            value,
+            kind: PhantomData,
+        }
+    }
+
+    pub fn value(self) -> f64 {
+        self.value
+    }
+
+    /// Whether taking `amount` out leaves the balance at zero or above.
+    pub fn covers(self, amount: Amount) -> bool {
+        amount.0 <= self.value
+    }
+}
+
+impl Balance<Total> {
+    pub fn of(available: Balance<Available>, held: Amount) -> Self {
+        Balance::new(available.value + held.0)
+    }
+}
+
+// implemented by hand, as derives would require `K` to implement them too
+impl<K> Clone for Balance<K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K> Copy for Balance<K> {}
+
+impl<K> Default for Balance<K> {
+    fn default() -> Self {
+        Balance::new(0.0)
+    }
+}
+
+impl<K> PartialEq for Balance<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<K> PartialOrd for Balance<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+impl fmt::Debug for Balance<Available> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Available").field(&self.value).finish()
+    }
+}
+
+impl fmt::Debug for Balance<Total> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Total").field(&self.value).finish()
+    }
+}
+
+impl<K> fmt::Display for Balance<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<K> Serialize for Balance<K> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_f64(self.value)
+    }
+}
+
+impl<K> From<Amount> for Balance<K> {
+    fn from(amount: Amount) -> Self {
+        Balance::new(amount.0)
+    }
+}
+
+impl<K> Add for Balance<K> {
+    type Output = Balance<K>;
+
+    fn add(self, other: Balance<K>) -> Balance<K> {
+        Balance::new(self.value + other.value)
+    }
+}
+
+impl<K> Sub for Balance<K> {
+    type Output = Balance<K>;
+
+    fn sub(self, other: Balance<K>) -> Balance<K> {
+        Balance::new(self.value - other.value)
+    }
+}
+
+impl<K> Add<Amount> for Balance<K> {
+    type Output = Balance<K>;
+
+    fn add(self, amount: Amount) -> Balance<K> {
+        Balance::new(self.value + amount.0)
+    }
+}
+
+impl<K> Sub<Amount> for Balance<K> {
+    type Output = Balance<K>;
+
+    fn sub(self, amount: Amount) -> Balance<K> {
+        Balance::new(self.value - amount.0)
+    }
+}
+
+impl<K> AddAssign<Amount> for Balance<K> {
+    fn add_assign(&mut self, amount: Amount) {
+        self.value += amount.0;
+    }
+}
+
+impl<K> SubAssign<Amount> for Balance<K> {
+    fn sub_assign(&mut self, amount: Amount) {
+        self.value -= amount.0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn amounts_stay_non_negative() -> Result<(), Error> {
+        for invalid in [-0.5, f64::NAN, f64::INFINITY] {
+            assert!(Amount::new(invalid).is_err(), "{}", invalid);
+        }
+        let (two, three) = (Amount::new(2.0)?, Amount::try_from(3.0)?);
+        assert_eq!((two + three).value(), 5.0);
+        assert_eq!(three.checked_sub(two), Some(Amount::new(1.0)?));
+        assert_eq!(two.checked_sub(three), None);
+        assert_eq!(Amount::new(-0.0)?.to_string(), "0");
+        Ok(())
+    }
+
+    #[test]
+    fn signed_amounts_are_finite() -> Result<(), Error> {
+        assert!(SignedAmount::new(f64::NAN).is_err());
+        let withdrawal = SignedAmount::new(-1.5)?;
+        assert!(withdrawal.is_negative());
+        assert_eq!(withdrawal.magnitude(), Amount::new(1.5)?);
+        assert_eq!(-withdrawal, SignedAmount::from(Amount::new(1.5)?));
+        assert_eq!("2.5".parse::<Amount>()?.value(), 2.5);
+        assert!("-2.5".parse::<Amount>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn balances_can_go_negative() -> Result<(), Error> {
+        let mut available = Balance::<Available>::new(1.0);
+        assert!(available.covers(Amount::new(1.0)?));
+        available -= Amount::new(1.5)?;
+        assert_eq!(available.value(), -0.5);
+        assert!(!available.covers(Amount::new(0.1)?));
+        let total = Balance::of(available, Amount::new(2.0)?);
+        assert_eq!(total, Balance::<Total>::new(1.5));
+        assert_eq!(format!("{:?}", total), "Total(1.5)");
+        Ok(())
+    }
+}
@@ -0,0 +1,488 @@
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::sync::{mpsc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use crate::{ClientAccount, ClientId, Engine, Error, Tx, TxId, TxState, TxType};
+
+/// Transaction rejected by `process_parallel`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Rejection {
+    /// Position of the transaction in the input, counting from 1.
+    pub index: u64,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub error: Error,
+}
+
+/// Outcome of `process_parallel`.
+#[derive(Debug)]
+pub struct ParallelRun {
+    pub engine: Engine,
+    /// In input order.
+    pub rejections: Vec<Rejection>,
+}
+
+/// Deposit or withdrawal whose id is also used by the clients of another
+/// worker.
+struct Claim {
+    index: usize,
+    worker: usize,
+    /// Once processed, the state it created if it took the id.
+    outcome: Option<Option<TxState>>,
+}
+
+/// Ids used by the clients of several workers. A transaction using one of
+/// them waits until the other workers have processed every earlier deposit
+/// and withdrawal with that id, to know whether one of them took it.
+/// Waits only go to earlier transactions, so they always end.
+struct SharedIds {
+    claims: Mutex<HashMap<TxId, Vec<Claim>>>,
+    decided: Condvar,
+}
+
+impl SharedIds {
+    fn lock(&self) -> Result<MutexGuard<'_, HashMap<TxId, Vec<Claim>>>, Error> {
+        self.claims
+            .lock()
+            .map_err(|_| Error::new("Shared transaction ids lock poisoned"))
+    }
+
+    /// State created by an earlier deposit or withdrawal of another worker
+    /// that took `tx_id`, if any.
+    fn taken_before(
+        &self,
+        tx_id: TxId,
+        index: usize,
+        worker: usize,
+    ) -> Result<Option<TxState>, Error> {
+        let mut claims = self.lock()?;
+        loop {
+            let earlier = || {
+                // no entry when only disputes and the like use the id
+                claims
+                    .get(&tx_id)
+                    .into_iter()
+                    .flatten()
+                    .take_while(|claim| claim.index < index)
+                    .filter(|claim| claim.worker != worker)
+            };
+            if earlier().all(|claim| claim.outcome.is_some()) {
+                return Ok(earlier().find_map(|claim| claim.outcome.clone().flatten()));
+            }
+            claims = self
+                .decided
+                .wait(claims)
+                .map_err(|_| Error::new("Shared transaction ids lock poisoned"))?;
+        }
+    }
+
+    fn decide(&self, tx_id: TxId, index: usize, outcome: Option<TxState>) -> Result<(), Error> {
+        let mut claims = self.lock()?;
+        if let Some(claim) = claims
+            .get_mut(&tx_id)
+            .and_then(|claims| claims.iter_mut().find(|claim| claim.index == index))
+        {
+            claim.outcome = Some(outcome);
+        }
+        self.decided.notify_all();
+        Ok(())
+    }
+
+    /// Let the other workers go on when `worker` stops early, so a failed
+    /// run can't hang.
+    fn abandon(&self, worker: usize) {
+        if let Ok(mut claims) = self.claims.lock() {
+            for claim in claims.values_mut().flatten() {
+                if claim.worker == worker && claim.outcome.is_none() {
+                    claim.outcome = Some(None);
+                }
+            }
+        }
+        self.decided.notify_all();
+    }
+}
+
+/// Calls `SharedIds::abandon` when a worker returns or panics.
+struct Abandon<'a>(&'a SharedIds, usize);
+
+impl Drop for Abandon<'_> {
+    fn drop(&mut self) {
+        self.0.abandon(self.1);
+    }
+}
+
+struct Worker {
+    engine: Engine,
+    clients: HashSet<ClientId>,
+    rejections: Vec<Rejection>,
+    payout_indices: Vec<u64>,
+}
+
+/// Process `txs` on `threads` copies of `engine`, each taking the clients
+/// of one `ParallelRun::partition`, and merge them back. The result is the
+/// same as calling `Engine::process` on every transaction in order,
+/// whatever the number of threads: each client's transactions are processed
+/// in input order, a transaction id taken by a client of another thread
+/// counts as taken from the point in the input it was, and report entries
+/// are merged in input order.
+///
+/// Engines in `Compat::V1`, with dispute timeouts, pending withdrawals or
+/// rollback are refused, as their results depend on the order of transactions across
+/// clients.
+///
+/// `txs` can be borrowed, e.g. `txs.iter().collect()`, for callers that
+/// still need the transactions afterwards: each is then cloned as its
+/// thread processes it, rather than all of them up front.
+pub fn process_parallel<T: Borrow<Tx> + Into<Tx> + Send>(
+    engine: Engine,
+    txs: Vec<T>,
+    threads: usize,
+) -> Result<ParallelRun, Error> {
+    process_parallel_streaming(engine, txs, threads, |_| Ok(()))
+}
+
+/// `process_parallel`, calling `on_accounts` with the final accounts of
+/// each partition's clients, by client, as soon as its thread is done
+/// rather than once all are. Accounts no thread touched, e.g. initial
+/// balances of clients without transactions, come last. Every account is
+/// passed exactly once; an error from `on_accounts` fails the run.
+pub fn process_parallel_streaming<T: Borrow<Tx> + Into<Tx> + Send>(
+    engine: Engine,
+    txs: Vec<T>,
+    threads: usize,
+    mut on_accounts: impl FnMut(Vec<&ClientAccount>) -> Result<(), Error>,
+) -> Result<ParallelRun, Error> {
+    engine.check_parallel()?;
+    let threads = threads.max(1);
+    let processed = txs.len() as u64;
+
+    let mut queues: Vec<Vec<(usize, T)>> = (0..threads).map(|_| vec![]).collect();
+    let mut id_workers: HashMap<TxId, (usize, bool)> = HashMap::new();
+    for (index, tx) in txs.into_iter().enumerate() {
+        let (client_id, tx_id) = (tx.borrow().client_id, tx.borrow().tx_id);
+        let worker = ParallelRun::partition(client_id, threads);
+        let (first, shared) = id_workers.entry(tx_id).or_insert((worker, false));
+        *shared |= *first != worker;
+        queues[worker].push((index, tx));
+    }
+    let shared: HashSet<TxId> = id_workers
+        .into_iter()
+        .filter_map(|(tx_id, (_, shared))| shared.then_some(tx_id))
+        .collect();
+    let mut claims: HashMap<TxId, Vec<Claim>> = HashMap::new();
+    for (worker, queue) in queues.iter().enumerate() {
+        for (index, tx) in queue {
+            let tx: &Tx = tx.borrow();
+            if claims_id(tx) && shared.contains(&tx.tx_id) {
+                claims.entry(tx.tx_id).or_default().push(Claim {
+                    index: *index,
+                    worker,
+                    outcome: None,
+                });
+            }
+        }
+    }
+    for claims in claims.values_mut() {
+        claims.sort_by_key(|claim| claim.index);
+    }
+    let shared_ids = SharedIds {
+        claims: Mutex::new(claims),
+        decided: Condvar::new(),
+    };
+
+    let mut streamed: Result<(), Error> = Ok(());
+    let workers: Vec<Result<Worker, Error>> = thread::scope(|scope| {
+        let (done, finished) = mpsc::channel();
+        let handles: Vec<_> = queues
+            .into_iter()
+            .enumerate()
+            .map(|(worker, queue)| {
+                let engine = engine.clone();
+                let (shared, shared_ids, done) = (&shared, &shared_ids, done.clone());
+                scope.spawn(move || {
+                    let _abandon = Abandon(shared_ids, worker);
+                    let result = run_worker(engine, queue, worker, shared, shared_ids);
+                    // unless the receiving end failed already
+                    let _ = done.send((worker, result));
+                })
+            })
+            .collect();
+        drop(done);
+        let mut workers: Vec<Result<Worker, Error>> = (0..handles.len())
+            .map(|_| Err(Error::new("Worker thread panicked")))
+            .collect();
+        for (index, result) in finished {
+            if let (Ok(worker), Ok(())) = (&result, &streamed) {
+                streamed = on_accounts(worker.accounts());
+            }
+            workers[index] = result;
+        }
+        for handle in handles {
+            // a panicking worker sent nothing, and is left as an error
+            let _ = handle.join();
+        }
+        workers
+    });
+    streamed?;
+
+    let mut merged = engine;
+    let mut rejections = vec![];
+    let mut payout_indices = vec![];
+    let mut clients = HashSet::new();
+    for worker in workers {
+        let worker = worker?;
+        merged.absorb(worker.engine, &worker.clients);
+        clients.extend(worker.clients);
+        rejections.extend(worker.rejections);
+        payout_indices.extend(worker.payout_indices);
+    }
+    merged.order_by_index(&payout_indices, processed);
+    rejections.sort_by_key(|rejection| rejection.index);
+    let mut untouched: Vec<&ClientAccount> = merged
+        .accounts
+        .values()
+        .filter(|account| !clients.contains(&account.client))
+        .collect();
+    untouched.sort_by_key(|account| account.client);
+    on_accounts(untouched)?;
+    Ok(ParallelRun {
+        engine: merged,
+        rejections,
+    })
+}
+
+impl Worker {
+    /// Accounts of the worker's clients, by client.
+    fn accounts(&self) -> Vec<&ClientAccount> {
+        let mut accounts: Vec<&ClientAccount> = self
+            .clients
+            .iter()
+            .filter_map(|client_id| self.engine.accounts.get(client_id))
+            .collect();
+        accounts.sort_by_key(|account| account.client);
+        accounts
+    }
+}
+
+fn claims_id(tx: &Tx) -> bool {
+    matches!(tx.type_, TxType::Deposit | TxType::Withdrawal)
+}
+
+fn run_worker<T: Into<Tx>>(
+    mut engine: Engine,
+    queue: Vec<(usize, T)>,
+    worker: usize,
+    shared: &HashSet<TxId>,
+    shared_ids: &SharedIds,
+) -> Result<Worker, Error> {
+    let mut clients = HashSet::new();
+    let mut rejections = vec![];
+    let mut payout_indices = vec![];
+    for (index, tx) in queue {
+        let tx: Tx = tx.into();
+        let (client_id, tx_id) = (tx.client_id, tx.tx_id);
+        clients.insert(client_id);
+        let is_shared = shared.contains(&tx_id);
+        if is_shared {
+            if let Some(state) = shared_ids.taken_before(tx_id, index, worker)? {
+                engine.know_tx_state(tx_id, state);
+            }
+        }
+        let claim = (is_shared && claims_id(&tx)).then(|| engine.tx_states.contains_key(&tx_id));
+        let payouts = engine.payouts().len();
+
+        if let Err(error) = engine.process_at(index as u64 + 1, tx) {
+            rejections.push(Rejection {
+                index: index as u64 + 1,
+                client: client_id,
+                tx: tx_id,
+                error,
+            });
+        }
+
+        let made = engine.payouts().len() - payouts;
+        payout_indices.extend(std::iter::repeat_n(index as u64 + 1, made));
+        if let Some(known_before) = claim {
+            let taken = engine
+                .tx_states
+                .get(&tx_id)
+                .filter(|state| !known_before && state.client_id == client_id);
+            shared_ids.decide(tx_id, index, taken.cloned())?;
+        }
+    }
+    Ok(Worker {
+        engine,
+        clients,
+        rejections,
+        payout_indices,
+    })
+}
+
+impl ParallelRun {
+    /// Partition of `client_id` among `partitions`, the thread processing
+    /// it when there are as many, from the FNV-1a hash of the id as a
+    /// little-endian u64, so it doesn't depend on the platform or on the
+    /// width of `ClientId`.
+    pub fn partition(client_id: ClientId, partitions: usize) -> usize {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        #[allow(clippy::unnecessary_cast)] // ClientId is u64 with wide-client-ids
+        for byte in (client_id as u64).to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        (hash % partitions as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::TxBuilder;
+    use crate::Compat;
+
+    /// Pseudo-random transactions of a few clients, with tx ids reused
+    /// across clients and disputes of other clients' transactions.
+    #[allow(clippy::unnecessary_cast)] // ids are u64 with wide-client-ids and wide-tx-ids
+    fn mixed_txs(rows: u32) -> Vec<Tx> {
+        let mut state: u64 = 7;
+        let mut next = |n: u64| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) % n
+        };
+        (0..rows)
+            .map(|row| {
+                let client_id = next(7) as ClientId + 1;
+                let tx_id = next(u64::from(row) + 1) as TxId + 1;
+                let amount = next(1000) as f64 / 100.0;
+                match next(10) {
+                    0..=3 => TxBuilder::deposit(client_id)
+                        .tx(tx_id)
+                        .amount(amount)
+                        .build(),
+                    4..=5 => TxBuilder::withdrawal(client_id)
+                        .tx(tx_id)
+                        .amount(amount)
+                        .build(),
+                    6 => TxBuilder::dispute(client_id).tx(tx_id).build(),
+                    7 => TxBuilder::resolve(client_id).tx(tx_id).build(),
+                    8 => TxBuilder::chargeback(client_id).tx(tx_id).build(),
+                    _ if row % 50 == 0 => TxBuilder::close_account(client_id).tx(tx_id).build(),
+                    _ => TxBuilder::withdrawal(client_id).tx(tx_id).build(),
+                }
+            })
+            .collect()
+    }
+
+    fn engine() -> Engine {
+        Engine::new()
+            .with_compat(Compat::V2)
+            .with_audit()
+            .with_suspense()
+            .with_risk_monitor()
+            .with_last_activity()
+            .with_client_stats()
+    }
+
+    #[test]
+    fn same_result_whatever_the_thread_count() -> Result<(), Error> {
+        let txs = mixed_txs(2000);
+        let mut sequential = engine();
+        let mut rejections = vec![];
+        for (index, tx) in txs.iter().cloned().enumerate() {
+            let (client, tx_id) = (tx.client_id, tx.tx_id);
+            if let Err(error) = sequential.process(tx) {
+                rejections.push(Rejection {
+                    index: index as u64 + 1,
+                    client,
+                    tx: tx_id,
+                    error,
+                });
+            }
+        }
+        assert!(!rejections.is_empty());
+
+        for threads in [1, 2, 3, 8] {
+            let run = process_parallel(engine(), txs.clone(), threads)?;
+            assert_eq!(run.rejections, rejections, "{} threads", threads);
+            assert_eq!(run.engine.snapshot(), sequential.snapshot());
+            assert_eq!(run.engine.audit_trail(), sequential.audit_trail());
+            assert_eq!(run.engine.suspense_ledger(), sequential.suspense_ledger());
+            assert_eq!(run.engine.payouts(), sequential.payouts());
+            assert_eq!(run.engine.last_activity(), sequential.last_activity());
+            assert_eq!(run.engine.client_stats(), sequential.client_stats());
+            let borrowed = process_parallel(engine(), txs.iter().collect(), threads)?;
+            assert_eq!(borrowed.rejections, rejections);
+            assert_eq!(borrowed.engine.snapshot(), sequential.snapshot());
+            assert_eq!(
+                run.engine
+                    .risk_monitor()
+                    .map(|risk| risk.activity(3).cloned()),
+                sequential
+                    .risk_monitor()
+                    .map(|risk| risk.activity(3).cloned())
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn accounts_are_streamed_once_per_partition() -> Result<(), Error> {
+        let txs = mixed_txs(500);
+        let mut engine = engine();
+        let mut initial = ClientAccount::new(100);
+        initial.available = 1.0;
+        initial.total = 1.0;
+        engine.seed_accounts(HashMap::from([(100, initial)]))?;
+        let mut batches = vec![];
+        let run = process_parallel_streaming(engine, txs, 3, |accounts| {
+            batches.push(accounts.into_iter().cloned().collect::<Vec<_>>());
+            Ok(())
+        })?;
+        assert_eq!(batches.len(), 4);
+        assert_eq!(
+            batches[3].iter().map(|a| a.client).collect::<Vec<_>>(),
+            [100]
+        );
+        let mut streamed: Vec<ClientAccount> = batches.into_iter().flatten().collect();
+        streamed.sort_by_key(|account| account.client);
+        let mut accounts: Vec<ClientAccount> = run.engine.accounts.into_values().collect();
+        accounts.sort_by_key(|account| account.client);
+        assert_eq!(streamed, accounts);
+
+        let failing = process_parallel_streaming(Engine::new(), mixed_txs(50), 2, |_| {
+            Err(Error::new("Output closed"))
+        });
+        assert!(failing.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn order_dependent_engines_are_refused() {
+        let txs = vec![TxBuilder::deposit(1).tx(1).amount(1.0).build()];
+        let engine = Engine::new().with_dispute_timeout(2);
+        assert!(process_parallel(engine, txs.clone(), 2).is_err());
+        let engine = Engine::new().with_pending_withdrawals();
+        assert!(process_parallel(engine, txs, 2).is_err());
+    }
+
+    #[test]
+    fn partitions_are_stable_and_balanced() {
+        assert_eq!(
+            ParallelRun::partition(1, 128),
+            ParallelRun::partition(1, 128)
+        );
+        assert_eq!(ParallelRun::partition(42, 1), 0);
+        let mut sizes = [0; 8];
+        for client_id in 0..8000 {
+            sizes[ParallelRun::partition(client_id, 8)] += 1;
+        }
+        assert!(
+            sizes.iter().all(|&size| (900..1100).contains(&size)),
+            "{:?}",
+            sizes
+        );
+    }
+}
@@ -0,0 +1,13 @@
+use serde::Serialize;
+
+use crate::transaction::round_serialize;
+use crate::{ClientId, TxId};
+
+/// Available funds paid out when `tx` closed the client's account.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct Payout {
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(serialize_with = "round_serialize")]
+    pub amount: f64,
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::{ClientId, Tx};
+
+/// Withdrawals that exceeded the available funds when they arrived, kept per
+/// client in arrival order until a later deposit makes them affordable.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PendingWithdrawals {
+    queues: BTreeMap<ClientId, VecDeque<Tx>>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct PendingTotal {
+    pub client: ClientId,
+    pub count: usize,
+    pub total: f64,
+}
+
+impl PendingWithdrawals {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, tx: Tx) {
+        self.queues.entry(tx.client_id).or_default().push_back(tx);
+    }
+
+    /// Remove the withdrawals at the front of the client's queue that fit in
+    /// `available`. Stops at the first one that doesn't, to keep their order.
+    pub fn take_ready(&mut self, client_id: ClientId, available: f64) -> Vec<Tx> {
+        self.take_ready_with(client_id, available, |amount| amount)
+    }
+
+    /// Like `take_ready`, where a withdrawal of `amount` costs `cost(amount)`,
+    /// e.g. including its fee.
+    pub(crate) fn take_ready_with(
+        &mut self,
+        client_id: ClientId,
+        mut available: f64,
+        cost: impl Fn(f64) -> f64,
+    ) -> Vec<Tx> {
+        let mut ready = vec![];
+        if let Some(queue) = self.queues.get_mut(&client_id) {
+            while let Some(amount) = queue.front().and_then(|tx| tx.amount).map(&cost) {
+                if amount > available {
+                    break;
+                }
+                available -= amount;
+                ready.extend(queue.pop_front());
+            }
+            if queue.is_empty() {
+                self.queues.remove(&client_id);
+            }
+        }
+        ready
+    }
+
+    pub(crate) fn queue(&self, client_id: ClientId) -> Option<VecDeque<Tx>> {
+        self.queues.get(&client_id).cloned()
+    }
+
+    pub(crate) fn restore(&mut self, client_id: ClientId, queue: Option<VecDeque<Tx>>) {
+        match queue {
+            Some(queue) => self.queues.insert(client_id, queue),
+            None => self.queues.remove(&client_id),
+        };
+    }
+
+    pub fn totals(&self) -> Vec<PendingTotal> {
+        self.queues
+            .iter()
+            .map(|(&client, queue)| PendingTotal {
+                client,
+                count: queue.len(),
+                total: queue.iter().filter_map(|tx| tx.amount).sum(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{TxId, TxType};
+
+    fn withdrawal(tx_id: TxId, amount: f64) -> Tx {
+        Tx {
+            amount: Some(amount),
+            ..Tx::new(TxType::Withdrawal, 1, tx_id)
+        }
+    }
+
+    #[test]
+    fn take_ready_keeps_arrival_order() {
+        let mut pending = PendingWithdrawals::new();
+        pending.push(withdrawal(1, 5.0));
+        pending.push(withdrawal(2, 1.0));
+        assert_eq!(pending.take_ready(1, 4.0), vec![]);
+        assert_eq!(
+            pending.totals(),
+            vec![PendingTotal {
+                client: 1,
+                count: 2,
+                total: 6.0
+            }]
+        );
+        assert_eq!(
+            pending.take_ready(1, 6.0),
+            vec![withdrawal(1, 5.0), withdrawal(2, 1.0)]
+        );
+        assert_eq!(pending.totals(), vec![]);
+    }
+}
@@ -0,0 +1,7 @@
+//! The types most embedders need, imported with `use
+//! kitesurf_core::prelude::*`. They're kept stable across releases:
+//! renaming or removing one, or changing their existing methods, is a
+//! breaking change under semver, while the rest of what the crate exports
+//! may still change in minor releases.
+
+pub use crate::{ClientAccount, Engine, Error, RunSummary, Tx, TxType};
@@ -0,0 +1,28 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{AuditEvent, ClientId, TxId};
+
+/// A dispute, resolve or chargeback of `client` referring to a transaction
+/// of another client, `owner`. It's never applied, but a client making many
+/// of them may be probing for the transaction ids of others. `index` is its
+/// position in the input, as in the audit trail.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct ProbeAttempt {
+    pub index: u64,
+    pub event: AuditEvent,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub owner: ClientId,
+}
+
+impl ProbeAttempt {
+    /// Number of `attempts` made by each client.
+    pub fn counts(attempts: &[ProbeAttempt]) -> BTreeMap<ClientId, usize> {
+        let mut counts = BTreeMap::new();
+        for attempt in attempts {
+            *counts.entry(attempt.client).or_default() += 1;
+        }
+        counts
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::{
+    CancellationToken, ClientAccount, ClientId, Engine, Error, RunSummary, Snapshot, StateStore, Tx,
+};
+
+/// What the CLI and the servers need from an engine, so another one, e.g.
+/// batching transactions on a GPU or a mock in tests, can take the place
+/// of `Engine`: `WatchDir` runs on any, and a `Mutex` around one is a
+/// `StateStore` for `serve_grpc`. The CLI's sequential run and the shards
+/// of a `SharedEngine` go through it too.
+pub trait TransactionProcessor {
+    /// Process one transaction, as `Engine::process`.
+    fn apply(&mut self, tx: Tx) -> Result<(), Error>;
+
+    /// Accounts and disputable transactions, to save and restore the
+    /// processor's state.
+    fn snapshot(&self) -> Snapshot;
+
+    /// Accounts by client, as written to the output.
+    fn report(&self) -> HashMap<ClientId, ClientAccount>;
+
+    /// `apply` each transaction of `txs`, as `Engine::process_stream`.
+    fn process_stream(
+        &mut self,
+        txs: impl IntoIterator<Item = Tx>,
+        cancel: &CancellationToken,
+    ) -> RunSummary
+    where
+        Self: Sized,
+    {
+        let started = Instant::now();
+        let mut summary = RunSummary::new();
+        for tx in txs {
+            if cancel.is_cancelled() {
+                summary.cancelled = true;
+                break;
+            }
+            let (client, tx_id, tx_type) = (tx.client_id, tx.tx_id, tx.type_.clone());
+            let result = self.apply(tx);
+            summary.record(&tx_type, client, tx_id, result.as_ref().err());
+        }
+        summary.duration = started.elapsed();
+        summary
+    }
+}
+
+impl TransactionProcessor for Engine {
+    fn apply(&mut self, tx: Tx) -> Result<(), Error> {
+        self.process(tx)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Engine::snapshot(self)
+    }
+
+    fn report(&self) -> HashMap<ClientId, ClientAccount> {
+        self.accounts.clone()
+    }
+}
+
+/// Processes one transaction at a time, unlike `SharedEngine`. `account`
+/// goes through the whole `report`.
+impl<P: TransactionProcessor + Send> StateStore for Mutex<P> {
+    fn apply(&self, tx: Tx) -> Result<(), Error> {
+        lock(self)?.apply(tx)
+    }
+
+    fn account(&self, client_id: ClientId) -> Result<Option<ClientAccount>, Error> {
+        Ok(lock(self)?.report().remove(&client_id))
+    }
+
+    fn accounts(&self) -> Result<HashMap<ClientId, ClientAccount>, Error> {
+        Ok(lock(self)?.report())
+    }
+}
+
+fn lock<P>(processor: &Mutex<P>) -> Result<std::sync::MutexGuard<'_, P>, Error> {
+    processor
+        .lock()
+        .map_err(|_| Error::new("Processor lock poisoned"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::TxBuilder;
+    use crate::{Amount, ErrorCode, TxType};
+
+    /// Takes deposits only, without keeping any transaction state.
+    #[derive(Default)]
+    struct DepositsOnly {
+        accounts: HashMap<ClientId, ClientAccount>,
+    }
+
+    impl TransactionProcessor for DepositsOnly {
+        fn apply(&mut self, tx: Tx) -> Result<(), Error> {
+            match (&tx.type_, tx.amount()) {
+                (TxType::Deposit, Some(amount)) => self
+                    .accounts
+                    .entry(tx.client_id)
+                    .or_insert_with(|| ClientAccount::new(tx.client_id))
+                    .deposit(Amount::new(amount.value())?),
+                _ => Err(Error::coded(ErrorCode::NotApplicable, "Deposits only")),
+            }
+        }
+
+        fn snapshot(&self) -> Snapshot {
+            Snapshot {
+                accounts: self.accounts.clone(),
+                ..Snapshot::default()
+            }
+        }
+
+        fn report(&self) -> HashMap<ClientId, ClientAccount> {
+            self.accounts.clone()
+        }
+    }
+
+    #[test]
+    fn other_processors_run_streams_and_serve_as_stores() -> Result<(), Error> {
+        let mut processor = DepositsOnly::default();
+        let summary = processor.process_stream(
+            [
+                TxBuilder::deposit(1).tx(1).amount(2.0).build(),
+                TxBuilder::withdrawal(1).tx(1).amount(1.0).build(),
+            ],
+            &CancellationToken::new(),
+        );
+        assert_eq!(summary.processed, 2);
+        assert_eq!(summary.rejected_by_reason[&ErrorCode::NotApplicable], 1);
+
+        let store: &dyn StateStore = &Mutex::new(processor);
+        store.apply(TxBuilder::deposit(2).tx(1).amount(3.0).build())?;
+        assert_eq!(store.account(1)?.map(|account| account.total), Some(2.0));
+        assert_eq!(store.accounts()?.len(), 2);
+
+        let store: &dyn StateStore = &Mutex::new(Engine::new());
+        store.apply(TxBuilder::deposit(1).tx(1).amount(1.0).build())?;
+        assert_eq!(store.account(1)?.map(|account| account.total), Some(1.0));
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+
+use crate::{Error, StateStore, Tx};
+
+pub const DEFAULT_QUEUE_CAPACITY: usize = 10_000;
+
+/// Outcome of a non-blocking submission to the ingestion queue.
+///
+/// `Backpressure` hands the transaction back to the producer, which should
+/// signal the upstream (e.g. answer `429 Too Many Requests`, pause the
+/// consumer group) and retry later.
+#[derive(Debug, PartialEq)]
+pub enum Submit {
+    Accepted,
+    Backpressure(Tx),
+}
+
+/// Transactions a receiver took off the queue, see `IngestReceiver::run`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Drained {
+    pub applied: usize,
+    /// Transactions the store refused, e.g. withdrawals without an amount.
+    pub rejected: usize,
+}
+
+/// Producer side of a bounded ingestion queue. Cheap to clone, one per worker.
+#[derive(Debug, Clone)]
+pub struct IngestSender {
+    sender: SyncSender<Tx>,
+}
+
+/// Consumer side of a bounded ingestion queue.
+#[derive(Debug)]
+pub struct IngestReceiver {
+    receiver: Receiver<Tx>,
+}
+
+/// Create a queue holding at most `capacity` transactions waiting to be applied.
+pub fn ingest_queue(capacity: usize) -> (IngestSender, IngestReceiver) {
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    (IngestSender { sender }, IngestReceiver { receiver })
+}
+
+impl IngestSender {
+    /// Enqueue without blocking, reporting backpressure when the queue is full.
+    pub fn try_submit(&self, tx: Tx) -> Result<Submit, Error> {
+        match self.sender.try_send(tx) {
+            Ok(()) => Ok(Submit::Accepted),
+            Err(TrySendError::Full(tx)) => Ok(Submit::Backpressure(tx)),
+            Err(TrySendError::Disconnected(_)) => Err(Error::new("Ingestion queue is closed")),
+        }
+    }
+
+    /// Enqueue, blocking the producer until there is room in the queue.
+    pub fn submit(&self, tx: Tx) -> Result<(), Error> {
+        self.sender
+            .send(tx)
+            .map_err(|_| Error::new("Ingestion queue is closed"))
+    }
+}
+
+impl IngestReceiver {
+    /// Apply queued transactions to `store` until every sender has been
+    /// dropped, counting the ones applied and rejected.
+    pub fn run(self, store: &dyn StateStore) -> Drained {
+        let mut drained = Drained::default();
+        for tx in self.receiver {
+            match store.apply(tx) {
+                Ok(()) => drained.applied += 1,
+                Err(_) => drained.rejected += 1,
+            }
+        }
+        drained
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{SharedEngine, TxId, TxType};
+    use std::thread;
+
+    fn deposit(tx_id: TxId) -> Tx {
+        Tx {
+            amount: Some(1.0),
+            ..Tx::new(TxType::Deposit, 1, tx_id)
+        }
+    }
+
+    #[test]
+    fn full_queue_signals_backpressure() -> Result<(), Error> {
+        let (sender, _receiver) = ingest_queue(2);
+        assert_eq!(sender.try_submit(deposit(1))?, Submit::Accepted);
+        assert_eq!(sender.try_submit(deposit(2))?, Submit::Accepted);
+        assert_eq!(
+            sender.try_submit(deposit(3))?,
+            Submit::Backpressure(deposit(3))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn closed_queue_is_an_error() {
+        let (sender, receiver) = ingest_queue(2);
+        drop(receiver);
+        assert!(sender.try_submit(deposit(1)).is_err());
+        assert!(sender.submit(deposit(1)).is_err());
+    }
+
+    #[test]
+    fn receiver_applies_transactions() -> Result<(), Error> {
+        let engine = SharedEngine::new(2);
+        let (sender, receiver) = ingest_queue(1);
+        let producer = thread::spawn(move || {
+            for tx_id in 1..=10 {
+                sender.submit(deposit(tx_id)).unwrap();
+            }
+            let withdrawal = Tx {
+                type_: TxType::Withdrawal,
+                amount: None,
+                ..deposit(11)
+            };
+            sender.submit(withdrawal).unwrap();
+        });
+        assert_eq!(
+            receiver.run(&engine),
+            Drained {
+                applied: 10,
+                rejected: 1
+            }
+        );
+        producer.join().unwrap();
+        assert_eq!(engine.account(1)?.unwrap().total, 10.0);
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{ClientId, RoundingMode};
+
+/// Negative balance a client was left with by chargebacks of deposits they
+/// already spent, and how much of it later deposits paid back.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct Recovery {
+    chargebacks: u64,
+    exposure: f64,
+    recovered: f64,
+}
+
+impl Recovery {
+    fn outstanding(&self) -> f64 {
+        self.exposure - self.recovered
+    }
+}
+
+/// Chargeback exposure per client, see `Engine::with_recoveries`.
+#[derive(Debug, Default, Clone)]
+pub struct RecoveryLedger {
+    clients: BTreeMap<ClientId, Recovery>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RecoveryLine {
+    pub client: ClientId,
+    /// Chargebacks that took the total below zero.
+    pub chargebacks: u64,
+    pub exposure: f64,
+    pub recovered: f64,
+    pub outstanding: f64,
+}
+
+impl RecoveryLedger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track the change of a client's total from `before` to `after`. A
+    /// chargeback adds what it takes below zero to the exposure, any other
+    /// transaction raising a negative total recovers the difference.
+    pub fn record(&mut self, client_id: ClientId, chargeback: bool, before: f64, after: f64) {
+        let shortfall = |total: f64| (-total).max(0.0);
+        let (before, after) = (shortfall(before), shortfall(after));
+        if chargeback && after > before {
+            let recovery = self.clients.entry(client_id).or_default();
+            recovery.chargebacks += 1;
+            recovery.exposure += after - before;
+        } else if let Some(recovery) = self.clients.get_mut(&client_id) {
+            if after < before {
+                recovery.recovered += (before - after).min(recovery.outstanding());
+            }
+        }
+    }
+
+    pub(crate) fn client(&self, client_id: ClientId) -> Option<Recovery> {
+        self.clients.get(&client_id).cloned()
+    }
+
+    pub(crate) fn restore(&mut self, client_id: ClientId, recovery: Option<Recovery>) {
+        match recovery {
+            Some(recovery) => self.clients.insert(client_id, recovery),
+            None => self.clients.remove(&client_id),
+        };
+    }
+
+    /// Sum of what is still owed across clients.
+    pub fn outstanding(&self) -> f64 {
+        RoundingMode::default().round(self.clients.values().map(Recovery::outstanding).sum())
+    }
+
+    /// Ordered by client.
+    pub fn lines(&self) -> Vec<RecoveryLine> {
+        let round = |amount| RoundingMode::default().round(amount);
+        self.clients
+            .iter()
+            .map(|(&client, recovery)| RecoveryLine {
+                client,
+                chargebacks: recovery.chargebacks,
+                exposure: round(recovery.exposure),
+                recovered: round(recovery.recovered),
+                outstanding: round(recovery.outstanding()),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deposits_recover_chargeback_exposure() {
+        let mut recoveries = RecoveryLedger::new();
+        // chargeback of 5 after 3 of it were withdrawn
+        recoveries.record(1, true, 2.0, -3.0);
+        recoveries.record(1, false, -3.0, -1.0);
+        // a chargeback that stays covered isn't exposure
+        recoveries.record(2, true, 10.0, 4.0);
+        assert_eq!(
+            recoveries.lines(),
+            vec![RecoveryLine {
+                client: 1,
+                chargebacks: 1,
+                exposure: 3.0,
+                recovered: 2.0,
+                outstanding: 1.0,
+            }]
+        );
+        recoveries.record(1, false, -1.0, 4.0);
+        assert_eq!(recoveries.outstanding(), 0.0);
+    }
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::{Tx, TxId};
+
+/// Resolves and chargebacks that arrived before the dispute they settle,
+/// held for a number of further transactions in case it shows up.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ReorderBuffer {
+    window: u64,
+    /// In arrival order, with the index they arrived at.
+    held: VecDeque<(u64, Tx)>,
+}
+
+impl ReorderBuffer {
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            held: VecDeque::new(),
+        }
+    }
+
+    pub fn hold(&mut self, index: u64, tx: Tx) {
+        self.held.push_back((index, tx));
+    }
+
+    /// Remove the first held transaction settling `tx_id`.
+    pub fn take(&mut self, tx_id: TxId) -> Option<Tx> {
+        let position = self.held.iter().position(|(_, tx)| tx.tx_id == tx_id)?;
+        self.held.remove(position).map(|(_, tx)| tx)
+    }
+
+    /// Drop and return the transactions still waiting `window` transactions
+    /// after they arrived, as of `index`.
+    pub fn expire(&mut self, index: u64) -> Vec<Tx> {
+        let mut expired = vec![];
+        while let Some((arrived, _)) = self.held.front() {
+            if arrived + self.window > index {
+                break;
+            }
+            expired.extend(self.held.pop_front().map(|(_, tx)| tx));
+        }
+        expired
+    }
+
+    pub fn len(&self) -> usize {
+        self.held.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TxType;
+
+    fn resolve(tx_id: TxId) -> Tx {
+        Tx::new(TxType::Resolve, 1, tx_id)
+    }
+
+    #[test]
+    fn held_transactions_expire_after_the_window() {
+        let mut buffer = ReorderBuffer::new(2);
+        buffer.hold(1, resolve(10));
+        buffer.hold(2, resolve(20));
+        assert!(buffer.expire(2).is_empty());
+        assert_eq!(buffer.take(20).map(|tx| tx.tx_id), Some(20));
+        assert_eq!(buffer.expire(3).len(), 1);
+        assert!(buffer.is_empty());
+    }
+}
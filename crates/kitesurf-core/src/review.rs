@@ -0,0 +1,19 @@
+use serde::Serialize;
+
+use crate::transaction::round_serialize;
+use crate::{ClientId, TxId};
+
+/// A dispute that held more than the client's available funds, leaving
+/// them negative, under `DisputeHoldPolicy::FlagForReview`. `index` is its
+/// position in the input, as in the audit trail.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct HoldReview {
+    pub index: u64,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(serialize_with = "round_serialize")]
+    pub held: f64,
+    /// Available funds before the dispute.
+    #[serde(serialize_with = "round_serialize")]
+    pub available: f64,
+}
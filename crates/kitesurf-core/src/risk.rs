@@ -0,0 +1,183 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{ClientId, Tx, TxType};
+
+/// A withdrawal this many transactions or fewer after a deposit of the same
+/// client counts as a rapid deposit-withdraw cycle.
+const RAPID_CYCLE_WINDOW: u64 = 10;
+
+/// Per-client activity the risk scorers work from.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct ClientActivity {
+    pub deposits: u32,
+    pub withdrawals: u32,
+    pub disputes: u32,
+    pub chargebacks: u32,
+    pub rapid_cycles: u32,
+    last_deposit_at: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RiskScore {
+    /// Between 0.0 (no risk) and 1.0.
+    pub score: f64,
+    pub reasons: Vec<String>,
+}
+
+pub trait RiskScorer {
+    fn score(&self, activity: &ClientActivity) -> RiskScore;
+}
+
+/// Weighted sum of rapid deposit-withdraw cycles, the share of deposits
+/// that got disputed, and chargebacks.
+#[derive(Debug, Clone)]
+pub struct DefaultRiskScorer {
+    pub rapid_cycle_weight: f64,
+    pub dispute_ratio_weight: f64,
+    pub chargeback_weight: f64,
+}
+
+impl Default for DefaultRiskScorer {
+    fn default() -> Self {
+        Self {
+            rapid_cycle_weight: 0.3,
+            dispute_ratio_weight: 0.3,
+            chargeback_weight: 0.4,
+        }
+    }
+}
+
+impl RiskScorer for DefaultRiskScorer {
+    fn score(&self, activity: &ClientActivity) -> RiskScore {
+        let mut score = 0.0;
+        let mut reasons = vec![];
+        if activity.rapid_cycles > 0 {
+            score += self.rapid_cycle_weight * (activity.rapid_cycles as f64 / 3.0).min(1.0);
+            reasons.push(format!(
+                "{} rapid deposit-withdraw cycles",
+                activity.rapid_cycles
+            ));
+        }
+        if activity.disputes > 0 {
+            let ratio = (activity.disputes as f64 / activity.deposits.max(1) as f64).min(1.0);
+            score += self.dispute_ratio_weight * ratio;
+            reasons.push(format!("{:.0}% of deposits disputed", ratio * 100.0));
+        }
+        if activity.chargebacks > 0 {
+            score += self.chargeback_weight;
+            reasons.push(format!("{} chargebacks", activity.chargebacks));
+        }
+        RiskScore { score, reasons }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct RiskFlag {
+    pub client: ClientId,
+    pub score: f64,
+    pub reasons: String,
+}
+
+/// Collects `ClientActivity` while the engine processes transactions.
+#[derive(Debug, Default, Clone)]
+pub struct RiskMonitor {
+    activity: BTreeMap<ClientId, ClientActivity>,
+}
+
+impl RiskMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a transaction the engine accepted, `index` being its position
+    /// in the input.
+    pub fn record(&mut self, index: u64, tx: &Tx) {
+        let activity = self.activity.entry(tx.client_id).or_default();
+        match tx.type_ {
+            TxType::Deposit => {
+                activity.deposits += 1;
+                activity.last_deposit_at = Some(index);
+            }
+            TxType::Withdrawal => {
+                activity.withdrawals += 1;
+                if let Some(deposit_at) = activity.last_deposit_at.take() {
+                    if index - deposit_at <= RAPID_CYCLE_WINDOW {
+                        activity.rapid_cycles += 1;
+                    }
+                }
+            }
+            TxType::Dispute => activity.disputes += 1,
+            TxType::Resolve | TxType::CloseAccount | TxType::Adjustment | TxType::Custom(_) => {}
+            TxType::Chargeback => activity.chargebacks += 1,
+        }
+    }
+
+    pub fn activity(&self, client_id: ClientId) -> Option<&ClientActivity> {
+        self.activity.get(&client_id)
+    }
+
+    pub(crate) fn restore(&mut self, client_id: ClientId, activity: Option<ClientActivity>) {
+        match activity {
+            Some(activity) => self.activity.insert(client_id, activity),
+            None => self.activity.remove(&client_id),
+        };
+    }
+
+    /// Accounts scoring at least `threshold`, ordered by client.
+    pub fn flagged(&self, scorer: &dyn RiskScorer, threshold: f64) -> Vec<RiskFlag> {
+        self.activity
+            .iter()
+            .filter_map(|(&client, activity)| {
+                let risk = scorer.score(activity);
+                (risk.score >= threshold).then(|| RiskFlag {
+                    client,
+                    score: (risk.score * 100.0).round() / 100.0,
+                    reasons: risk.reasons.join("; "),
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testkit::TxBuilder;
+
+    #[test]
+    fn rapid_cycles_and_chargebacks_are_flagged() {
+        let mut monitor = RiskMonitor::new();
+        monitor.record(1, &TxBuilder::deposit(1).tx(1).amount(1.0).build());
+        monitor.record(2, &TxBuilder::withdrawal(1).tx(2).amount(1.0).build());
+        monitor.record(3, &TxBuilder::dispute(1).tx(1).build());
+        monitor.record(4, &TxBuilder::chargeback(1).tx(1).build());
+        monitor.record(5, &TxBuilder::deposit(2).tx(3).amount(1.0).build());
+        monitor.record(50, &TxBuilder::withdrawal(2).tx(4).amount(1.0).build());
+
+        let activity = monitor.activity(1).unwrap();
+        assert_eq!((activity.rapid_cycles, activity.chargebacks), (1, 1));
+        assert_eq!(monitor.activity(2).unwrap().rapid_cycles, 0);
+
+        let flags = monitor.flagged(&DefaultRiskScorer::default(), 0.5);
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].client, 1);
+        assert_eq!(flags[0].score, 0.8);
+    }
+
+    #[test]
+    fn custom_scorer() {
+        struct DisputesOnly;
+        impl RiskScorer for DisputesOnly {
+            fn score(&self, activity: &ClientActivity) -> RiskScore {
+                RiskScore {
+                    score: activity.disputes as f64,
+                    reasons: vec![],
+                }
+            }
+        }
+        let mut monitor = RiskMonitor::new();
+        monitor.record(1, &TxBuilder::dispute(3).tx(1).build());
+        assert_eq!(monitor.flagged(&DisputesOnly, 1.0)[0].client, 3);
+    }
+}
@@ -0,0 +1,91 @@
+use std::str::FromStr;
+
+use crate::Error;
+
+/// Amounts are kept and written with this many decimal places.
+pub const DECIMALS: i32 = 4;
+
+/// Differences below half the last kept decimal, which float arithmetic can
+/// leave between amounts that are equal once rounded.
+pub(crate) fn tolerance() -> f64 {
+    0.5 * 10f64.powi(-DECIMALS)
+}
+
+/// How amounts are rounded to `DECIMALS` places. Examples are for 4 places.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum RoundingMode {
+    /// Ties away from zero: 0.00005 -> 0.0001, -0.00005 -> -0.0001.
+    #[default]
+    HalfAwayFromZero,
+    /// Ties to the even digit, aka banker's rounding: 0.00005 -> 0.0,
+    /// 0.00015 -> 0.0002.
+    HalfEven,
+    /// Ties towards positive infinity: -0.00005 -> 0.0.
+    HalfUp,
+    /// Towards zero, i.e. truncate: 0.00019 -> 0.0001.
+    Down,
+    /// Away from zero: 0.00011 -> 0.0002.
+    Up,
+}
+
+impl RoundingMode {
+    /// Round `x` to `DECIMALS` places. Amounts that round to zero are
+    /// always `0.0`, never `-0.0`.
+    pub fn round(self, x: f64) -> f64 {
+        self.round_to(x, DECIMALS)
+    }
+
+    /// Round `x` to `decimals` places, as `round`.
+    pub fn round_to(self, x: f64, decimals: i32) -> f64 {
+        let scale = 10f64.powi(decimals);
+        let scaled = x * scale;
+        let rounded = match self {
+            RoundingMode::HalfAwayFromZero => scaled.round(),
+            RoundingMode::HalfEven => scaled.round_ties_even(),
+            RoundingMode::HalfUp => (scaled + 0.5).floor(),
+            RoundingMode::Down => scaled.trunc(),
+            RoundingMode::Up => scaled.abs().ceil().copysign(scaled),
+        };
+        rounded / scale + 0.0
+    }
+}
+
+impl FromStr for RoundingMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "half_away_from_zero" => Ok(RoundingMode::HalfAwayFromZero),
+            "half_even" => Ok(RoundingMode::HalfEven),
+            "half_up" => Ok(RoundingMode::HalfUp),
+            "down" => Ok(RoundingMode::Down),
+            "up" => Ok(RoundingMode::Up),
+            _ => Err(Error::new(&format!("Unknown rounding mode: {}", s))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rounding_modes() -> Result<(), Error> {
+        let cases = [
+            ("half_away_from_zero", [0.0003, -0.0003, 0.0002, 0.0001]),
+            ("half_even", [0.0002, -0.0002, 0.0002, 0.0001]),
+            ("half_up", [0.0003, -0.0002, 0.0002, 0.0001]),
+            ("down", [0.0002, -0.0002, 0.0001, 0.0001]),
+            ("up", [0.0003, -0.0003, 0.0002, 0.0002]),
+        ];
+        for (mode, expected) in cases {
+            let mode: RoundingMode = mode.parse()?;
+            let rounded = [0.00025, -0.00025, 0.00019, 0.00011].map(|x| mode.round(x));
+            assert_eq!(rounded, expected, "{:?}", mode);
+        }
+        assert!(RoundingMode::default().round(-0.00001).is_sign_positive());
+        assert_eq!(RoundingMode::HalfEven.round_to(0.125, 2), 0.12);
+        assert!("nearest".parse::<RoundingMode>().is_err());
+        Ok(())
+    }
+}
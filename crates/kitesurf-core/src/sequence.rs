@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::{ClientId, Error, ErrorCode, Tx};
+
+/// Enforces strictly consecutive `seq` numbers per client.
+///
+/// The first sequence number seen for a client is taken as its starting
+/// point; every following transaction of that client must carry the next
+/// number. Transactions without a `seq`, replayed or out-of-order numbers
+/// and gaps are all rejected, so merged inputs either apply in one
+/// deterministic order or not at all.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
+pub struct SequenceChecker {
+    last_seq: HashMap<ClientId, u64>,
+}
+
+impl SequenceChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&mut self, tx: &Tx) -> Result<(), Error> {
+        let seq = tx.seq.ok_or_else(|| {
+            Error::coded(
+                ErrorCode::Sequence,
+                &format!("Transaction {} has no sequence number", tx.tx_id),
+            )
+        })?;
+        if let Some(&last) = self.last_seq.get(&tx.client_id) {
+            if seq <= last {
+                return Err(Error::coded(
+                    ErrorCode::Sequence,
+                    &format!(
+                        "Out-of-order sequence number {} for client {}, last was {}",
+                        seq, tx.client_id, last
+                    ),
+                ));
+            }
+            if seq != last + 1 {
+                return Err(Error::coded(
+                    ErrorCode::Sequence,
+                    &format!(
+                        "Gap in sequence numbers for client {}: expected {}, got {}",
+                        tx.client_id,
+                        last + 1,
+                        seq
+                    ),
+                ));
+            }
+        }
+        self.last_seq.insert(tx.client_id, seq);
+        Ok(())
+    }
+
+    pub(crate) fn last_seq(&self, client_id: ClientId) -> Option<u64> {
+        self.last_seq.get(&client_id).copied()
+    }
+
+    pub(crate) fn restore(&mut self, client_id: ClientId, last_seq: Option<u64>) {
+        match last_seq {
+            Some(seq) => self.last_seq.insert(client_id, seq),
+            None => self.last_seq.remove(&client_id),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TxType;
+
+    fn deposit(client_id: ClientId, seq: Option<u64>) -> Tx {
+        Tx {
+            amount: Some(1.0),
+            seq,
+            ..Tx::new(TxType::Deposit, client_id, 1)
+        }
+    }
+
+    #[test]
+    fn consecutive_sequences_per_client() -> Result<(), Error> {
+        let mut checker = SequenceChecker::new();
+        checker.check(&deposit(1, Some(5)))?;
+        checker.check(&deposit(2, Some(1)))?;
+        checker.check(&deposit(1, Some(6)))?;
+        checker.check(&deposit(2, Some(2)))?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_out_of_order_and_gaps() -> Result<(), Error> {
+        let mut checker = SequenceChecker::new();
+        checker.check(&deposit(1, Some(1)))?;
+        checker.check(&deposit(1, Some(2)))?;
+        assert!(checker.check(&deposit(1, Some(2))).is_err());
+        assert!(checker.check(&deposit(1, Some(1))).is_err());
+        assert!(checker.check(&deposit(1, Some(4))).is_err());
+        // rejected transactions don't advance the sequence
+        checker.check(&deposit(1, Some(3)))?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_missing_sequence() {
+        let mut checker = SequenceChecker::new();
+        assert!(checker.check(&deposit(1, None)).is_err());
+    }
+}
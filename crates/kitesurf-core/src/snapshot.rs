@@ -0,0 +1,216 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+
+use crate::rounding::tolerance;
+use crate::{
+    ClientAccount, ClientId, Error, ErrorCode, LockInfo, PendingWithdrawals, ReorderBuffer,
+    SequenceChecker, TxId, TxState,
+};
+
+/// Exported engine state, enough to continue processing where a previous run
+/// stopped: balances and the transactions that can still be disputed.
+/// Accounts are kept as `AccountRecord`s, exact and closed ones included.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Snapshot {
+    #[serde(with = "account_records")]
+    pub accounts: HashMap<ClientId, ClientAccount>,
+    pub tx_states: HashMap<TxId, TxState>,
+}
+
+/// Everything an `Engine` goes on processing from, see `Engine::state`: a
+/// `Snapshot`'s balances and transaction states, plus the number of
+/// transactions processed, the disputes a dispute timeout will resolve, and
+/// the state of sequence checks, pending withdrawals and the reorder
+/// window. Serialize it in any format serde supports. Reports (audit trail,
+/// payouts...) and settings such as the dispute timeout aren't part of it.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
+pub struct EngineState {
+    #[serde(with = "account_records")]
+    pub accounts: HashMap<ClientId, ClientAccount>,
+    pub tx_states: HashMap<TxId, TxState>,
+    #[serde(default)]
+    pub processed: u64,
+    /// Disputes still open, with the index of the transaction that opened
+    /// them, in that order.
+    #[serde(default)]
+    pub open_disputes: Vec<(u64, TxId)>,
+    pub sequences: Option<SequenceChecker>,
+    pub pending: Option<PendingWithdrawals>,
+    pub reorder: Option<ReorderBuffer>,
+}
+
+/// A `ClientAccount` as persisted, in snapshots and stores: unlike the CSV
+/// output, balances aren't rounded and every field is kept.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct AccountRecord {
+    pub client: ClientId,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock: Option<LockInfo>,
+}
+
+impl From<&ClientAccount> for AccountRecord {
+    fn from(account: &ClientAccount) -> Self {
+        Self {
+            client: account.client,
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+            closed: account.closed,
+            lock: account.lock,
+        }
+    }
+}
+
+/// Refuses records whose balances aren't finite or don't add up, e.g. an
+/// edited snapshot or a corrupt input.
+impl TryFrom<AccountRecord> for ClientAccount {
+    type Error = Error;
+
+    fn try_from(record: AccountRecord) -> Result<Self, Self::Error> {
+        let balances = [record.available, record.held, record.total];
+        if !balances.iter().all(|balance| balance.is_finite())
+            || (record.total - (record.available + record.held)).abs() >= tolerance()
+        {
+            return Err(Error::coded(
+                ErrorCode::Invariants,
+                &format!(
+                    "client {}: total {} != available {} + held {}",
+                    record.client, record.total, record.available, record.held
+                ),
+            ));
+        }
+        Ok(Self {
+            client: record.client,
+            available: record.available,
+            held: record.held,
+            total: record.total,
+            locked: record.locked,
+            closed: record.closed,
+            lock: record.lock,
+        })
+    }
+}
+
+/// (De)serialize accounts by client as `AccountRecord`s.
+mod account_records {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        accounts: &HashMap<ClientId, ClientAccount>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(
+            accounts
+                .iter()
+                .map(|(client_id, account)| (client_id, AccountRecord::from(account))),
+        )
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<ClientId, ClientAccount>, D::Error> {
+        let records = HashMap::<ClientId, AccountRecord>::deserialize(deserializer)?;
+        records
+            .into_iter()
+            .map(|(client_id, record)| {
+                ClientAccount::try_from(record)
+                    .map(|account| (client_id, account))
+                    .map_err(serde::de::Error::custom)
+            })
+            .collect()
+    }
+}
+
+impl Snapshot {
+    /// Combine snapshots of disjoint client shards into one.
+    pub fn merge(snapshots: Vec<Snapshot>) -> Result<Snapshot, Error> {
+        let mut merged = Snapshot::default();
+        for snapshot in snapshots {
+            for (client_id, account) in snapshot.accounts {
+                if merged.accounts.insert(client_id, account).is_some() {
+                    return Err(Error::new(&format!(
+                        "Client {} appears in more than one snapshot",
+                        client_id
+                    )));
+                }
+            }
+            for (tx_id, tx_state) in snapshot.tx_states {
+                if merged.tx_states.insert(tx_id, tx_state).is_some() {
+                    return Err(Error::new(&format!(
+                        "Transaction {} appears in more than one snapshot",
+                        tx_id
+                    )));
+                }
+            }
+        }
+        Ok(merged)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Engine, Tx, TxType};
+
+    fn engine_with_deposit(client_id: ClientId, tx_id: TxId) -> Result<Engine, Error> {
+        let mut engine = Engine::new();
+        engine.process(Tx {
+            amount: Some(5.0),
+            ..Tx::new(TxType::Deposit, client_id, tx_id)
+        })?;
+        Ok(engine)
+    }
+
+    #[test]
+    fn merge_disjoint_snapshots_and_dispute_across_them() -> Result<(), Error> {
+        let merged = Snapshot::merge(vec![
+            engine_with_deposit(1, 1)?.snapshot(),
+            engine_with_deposit(2, 2)?.snapshot(),
+        ])?;
+        let mut engine = Engine::from_snapshot(merged);
+        engine.process(Tx::new(TxType::Dispute, 2, 2))?;
+        assert_eq!(engine.accounts.len(), 2);
+        assert_eq!(engine.accounts[&2].held, 5.0);
+        assert_eq!(engine.accounts[&1].available, 5.0);
+        Ok(())
+    }
+
+    #[test]
+    fn snapshots_keep_closed_accounts_and_exact_balances() -> Result<(), Error> {
+        let mut engine = engine_with_deposit(1, 1)?;
+        let account = engine.accounts.get_mut(&1).unwrap();
+        (account.available, account.total) = (0.123456, 0.123456);
+        account.closed = true;
+        let json = serde_json::to_string(&engine.snapshot()).unwrap();
+        let restored: Snapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, engine.snapshot());
+        assert!(restored.accounts[&1].closed);
+
+        // balances that don't add up aren't restored
+        let tampered = json.replace("\"total\":0.123456", "\"total\":5.0");
+        assert!(serde_json::from_str::<Snapshot>(&tampered).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merge_overlapping_snapshots_is_error() -> Result<(), Error> {
+        let result = Snapshot::merge(vec![
+            engine_with_deposit(1, 1)?.snapshot(),
+            engine_with_deposit(1, 2)?.snapshot(),
+        ]);
+        assert!(result.is_err());
+        let result = Snapshot::merge(vec![
+            engine_with_deposit(1, 1)?.snapshot(),
+            engine_with_deposit(2, 1)?.snapshot(),
+        ]);
+        assert!(result.is_err());
+        Ok(())
+    }
+}
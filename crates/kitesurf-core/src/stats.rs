@@ -0,0 +1,52 @@
+use serde::Serialize;
+
+use crate::{TxState, TxStateType};
+
+/// Counts of the transactions that took effect on an account, see
+/// `Engine::with_client_stats`.
+#[derive(Debug, Default, Serialize, PartialEq, Eq, Clone, Copy)]
+pub struct ClientStats {
+    pub deposits: u64,
+    pub withdrawals: u64,
+    /// Disputes currently holding funds.
+    pub open_disputes: u64,
+    /// Disputes closed by a resolve, including ones resolved by
+    /// `DisputePolicy::AutoResolveAfter`.
+    pub resolved_disputes: u64,
+    pub chargebacks: u64,
+}
+
+impl ClientStats {
+    /// Count a change of the state of one transaction of the client, from
+    /// `before` to `after`.
+    pub(crate) fn record(&mut self, before: Option<&TxState>, after: Option<&TxState>) {
+        let after = match after {
+            Some(after) => after,
+            None => return,
+        };
+        let before = match before {
+            Some(before) => before,
+            None => {
+                match after.type_ {
+                    TxStateType::Deposit => self.deposits += 1,
+                    TxStateType::Withdrawal => self.withdrawals += 1,
+                    TxStateType::Adjustment => {}
+                }
+                return;
+            }
+        };
+        if !before.charged_back && after.charged_back {
+            self.chargebacks += 1;
+        }
+        match (before.disputed, after.disputed) {
+            (false, true) => self.open_disputes += 1,
+            (true, false) => {
+                self.open_disputes = self.open_disputes.saturating_sub(1);
+                if !after.charged_back {
+                    self.resolved_disputes += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+}
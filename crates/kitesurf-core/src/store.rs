@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+use crate::{ClientAccount, ClientId, Error, SharedEngine, Tx};
+
+/// Where account and transaction state lives. Implementations must be safe to
+/// share between workers; `apply` is atomic per transaction.
+pub trait StateStore: Send + Sync {
+    fn apply(&self, tx: Tx) -> Result<(), Error>;
+    fn account(&self, client_id: ClientId) -> Result<Option<ClientAccount>, Error>;
+    fn accounts(&self) -> Result<HashMap<ClientId, ClientAccount>, Error>;
+}
+
+impl StateStore for SharedEngine {
+    fn apply(&self, tx: Tx) -> Result<(), Error> {
+        SharedEngine::apply(self, tx)
+    }
+
+    fn account(&self, client_id: ClientId) -> Result<Option<ClientAccount>, Error> {
+        SharedEngine::account(self, client_id)
+    }
+
+    fn accounts(&self) -> Result<HashMap<ClientId, ClientAccount>, Error> {
+        SharedEngine::accounts(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::TxType;
+
+    fn deposit_twice(store: &dyn StateStore) -> Result<(), Error> {
+        for tx_id in 1..=2 {
+            store.apply(Tx {
+                amount: Some(2.5),
+                ..Tx::new(TxType::Deposit, 1, tx_id)
+            })?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn shared_engine_as_state_store() -> Result<(), Error> {
+        let store = SharedEngine::new(2);
+        deposit_twice(&store)?;
+        assert_eq!(StateStore::account(&store, 1)?.unwrap().total, 5.0);
+        assert_eq!(StateStore::accounts(&store)?.len(), 1);
+        Ok(())
+    }
+}
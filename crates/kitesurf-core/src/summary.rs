@@ -0,0 +1,86 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+use crate::{ClientId, Error, ErrorCode, Rejection, TxId, TxType};
+
+/// Outcome of a run, returned by `Engine::process_stream`. Hosts running
+/// their own loop around `Engine::process`, like the CLI, `record` each
+/// transaction into one, so they count them the same way.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct RunSummary {
+    /// Transactions processed, rejected ones included.
+    pub processed: u64,
+    /// Transactions processed per type, rejected ones included.
+    pub tx_types: BTreeMap<TxType, u64>,
+    /// In processing order, indexed from the start of the run.
+    pub rejections: Vec<Rejection>,
+    /// Rejected transactions per error code.
+    pub rejected_by_reason: BTreeMap<ErrorCode, u64>,
+    /// Clients with at least one transaction that wasn't rejected.
+    pub accounts_touched: HashSet<ClientId>,
+    /// Wall-clock time the run took.
+    pub duration: Duration,
+    /// Whether the run was cancelled before the end of its input, see
+    /// `CancellationToken`.
+    pub cancelled: bool,
+}
+
+impl RunSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a processed transaction, with the error `Engine::process`
+    /// rejected it with, if any.
+    pub fn record(&mut self, tx_type: &TxType, client: ClientId, tx: TxId, error: Option<&Error>) {
+        self.processed += 1;
+        *self.tx_types.entry(tx_type.clone()).or_default() += 1;
+        match error {
+            Some(error) => {
+                *self.rejected_by_reason.entry(error.code).or_default() += 1;
+                self.rejections.push(Rejection {
+                    index: self.processed,
+                    client,
+                    tx,
+                    error: error.clone().with_tx(tx),
+                });
+            }
+            None => {
+                self.accounts_touched.insert(client);
+            }
+        }
+    }
+
+    pub fn rejected(&self) -> usize {
+        self.rejections.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_counts_types_and_reasons() {
+        let mut summary = RunSummary::new();
+        let funds = Error::coded(ErrorCode::InsufficientFunds, "Insufficient funds");
+        summary.record(&TxType::Deposit, 1, 1, None);
+        summary.record(&TxType::Withdrawal, 2, 2, Some(&funds));
+        summary.record(&TxType::Withdrawal, 1, 3, None);
+        summary.record(&TxType::Withdrawal, 3, 4, Some(&funds));
+
+        assert_eq!(summary.processed, 4);
+        assert_eq!(
+            summary.tx_types,
+            BTreeMap::from([(TxType::Deposit, 1), (TxType::Withdrawal, 3)])
+        );
+        assert_eq!(
+            summary.rejected_by_reason,
+            BTreeMap::from([(ErrorCode::InsufficientFunds, 2)])
+        );
+        assert_eq!(summary.accounts_touched, HashSet::from([1]));
+        assert_eq!(summary.rejected(), 2);
+        assert_eq!(summary.rejections[1].index, 4);
+        assert_eq!(summary.rejections[1].error.tx, Some(4));
+    }
+}
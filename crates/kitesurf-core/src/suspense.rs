@@ -0,0 +1,24 @@
+use serde::Serialize;
+
+use crate::{AuditEvent, ClientId, TxId};
+
+#[derive(Debug, Serialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SuspenseReason {
+    /// No deposit or withdrawal with this id was seen.
+    UnknownTx,
+    /// The transaction belongs to another client, which `Compat::V2` ignores.
+    OtherClient,
+}
+
+/// A dispute, resolve or chargeback that couldn't be matched to its
+/// original transaction, so its amount is unknown. `index` is its position
+/// in the input, as in the audit trail.
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct SuspenseEntry {
+    pub index: u64,
+    pub event: AuditEvent,
+    pub client: ClientId,
+    pub tx: TxId,
+    pub reason: SuspenseReason,
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use crate::{ClientId, Error};
+
+/// Interning table keeping the clients of several tenants (e.g. platforms
+/// sharing a run) apart: each `(tenant, client)` pair gets its own internal
+/// `ClientId`, assigned in order of first appearance, which the engine
+/// keys accounts by.
+#[derive(Debug, Default, Clone)]
+pub struct Tenants {
+    ids: HashMap<(String, ClientId), ClientId>,
+    clients: Vec<(String, ClientId)>,
+}
+
+impl Tenants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, tenant: &str, client: ClientId) -> Result<ClientId, Error> {
+        let key = (tenant.to_string(), client);
+        if let Some(&id) = self.ids.get(&key) {
+            return Ok(id);
+        }
+        let id = ClientId::try_from(self.clients.len())
+            .map_err(|_| Error::new("Too many distinct tenant clients"))?;
+        self.ids.insert(key.clone(), id);
+        self.clients.push(key);
+        Ok(id)
+    }
+
+    /// The tenant and client an internal id was assigned to.
+    pub fn resolve(&self, id: ClientId) -> Option<(&str, ClientId)> {
+        self.clients
+            .get(id as usize)
+            .map(|(tenant, client)| (tenant.as_str(), *client))
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clients_are_kept_apart_by_tenant() -> Result<(), Error> {
+        let mut tenants = Tenants::new();
+        let a = tenants.intern("shop-a", 1)?;
+        let b = tenants.intern("shop-b", 1)?;
+        assert_ne!(a, b);
+        assert_eq!(tenants.intern("shop-a", 1)?, a);
+        assert_eq!(tenants.resolve(b), Some(("shop-b", 1)));
+        assert_eq!(tenants.resolve(7), None);
+        assert_eq!(tenants.len(), 2);
+        Ok(())
+    }
+}
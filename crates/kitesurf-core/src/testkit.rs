@@ -0,0 +1,260 @@
+//! Builders and assertions for tests of code embedding the engine, with the
+//! `test-util` feature.
+//!
+//! ```
+//! use kitesurf_core::testkit::{Scenario, TxBuilder};
+//!
+//! let scenario = Scenario::new()
+//!     .tx(TxBuilder::deposit(1).tx(1).amount(5.0))
+//!     .tx(TxBuilder::dispute(1).tx(1));
+//! scenario.account(1).available(0.0).held(5.0).total(5.0).locked(false);
+//! ```
+
+use crate::{ClientAccount, ClientId, Engine, Error, RoundingMode, Tx, TxId, TxType};
+
+/// Builds a `Tx` of one type for one client. The transaction id defaults to
+/// 0 and every optional column to `None`.
+#[derive(Debug, Clone)]
+pub struct TxBuilder {
+    tx: Tx,
+}
+
+impl TxBuilder {
+    pub fn new(type_: TxType, client_id: ClientId) -> Self {
+        Self {
+            tx: Tx::new(type_, client_id, 0),
+        }
+    }
+
+    pub fn deposit(client_id: ClientId) -> Self {
+        Self::new(TxType::Deposit, client_id)
+    }
+
+    pub fn withdrawal(client_id: ClientId) -> Self {
+        Self::new(TxType::Withdrawal, client_id)
+    }
+
+    pub fn dispute(client_id: ClientId) -> Self {
+        Self::new(TxType::Dispute, client_id)
+    }
+
+    pub fn resolve(client_id: ClientId) -> Self {
+        Self::new(TxType::Resolve, client_id)
+    }
+
+    pub fn chargeback(client_id: ClientId) -> Self {
+        Self::new(TxType::Chargeback, client_id)
+    }
+
+    pub fn close_account(client_id: ClientId) -> Self {
+        Self::new(TxType::CloseAccount, client_id)
+    }
+
+    pub fn adjustment(client_id: ClientId) -> Self {
+        Self::new(TxType::Adjustment, client_id)
+    }
+
+    pub fn tx(mut self, tx_id: TxId) -> Self {
+        self.tx.tx_id = tx_id;
+        self
+    }
+
+    pub fn amount(mut self, amount: f64) -> Self {
+        self.tx.amount = Some(amount);
+        self
+    }
+
+    pub fn seq(mut self, seq: u64) -> Self {
+        self.tx.seq = Some(seq);
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.tx.description = Some(description.to_string());
+        self
+    }
+
+    pub fn merchant(mut self, merchant: &str) -> Self {
+        self.tx.merchant = Some(merchant.to_string());
+        self
+    }
+
+    pub fn category(mut self, category: &str) -> Self {
+        self.tx.category = Some(category.to_string());
+        self
+    }
+
+    pub fn build(self) -> Tx {
+        self.tx
+    }
+}
+
+impl From<TxBuilder> for Tx {
+    fn from(builder: TxBuilder) -> Self {
+        builder.build()
+    }
+}
+
+/// Chained assertions on one account. Amounts are compared after rounding
+/// both sides to the output precision, so `0.1 + 0.2` equals `0.3`.
+#[derive(Debug)]
+pub struct AccountAssert<'a> {
+    account: &'a ClientAccount,
+}
+
+impl<'a> AccountAssert<'a> {
+    /// Panics if the engine has no account for `client_id`.
+    #[track_caller]
+    pub fn of(engine: &'a Engine, client_id: ClientId) -> Self {
+        match engine.accounts.get(&client_id) {
+            Some(account) => Self::new(account),
+            None => panic!("No account for client {}", client_id),
+        }
+    }
+
+    pub fn new(account: &'a ClientAccount) -> Self {
+        Self { account }
+    }
+
+    #[track_caller]
+    pub fn available(self, expected: f64) -> Self {
+        self.amount("available", self.account.available, expected);
+        self
+    }
+
+    #[track_caller]
+    pub fn held(self, expected: f64) -> Self {
+        self.amount("held", self.account.held, expected);
+        self
+    }
+
+    #[track_caller]
+    pub fn total(self, expected: f64) -> Self {
+        self.amount("total", self.account.total, expected);
+        self
+    }
+
+    #[track_caller]
+    pub fn locked(self, expected: bool) -> Self {
+        assert_eq!(
+            self.account.locked, expected,
+            "locked of client {}",
+            self.account.client
+        );
+        self
+    }
+
+    #[track_caller]
+    pub fn closed(self, expected: bool) -> Self {
+        assert_eq!(
+            self.account.closed, expected,
+            "closed of client {}",
+            self.account.client
+        );
+        self
+    }
+
+    #[track_caller]
+    fn amount(&self, field: &str, actual: f64, expected: f64) {
+        let rounding = RoundingMode::default();
+        assert_eq!(
+            rounding.round(actual),
+            rounding.round(expected),
+            "{} of client {}",
+            field,
+            self.account.client
+        );
+    }
+}
+
+/// An engine fed one transaction at a time, keeping the result of each so
+/// rejections can be checked after the fact.
+#[derive(Debug, Default)]
+pub struct Scenario {
+    engine: Engine,
+    results: Vec<Result<(), Error>>,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start from a configured engine, e.g. `Engine::new().with_audit()`.
+    pub fn with_engine(engine: Engine) -> Self {
+        Self {
+            engine,
+            results: vec![],
+        }
+    }
+
+    pub fn tx(mut self, tx: impl Into<Tx>) -> Self {
+        let result = self.engine.process(tx.into());
+        self.results.push(result);
+        self
+    }
+
+    pub fn txs<T: Into<Tx>>(self, txs: impl IntoIterator<Item = T>) -> Self {
+        txs.into_iter().fold(self, Scenario::tx)
+    }
+
+    /// Result of each transaction, in order.
+    pub fn results(&self) -> &[Result<(), Error>] {
+        &self.results
+    }
+
+    /// Indexes of the transactions `process` returned an error for.
+    pub fn rejected(&self) -> Vec<usize> {
+        (0..self.results.len())
+            .filter(|index| self.results[*index].is_err())
+            .collect()
+    }
+
+    #[track_caller]
+    pub fn account(&self, client_id: ClientId) -> AccountAssert<'_> {
+        AccountAssert::of(&self.engine, client_id)
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn into_engine(self) -> Engine {
+        self.engine
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn chargeback_scenario() {
+        let scenario = Scenario::with_engine(Engine::new().with_audit()).txs([
+            TxBuilder::deposit(1).tx(1).amount(0.1),
+            TxBuilder::deposit(1).tx(2).amount(0.2),
+            TxBuilder::withdrawal(1).tx(3),
+            TxBuilder::dispute(1).tx(2),
+            TxBuilder::chargeback(1).tx(2),
+        ]);
+
+        assert_eq!(scenario.rejected(), vec![2]);
+        scenario
+            .account(1)
+            .available(0.1)
+            .held(0.0)
+            .total(0.1)
+            .locked(true)
+            .closed(false);
+        assert_eq!(scenario.engine().audit_trail().len(), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "held of client 1")]
+    fn mismatch_names_the_field() {
+        Scenario::new()
+            .tx(TxBuilder::deposit(1).tx(1).amount(1.0))
+            .account(1)
+            .held(1.0);
+    }
+}
@@ -0,0 +1,269 @@
+use serde::de::{self, Deserializer, Visitor};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Error;
+
+/// Milliseconds since the Unix epoch, UTC.
+pub type Timestamp = i64;
+
+/// Epoch numbers from this magnitude on are taken as milliseconds by
+/// `TimestampFormat::Auto`, as seconds that large are past the year 5000.
+const AUTO_MILLIS_FROM: i64 = 100_000_000_000;
+
+/// How the `timestamp` column of an input is written.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum TimestampFormat {
+    /// RFC 3339 for text, epoch seconds or milliseconds for numbers
+    /// depending on their magnitude.
+    #[default]
+    Auto,
+    /// e.g. `2024-01-02T10:00:00Z` or `2024-01-02T11:00:00.250+01:00`.
+    Rfc3339,
+    EpochSeconds,
+    EpochMillis,
+}
+
+impl FromStr for TimestampFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(TimestampFormat::Auto),
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "seconds" => Ok(TimestampFormat::EpochSeconds),
+            "millis" => Ok(TimestampFormat::EpochMillis),
+            _ => Err(Error::new(&format!("Unknown timestamp format: {}", s))),
+        }
+    }
+}
+
+impl TimestampFormat {
+    pub fn parse(self, value: &str) -> Result<Timestamp, Error> {
+        let value = value.trim();
+        let invalid = || Error::new(&format!("Invalid timestamp: {}", value));
+        let epoch = || value.parse::<i64>().map_err(|_| invalid());
+        match self {
+            TimestampFormat::Auto => match epoch() {
+                Ok(number) => Ok(auto_epoch(number)),
+                Err(_) => parse_rfc3339(value).ok_or_else(invalid),
+            },
+            TimestampFormat::Rfc3339 => parse_rfc3339(value).ok_or_else(invalid),
+            TimestampFormat::EpochSeconds => epoch()?.checked_mul(1000).ok_or_else(invalid),
+            TimestampFormat::EpochMillis => epoch(),
+        }
+    }
+}
+
+fn auto_epoch(number: i64) -> Timestamp {
+    if number.unsigned_abs() >= AUTO_MILLIS_FROM as u64 {
+        number
+    } else {
+        number * 1000
+    }
+}
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// `days_from_civil` the other way round, as (year, month, day).
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn parse_rfc3339(value: &str) -> Option<Timestamp> {
+    let bytes = value.as_bytes();
+    let number = |from: usize, to: usize| -> Option<i64> {
+        let digits = value.get(from..to)?;
+        digits
+            .bytes()
+            .all(|byte| byte.is_ascii_digit())
+            .then(|| digits.parse().ok())?
+    };
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if bytes.len() < 20
+        || separators.iter().any(|&(at, byte)| bytes[at] != byte)
+        || !matches!(bytes[10], b'T' | b't' | b' ')
+    {
+        return None;
+    }
+    let (year, month, day) = (number(0, 4)?, number(5, 7)?, number(8, 10)?);
+    let (hour, minute, second) = (number(11, 13)?, number(14, 16)?, number(17, 19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut at = 19;
+    let mut millis = 0;
+    if bytes[at] == b'.' {
+        let digits = bytes[at + 1..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count();
+        if digits == 0 {
+            return None;
+        }
+        // only milliseconds are kept
+        let kept = &value[at + 1..at + 1 + digits.min(3)];
+        millis = kept.parse::<i64>().ok()? * 10_i64.pow(3 - kept.len() as u32);
+        at += 1 + digits;
+    }
+    let offset = match &value[at..] {
+        "Z" | "z" => 0,
+        zone if zone.len() == 6 && zone.as_bytes()[3] == b':' => {
+            let sign = match zone.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            sign * (number(at + 1, at + 3)? * 60 + number(at + 4, at + 6)?)
+        }
+        _ => return None,
+    };
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second
+        - offset * 60;
+    Some(seconds * 1000 + millis)
+}
+
+/// `timestamp` in RFC 3339 in UTC, with milliseconds only when there are
+/// some, e.g. `2024-01-02T10:00:00Z`.
+pub fn format_timestamp(timestamp: Timestamp) -> String {
+    let (seconds, millis) = (timestamp.div_euclid(1000), timestamp.rem_euclid(1000));
+    let (days, time) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    let (hour, minute, second) = (time / 3600, time % 3600 / 60, time % 60);
+    let fraction = match millis {
+        0 => String::new(),
+        millis => format!(".{:03}", millis),
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year, month, day, hour, minute, second, fraction
+    )
+}
+
+struct TimestampVisitor;
+
+impl<'de> Visitor<'de> for TimestampVisitor {
+    type Value = Option<Timestamp>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an RFC 3339 date and time, or epoch seconds or milliseconds")
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+        Ok(Some(auto_epoch(value)))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+        i64::try_from(value)
+            .map(|value| Some(auto_epoch(value)))
+            .map_err(|_| E::custom(format!("Invalid timestamp: {}", value)))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        if value.trim().is_empty() {
+            return Ok(None);
+        }
+        TimestampFormat::Auto
+            .parse(value)
+            .map(Some)
+            .map_err(|err| E::custom(err.message))
+    }
+}
+
+/// Read an optional timestamp in any of the `TimestampFormat::Auto` forms,
+/// for `#[serde(deserialize_with)]`.
+pub fn deserialize_timestamp<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Timestamp>, D::Error> {
+    deserializer.deserialize_option(TimestampVisitor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats_are_parsed() -> Result<(), Error> {
+        let noon = 1_704_196_800_000;
+        for value in [
+            "2024-01-02T12:00:00Z",
+            "2024-01-02t13:30:00+01:30",
+            "2024-01-02 07:00:00.000-05:00",
+            "1704196800",
+            "1704196800000",
+        ] {
+            assert_eq!(TimestampFormat::Auto.parse(value)?, noon, "{}", value);
+        }
+        assert_eq!(
+            TimestampFormat::Rfc3339.parse("2024-01-02T12:00:00.25Z")?,
+            noon + 250
+        );
+        assert_eq!(TimestampFormat::EpochSeconds.parse("1704196800")?, noon);
+        assert_eq!(
+            TimestampFormat::EpochMillis.parse("1704196800")?,
+            1_704_196_800
+        );
+        assert_eq!(TimestampFormat::Auto.parse("-86400")?, -86_400_000);
+        for invalid in [
+            "2024-13-02T12:00:00Z",
+            "2024-01-02T12:00:00",
+            "2024-01-02",
+            "yesterday",
+        ] {
+            assert!(TimestampFormat::Auto.parse(invalid).is_err(), "{}", invalid);
+        }
+        assert!(TimestampFormat::EpochSeconds
+            .parse("2024-01-02T12:00:00Z")
+            .is_err());
+        assert!("julian".parse::<TimestampFormat>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn timestamps_are_formatted_in_utc() {
+        assert_eq!(format_timestamp(1_704_196_800_000), "2024-01-02T12:00:00Z");
+        assert_eq!(
+            format_timestamp(951_782_400_250),
+            "2000-02-29T00:00:00.250Z"
+        );
+        assert_eq!(format_timestamp(-1), "1969-12-31T23:59:59.999Z");
+    }
+}
@@ -0,0 +1,1652 @@
+use serde::de::{self, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::custom::unknown_type;
+use crate::rounding::tolerance;
+use crate::{
+    deserialize_timestamp, AccountRecord, Amount, Available, Balance, Error, ErrorCode, LockInfo,
+    LockReason, RoundingMode, SignedAmount, Timestamp, Total,
+};
+
+/// Client identifier. `u16` by default, `u64` with the `wide-client-ids` feature.
+#[cfg(not(feature = "wide-client-ids"))]
+pub type ClientId = u16;
+#[cfg(feature = "wide-client-ids")]
+pub type ClientId = u64;
+
+/// Transaction identifier. `u32` by default, `u64` with the `wide-tx-ids` feature.
+#[cfg(not(feature = "wide-tx-ids"))]
+pub type TxId = u32;
+#[cfg(feature = "wide-tx-ids")]
+pub type TxId = u64;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Tx {
+    #[serde(rename = "type")]
+    pub type_: TxType,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(rename = "tx")]
+    pub tx_id: TxId,
+    /// Signed, as `Compat::V1` and adjustments take them, but always finite.
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    pub(crate) amount: Option<f64>,
+    /// Optional per-client sequence number, see `SequenceChecker`.
+    pub seq: Option<u64>,
+    /// Optional metadata columns, carried through to the audit trail.
+    pub description: Option<String>,
+    pub merchant: Option<String>,
+    pub category: Option<String>,
+    /// Optional currency of `amount`, converted by a `CurrencyConverter`.
+    pub currency: Option<String>,
+    /// `amount` before a `CurrencyConverter` converted it from `currency`.
+    #[serde(skip_deserializing)]
+    pub(crate) original_amount: Option<f64>,
+    /// Optional time of the transaction, read from RFC 3339 or epoch
+    /// seconds or milliseconds, see `TimestampFormat`.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_timestamp",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub timestamp: Option<Timestamp>,
+}
+
+impl Tx {
+    /// A transaction without an amount nor any of the optional columns,
+    /// e.g. a dispute. Set the amount with `with_amount`, the others
+    /// directly.
+    pub fn new(type_: TxType, client_id: ClientId, tx_id: TxId) -> Self {
+        Self {
+            type_,
+            client_id,
+            tx_id,
+            amount: None,
+            seq: None,
+            description: None,
+            merchant: None,
+            category: None,
+            currency: None,
+            original_amount: None,
+            timestamp: None,
+        }
+    }
+
+    /// This transaction for `amount`.
+    pub fn with_amount(self, amount: Amount) -> Self {
+        Self {
+            amount: Some(amount.value()),
+            ..self
+        }
+    }
+
+    /// This transaction for the signed `amount`, e.g. a negative adjustment,
+    /// or for none.
+    pub fn with_signed_amount(self, amount: Option<SignedAmount>) -> Self {
+        Self {
+            amount: amount.map(SignedAmount::value),
+            ..self
+        }
+    }
+
+    pub fn amount(&self) -> Option<SignedAmount> {
+        self.amount.map(SignedAmount::finite)
+    }
+
+    /// `amount` before a `CurrencyConverter` converted it from `currency`.
+    pub fn original_amount(&self) -> Option<SignedAmount> {
+        self.original_amount.map(SignedAmount::finite)
+    }
+}
+
+fn check_finite(amount: Option<f64>) -> Result<(), Error> {
+    match amount {
+        Some(amount) if !amount.is_finite() => {
+            Err(Error::new(&format!("Invalid amount {}", amount)))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn deserialize_amount<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let amount = Option::<f64>::deserialize(deserializer)?;
+    check_finite(amount).map_err(serde::de::Error::custom)?;
+    Ok(amount)
+}
+
+/// A deposit of id 0 by client 0 without an amount, to fill in with struct
+/// update syntax.
+impl Default for Tx {
+    fn default() -> Self {
+        Tx::new(TxType::Deposit, 0, 0)
+    }
+}
+
+/// Lets the functions taking transactions by value, like
+/// `process_parallel`, take borrowed ones too, cloning each only as it's
+/// processed.
+impl From<&Tx> for Tx {
+    fn from(tx: &Tx) -> Self {
+        tx.clone()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum TxType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// Pay out the available funds and close the account for good.
+    CloseAccount,
+    /// Correction of an existing account by a signed amount, negative to
+    /// decrease its balance. Can't be disputed.
+    Adjustment,
+    /// Handled by the `CustomTx` registered under this name, see
+    /// `Engine::with_custom_types`. Only `read_csv_with` given
+    /// `ReadOptions::custom_types` reads these, other readers reject unknown
+    /// types.
+    Custom(String),
+}
+
+impl FromStr for TxType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deposit" => Ok(TxType::Deposit),
+            "withdrawal" => Ok(TxType::Withdrawal),
+            "dispute" => Ok(TxType::Dispute),
+            "resolve" => Ok(TxType::Resolve),
+            "chargeback" => Ok(TxType::Chargeback),
+            "close_account" => Ok(TxType::CloseAccount),
+            "adjustment" => Ok(TxType::Adjustment),
+            _ => Err(Error::coded(
+                ErrorCode::UnknownTxType,
+                &format!("Unknown transaction type {}", s),
+            )),
+        }
+    }
+}
+
+impl Serialize for TxType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            TxType::Deposit => "deposit",
+            TxType::Withdrawal => "withdrawal",
+            TxType::Dispute => "dispute",
+            TxType::Resolve => "resolve",
+            TxType::Chargeback => "chargeback",
+            TxType::CloseAccount => "close_account",
+            TxType::Adjustment => "adjustment",
+            TxType::Custom(name) => name,
+        })
+    }
+}
+
+/// Reads the type from the borrowed field, without a `String` per row.
+struct TxTypeVisitor;
+
+impl<'de> Visitor<'de> for TxTypeVisitor {
+    type Value = TxType;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a transaction type, e.g. deposit")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+        value.parse().map_err(|err: Error| E::custom(err.message))
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+        match std::str::from_utf8(value) {
+            Ok(value) => self.visit_str(value),
+            Err(_) => Err(E::invalid_value(de::Unexpected::Bytes(value), &self)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TxType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(TxTypeVisitor)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct TxState {
+    pub amount: SignedAmount,
+    pub type_: TxStateType,
+    pub client_id: ClientId,
+    pub disputed: bool,
+    pub charged_back: bool,
+    /// Funds its dispute held, when `DisputeHoldPolicy::CapAtAvailable`
+    /// capped them below `amount`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held: Option<f64>,
+    /// `Tx::timestamp` of the transaction, when the input had one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<Timestamp>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub enum TxStateType {
+    Deposit,
+    Withdrawal,
+    Adjustment,
+}
+
+impl TxState {
+    fn new(tx: &Tx, amount: SignedAmount, type_: TxStateType) -> Self {
+        Self {
+            amount,
+            type_,
+            client_id: tx.client_id,
+            disputed: false,
+            charged_back: false,
+            held: None,
+            timestamp: tx.timestamp,
+        }
+    }
+
+    /// Funds a dispute of this transaction holds, or held before a
+    /// chargeback.
+    pub fn held_amount(&self) -> f64 {
+        self.held.unwrap_or(self.amount.value())
+    }
+}
+
+/// Serialize an amount rounded to `DECIMALS` places, for output schemas.
+pub(crate) fn round_serialize<S>(x: &f64, s: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    s.serialize_f64(RoundingMode::default().round(*x))
+}
+
+/// A client's balances, changed through the methods keeping its invariants
+/// and read as `Balance`s and `Amount`s. Reads as an `AccountRecord`, whose
+/// balances are checked.
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(try_from = "AccountRecord")]
+pub struct ClientAccount {
+    pub client: ClientId,
+    #[serde(serialize_with = "round_serialize")]
+    pub(crate) available: f64,
+    #[serde(serialize_with = "round_serialize")]
+    pub(crate) held: f64,
+    #[serde(serialize_with = "round_serialize")]
+    pub(crate) total: f64,
+    pub locked: bool,
+    /// Closed by a `close_account` transaction, ignores anything after.
+    /// Not part of the CSV output unless `--output-schema` asks for it;
+    /// snapshots and stores keep it through `AccountRecord`.
+    #[serde(skip)]
+    pub closed: bool,
+    /// Why and when `locked` was set. Not part of the CSV output, see
+    /// `lock_report`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock: Option<LockInfo>,
+}
+
+impl ClientAccount {
+    pub fn new(client_id: ClientId) -> Self {
+        Self {
+            client: client_id,
+            available: 0.0,
+            held: 0.0,
+            total: 0.0,
+            locked: false,
+            closed: false,
+            lock: None,
+        }
+    }
+
+    /// No funds, not locked nor closed, as if it had never been used.
+    pub fn is_empty(&self) -> bool {
+        self.available == 0.0
+            && self.held == 0.0
+            && self.total == 0.0
+            && !self.locked
+            && !self.closed
+    }
+
+    pub fn available(&self) -> Balance<Available> {
+        Balance::new(self.available)
+    }
+
+    /// Held funds, an error when they went negative, as `Compat::V1`
+    /// disputes of negative deposits leave them.
+    pub fn held(&self) -> Result<Amount, Error> {
+        if self.held <= -tolerance() {
+            return Err(Error::coded(
+                ErrorCode::Invariants,
+                &format!("client {}: negative held {}", self.client, self.held),
+            ));
+        }
+        // no float noise below zero
+        Amount::new(self.held.max(0.0))
+    }
+
+    pub fn total(&self) -> Balance<Total> {
+        Balance::new(self.total)
+    }
+
+    /// This account with its balances rounded by `rounding`, e.g. for a
+    /// report.
+    pub fn rounded(&self, rounding: RoundingMode) -> ClientAccount {
+        ClientAccount {
+            available: rounding.round(self.available),
+            held: rounding.round(self.held),
+            total: rounding.round(self.total),
+            ..self.clone()
+        }
+    }
+
+    fn check_open(&self) -> Result<(), Error> {
+        if self.closed {
+            return Err(Error::coded(ErrorCode::AccountClosed, "Account is closed"));
+        }
+        Ok(())
+    }
+
+    /// Add `amount` to the available and total funds.
+    pub fn deposit(&mut self, amount: Amount) -> Result<(), Error> {
+        self.check_open()?;
+        self.total += amount.value();
+        self.available += amount.value();
+        Ok(())
+    }
+
+    /// Take `amount` out of the available and total funds, unless that
+    /// would leave the available funds negative.
+    pub fn withdraw(&mut self, amount: Amount) -> Result<(), Error> {
+        self.check_open()?;
+        if !self.available().covers(amount) {
+            return Err(Error::coded(
+                ErrorCode::InsufficientFunds,
+                "Insufficient funds",
+            ));
+        }
+        self.total -= amount.value();
+        self.available -= amount.value();
+        Ok(())
+    }
+
+    /// Add the signed `amount` to the available and total funds, which can
+    /// leave them negative.
+    pub fn adjust(&mut self, amount: SignedAmount) -> Result<(), Error> {
+        self.check_open()?;
+        self.total += amount.value();
+        self.available += amount.value();
+        Ok(())
+    }
+
+    /// Move `amount` from the available to the held funds, for a dispute.
+    /// The available funds can go negative when they were already spent.
+    pub fn hold(&mut self, amount: Amount) -> Result<(), Error> {
+        self.check_open()?;
+        self.available -= amount.value();
+        self.held += amount.value();
+        Ok(())
+    }
+
+    /// Move `amount` from the held back to the available funds, when a
+    /// dispute is resolved.
+    pub fn release(&mut self, amount: Amount) -> Result<(), Error> {
+        self.check_held(amount)?;
+        self.available += amount.value();
+        self.held -= amount.value();
+        Ok(())
+    }
+
+    /// Take `amount` out of the held and total funds for a chargeback of
+    /// transaction `tx`, and lock the account. The first chargeback is the
+    /// one kept as the reason of the lock.
+    pub fn charge_back(&mut self, amount: Amount, tx: TxId) -> Result<(), Error> {
+        self.check_held(amount)?;
+        self.total -= amount.value();
+        self.held -= amount.value();
+        if !self.locked {
+            self.lock = Some(LockInfo {
+                reason: LockReason::Chargeback,
+                tx,
+                tx_index: None,
+            });
+        }
+        self.locked = true;
+        Ok(())
+    }
+
+    /// Move the signed `amount` from the available to the held funds, as
+    /// `Compat::V1` disputes and resolves deposits of a negative amount:
+    /// unlike `hold` and `release`, the held funds can go negative.
+    pub(crate) fn move_to_held(&mut self, amount: f64) -> Result<(), Error> {
+        self.check_open()?;
+        self.available -= amount;
+        self.held += amount;
+        Ok(())
+    }
+
+    /// `charge_back` of the signed `amount`, for `Compat::V1` chargebacks
+    /// of deposits of a negative amount.
+    pub(crate) fn take_held(&mut self, amount: f64, tx: TxId) -> Result<(), Error> {
+        self.total -= amount;
+        self.held -= amount;
+        if !self.locked {
+            self.lock = Some(LockInfo {
+                reason: LockReason::Chargeback,
+                tx,
+                tx_index: None,
+            });
+        }
+        self.locked = true;
+        Ok(())
+    }
+
+    /// Close the account, dropping its available funds. Accounts still
+    /// holding funds for a dispute can't be closed.
+    pub fn close(&mut self) -> Result<(), Error> {
+        if self.held != 0.0 {
+            return Err(Error::coded(
+                ErrorCode::NotApplicable,
+                "Account with held funds can't be closed",
+            ));
+        }
+        self.available = 0.0;
+        self.total = 0.0;
+        self.closed = true;
+        Ok(())
+    }
+
+    /// Held funds cover `amount`, give or take float rounding.
+    fn check_held(&self, amount: Amount) -> Result<(), Error> {
+        if amount.value() - self.held >= tolerance() {
+            return Err(Error::coded(
+                ErrorCode::InsufficientFunds,
+                "Insufficient held funds",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// What a locked (charged back) account still accepts.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum LockedPolicy {
+    /// Drop every transaction.
+    #[default]
+    BlockAll,
+    /// Keep processing disputes, resolves and chargebacks of earlier
+    /// transactions, as card networks do.
+    AllowDisputes,
+    /// Like `AllowDisputes`, and also accept deposits so operators can still
+    /// credit the account. Withdrawals are always blocked.
+    AllowAdmin,
+}
+
+impl LockedPolicy {
+    pub(crate) fn allows(&self, type_: &TxType) -> bool {
+        match (self, type_) {
+            (_, TxType::Withdrawal) | (LockedPolicy::BlockAll, _) => false,
+            (
+                LockedPolicy::AllowDisputes,
+                TxType::Deposit | TxType::CloseAccount | TxType::Adjustment | TxType::Custom(_),
+            ) => false,
+            (LockedPolicy::AllowDisputes, _) | (LockedPolicy::AllowAdmin, _) => true,
+        }
+    }
+}
+
+impl FromStr for LockedPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block_all" => Ok(LockedPolicy::BlockAll),
+            "allow_disputes" => Ok(LockedPolicy::AllowDisputes),
+            "allow_admin" => Ok(LockedPolicy::AllowAdmin),
+            _ => Err(Error::new(&format!("Unknown locked account policy: {}", s))),
+        }
+    }
+}
+
+/// What a dispute holds when the client already spent part of the
+/// disputed deposit.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum DisputeHoldPolicy {
+    /// Hold the whole amount, leaving negative available funds.
+    #[default]
+    AllowNegative,
+    /// Hold no more than the available funds. Resolving releases, and a
+    /// chargeback takes, only what was held.
+    CapAtAvailable,
+    /// Hold the whole amount as `AllowNegative`, and report the account
+    /// for manual review, see `Engine::hold_reviews`.
+    FlagForReview,
+}
+
+impl FromStr for DisputeHoldPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow_negative" => Ok(DisputeHoldPolicy::AllowNegative),
+            "cap_at_available" => Ok(DisputeHoldPolicy::CapAtAvailable),
+            "flag_for_review" => Ok(DisputeHoldPolicy::FlagForReview),
+            _ => Err(Error::new(&format!("Unknown dispute hold policy: {}", s))),
+        }
+    }
+}
+
+/// What happens to deposits and withdrawals of a zero amount, which would
+/// otherwise be recorded and could be disputed for nothing.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum ZeroAmountPolicy {
+    /// Process them as any other amount.
+    #[default]
+    Allow,
+    /// Drop them without effect.
+    Ignore,
+    /// Reject them with `ErrorCode::ZeroAmount`.
+    Reject,
+}
+
+impl FromStr for ZeroAmountPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(ZeroAmountPolicy::Allow),
+            "ignore" => Ok(ZeroAmountPolicy::Ignore),
+            "reject" => Ok(ZeroAmountPolicy::Reject),
+            _ => Err(Error::new(&format!("Unknown zero amount policy: {}", s))),
+        }
+    }
+}
+
+/// Which transactions open an account for a client that doesn't have one
+/// yet. Accounts can be registered beforehand with
+/// `Engine::register_accounts`, e.g. the clients that passed KYC.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum AccountCreation {
+    /// The transactions that open an account without a policy: deposits and
+    /// withdrawals, or any transaction under `Compat::V1`.
+    #[default]
+    Implicit,
+    /// A deposit only, other transactions of new clients are rejected with
+    /// `ErrorCode::UnknownClient`.
+    FirstDeposit,
+    /// None, only registered clients have accounts and the transactions of
+    /// the others are rejected with `ErrorCode::UnknownClient`.
+    Never,
+}
+
+impl FromStr for AccountCreation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "implicit" | "any" => Ok(AccountCreation::Implicit),
+            "first_deposit" => Ok(AccountCreation::FirstDeposit),
+            "never" => Ok(AccountCreation::Never),
+            _ => Err(Error::new(&format!(
+                "Unknown account creation policy: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Which semantics `Engine::process` follows where they changed over time.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Compat {
+    /// The original behavior: negative deposits credit their absolute
+    /// amount, and their disputes hold their negative amount; negative
+    /// withdrawals credit the account; locked accounts block every
+    /// transaction and duplicate transaction ids are dropped silently; any
+    /// transaction opens an account, and disputes, resolves and chargebacks
+    /// of another client's transaction move the referring client's funds.
+    #[default]
+    V1,
+    /// Negative deposits and withdrawals are rejected with
+    /// `ErrorCode::NegativeAmount`, disputes of negative deposits with
+    /// `ErrorCode::NotApplicable`, duplicate deposit, withdrawal and
+    /// adjustment ids with `ErrorCode::DuplicateTx`, and locked accounts
+    /// still take disputes, see `Compat::locked_policy`. Only deposits and
+    /// withdrawals open an account, and references to another client's
+    /// transaction are ignored.
+    V2,
+}
+
+impl Compat {
+    /// What locked accounts accept unless configured otherwise.
+    pub fn locked_policy(&self) -> LockedPolicy {
+        match self {
+            Compat::V1 => LockedPolicy::BlockAll,
+            Compat::V2 => LockedPolicy::AllowDisputes,
+        }
+    }
+}
+
+impl FromStr for Compat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(Compat::V1),
+            "v2" => Ok(Compat::V2),
+            _ => Err(Error::new(&format!("Unknown compatibility mode: {}", s))),
+        }
+    }
+}
+
+/// Process `tx` like engines in `Compat::V1` with the default policies.
+#[deprecated(since = "0.1.0", note = "process transactions with an `Engine`")]
+pub fn process_tx(
+    tx: Tx,
+    accounts: &mut HashMap<ClientId, ClientAccount>,
+    tx_states: &mut HashMap<TxId, TxState>,
+) -> Result<(), Error> {
+    process_tx_with_policies(
+        tx,
+        accounts,
+        tx_states,
+        LockedPolicy::BlockAll,
+        DisputeHoldPolicy::default(),
+        Compat::V1,
+    )
+}
+
+pub(crate) fn process_tx_with_policies(
+    tx: Tx,
+    accounts: &mut HashMap<ClientId, ClientAccount>,
+    tx_states: &mut HashMap<TxId, TxState>,
+    locked_policy: LockedPolicy,
+    hold_policy: DisputeHoldPolicy,
+    compat: Compat,
+) -> Result<(), Error> {
+    if let TxType::Custom(name) = &tx.type_ {
+        return Err(unknown_type(name));
+    }
+    let client_id = tx.client_id;
+    let tx_id = tx.tx_id;
+    // Only deposits and withdrawals open an account: disputes, resolves,
+    // chargebacks and adjustments refer to an existing one. `Compat::V1`
+    // opens one for any transaction.
+    if compat == Compat::V2
+        && !matches!(tx.type_, TxType::Deposit | TxType::Withdrawal)
+        && !accounts.contains_key(&client_id)
+    {
+        return Ok(());
+    }
+    let account = accounts
+        .entry(client_id)
+        .or_insert(ClientAccount::new(client_id));
+
+    if account.closed || account.locked && !locked_policy.allows(&tx.type_) {
+        return Ok(());
+    }
+    if tx.type_ == TxType::CloseAccount {
+        return account.close();
+    }
+
+    let unchecked =
+        |tx_state: &TxState| tx_state.amount.is_negative() || tx_state.client_id != client_id;
+    match tx_states.get_mut(&tx_id) {
+        // transactions of other clients can't be referred to, though
+        // `Compat::V1` moves the funds of the referring client
+        Some(tx_state) if compat == Compat::V2 && tx_state.client_id != client_id => {}
+        Some(tx_state) => match tx.type_ {
+            TxType::Deposit => {}
+            TxType::Withdrawal => {}
+            // deposits of a negative amount and transactions of other
+            // clients, which only `Compat::V1` takes, move their signed
+            // amount unchecked
+            TxType::Dispute
+                if !tx_state.disputed
+                    && tx_state.type_ == TxStateType::Deposit
+                    && unchecked(tx_state) =>
+            {
+                account.move_to_held(tx_state.amount.value())?;
+                tx_state.disputed = true;
+                tx_state.charged_back = false;
+            }
+            TxType::Resolve
+                if tx_state.disputed
+                    && tx_state.type_ == TxStateType::Deposit
+                    && unchecked(tx_state) =>
+            {
+                account.move_to_held(-tx_state.amount.value())?;
+                tx_state.disputed = false;
+                tx_state.charged_back = false;
+            }
+            TxType::Chargeback
+                if tx_state.disputed
+                    && tx_state.type_ == TxStateType::Deposit
+                    && unchecked(tx_state) =>
+            {
+                account.take_held(tx_state.amount.value(), tx_id)?;
+                tx_state.disputed = false;
+                tx_state.charged_back = true;
+            }
+            TxType::Dispute => {
+                if let (false, TxStateType::Deposit, Ok(amount)) = (
+                    tx_state.disputed,
+                    &tx_state.type_,
+                    Amount::new(tx_state.amount.value()),
+                ) {
+                    let amount = match hold_policy {
+                        DisputeHoldPolicy::CapAtAvailable
+                            if !account.available().covers(amount) =>
+                        {
+                            // nothing left to hold when already spent
+                            let held = Amount::new(account.available.max(0.0))?;
+                            tx_state.held = Some(held.value());
+                            held
+                        }
+                        _ => {
+                            tx_state.held = None;
+                            amount
+                        }
+                    };
+                    account.hold(amount)?;
+                    tx_state.disputed = true;
+                    tx_state.charged_back = false;
+                }
+            }
+            TxType::Resolve => {
+                if tx_state.disputed && tx_state.type_ == TxStateType::Deposit {
+                    account.release(Amount::new(tx_state.held_amount())?)?;
+                    tx_state.disputed = false;
+                    tx_state.charged_back = false;
+                    tx_state.held = None;
+                };
+            }
+            TxType::Chargeback => {
+                if tx_state.disputed && tx_state.type_ == TxStateType::Deposit {
+                    account.charge_back(Amount::new(tx_state.held_amount())?, tx_id)?;
+                    tx_state.disputed = false;
+                    tx_state.charged_back = true;
+                }
+            }
+            TxType::CloseAccount | TxType::Adjustment | TxType::Custom(_) => {}
+        },
+        None => match tx.type_ {
+            TxType::Deposit => {
+                let amount = tx.amount.ok_or(Error::coded(
+                    ErrorCode::MissingAmount,
+                    "Deposit transaction expected to have an amount",
+                ))?;
+                let amount = SignedAmount::new(amount)?;
+                account.deposit(amount.magnitude())?;
+                tx_states.insert(tx_id, TxState::new(&tx, amount, TxStateType::Deposit));
+            }
+            TxType::Withdrawal => {
+                let amount = tx.amount.ok_or(Error::coded(
+                    ErrorCode::MissingAmount,
+                    "Withdrawal transaction expected to have an amount",
+                ))?;
+                match Amount::new(amount) {
+                    Ok(amount) if account.available().covers(amount) => {
+                        account.withdraw(amount)?;
+                        tx_states.insert(
+                            tx_id,
+                            TxState::new(&tx, -SignedAmount::from(amount), TxStateType::Withdrawal),
+                        );
+                    }
+                    Ok(_) => {}
+                    // negative withdrawals, which only `Compat::V1` takes,
+                    // credit the account as they originally did
+                    Err(_) if amount <= account.available => {
+                        let amount = -SignedAmount::new(amount)?;
+                        account.adjust(amount)?;
+                        tx_states.insert(tx_id, TxState::new(&tx, amount, TxStateType::Withdrawal));
+                    }
+                    Err(_) => {}
+                }
+            }
+            TxType::Adjustment => {
+                let amount = tx.amount.ok_or(Error::coded(
+                    ErrorCode::MissingAmount,
+                    "Adjustment transaction expected to have an amount",
+                ))?;
+                let amount = SignedAmount::new(amount)?;
+                account.adjust(amount)?;
+                tx_states.insert(tx_id, TxState::new(&tx, amount, TxStateType::Adjustment));
+            }
+            TxType::Dispute => {}
+            TxType::Resolve => {}
+            TxType::Chargeback => {}
+            TxType::CloseAccount | TxType::Custom(_) => {}
+        },
+    };
+    Ok(())
+}
+
+#[cfg(test)]
+// the baseline tests go through the deprecated `process_tx`
+#[allow(deprecated)]
+mod test {
+    use super::*;
+
+    /// `tx` as engines in `Compat::V2` apply it, once validated.
+    fn process_v2(
+        tx: Tx,
+        accounts: &mut HashMap<ClientId, ClientAccount>,
+        tx_states: &mut HashMap<TxId, TxState>,
+        locked_policy: LockedPolicy,
+    ) -> Result<(), Error> {
+        process_tx_with_policies(
+            tx,
+            accounts,
+            tx_states,
+            locked_policy,
+            DisputeHoldPolicy::default(),
+            Compat::V2,
+        )
+    }
+
+    #[test]
+    fn deposit() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let tx = Tx {
+            type_: TxType::Deposit,
+            client_id: 1,
+            tx_id: 1,
+            amount: Some(1.0),
+            ..Default::default()
+        };
+        process_tx(tx, &mut accounts, &mut tx_states)?;
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 1.0,
+                held: 0.0,
+                total: 1.0,
+                locked: false,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_deposit() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(1.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Dispute,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                ..Default::default()
+            },
+        ];
+        for tx in txs {
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 0.0,
+                held: 1.0,
+                total: 1.0,
+                locked: false,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_dispute() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(1.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Dispute,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Resolve,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                ..Default::default()
+            },
+        ];
+        for tx in txs {
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 1.0,
+                held: 0.0,
+                total: 1.0,
+                locked: false,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn chargeback_dispute() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(1.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Dispute,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Chargeback,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                ..Default::default()
+            },
+        ];
+        for tx in txs {
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 0.0,
+                held: 0.0,
+                total: 0.0,
+                locked: true,
+                lock: Some(LockInfo {
+                    reason: LockReason::Chargeback,
+                    tx: 1,
+                    tx_index: None,
+                }),
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn withdrawal() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(10.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Withdrawal,
+                client_id: 1,
+                tx_id: 2,
+                amount: Some(7.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Withdrawal,
+                client_id: 1,
+                tx_id: 3,
+                amount: Some(3.0),
+                ..Default::default()
+            },
+        ];
+        for tx in txs {
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 0.0,
+                held: 0.0,
+                total: 0.0,
+                locked: false,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn block_withdrawal() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(5.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Withdrawal,
+                client_id: 1,
+                tx_id: 2,
+                amount: Some(10.0),
+                ..Default::default()
+            },
+        ];
+        for tx in txs {
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 5.0,
+                held: 0.0,
+                total: 5.0,
+                locked: false,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_withdrawal_is_ignored() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(10.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Withdrawal,
+                client_id: 1,
+                tx_id: 2,
+                amount: Some(5.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Dispute,
+                client_id: 1,
+                tx_id: 2,
+                amount: None,
+                ..Default::default()
+            },
+        ];
+        for tx in txs {
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 5.0,
+                held: 0.0,
+                total: 5.0,
+                locked: false,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn deposit_without_amount_throws_error() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let tx = Tx {
+            type_: TxType::Deposit,
+            client_id: 1,
+            tx_id: 1,
+            amount: None,
+            ..Default::default()
+        };
+        let result = process_tx(tx, &mut accounts, &mut tx_states);
+
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn withdrawal_without_amount_throws_error() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let tx = Tx {
+            type_: TxType::Deposit,
+            client_id: 1,
+            tx_id: 1,
+            amount: Some(10.0),
+            ..Default::default()
+        };
+        process_tx(tx, &mut accounts, &mut tx_states)?;
+        let tx = Tx {
+            type_: TxType::Withdrawal,
+            client_id: 1,
+            tx_id: 2,
+            amount: None,
+            ..Default::default()
+        };
+        let result = process_tx(tx, &mut accounts, &mut tx_states);
+
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn account_with_held_funds_is_not_closed() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        for (type_, tx_id, amount) in [(TxType::Deposit, 1, Some(5.0)), (TxType::Dispute, 1, None)]
+        {
+            let tx = Tx {
+                amount,
+                ..Tx::new(type_, 1, tx_id)
+            };
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+        let close = Tx::new(TxType::CloseAccount, 1, 2);
+        assert!(process_tx(close, &mut accounts, &mut tx_states).is_err());
+        assert!(!accounts[&1].closed);
+        assert_eq!(accounts[&1].held, 5.0);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_does_not_create_account() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let deposit = Tx {
+            amount: Some(5.0),
+            ..Tx::new(TxType::Deposit, 1, 1)
+        };
+        process_v2(
+            deposit,
+            &mut accounts,
+            &mut tx_states,
+            LockedPolicy::BlockAll,
+        )?;
+        for (type_, tx_id) in [
+            (TxType::Dispute, 1),
+            (TxType::Dispute, 7),
+            (TxType::Resolve, 1),
+            (TxType::Chargeback, 1),
+        ] {
+            let tx = Tx::new(type_, 2, tx_id);
+            process_v2(tx, &mut accounts, &mut tx_states, LockedPolicy::BlockAll)?;
+        }
+
+        assert!(!accounts.contains_key(&2));
+        assert_eq!(accounts[&1].held, 0.0);
+        assert!(!tx_states[&1].disputed);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_of_another_clients_tx_is_ignored() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        for (type_, client_id, tx_id, amount) in [
+            (TxType::Deposit, 1, 1, Some(5.0)),
+            (TxType::Deposit, 2, 2, Some(3.0)),
+            (TxType::Dispute, 2, 1, None),
+        ] {
+            let tx = Tx {
+                amount,
+                ..Tx::new(type_, client_id, tx_id)
+            };
+            process_v2(tx, &mut accounts, &mut tx_states, LockedPolicy::BlockAll)?;
+        }
+
+        assert_eq!(accounts[&1].held, 0.0);
+        assert_eq!(accounts[&2].available, 3.0);
+        assert!(!tx_states[&1].disputed);
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_on_nonexistent_tx_is_ignored() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(5.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Dispute,
+                client_id: 1,
+                tx_id: 2,
+                amount: None,
+                ..Default::default()
+            },
+        ];
+        for tx in txs {
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 5.0,
+                held: 0.0,
+                total: 5.0,
+                locked: false,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_on_nondisputed_tx_is_ignored() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(5.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Resolve,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                ..Default::default()
+            },
+        ];
+        for tx in txs {
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 5.0,
+                held: 0.0,
+                total: 5.0,
+                locked: false,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn chargeback_on_nondisputed_tx_is_ignored() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(5.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Chargeback,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                ..Default::default()
+            },
+        ];
+        for tx in txs {
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 5.0,
+                held: 0.0,
+                total: 5.0,
+                locked: false,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_on_disputed_tx_is_ignored() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(5.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Dispute,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Dispute,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                ..Default::default()
+            },
+        ];
+        for tx in txs {
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 0.0,
+                held: 5.0,
+                total: 5.0,
+                locked: false,
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn block_tx_on_frozen_account() -> Result<(), Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 1,
+                amount: Some(5.0),
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Dispute,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Chargeback,
+                client_id: 1,
+                tx_id: 1,
+                amount: None,
+                ..Default::default()
+            },
+            Tx {
+                type_: TxType::Deposit,
+                client_id: 1,
+                tx_id: 2,
+                amount: Some(100.0),
+                ..Default::default()
+            },
+        ];
+        for tx in txs {
+            process_tx(tx, &mut accounts, &mut tx_states)?;
+        }
+
+        let account = accounts.get(&1).unwrap();
+        assert_eq!(
+            *account,
+            ClientAccount {
+                client: 1,
+                available: 0.0,
+                held: 0.0,
+                total: 0.0,
+                locked: true,
+                lock: Some(LockInfo {
+                    reason: LockReason::Chargeback,
+                    tx: 1,
+                    tx_index: None,
+                }),
+                ..Default::default()
+            }
+        );
+        Ok(())
+    }
+
+    fn lock_account_with_two_deposits(
+        policy: LockedPolicy,
+    ) -> Result<HashMap<ClientId, ClientAccount>, Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            (TxType::Deposit, 1, Some(5.0)),
+            (TxType::Deposit, 2, Some(3.0)),
+            (TxType::Dispute, 1, None),
+            (TxType::Chargeback, 1, None),
+            (TxType::Dispute, 2, None),
+            (TxType::Deposit, 3, Some(10.0)),
+            (TxType::Withdrawal, 4, Some(1.0)),
+        ];
+        for (type_, tx_id, amount) in txs {
+            let tx = Tx {
+                amount,
+                ..Tx::new(type_, 1, tx_id)
+            };
+            process_v2(tx, &mut accounts, &mut tx_states, policy)?;
+        }
+        Ok(accounts)
+    }
+
+    #[test]
+    fn locked_policy_allow_disputes() -> Result<(), Error> {
+        let blocked = lock_account_with_two_deposits(LockedPolicy::BlockAll)?;
+        assert_eq!((blocked[&1].available, blocked[&1].held), (3.0, 0.0));
+
+        let accounts = lock_account_with_two_deposits(LockedPolicy::AllowDisputes)?;
+        assert_eq!(
+            accounts[&1],
+            ClientAccount {
+                client: 1,
+                available: 0.0,
+                held: 3.0,
+                total: 3.0,
+                locked: true,
+                closed: false,
+                lock: Some(LockInfo {
+                    reason: LockReason::Chargeback,
+                    tx: 1,
+                    tx_index: None,
+                }),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn locked_policy_allow_admin() -> Result<(), Error> {
+        let accounts = lock_account_with_two_deposits(LockedPolicy::AllowAdmin)?;
+        assert_eq!(
+            accounts[&1],
+            ClientAccount {
+                client: 1,
+                available: 10.0,
+                held: 3.0,
+                total: 13.0,
+                locked: true,
+                closed: false,
+                lock: Some(LockInfo {
+                    reason: LockReason::Chargeback,
+                    tx: 1,
+                    tx_index: None,
+                }),
+            }
+        );
+        assert_eq!(
+            "allow_admin".parse::<LockedPolicy>()?,
+            LockedPolicy::AllowAdmin
+        );
+        assert!("allow_all".parse::<LockedPolicy>().is_err());
+        Ok(())
+    }
+
+    fn dispute_spent_deposit(
+        hold_policy: DisputeHoldPolicy,
+        settle: TxType,
+    ) -> Result<ClientAccount, Error> {
+        let mut accounts: HashMap<ClientId, ClientAccount> = HashMap::new();
+        let mut tx_states: HashMap<TxId, TxState> = HashMap::new();
+        let txs = vec![
+            (TxType::Deposit, 1, Some(5.0)),
+            (TxType::Withdrawal, 2, Some(3.0)),
+            (TxType::Dispute, 1, None),
+            (settle, 1, None),
+        ];
+        for (type_, tx_id, amount) in txs {
+            let tx = Tx {
+                amount,
+                ..Tx::new(type_, 1, tx_id)
+            };
+            process_tx_with_policies(
+                tx,
+                &mut accounts,
+                &mut tx_states,
+                LockedPolicy::default(),
+                hold_policy,
+                Compat::V2,
+            )?;
+        }
+        Ok(accounts[&1].clone())
+    }
+
+    #[test]
+    fn dispute_hold_capped_at_available() -> Result<(), Error> {
+        let negative = dispute_spent_deposit(DisputeHoldPolicy::AllowNegative, TxType::Dispute)?;
+        assert_eq!((negative.available, negative.held), (-3.0, 5.0));
+
+        let capped = dispute_spent_deposit(DisputeHoldPolicy::CapAtAvailable, TxType::Dispute)?;
+        assert_eq!(
+            (capped.available, capped.held, capped.total),
+            (0.0, 2.0, 2.0)
+        );
+        let resolved = dispute_spent_deposit(DisputeHoldPolicy::CapAtAvailable, TxType::Resolve)?;
+        assert_eq!((resolved.available, resolved.held), (2.0, 0.0));
+        let charged_back =
+            dispute_spent_deposit(DisputeHoldPolicy::CapAtAvailable, TxType::Chargeback)?;
+        assert_eq!(
+            (charged_back.held, charged_back.total, charged_back.locked),
+            (0.0, 0.0, true)
+        );
+        assert_eq!(
+            "cap_at_available".parse::<DisputeHoldPolicy>()?,
+            DisputeHoldPolicy::CapAtAvailable
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn account_methods_keep_invariants() -> Result<(), Error> {
+        let mut account = ClientAccount::new(1);
+        account.deposit(Amount::new(5.0)?)?;
+        let err = account.withdraw(Amount::new(6.0)?).unwrap_err();
+        assert_eq!(err.code, ErrorCode::InsufficientFunds);
+        account.withdraw(Amount::new(2.0)?)?;
+        account.hold(Amount::new(4.0)?)?;
+        assert_eq!(account.available(), Balance::new(-1.0));
+        assert_eq!(account.held()?, Amount::new(4.0)?);
+        assert!(account.release(Amount::new(4.5)?).is_err());
+        assert!(account.close().is_err());
+        account.charge_back(Amount::new(4.0)?, 7)?;
+        assert_eq!(account.total(), Balance::new(-1.0));
+        assert_eq!(account.lock.as_ref().map(|lock| lock.tx), Some(7));
+        account.adjust(SignedAmount::new(1.0)?)?;
+        account.close()?;
+        assert_eq!(
+            account.deposit(Amount::new(1.0)?).unwrap_err().code,
+            ErrorCode::AccountClosed
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn broken_amounts_are_errors() -> Result<(), Error> {
+        // as a `Compat::V1` dispute of a negative deposit leaves it
+        let mut account = ClientAccount::new(1);
+        account.held = -3.0;
+        assert_eq!(account.held().unwrap_err().code, ErrorCode::Invariants);
+
+        let amount = SignedAmount::new(-1.5)?;
+        let tx = Tx::new(TxType::Adjustment, 1, 1).with_signed_amount(Some(amount));
+        assert_eq!(tx.amount(), Some(amount));
+        Ok(())
+    }
+}
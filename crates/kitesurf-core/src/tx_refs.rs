@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+use crate::{Error, TxId};
+
+/// Interning table mapping external string transaction references (e.g.
+/// UUIDs) to compact `TxId`s, assigned in order of first appearance.
+#[derive(Debug, Default)]
+pub struct TxRefs {
+    ids: HashMap<String, TxId>,
+    refs: Vec<String>,
+}
+
+impl TxRefs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, tx_ref: &str) -> Result<TxId, Error> {
+        if let Some(&tx_id) = self.ids.get(tx_ref) {
+            return Ok(tx_id);
+        }
+        let tx_id = TxId::try_from(self.refs.len())
+            .map_err(|_| Error::new("Too many distinct transaction references"))?;
+        self.ids.insert(tx_ref.to_string(), tx_id);
+        self.refs.push(tx_ref.to_string());
+        Ok(tx_id)
+    }
+
+    /// The external reference an interned id was assigned to.
+    pub fn resolve(&self, tx_id: TxId) -> Option<&str> {
+        self.refs.get(tx_id as usize).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.refs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.refs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intern_and_resolve() -> Result<(), Error> {
+        let mut refs = TxRefs::new();
+        let a = refs.intern("7f9c2ba4-e88f-11ec-8ea0-0242ac120002")?;
+        let b = refs.intern("ref-b")?;
+        assert_ne!(a, b);
+        assert_eq!(refs.intern("7f9c2ba4-e88f-11ec-8ea0-0242ac120002")?, a);
+        assert_eq!(refs.resolve(b), Some("ref-b"));
+        assert_eq!(refs.len(), 2);
+        Ok(())
+    }
+}